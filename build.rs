@@ -1,16 +1,23 @@
-use std::io::Result;
+use std::{env, io::Result, path::PathBuf};
 
 fn main() -> Result<()> {
+    // 供服务端反射使用
+    let descriptor_path = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"))
+        .join("netsrv_descriptor.bin");
+
     tonic_build::configure()
         .build_server(true)
         .build_client(true)
         .out_dir("protos")
+        .file_descriptor_set_path(descriptor_path)
         .compile(
             &[
                 "protos/voting.proto",
                 "protos/hello.proto",
                 "protos/web.proto",
                 "protos/tutorial.proto",
+                "protos/conversation.proto",
+                "protos/error_details.proto",
             ],
             &["protos"],
         )?;
@@ -0,0 +1,208 @@
+#[derive(Clone)]
+struct Duck;
+
+impl Duck {
+    fn swim(&self) -> String {
+        String::from("Look, the duck is swimming")
+    }
+}
+
+impl Bird for Duck {
+    fn quack(&self) -> String {
+        String::from("duck duck")
+    }
+}
+
+#[derive(Clone)]
+struct Swan;
+
+impl Swan {
+    fn fly(&self) -> String {
+        String::from("Look, the duck.. oh sorry, the swan is flying")
+    }
+}
+
+impl Bird for Swan {
+    fn quack(&self) -> String {
+        String::from("swan swan")
+    }
+}
+
+trait Bird {
+    fn quack(&self) -> String;
+}
+
+// 超出容量时的处理方式
+enum EvictionPolicy<T: ?Sized> {
+    RejectNew,
+    EvictFirst,
+    EvictLast,
+    // 移除第一个满足条件的元素, 都不满足时按 RejectNew 处理
+    EvictByPredicate(Box<dyn Fn(&T) -> bool>),
+}
+
+struct Manager<T: ?Sized> {
+    items: Vec<Box<T>>,
+    capacity: usize,
+    policy: EvictionPolicy<T>,
+}
+
+impl<T: ?Sized + Bird> Manager<T> {
+    fn new(capacity: usize, policy: EvictionPolicy<T>) -> Self {
+        Self {
+            items: Vec::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    // 成功时返回被淘汰的元素, 被拒绝时通过 Err 把新元素还给调用方
+    fn add(&mut self, item: Box<T>) -> Result<Option<Box<T>>, Box<T>> {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+            return Ok(None);
+        }
+
+        let evicted = match &self.policy {
+            EvictionPolicy::RejectNew => None,
+            EvictionPolicy::EvictFirst => (!self.items.is_empty()).then(|| self.items.remove(0)),
+            EvictionPolicy::EvictLast => self.items.pop(),
+            EvictionPolicy::EvictByPredicate(predicate) => self
+                .items
+                .iter()
+                .position(|i| predicate(i))
+                .map(|index| self.items.remove(index)),
+        };
+
+        match evicted {
+            Some(evicted) => {
+                self.items.push(item);
+                Ok(Some(evicted))
+            }
+            None => Err(item),
+        }
+    }
+
+    fn remove(&mut self, item: &T) {
+        self.items.retain(|i| i.quack() != item.quack());
+    }
+
+    fn service(&mut self) {
+        for i in self.items.iter() {
+            println!("{}", i.quack());
+        }
+    }
+}
+
+fn main() {
+    let mut items = Manager::new(
+        1,
+        EvictionPolicy::EvictByPredicate(Box::new(|i: &(dyn Bird + 'static)| {
+            i.quack() == "duck duck"
+        })),
+    );
+    let duck = Box::new(Duck);
+    let bird = Box::new(Swan);
+
+    let _ = items.add(duck.clone() as Box<dyn Bird>);
+    if let Ok(Some(evicted)) = items.add(bird.clone() as Box<dyn Bird>) {
+        println!("evicted: {}", evicted.quack());
+    }
+    if let Err(rejected) = items.add(duck.clone() as Box<dyn Bird>) {
+        println!("rejected: {}", rejected.quack());
+    }
+    items.service();
+
+    items.remove(&Swan);
+    items.service();
+
+    // 其余策略: 容量为 1 时依次加入 duck 和 swan
+    let policies: Vec<(&str, EvictionPolicy<dyn Bird>)> = vec![
+        ("reject new", EvictionPolicy::RejectNew),
+        ("evict first", EvictionPolicy::EvictFirst),
+        ("evict last", EvictionPolicy::EvictLast),
+    ];
+    for (name, policy) in policies {
+        let mut items = Manager::new(1, policy);
+        let _ = items.add(duck.clone() as Box<dyn Bird>);
+        match items.add(bird.clone() as Box<dyn Bird>) {
+            Ok(Some(evicted)) => println!("{}: evicted {}", name, evicted.quack()),
+            Ok(None) => println!("{}: added", name),
+            Err(rejected) => println!("{}: rejected {}", name, rejected.quack()),
+        }
+    }
+
+    println!("{}", duck.swim());
+    println!("{}", bird.fly());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quacks(manager: &Manager<dyn Bird>) -> Vec<String> {
+        manager.items.iter().map(|i| i.quack()).collect()
+    }
+
+    fn full_manager(policy: EvictionPolicy<dyn Bird>) -> Manager<dyn Bird> {
+        let mut manager = Manager::new(2, policy);
+        assert!(matches!(manager.add(Box::new(Duck)), Ok(None)));
+        assert!(matches!(manager.add(Box::new(Swan)), Ok(None)));
+        manager
+    }
+
+    #[test]
+    fn reject_new_hands_the_item_back() {
+        let mut manager = full_manager(EvictionPolicy::RejectNew);
+
+        let rejected = manager.add(Box::new(Duck)).err().expect("rejected");
+        assert_eq!(rejected.quack(), "duck duck");
+        assert_eq!(quacks(&manager), ["duck duck", "swan swan"]);
+    }
+
+    #[test]
+    fn evict_first_drops_the_oldest_item() {
+        let mut manager = full_manager(EvictionPolicy::EvictFirst);
+
+        let evicted = manager.add(Box::new(Swan)).ok().flatten().expect("evicted");
+        assert_eq!(evicted.quack(), "duck duck");
+        assert_eq!(quacks(&manager), ["swan swan", "swan swan"]);
+    }
+
+    #[test]
+    fn evict_last_drops_the_newest_item() {
+        let mut manager = full_manager(EvictionPolicy::EvictLast);
+
+        let evicted = manager.add(Box::new(Duck)).ok().flatten().expect("evicted");
+        assert_eq!(evicted.quack(), "swan swan");
+        assert_eq!(quacks(&manager), ["duck duck", "duck duck"]);
+    }
+
+    #[test]
+    fn evict_by_predicate_drops_the_first_match() {
+        let mut manager = full_manager(EvictionPolicy::EvictByPredicate(Box::new(|i| {
+            i.quack() == "swan swan"
+        })));
+
+        let evicted = manager.add(Box::new(Duck)).ok().flatten().expect("evicted");
+        assert_eq!(evicted.quack(), "swan swan");
+        assert_eq!(quacks(&manager), ["duck duck", "duck duck"]);
+    }
+
+    #[test]
+    fn evict_by_predicate_without_match_rejects() {
+        let mut manager = full_manager(EvictionPolicy::EvictByPredicate(Box::new(|_| false)));
+
+        let rejected = manager.add(Box::new(Swan)).err().expect("rejected");
+        assert_eq!(rejected.quack(), "swan swan");
+        assert_eq!(quacks(&manager), ["duck duck", "swan swan"]);
+    }
+
+    #[test]
+    fn remove_drops_matching_items() {
+        let mut manager = full_manager(EvictionPolicy::RejectNew);
+
+        manager.remove(&Duck);
+        assert_eq!(quacks(&manager), ["swan swan"]);
+    }
+}
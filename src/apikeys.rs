@@ -0,0 +1,308 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use tonic::{
+    body::BoxBody,
+    codegen::{http, BoxFuture, Service},
+    Status,
+};
+use tower::Layer;
+
+use crate::{auth::EXEMPT_SERVICES, rate_limited};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(Debug, Deserialize)]
+struct KeyConfig {
+    key: String,
+    name: String,
+    requests_per_minute: u32,
+}
+
+// 通过 x-api-key 识别的调用方, 由 ApiKeyLayer 放入请求的 extensions
+#[derive(Debug, Clone)]
+pub struct ApiKeyName(pub String);
+
+// 令牌桶: 最多攒 requests_per_minute 个令牌, 每分钟补满
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    // 每秒补充的令牌数
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(requests_per_minute: u32) -> Self {
+        let capacity = f64::from(requests_per_minute);
+        Bucket {
+            capacity,
+            rate: capacity / 60.0,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    // 有令牌时取走一个; 否则返回攒到一个令牌还需要等待的时间
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+    }
+}
+
+#[derive(Debug)]
+struct KeyRecord {
+    name: String,
+    bucket: Mutex<Bucket>,
+}
+
+// 要求 x-api-key 是启动时加载的 key 之一, 每个 key 按自己的 requests_per_minute 限流;
+// 没有加载 key 时不做检查, EXEMPT_SERVICES 中的服务也不检查
+#[derive(Debug, Clone, Default)]
+pub struct ApiKeyLayer {
+    // key -> 记录
+    keys: Option<Arc<HashMap<String, KeyRecord>>>,
+}
+
+impl ApiKeyLayer {
+    // JSON 数组, 每项为 {"key": ..., "name": ..., "requests_per_minute": ...}, requests_per_minute 不能为 0
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let configs: Vec<KeyConfig> = serde_json::from_slice(&fs::read(path)?)?;
+        if let Some(config) = configs
+            .iter()
+            .find(|config| config.requests_per_minute == 0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: requests_per_minute of {} must be positive",
+                    path.display(),
+                    config.name
+                ),
+            ));
+        }
+        let keys = configs
+            .into_iter()
+            .map(|config| {
+                let record = KeyRecord {
+                    name: config.name,
+                    bucket: Mutex::new(Bucket::new(config.requests_per_minute)),
+                };
+                (config.key, record)
+            })
+            .collect();
+
+        Ok(ApiKeyLayer {
+            keys: Some(Arc::new(keys)),
+        })
+    }
+}
+
+impl<S> Layer<S> for ApiKeyLayer {
+    type Service = ApiKeyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiKeyService {
+            inner,
+            keys: self.keys.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKeyService<S> {
+    inner: S,
+    keys: Option<Arc<HashMap<String, KeyRecord>>>,
+}
+
+impl<S> ApiKeyService<S> {
+    #[allow(clippy::result_large_err)]
+    fn check<B>(&self, request: &mut http::Request<B>) -> Result<(), Status> {
+        let Some(keys) = &self.keys else {
+            return Ok(());
+        };
+        // 路径形如 "/hello.Greeter/SayHello"
+        let service = request.uri().path().split('/').nth(1).unwrap_or_default();
+        if EXEMPT_SERVICES.contains(&service) {
+            return Ok(());
+        }
+
+        let record = request
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|key| keys.get(key))
+            .ok_or_else(|| Status::unauthenticated("missing or unknown x-api-key"))?;
+        if let Err(retry_after) = record.bucket.lock().unwrap().take(Instant::now()) {
+            return Err(rate_limited("api key quota exceeded", retry_after));
+        }
+        request
+            .extensions_mut()
+            .insert(ApiKeyName(record.name.clone()));
+
+        Ok(())
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for ApiKeyService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        if let Err(status) = self.check(&mut request) {
+            let response = status.to_http();
+            return Box::pin(async move { Ok(response) });
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, path::PathBuf};
+
+    use tonic::{body::empty_body, Code};
+    use tower::service_fn;
+
+    use super::*;
+
+    fn temp_keys(text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("apikeys-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(text: &str) -> io::Result<ApiKeyLayer> {
+        let path = temp_keys(text);
+        let layer = ApiKeyLayer::load(&path);
+        fs::remove_file(&path).unwrap();
+        layer
+    }
+
+    // 内层服务返回调用方的名字, 没有经过检查时为空
+    #[allow(clippy::result_large_err)]
+    fn call(layer: &ApiKeyLayer, path: &str, key: Option<&str>) -> Result<String, Status> {
+        let mut service = layer.layer(service_fn(|request: http::Request<()>| async move {
+            let name = request.extensions().get::<ApiKeyName>().cloned();
+            let mut response = http::Response::new(empty_body());
+            response.extensions_mut().insert(name);
+            Ok::<_, Infallible>(response)
+        }));
+        let mut request = http::Request::builder().uri(path);
+        if let Some(key) = key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        let response = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(service.call(request.body(()).unwrap()))
+            .unwrap();
+        if let Some(status) = Status::from_header_map(response.headers()) {
+            return Err(status);
+        }
+
+        let name = response.extensions().get::<Option<ApiKeyName>>().cloned();
+        Ok(name.flatten().map(|name| name.0).unwrap_or_default())
+    }
+
+    const KEYS: &str = r#"[{"key": "k1", "name": "dashboard", "requests_per_minute": 2}]"#;
+
+    #[test]
+    fn known_key_is_named() {
+        let layer = load(KEYS).unwrap();
+        assert_eq!(
+            call(&layer, "/hello.Greeter/SayHello", Some("k1")).unwrap(),
+            "dashboard"
+        );
+    }
+
+    #[test]
+    fn missing_or_unknown_key_is_unauthenticated() {
+        let layer = load(KEYS).unwrap();
+        for key in [None, Some("k2")] {
+            let status = call(&layer, "/hello.Greeter/SayHello", key).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+        }
+    }
+
+    #[test]
+    fn exhausted_quota_reports_retry_after() {
+        let layer = load(KEYS).unwrap();
+        call(&layer, "/hello.Greeter/SayHello", Some("k1")).unwrap();
+        call(&layer, "/voting.Voting/Vote", Some("k1")).unwrap();
+
+        let status = call(&layer, "/hello.Greeter/SayHello", Some("k1")).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        // 每分钟 2 个令牌, 攒一个需要 30 秒
+        let retry_after = status.metadata().get("retry-after").unwrap();
+        assert_eq!(retry_after, "30");
+    }
+
+    #[test]
+    fn exempt_services_skip_the_check() {
+        let layer = load(KEYS).unwrap();
+        assert_eq!(
+            call(&layer, "/grpc.health.v1.Health/Check", None).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn no_keys_means_no_check() {
+        let layer = ApiKeyLayer::default();
+        assert_eq!(call(&layer, "/hello.Greeter/SayHello", None).unwrap(), "");
+    }
+
+    #[test]
+    fn zero_quota_is_rejected() {
+        let error =
+            load(r#"[{"key": "k1", "name": "dashboard", "requests_per_minute": 0}]"#).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("dashboard"));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = Bucket::new(60);
+        for _ in 0..60 {
+            bucket.take(start).unwrap();
+        }
+
+        let wait = bucket.take(start).unwrap_err();
+        assert!(wait > Duration::from_millis(999) && wait <= Duration::from_secs(1));
+        bucket.take(start + Duration::from_secs(1)).unwrap();
+        // 补充的令牌不超过上限
+        let later = start + Duration::from_secs(3600);
+        for _ in 0..60 {
+            bucket.take(later).unwrap();
+        }
+        assert!(bucket.take(later).is_err());
+    }
+}
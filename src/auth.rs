@@ -0,0 +1,178 @@
+use std::{collections::HashMap, fs, io, path::Path, sync::Arc};
+
+use tonic::{service::Interceptor, Request, Status};
+
+// 不需要令牌的服务: 健康检查和反射
+pub const EXEMPT_SERVICES: &[&str] = &[
+    "grpc.health.v1.Health",
+    "grpc.reflection.v1alpha.ServerReflection",
+];
+
+// 通过 bearer 令牌认证的调用方, 由 BearerAuth 放入请求的 extensions
+#[derive(Debug, Clone)]
+pub struct Principal(pub String);
+
+// 要求 "authorization: Bearer <token>", token 必须是启动时加载的令牌之一;
+// 没有加载令牌时不做检查
+#[derive(Debug, Clone, Default)]
+pub struct BearerAuth {
+    // token -> principal
+    tokens: Option<Arc<HashMap<String, String>>>,
+    exempt: bool,
+}
+
+impl BearerAuth {
+    pub fn new<I, T, P>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = (T, P)>,
+        T: Into<String>,
+        P: Into<String>,
+    {
+        let tokens = tokens
+            .into_iter()
+            .map(|(token, principal)| (token.into(), principal.into()))
+            .collect();
+
+        BearerAuth {
+            tokens: Some(Arc::new(tokens)),
+            exempt: false,
+        }
+    }
+
+    // 每行 "<principal> <token>", 空行和 # 开头的行会被忽略
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut tokens = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(principal), Some(token), None) => tokens.push((token, principal)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}:{}: expected \"<principal> <token>\"",
+                            path.display(),
+                            n + 1
+                        ),
+                    ))
+                }
+            }
+        }
+
+        Ok(Self::new(tokens))
+    }
+
+    // 用于名为 service 的服务, EXEMPT_SERVICES 中的服务不检查令牌
+    pub fn for_service(&self, service: &str) -> Self {
+        BearerAuth {
+            tokens: self.tokens.clone(),
+            exempt: EXEMPT_SERVICES.contains(&service),
+        }
+    }
+}
+
+impl Interceptor for BearerAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(tokens) = self.tokens.as_ref().filter(|_| !self.exempt) else {
+            return Ok(request);
+        };
+
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+        let principal = tokens
+            .get(token)
+            .ok_or_else(|| Status::unauthenticated("invalid bearer token"))?
+            .clone();
+        request.extensions_mut().insert(Principal(principal));
+
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    fn auth() -> BearerAuth {
+        BearerAuth::new([("secret-1", "alice"), ("secret-2", "bob")])
+    }
+
+    fn with_authorization(value: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("authorization", value.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn missing_token_is_unauthenticated() {
+        let status = auth().call(Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(status.message(), "missing bearer token");
+
+        // 不是 Bearer 方案的 authorization 也算没有令牌
+        let status = auth()
+            .call(with_authorization("Basic c2VjcmV0"))
+            .unwrap_err();
+        assert_eq!(status.message(), "missing bearer token");
+    }
+
+    #[test]
+    fn wrong_token_is_unauthenticated() {
+        let status = auth()
+            .call(with_authorization("Bearer secret-3"))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(status.message(), "invalid bearer token");
+    }
+
+    #[test]
+    fn valid_token_puts_the_principal_in_the_extensions() {
+        let request = auth().call(with_authorization("Bearer secret-2")).unwrap();
+        let Principal(principal) = request.extensions().get::<Principal>().unwrap();
+        assert_eq!(principal, "bob");
+    }
+
+    #[test]
+    fn exempt_services_and_no_tokens_skip_the_check() {
+        let mut health = auth().for_service("grpc.health.v1.Health");
+        let request = health.call(Request::new(())).unwrap();
+        assert!(request.extensions().get::<Principal>().is_none());
+
+        assert!(auth()
+            .for_service("voting.Voting")
+            .call(Request::new(()))
+            .is_err());
+        BearerAuth::default().call(Request::new(())).unwrap();
+    }
+
+    #[test]
+    fn load_reads_principal_token_lines() {
+        let path = std::env::temp_dir().join(format!("tokens-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, "# comment\n\nalice secret-1\n  bob   secret-2  \n").unwrap();
+        let mut auth = BearerAuth::load(&path).unwrap();
+        let request = auth.call(with_authorization("Bearer secret-2")).unwrap();
+        assert_eq!(request.extensions().get::<Principal>().unwrap().0, "bob");
+
+        fs::write(&path, "alice secret-1 extra\n").unwrap();
+        let error = BearerAuth::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error
+            .to_string()
+            .ends_with(":1: expected \"<principal> <token>\""));
+
+        fs::remove_file(path).unwrap();
+    }
+}
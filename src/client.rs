@@ -1,15 +1,34 @@
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use rand::{rngs::ThreadRng, Rng};
+use prost::Message;
+use rand::Rng;
 use tokio::time;
 use tonic::{
+    metadata::{Ascii, KeyAndValueRef, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
     transport::{Channel, Endpoint},
-    Request,
+    Request, Response, Status,
 };
+use uuid::Uuid;
 
-use greet::{greeter_client::GreeterClient, HelloReq};
-use routeguide::{route_guide_client::RouteGuideClient, Point, Rectangle, RouteNote};
-use voting::{voting_client::VotingClient, voting_request, VotingRequest};
+use greet::{greeter_client::GreeterClient, GreetingSummary, HelloReq, HelloResp};
+use routeguide::{
+    feature::Category, route_guide_client::RouteGuideClient, ApproveCorrectionRequest,
+    CorrectionRequest, Empty, ExportRequest, Feature, ListFeaturesRequest, Point, Rectangle,
+    RouteNote, RouteSummary, SearchRequest,
+};
+use voting::{
+    top_urls_request, voting_client::VotingClient, voting_request, BatchVotingRequest,
+    TopUrlsRequest, VoteCountRequest, VoteHistoryRequest, VotingRequest,
+};
 
 pub mod voting {
     include!("../protos/voting.rs");
@@ -23,60 +42,793 @@ pub mod routeguide {
     include!("../protos/tutorial.rs");
 }
 
+pub mod google_rpc {
+    include!("../protos/google.rpc.rs");
+}
+
+mod featurejson;
+
 type ThisErr = Box<dyn std::error::Error>;
 
-async fn voting(client: &mut VotingClient<Channel>) -> Result<(), ThisErr> {
+// 给每个投票请求带上 x-user-id, 服务端按用户去重
+#[derive(Clone)]
+struct UserIdInterceptor {
+    user_id: MetadataValue<Ascii>,
+}
+
+impl Interceptor for UserIdInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert("x-user-id", self.user_id.clone());
+        Ok(request)
+    }
+}
+
+type UserVotingClient = VotingClient<InterceptedService<Channel, UserIdInterceptor>>;
+
+// 给每个问候请求带上 accept-language, 服务端按该语言问候
+#[derive(Clone)]
+struct AcceptLanguageInterceptor {
+    accept_language: MetadataValue<Ascii>,
+}
+
+impl Interceptor for AcceptLanguageInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert("accept-language", self.accept_language.clone());
+        Ok(request)
+    }
+}
+
+async fn voting(client: &mut UserVotingClient) -> Result<(), ThisErr> {
     let url = "http://helloword.com/post1";
     let mut n = 0;
+    let mut last_count = 0;
 
     loop {
-        let vote_res = if n % 2 == 0 {
-            voting_request::Vote::Up
-        } else {
-            voting_request::Vote::Down
+        // 依次投赞成, 反对和弃权票
+        let vote_res = match n % 3 {
+            0 => voting_request::Vote::Up,
+            1 => voting_request::Vote::Down,
+            _ => voting_request::Vote::Abstain,
         };
-        let request = tonic::Request::new(VotingRequest {
+        // 只有反对票附上理由
+        let reason = match vote_res {
+            voting_request::Vote::Down => "not helpful".to_string(),
+            _ => String::new(),
+        };
+        let mut request = tonic::Request::new(VotingRequest {
             url: url.to_string(),
             vote: vote_res.into(),
+            voter_id: "client-demo".to_string(),
+            reason,
         });
-        let response = client.vote(request).await?;
-        println!("voting {}, Got: '{}'", n, response.get_ref().confirmation);
+        // 每一票一个新的 key, 网络重试时服务端不会重复计票
+        let idempotency_key: MetadataValue<Ascii> = Uuid::new_v4().to_string().parse()?;
+        request
+            .metadata_mut()
+            .insert("x-idempotency-key", idempotency_key);
+        match client.vote(request).await {
+            Ok(response) => {
+                let response = response.get_ref();
+                println!(
+                    "voting {}, Got: {} {} (up {}, down {}, abstain {})",
+                    n,
+                    response.vote().as_str_name(),
+                    response.url,
+                    response.up_count,
+                    response.down_count,
+                    response.abstain_count
+                )
+            }
+            // 同一个用户重复投同样的票会被拒绝
+            Err(status) if status.code() == tonic::Code::AlreadyExists => {
+                println!("voting {}, rejected: '{}'", n, status.message())
+            }
+            // 同一用户对同一 url 投票太频繁, 按 retry-after 等待后再投
+            Err(status) if status.code() == tonic::Code::ResourceExhausted => {
+                let retry_after = status
+                    .metadata()
+                    .get("retry-after")
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .unwrap_or(1);
+                println!("voting {}, retry after {}s", n, retry_after);
+                tokio::time::sleep(Duration::from_secs(retry_after)).await;
+                continue;
+            }
+            Err(status) => {
+                for violation in field_violations(&status) {
+                    println!(
+                        "voting {}, bad {}: {}",
+                        n, violation.field, violation.description
+                    );
+                }
+                return Err(status.into());
+            }
+        }
         n += 1;
 
+        // 每 5 票核对一次计数, 改票只会在赞成和反对之间移动, 总数不会减少
+        if n % 5 == 0 {
+            let count = client
+                .get_vote_count(tonic::Request::new(VoteCountRequest {
+                    url: url.to_string(),
+                    ..Default::default()
+                }))
+                .await?
+                .into_inner();
+            println!(
+                "vote count for {}: up {}, down {}, total {}",
+                count.url, count.up_count, count.down_count, count.total
+            );
+            if count.total < last_count {
+                println!("vote count went backwards: {} -> {:?}", last_count, count);
+            }
+            last_count = count.total;
+        }
+
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 }
 
-async fn greet(client: &mut GreeterClient<Channel>) -> Result<(), ThisErr> {
+// 由 (url, 票) 构建一次批量投票
+fn batch_request(votes: &[(&str, voting_request::Vote)]) -> BatchVotingRequest {
+    BatchVotingRequest {
+        votes: votes
+            .iter()
+            .map(|(url, vote)| VotingRequest {
+                url: url.to_string(),
+                vote: (*vote).into(),
+                voter_id: "client-demo".to_string(),
+                ..Default::default()
+            })
+            .collect(),
+    }
+}
+
+// 一次提交多票, 其中无效的 url 只影响自己那一票
+async fn batch_voting(client: &mut UserVotingClient) -> Result<(), ThisErr> {
+    let request = batch_request(&[
+        ("http://helloword.com/post2", voting_request::Vote::Up),
+        ("ftp://helloword.com/post3", voting_request::Vote::Up),
+        ("http://helloword.com/post4", voting_request::Vote::Down),
+    ]);
+    let response = client.batch_vote(request).await?.into_inner();
+    for result in response.results {
+        if result.accepted {
+            println!(
+                "batch vote {}: '{}' (up {}, down {})",
+                result.index, result.message, result.up_count, result.down_count
+            );
+        } else {
+            println!(
+                "batch vote {} failed: {:?} '{}'",
+                result.index,
+                tonic::Code::from_i32(result.code),
+                result.message
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// 持续提交 50 票, 其中混入几张无效的票
+async fn stream_votes(client: &mut UserVotingClient) -> Result<(), ThisErr> {
+    let votes = (0..50).map(|i| VotingRequest {
+        url: match i {
+            10 => "not a url".to_string(),
+            30 => "ftp://helloword.com/stream".to_string(),
+            _ => format!("http://helloword.com/stream{}", i % 5),
+        },
+        vote: if i % 2 == 0 {
+            voting_request::Vote::Up
+        } else {
+            voting_request::Vote::Down
+        }
+        .into(),
+        voter_id: "client-demo".to_string(),
+        ..Default::default()
+    });
+
+    let summary = client
+        .stream_votes(Request::new(tokio_stream::iter(votes)))
+        .await?
+        .into_inner();
+    println!(
+        "stream votes: accepted {}, rejected {} {:?} in {}ms",
+        summary.accepted, summary.rejected, summary.rejected_by_reason, summary.elapsed_time_millis
+    );
+
+    Ok(())
+}
+
+// 净票数最高的 limit 个 url
+async fn print_top_urls(client: &mut UserVotingClient, limit: u32) -> Result<(), ThisErr> {
+    let mut stream = client
+        .top_urls(Request::new(TopUrlsRequest {
+            limit,
+            order: top_urls_request::Order::Net.into(),
+        }))
+        .await?
+        .into_inner();
+    let mut rank = 0;
+    while let Some(count) = stream.message().await? {
+        rank += 1;
+        println!(
+            "{:>3}. {} (up {}, down {})",
+            rank, count.url, count.up_count, count.down_count
+        );
+    }
+
+    Ok(())
+}
+
+// user_id 最近的投票, 新的在前
+async fn print_vote_history(client: &mut UserVotingClient, user_id: String) -> Result<(), ThisErr> {
+    let mut stream = client
+        .get_vote_history(Request::new(VoteHistoryRequest { user_id, limit: 20 }))
+        .await?
+        .into_inner();
+    while let Some(event) = stream.message().await? {
+        let vote =
+            voting_request::Vote::from_i32(event.vote).map_or("unknown", |vote| vote.as_str_name());
+        println!("{} {} {}", event.timestamp_millis, vote, event.url);
+    }
+
+    Ok(())
+}
+
+// INVALID_ARGUMENT 的 details 中 google.rpc.BadRequest 列出的字段错误
+fn field_violations(status: &Status) -> Vec<google_rpc::bad_request::FieldViolation> {
+    let Ok(details) = google_rpc::Status::decode(status.details()) else {
+        return vec![];
+    };
+
+    details
+        .details
+        .iter()
+        .filter(|any| any.type_url == "type.googleapis.com/google.rpc.BadRequest")
+        .filter_map(|any| google_rpc::BadRequest::decode(any.value.as_slice()).ok())
+        .flat_map(|bad_request| bad_request.field_violations)
+        .collect()
+}
+
+// 只增不减的计数, 在任务之间共享
+#[derive(Debug, Default)]
+struct Counter(AtomicU64);
+
+impl Counter {
+    fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+struct HedgeMetrics {
+    // 主实例超过 hedge_delay 没有响应, 向第二个实例发送了请求的次数
+    hedged: Counter,
+    // 其中第二个实例先返回的次数
+    hedge_wins: Counter,
+}
+
+// 配置了第二个实例时, 主实例在 hedge_delay 内没有响应就向第二个实例发送同样的请求, 取先返回的结果;
+// 默认只使用主实例
+struct HedgingClient {
+    primary: GreeterClient<Channel>,
+    // (第二个实例, hedge_delay)
+    hedge: Option<(Channel, Duration)>,
+    metrics: Arc<HedgeMetrics>,
+}
+
+impl HedgingClient {
+    fn new(primary: Channel) -> Self {
+        HedgingClient {
+            primary: GreeterClient::new(primary),
+            hedge: None,
+            metrics: Arc::new(HedgeMetrics::default()),
+        }
+    }
+
+    fn with_hedge(mut self, secondary_channel: Channel, hedge_delay: Duration) -> Self {
+        self.hedge = Some((secondary_channel, hedge_delay));
+        self
+    }
+
+    async fn say_hello(&self, req: HelloReq) -> Result<Response<HelloResp>, Status> {
+        let mut primary = self.primary.clone();
+        let Some((secondary_channel, hedge_delay)) = &self.hedge else {
+            return primary.say_hello(Request::new(req)).await;
+        };
+
+        let primary_call = primary.say_hello(Request::new(req.clone()));
+        tokio::pin!(primary_call);
+
+        tokio::select! {
+            resp = &mut primary_call => return resp,
+            _ = time::sleep(*hedge_delay) => {}
+        }
+
+        // 未完成的那个请求会随 future 一起被丢弃(取消)
+        self.metrics.hedged.increment();
+        let mut secondary = GreeterClient::new(secondary_channel.clone());
+        tokio::select! {
+            resp = &mut primary_call => resp,
+            resp = secondary.say_hello(Request::new(req)) => match resp {
+                Ok(resp) => {
+                    self.metrics.hedge_wins.increment();
+                    Ok(resp)
+                }
+                // 第二个实例出错时继续等主实例
+                Err(_) => primary_call.await,
+            },
+        }
+    }
+}
+
+async fn greet(client: &HedgingClient) -> Result<(), ThisErr> {
     let mut n = 0;
 
     loop {
-        let hello_content = format!("hello {}", n);
-        let req = tonic::Request::new(HelloReq {
+        // 每 10 次发送一次空的问候, 演示服务端的校验
+        let hello_content = match n % 10 {
+            9 => String::new(),
+            _ => format!("hello {}", n),
+        };
+        // 一半的问候带上 name, 由服务端按模板问候
+        let name = match n % 2 {
+            0 => "Client Demo".to_string(),
+            _ => String::new(),
+        };
+        let req = HelloReq {
             content: hello_content,
-        });
-        let resp = client.say_hello(req).await?;
-        println!("greet {}, Got: '{}'", n, resp.get_ref().content);
+            name,
+            ..Default::default()
+        };
+        match client.say_hello(req).await {
+            Ok(resp) => {
+                let resp = resp.get_ref();
+                println!(
+                    "greet {}, Got: '{}' (#{} from {} at {})",
+                    n, resp.content, resp.request_number, resp.hostname, resp.timestamp_millis
+                )
+            }
+            Err(status) if status.code() == tonic::Code::InvalidArgument => {
+                print_rejected_greeting(n, &status)
+            }
+            Err(status) => return Err(status.into()),
+        }
 
         n += 1;
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 }
 
+// 空的或太长的问候会被拒绝, 打印 BadRequest 中的原因
+fn print_rejected_greeting(n: u64, status: &Status) {
+    let violations = field_violations(status);
+    if violations.is_empty() {
+        println!("greet {}, rejected: {}", n, status.message());
+    }
+    for violation in violations {
+        println!(
+            "greet {}, rejected: {} {}",
+            n, violation.field, violation.description
+        );
+    }
+}
+
+// 服务端开启 GREETER_FAULT_INJECTION 时, 一半的请求会返回 UNAVAILABLE; 遇到 UNAVAILABLE 时退避后重试
+async fn greet_with_retry(
+    client: &mut GreeterClient<Channel>,
+    max_attempts: u32,
+) -> Result<HelloResp, Status> {
+    let mut backoff = Duration::from_millis(50);
+    let mut attempt = 1;
+    loop {
+        let mut request = Request::new(HelloReq {
+            content: format!("retry {}", attempt),
+            ..Default::default()
+        });
+        request
+            .metadata_mut()
+            .insert("x-fail-with", MetadataValue::from_static("unavailable"));
+        request
+            .metadata_mut()
+            .insert("x-fail-probability", MetadataValue::from_static("50"));
+
+        match client.say_hello(request).await {
+            Err(status) if status.code() == tonic::Code::Unavailable && attempt < max_attempts => {
+                println!(
+                    "greet attempt {} unavailable, retry in {:?}",
+                    attempt, backoff
+                );
+                time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result.map(Response::into_inner),
+        }
+    }
+}
+
+// 让服务端等待 delay 后再响应, 超过 timeout 时客户端放弃并返回 DEADLINE_EXCEEDED
+async fn greet_with_deadline(
+    client: &mut GreeterClient<Channel>,
+    delay: Duration,
+    timeout: Duration,
+) -> Result<HelloResp, Status> {
+    let mut request = Request::new(HelloReq {
+        content: "slow hello".to_string(),
+        delay_ms: delay.as_millis() as u32,
+        ..Default::default()
+    });
+    // 通过 grpc-timeout 告诉服务端, 超时后服务端也会取消处理
+    request.set_timeout(timeout);
+
+    match time::timeout(timeout, client.say_hello(request)).await {
+        Ok(resp) => resp.map(Response::into_inner),
+        Err(_) => Err(Status::deadline_exceeded(format!(
+            "no response within {:?}",
+            timeout
+        ))),
+    }
+}
+
+// 发送 x-echo- 开头的 metadata, 检查经过代理后服务端原样返回
+async fn print_echoed_headers(client: &mut GreeterClient<Channel>) -> Result<(), ThisErr> {
+    let mut request = Request::new(HelloReq {
+        content: "echo".to_string(),
+        ..Default::default()
+    });
+    request
+        .metadata_mut()
+        .insert("x-echo-trace", MetadataValue::from_static("client-demo"));
+    request.metadata_mut().insert_bin(
+        "x-echo-payload-bin",
+        MetadataValue::from_bytes(&[0, 159, 146, 150]),
+    );
+
+    let resp = client.say_hello(request).await?;
+    for entry in resp.metadata().iter() {
+        match entry {
+            KeyAndValueRef::Ascii(key, value) if key.as_str().starts_with("x-echo-") => {
+                println!("echoed {}: {:?}", key, value)
+            }
+            KeyAndValueRef::Binary(key, value) if key.as_str().starts_with("x-echo-") => {
+                println!("echoed {}: {:?}", key, value.to_bytes()?)
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// 打印服务端按 accept_language 选中的语言和问候
+async fn greet_in_locale(channel: Channel, accept_language: &'static str) -> Result<(), ThisErr> {
+    let mut client = GreeterClient::with_interceptor(
+        channel,
+        AcceptLanguageInterceptor {
+            accept_language: MetadataValue::from_static(accept_language),
+        },
+    );
+    let resp = client
+        .say_hello(Request::new(HelloReq {
+            content: "from client-demo".to_string(),
+            ..Default::default()
+        }))
+        .await?;
+    let locale = resp
+        .metadata()
+        .get("content-language")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("none")
+        .to_string();
+    println!(
+        "greet in {:?}, Got: '{}' ({})",
+        accept_language,
+        resp.into_inner().content,
+        locale
+    );
+
+    Ok(())
+}
+
+// 逐条打印 say_hello_stream 返回的问候
+async fn print_greeting_stream(
+    client: &mut GreeterClient<Channel>,
+    count: u32,
+) -> Result<(), ThisErr> {
+    let mut stream = client
+        .say_hello_stream(Request::new(HelloReq {
+            content: "from client-demo".to_string(),
+            count,
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    while let Some(resp) = stream.message().await? {
+        println!("greet stream, Got: '{}'", resp.content);
+    }
+
+    Ok(())
+}
+
+// 把 greetings 逐条发给 lots_of_greetings 并打印汇总
+async fn run_lots_of_greetings(
+    client: &mut GreeterClient<Channel>,
+    greetings: &[&str],
+) -> Result<(), ThisErr> {
+    let requests: Vec<_> = greetings
+        .iter()
+        .map(|content| HelloReq {
+            content: content.to_string(),
+            ..Default::default()
+        })
+        .collect();
+
+    match client
+        .lots_of_greetings(Request::new(tokio_stream::iter(requests)))
+        .await
+    {
+        Ok(response) => {
+            let summary = response.into_inner();
+            println!(
+                "GREETING SUMMARY: {} greetings, {} bytes, longest '{}'",
+                summary.greeting_count, summary.total_bytes, summary.longest_content
+            );
+        }
+        Err(status) => {
+            println!("lots_of_greetings failed: {:?}", status);
+            let partial = match status.details() {
+                [] => None,
+                details => GreetingSummary::decode(details).ok(),
+            };
+            if let Some(partial) = partial {
+                println!(
+                    "PARTIAL GREETING SUMMARY: {} greetings, {} bytes",
+                    partial.greeting_count, partial.total_bytes
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// 每隔 200ms 发送一条, 共 5 条, 检查按顺序收到 5 条回复
+async fn run_greet_chat(client: &mut GreeterClient<Channel>) -> Result<(), ThisErr> {
+    const MESSAGES: u64 = 5;
+
+    let outbound = async_stream::stream! {
+        let mut interval = time::interval(Duration::from_millis(200));
+        for n in 1..=MESSAGES {
+            interval.tick().await;
+            yield HelloReq {
+                content: format!("chat {}", n),
+                ..Default::default()
+            };
+        }
+    };
+
+    let mut inbound = client
+        .greet_chat(Request::new(outbound))
+        .await?
+        .into_inner();
+    let mut received = 0;
+    while let Some(resp) = inbound.message().await? {
+        received += 1;
+        if resp.sequence != received {
+            return Err(format!("expected reply {}, got {}", received, resp.sequence).into());
+        }
+        println!(
+            "greet chat {}, Got: '{}' at {}",
+            resp.sequence, resp.content, resp.timestamp_millis
+        );
+    }
+    if received != MESSAGES {
+        return Err(format!("expected {} replies, got {}", MESSAGES, received).into());
+    }
+
+    Ok(())
+}
+
+async fn print_greet_stats(client: &mut GreeterClient<Channel>) -> Result<(), Box<dyn Error>> {
+    let stats = client
+        .get_greet_stats(Request::new(greet::Empty {}))
+        .await?
+        .into_inner();
+    println!(
+        "GREET STATS: {} requests from {} peers",
+        stats.total_requests, stats.unique_peers
+    );
+    for peer in stats.top_peers {
+        println!("  {}: {}", peer.peer, peer.count);
+    }
+
+    Ok(())
+}
+
+// 一直打印其他客户端的问候, 直到服务端结束
+async fn watch_greetings(client: &mut GreeterClient<Channel>) -> Result<(), Box<dyn Error>> {
+    let mut stream = client
+        .watch_greetings(Request::new(greet::Empty {}))
+        .await?
+        .into_inner();
+    while let Some(resp) = stream.message().await? {
+        if resp.missed > 0 {
+            println!("missed {} greetings", resp.missed);
+            continue;
+        }
+        println!(
+            "greeting #{} from {}: '{}'",
+            resp.request_number, resp.hostname, resp.content
+        );
+    }
+
+    Ok(())
+}
+
+async fn print_nearest_feature(
+    client: &mut RouteGuideClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let response = client
+        .get_nearest_feature(Request::new(Point {
+            latitude: 409_146_138,
+            longitude: -746_188_906,
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+
+    println!(
+        "NEAREST = {:?}, distance: {:.0}m",
+        response.feature, response.distance_meters
+    );
+
+    Ok(())
+}
+
 async fn print_features(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
     let rectangle = Rectangle {
         lo: Some(Point {
             latitude: 400_000_000,
             longitude: -750_000_000,
+            ..Default::default()
         }),
         hi: Some(Point {
             latitude: 420_000_000,
             longitude: -730_000_000,
+            ..Default::default()
         }),
+        ..Default::default()
+    };
+
+    // 每页 5 条, 直到 trailers 中的 x-next-page-token 为空
+    let mut page_token = String::new();
+    loop {
+        let mut stream = client
+            .list_features(Request::new(ListFeaturesRequest {
+                rect: Some(rectangle.clone()),
+                page_size: 5,
+                page_token,
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+
+        while let Some(feature) = stream.message().await? {
+            println!("NOTE = {:?}{}", feature.name, feature_details(&feature));
+        }
+
+        page_token = stream
+            .trailers()
+            .await?
+            .and_then(|trailers| {
+                let token = trailers.get("x-next-page-token")?.to_str().ok()?;
+                Some(token.to_string())
+            })
+            .unwrap_or_default();
+        if page_token.is_empty() {
+            break;
+        }
+        println!("--- next page: {}", page_token);
+    }
+
+    Ok(())
+}
+
+// 位置以及设置了的海拔、分类和标签
+fn feature_details(feature: &Feature) -> String {
+    let mut details = String::new();
+    if let Some(location) = &feature.location {
+        details += &format!(" at {},{}", location.latitude, location.longitude);
+    }
+    if feature.elevation != 0.0 {
+        details += &format!(", elevation {}m", feature.elevation);
+    }
+    if feature.category() != Category::Unspecified {
+        details += &format!(", category {}", feature.category().as_str_name());
+    }
+    if !feature.tags.is_empty() {
+        details += &format!(", tags [{}]", feature.tags.join(", "));
+    }
+
+    details
+}
+
+async fn print_features_by_distance(
+    client: &mut RouteGuideClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let origin = Point {
+        latitude: 409_146_138,
+        longitude: -746_188_906,
+        ..Default::default()
+    };
+    let rectangle = Rectangle {
+        lo: Some(Point {
+            latitude: 400_000_000,
+            longitude: -750_000_000,
+            ..Default::default()
+        }),
+        hi: Some(Point {
+            latitude: 420_000_000,
+            longitude: -730_000_000,
+            ..Default::default()
+        }),
+        ..Default::default()
     };
 
     let mut stream = client
-        .list_features(Request::new(rectangle))
+        .list_features(Request::new(ListFeaturesRequest {
+            rect: Some(rectangle),
+            order_from: Some(origin.clone()),
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+
+    while let Some(feature) = stream.message().await? {
+        let distance = feature
+            .location
+            .as_ref()
+            .map_or(0.0, |location| distance_meters(&origin, location));
+        println!("{:>10.0}m  {}", distance, feature.name);
+    }
+
+    Ok(())
+}
+
+// lo 在 hi 东边, 矩形跨越 ±180° 经线
+async fn print_features_across_antimeridian(
+    client: &mut RouteGuideClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let rectangle = Rectangle {
+        lo: Some(Point {
+            latitude: -900_000_000,
+            longitude: 1_700_000_000,
+            ..Default::default()
+        }),
+        hi: Some(Point {
+            latitude: 900_000_000,
+            longitude: -1_700_000_000,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let mut stream = client
+        .list_features(Request::new(ListFeaturesRequest {
+            rect: Some(rectangle),
+            ..Default::default()
+        }))
         .await?
         .into_inner();
 
@@ -87,7 +839,220 @@ async fn print_features(client: &mut RouteGuideClient<Channel>) -> Result<(), Bo
     Ok(())
 }
 
-async fn run_record_route(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
+// 两点间的球面距离(米)
+fn distance_meters(p1: &Point, p2: &Point) -> f64 {
+    const R: f64 = 6_371_000.0;
+
+    let lat1 = (p1.latitude as f64 / 1e7).to_radians();
+    let lat2 = (p2.latitude as f64 / 1e7).to_radians();
+    let delta_lat = lat2 - lat1;
+    let delta_lng = ((p2.longitude - p1.longitude) as f64 / 1e7).to_radians();
+
+    let a =
+        (delta_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (delta_lng / 2.0).sin().powi(2);
+
+    2.0 * R * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+// run_add_feature 和 run_delete_feature 使用的位置
+const DEMO_FEATURE_LOCATION: Point = Point {
+    latitude: 407_000_000,
+    longitude: -745_000_000,
+    timestamp: 0,
+    floor: 0,
+};
+
+// 注册一个新的 feature 并用 get_feature 读回; 上次运行没有删除时会返回 ALREADY_EXISTS
+async fn run_add_feature(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
+    let location = DEMO_FEATURE_LOCATION;
+    let feature = Feature {
+        name: "Demo Lookout, Somerset, NJ".to_string(),
+        location: Some(location.clone()),
+        ..Default::default()
+    };
+
+    match client.add_feature(Request::new(feature)).await {
+        Ok(response) => println!("ADDED = {:?}", response.into_inner()),
+        Err(status) if status.code() == tonic::Code::AlreadyExists => {
+            println!("add_feature rejected: '{}'", status.message())
+        }
+        Err(status) => return Err(status.into()),
+    }
+
+    let response = client.get_feature(Request::new(location)).await?;
+    println!("RESPONSE = {:?}", response.into_inner());
+
+    Ok(())
+}
+
+// 删除 run_add_feature 注册的 feature, 再删除一次会返回 NOT_FOUND
+async fn run_delete_feature(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
+    let response = client
+        .delete_feature(Request::new(DEMO_FEATURE_LOCATION))
+        .await?;
+    println!("DELETED = {:?}", response.into_inner());
+
+    match client
+        .delete_feature(Request::new(DEMO_FEATURE_LOCATION))
+        .await
+    {
+        Err(status) if status.code() == tonic::Code::NotFound => {
+            println!("delete_feature again: '{}'", status.message())
+        }
+        other => println!("delete_feature again = {:?}", other),
+    }
+
+    Ok(())
+}
+
+// 把 route_guide_db.json 格式文件中的 feature 以流的方式批量导入
+async fn run_import_features(
+    client: &mut RouteGuideClient<Channel>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let features = featurejson::load_json(path)?;
+    println!(
+        "Importing {} features from {}",
+        features.len(),
+        path.display()
+    );
+
+    let summary = client
+        .import_features(Request::new(tokio_stream::iter(features)))
+        .await?
+        .into_inner();
+    println!(
+        "IMPORTED: {} accepted, {} rejected ({} missing location, {} duplicate, {} empty name), {} bytes",
+        summary.accepted,
+        summary.rejected,
+        summary.missing_location,
+        summary.duplicate,
+        summary.empty_name,
+        summary.total_bytes
+    );
+
+    Ok(())
+}
+
+// 导出服务端的全部 feature, 写成 route_guide_db.json 格式, 可以再用 --import 导入
+async fn run_export_features(
+    client: &mut RouteGuideClient<Channel>,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let mut stream = client
+        .export_features(Request::new(ExportRequest { rect: None }))
+        .await?
+        .into_inner();
+    let mut features = vec![];
+    while let Some(feature) = stream.message().await? {
+        features.push(feature);
+    }
+
+    featurejson::save_json(path, &features)?;
+    println!(
+        "EXPORTED: {} features to {}",
+        features.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+// 按名称查找 feature, 不区分大小写
+async fn run_search_features(
+    client: &mut RouteGuideClient<Channel>,
+    query: String,
+) -> Result<(), Box<dyn Error>> {
+    let mut stream = client
+        .search_features(Request::new(SearchRequest {
+            query,
+            limit: 10,
+            ..Default::default()
+        }))
+        .await?
+        .into_inner();
+    while let Some(feature) = stream.message().await? {
+        println!("FOUND = {:?}{}", feature.name, feature_details(&feature));
+    }
+
+    Ok(())
+}
+
+async fn print_feature_stats(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
+    let stats = client
+        .get_feature_stats(Request::new(Empty {}))
+        .await?
+        .into_inner();
+    println!(
+        "STATS: {} features, last modified at {}",
+        stats.total, stats.last_modified_millis
+    );
+    if let Some(bounds) = stats.bounds {
+        println!("BOUNDS: {:?} - {:?}", bounds.lo, bounds.hi);
+    }
+    let mut per_category: Vec<_> = stats.per_category.into_iter().collect();
+    per_category.sort();
+    for (category, count) in per_category {
+        println!("  {}: {}", category, count);
+    }
+
+    Ok(())
+}
+
+// 形如 "--name value" 的命令行参数
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
+
+// 提交一条纠错建议, 配置了 ROUTEGUIDE_ADMIN_TOKEN 时再以管理员身份审核通过
+async fn run_feature_correction(
+    client: &mut RouteGuideClient<Channel>,
+) -> Result<(), Box<dyn Error>> {
+    let response = client
+        .suggest_feature_correction(Request::new(CorrectionRequest {
+            feature_location: Some(Point {
+                latitude: 407_838_351,
+                longitude: -746_143_763,
+                ..Default::default()
+            }),
+            suggested_name: "Patriots Path, Mendham, NJ 07945".to_string(),
+            correction_reason: "official trail name".to_string(),
+        }))
+        .await?
+        .into_inner();
+    println!("CORRECTION = {}", response.correction_id);
+
+    let Ok(admin_token) = std::env::var("ROUTEGUIDE_ADMIN_TOKEN") else {
+        return Ok(());
+    };
+    let admin_token: MetadataValue<Ascii> = admin_token.parse()?;
+
+    let mut request = Request::new(Empty {});
+    request
+        .metadata_mut()
+        .insert("x-admin-token", admin_token.clone());
+    let mut stream = client.list_pending_corrections(request).await?.into_inner();
+    while let Some(correction) = stream.message().await? {
+        println!("PENDING = {:?}", correction);
+    }
+
+    let mut request = Request::new(ApproveCorrectionRequest {
+        correction_id: response.correction_id,
+    });
+    request.metadata_mut().insert("x-admin-token", admin_token);
+    client.approve_correction(request).await?;
+    println!("APPROVED");
+
+    Ok(())
+}
+
+// send_bad_point 为 true 时在路线中间混入一个纬度越界的点, 用于演示服务端的校验
+async fn run_record_route(
+    client: &mut RouteGuideClient<Channel>,
+    send_bad_point: bool,
+) -> Result<(), Box<dyn Error>> {
     let mut rng = rand::thread_rng();
     let point_count: i32 = rng.gen_range(2..100);
     let mut points = vec![];
@@ -95,18 +1060,57 @@ async fn run_record_route(client: &mut RouteGuideClient<Channel>) -> Result<(),
     for _i in 0..=point_count {
         points.push(random_point());
     }
+    if send_bad_point {
+        points.insert(
+            points.len() / 2,
+            Point {
+                latitude: 910_000_000,
+                longitude: 0,
+                ..Default::default()
+            },
+        );
+    }
 
     println!("Traversing {} points", points.len());
     let request = Request::new(tokio_stream::iter(points));
 
     match client.record_route(request).await {
-        Ok(response) => println!("SUMMARY: {:?}", response.into_inner()),
-        Err(e) => println!("something went wrong: {:?}", e),
+        Ok(response) => {
+            let summary = response.into_inner();
+            println!(
+                "SUMMARY: {} points, {} features, {}m, {}ms",
+                summary.point_count,
+                summary.feature_count,
+                summary.distance,
+                summary.elapsed_time_millis
+            );
+            if let Some(bounds) = summary.bounds {
+                println!("BOUNDS: {:?} - {:?}", bounds.lo, bounds.hi);
+            }
+        }
+        Err(e) => {
+            println!("something went wrong: {:?}", e);
+            if let Some(partial) = partial_summary(&e) {
+                println!(
+                    "PARTIAL SUMMARY: {} points, {} features, {}m",
+                    partial.point_count, partial.feature_count, partial.distance
+                );
+            }
+        }
     }
 
     Ok(())
 }
 
+// record_route 中途失败时, 服务端把已收到部分的 RouteSummary 放在 details 里
+fn partial_summary(status: &Status) -> Option<RouteSummary> {
+    if status.details().is_empty() {
+        return None;
+    }
+
+    RouteSummary::decode(status.details()).ok()
+}
+
 async fn run_route_chat(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
     let start = time::Instant::now();
 
@@ -119,6 +1123,7 @@ async fn run_route_chat(client: &mut RouteGuideClient<Channel>) -> Result<(), Bo
           location: Some(Point{
               latitude:409146138 + elapsed.as_secs() as i32,
               longitude: -746188906,
+              ..Default::default()
           }),
             message: format!("at {:?}", elapsed),
         };
@@ -131,6 +1136,10 @@ async fn run_route_chat(client: &mut RouteGuideClient<Channel>) -> Result<(), Bo
     let mut inbound = response.into_inner();
 
     while let Some(note) = inbound.message().await? {
+        // 服务端的保活留言
+        if note.location.is_none() && note.message.is_empty() {
+            continue;
+        }
         println!("NOTE = {:?}", note);
     }
 
@@ -145,6 +1154,7 @@ fn random_point() -> Point {
     Point {
         latitude,
         longitude,
+        ..Default::default()
     }
 }
 
@@ -154,13 +1164,53 @@ async fn main() -> Result<(), ThisErr> {
     let channel = Endpoint::from_static("http://[::1]:8080").connect().await?;
 
     // 构建多个客户端
-    let voting_client = VotingClient::new(channel.clone());
-    let greet_client = GreeterClient::new(channel.clone());
+    let voting_client = VotingClient::with_interceptor(
+        channel.clone(),
+        UserIdInterceptor {
+            user_id: MetadataValue::from_static("client-demo"),
+        },
+    );
+    // --hedge-secondary http://[::1]:8081 时对问候请求进行对冲
+    let mut greet_client = HedgingClient::new(channel.clone());
+    if let Some(secondary) = arg_value("--hedge-secondary") {
+        let hedge_delay = match arg_value("--hedge-delay-ms") {
+            Some(ms) => Duration::from_millis(ms.parse()?),
+            None => Duration::from_millis(50),
+        };
+        let secondary_channel = Endpoint::from_shared(secondary)?.connect_lazy();
+        greet_client = greet_client.with_hedge(secondary_channel, hedge_delay);
+    }
     let guide_client = RouteGuideClient::new(channel.clone());
 
+    // 只订阅问候, 不运行其他演示
+    if std::env::args().any(|arg| arg == "watch-greetings") {
+        println!("*** WATCH GREETINGS ***");
+        return watch_greetings(&mut GreeterClient::new(channel)).await;
+    }
+
+    if let Some(limit) = arg_value("top") {
+        println!("*** TOP URLS ***");
+        if let Err(e) = print_top_urls(&mut voting_client.clone(), limit.parse()?).await {
+            println!("print_top_urls error: {}", e);
+        }
+    }
+
+    if let Some(user_id) = arg_value("history") {
+        println!("*** VOTE HISTORY ***");
+        if let Err(e) = print_vote_history(&mut voting_client.clone(), user_id).await {
+            println!("print_vote_history error: {}", e);
+        }
+    }
+
     // 负责 vote 服务
     let _task_voting = tokio::spawn(async move {
         let mut c = voting_client.clone();
+        if let Err(e) = batch_voting(&mut c).await {
+            println!("batch voting error: {}", e);
+        }
+        if let Err(e) = stream_votes(&mut c).await {
+            println!("stream voting error: {}", e);
+        }
         if let Err(e) = voting(&mut c).await {
             println!("voting error: {}", e);
         }
@@ -168,20 +1218,89 @@ async fn main() -> Result<(), ThisErr> {
 
     // 负责 say_hello 服务
     let _task_greet = tokio::spawn(async move {
-        let mut c = greet_client.clone();
-        if let Err(e) = greet(&mut c).await {
+        if let Err(e) = greet(&greet_client).await {
             println!("greet error: {}", e);
         }
+        if greet_client.hedge.is_some() {
+            println!(
+                "greet hedged: {}, hedge wins: {}",
+                greet_client.metrics.hedged.get(),
+                greet_client.metrics.hedge_wins.get()
+            );
+        }
     });
 
     // tokio::try_join!(_task_greet, _task_voting);
 
-    println!("*** SIMPLE RPC ***");
+    println!("*** LOCALIZED GREETINGS ***");
+    for accept_language in ["zh-CN,zh;q=0.9,en;q=0.8", "es", "fr, de;q=0.5"] {
+        if let Err(e) = greet_in_locale(channel.clone(), accept_language).await {
+            println!("greet_in_locale error: {}", e);
+        }
+    }
+
+    println!("\n*** GREETING DEADLINE ***");
+    for delay in [Duration::from_millis(100), Duration::from_millis(800)] {
+        let result = greet_with_deadline(
+            &mut GreeterClient::new(channel.clone()),
+            delay,
+            Duration::from_millis(500),
+        )
+        .await;
+        match result {
+            Ok(resp) => println!("delay {:?}, Got: '{}'", delay, resp.content),
+            Err(status) => println!("delay {:?}, failed: {:?}", delay, status.code()),
+        }
+    }
+
+    println!("\n*** GREETING RETRY ***");
+    match greet_with_retry(&mut GreeterClient::new(channel.clone()), 5).await {
+        Ok(resp) => println!("greet with retry, Got: '{}'", resp.content),
+        Err(status) => println!("greet with retry, failed: {:?}", status.code()),
+    }
+
+    println!("\n*** ECHOED HEADERS ***");
+    if let Err(e) = print_echoed_headers(&mut GreeterClient::new(channel.clone())).await {
+        println!("print_echoed_headers error: {}", e);
+    }
+
+    println!("\n*** GREETING STREAM ***");
+    if let Err(e) = print_greeting_stream(&mut GreeterClient::new(channel.clone()), 5).await {
+        println!("print_greeting_stream error: {}", e);
+    }
+
+    println!("\n*** CLIENT STREAMING GREETINGS ***");
+    if let Err(e) = run_lots_of_greetings(
+        &mut GreeterClient::new(channel.clone()),
+        &["hello", "bonjour", "こんにちは", "hola"],
+    )
+    .await
+    {
+        println!("run_lots_of_greetings error: {}", e);
+    }
+
+    println!("\n*** BIDIRECTIONAL GREETINGS ***");
+    if let Err(e) = run_greet_chat(&mut GreeterClient::new(channel.clone())).await {
+        println!("run_greet_chat error: {}", e);
+    }
+
+    println!("\n*** GREET STATS ***");
+    if let Err(e) = print_greet_stats(&mut GreeterClient::new(channel.clone())).await {
+        println!("print_greet_stats error: {}", e);
+    }
+
     let mut c = guide_client.clone();
+    println!("\n*** FEATURE STATS ***");
+    if let Err(e) = print_feature_stats(&mut c).await {
+        println!("print_feature_stats error: {}", e);
+    }
+
+    println!("\n*** SIMPLE RPC ***");
     let response = c
         .get_feature(Request::new(Point {
             latitude: 409_146_138,
             longitude: -746_188_906,
+            ..Default::default()
         }))
         .await;
     if let Err(e) = &response {
@@ -189,13 +1308,63 @@ async fn main() -> Result<(), ThisErr> {
     }
     println!("RESPONSE = {:?}", response);
 
+    println!("\n*** NEAREST FEATURE ***");
+    if let Err(e) = print_nearest_feature(&mut c).await {
+        println!("print_nearest_feature error: {}", e);
+    }
+
     println!("\n*** SERVER STREAMING ***");
     if let Err(e) = print_features(&mut c).await {
         println!("print_features error: {}", e);
     }
 
+    println!("\n*** SERVER STREAMING ORDERED BY DISTANCE ***");
+    if let Err(e) = print_features_by_distance(&mut c).await {
+        println!("print_features_by_distance error: {}", e);
+    }
+
+    println!("\n*** SERVER STREAMING ACROSS ANTIMERIDIAN ***");
+    if let Err(e) = print_features_across_antimeridian(&mut c).await {
+        println!("print_features_across_antimeridian error: {}", e);
+    }
+
+    println!("\n*** ADD FEATURE ***");
+    if let Err(e) = run_add_feature(&mut c).await {
+        println!("run_add_feature error: {}", e);
+    }
+    if let Err(e) = run_delete_feature(&mut c).await {
+        println!("run_delete_feature error: {}", e);
+    }
+
+    if let Some(query) = arg_value("search") {
+        println!("\n*** SEARCH FEATURES ***");
+        if let Err(e) = run_search_features(&mut c, query).await {
+            println!("run_search_features error: {}", e);
+        }
+    }
+
+    if let Some(path) = arg_value("--export") {
+        println!("\n*** EXPORT FEATURES ***");
+        if let Err(e) = run_export_features(&mut c, Path::new(&path)).await {
+            println!("run_export_features error: {}", e);
+        }
+    }
+
+    if let Some(path) = arg_value("--import") {
+        println!("\n*** IMPORT FEATURES ***");
+        if let Err(e) = run_import_features(&mut c, Path::new(&path)).await {
+            println!("run_import_features error: {}", e);
+        }
+    }
+
+    println!("\n*** FEATURE CORRECTION ***");
+    if let Err(e) = run_feature_correction(&mut c).await {
+        println!("run_feature_correction error: {}", e);
+    }
+
     println!("\n*** CLIENT STREAMING ***");
-    if let Err(e) = run_record_route(&mut c).await {
+    let send_bad_point = std::env::args().any(|arg| arg == "--bad-point");
+    if let Err(e) = run_record_route(&mut c, send_bad_point).await {
         println!("run_record_route error: {}", e);
     }
 
@@ -206,3 +1375,150 @@ async fn main() -> Result<(), ThisErr> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tonic::{
+        codegen::BoxStream,
+        transport::{server::TcpIncoming, Server},
+        Streaming,
+    };
+
+    use super::*;
+    use greet::{
+        greeter_server::{Greeter, GreeterServer},
+        Empty as GreetEmpty, GreetStats,
+    };
+
+    // 等待 delay 后回复, hostname 为 name
+    struct SlowGreeter {
+        name: &'static str,
+        delay: Duration,
+    }
+
+    #[tonic::async_trait]
+    impl Greeter for SlowGreeter {
+        async fn say_hello(&self, _: Request<HelloReq>) -> Result<Response<HelloResp>, Status> {
+            time::sleep(self.delay).await;
+            Ok(Response::new(HelloResp {
+                hostname: self.name.to_string(),
+                ..Default::default()
+            }))
+        }
+
+        type SayHelloStreamStream = BoxStream<HelloResp>;
+
+        async fn say_hello_stream(
+            &self,
+            _: Request<HelloReq>,
+        ) -> Result<Response<Self::SayHelloStreamStream>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+
+        async fn lots_of_greetings(
+            &self,
+            _: Request<Streaming<HelloReq>>,
+        ) -> Result<Response<GreetingSummary>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+
+        type GreetChatStream = BoxStream<HelloResp>;
+
+        async fn greet_chat(
+            &self,
+            _: Request<Streaming<HelloReq>>,
+        ) -> Result<Response<Self::GreetChatStream>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+
+        async fn get_greet_stats(
+            &self,
+            _: Request<GreetEmpty>,
+        ) -> Result<Response<GreetStats>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+
+        type WatchGreetingsStream = BoxStream<HelloResp>;
+
+        async fn watch_greetings(
+            &self,
+            _: Request<GreetEmpty>,
+        ) -> Result<Response<Self::WatchGreetingsStream>, Status> {
+            Err(Status::unimplemented("not used"))
+        }
+    }
+
+    async fn serve(name: &'static str, delay: Duration) -> Channel {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GreeterServer::new(SlowGreeter { name, delay }))
+                .serve_with_incoming(incoming),
+        );
+
+        Endpoint::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hedge_wins_when_primary_is_slow() {
+        let primary = serve("primary", Duration::from_secs(5)).await;
+        let secondary = serve("secondary", Duration::from_millis(20)).await;
+        let hedge_delay = Duration::from_millis(50);
+        let client = HedgingClient::new(primary).with_hedge(secondary, hedge_delay);
+
+        let start = Instant::now();
+        let response = client.say_hello(HelloReq::default()).await.unwrap();
+        assert_eq!(response.get_ref().hostname, "secondary");
+        // hedge_delay + 第二个实例的延迟, 再留一些余量
+        assert!(start.elapsed() < hedge_delay + Duration::from_millis(20) + Duration::from_secs(1));
+        assert_eq!(client.metrics.hedged.get(), 1);
+        assert_eq!(client.metrics.hedge_wins.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn primary_wins_when_it_answers_first() {
+        let primary = serve("primary", Duration::from_millis(100)).await;
+        let secondary = serve("secondary", Duration::from_secs(5)).await;
+        let client = HedgingClient::new(primary).with_hedge(secondary, Duration::from_millis(20));
+
+        let response = client.say_hello(HelloReq::default()).await.unwrap();
+        assert_eq!(response.get_ref().hostname, "primary");
+        assert_eq!(client.metrics.hedged.get(), 1);
+        assert_eq!(client.metrics.hedge_wins.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn hedging_is_off_by_default() {
+        let primary = serve("primary", Duration::from_millis(100)).await;
+        let client = HedgingClient::new(primary);
+
+        let response = client.say_hello(HelloReq::default()).await.unwrap();
+        assert_eq!(response.get_ref().hostname, "primary");
+        assert_eq!(client.metrics.hedged.get(), 0);
+    }
+
+    #[test]
+    fn partial_summary_reads_status_details() {
+        let summary = RouteSummary {
+            point_count: 2,
+            distance: 1_000,
+            ..Default::default()
+        };
+        let status = Status::with_details(
+            tonic::Code::Unavailable,
+            "client went away",
+            summary.encode_to_vec().into(),
+        );
+        assert_eq!(partial_summary(&status), Some(summary));
+
+        assert_eq!(partial_summary(&Status::unavailable("no details")), None);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{
+    body::BoxBody,
+    codegen::{
+        http::{self, HeaderMap},
+        Body, BoxFuture, Bytes, Service,
+    },
+    Status,
+};
+use tower::Layer;
+
+use crate::auth::EXEMPT_SERVICES;
+
+// 限制同时处理的请求数, 全局一个上限, 每个服务还可以有自己的上限; 达到上限时立即返回
+// RESOURCE_EXHAUSTED 而不是排队, 客户端可以稍后重试. 许可在 call 中获取, 一直持有到响应发送完,
+// 所以流也计入; 空闲的连接不占用许可. EXEMPT_SERVICES 中的服务不受限制
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyLayer {
+    global: Option<Arc<Semaphore>>,
+    // 服务名 -> 上限
+    services: Arc<HashMap<&'static str, Arc<Semaphore>>>,
+}
+
+impl ConcurrencyLayer {
+    pub fn new<I>(global: Option<usize>, services: I) -> Self
+    where
+        I: IntoIterator<Item = (&'static str, usize)>,
+    {
+        let services = services
+            .into_iter()
+            .map(|(service, max)| (service, Arc::new(Semaphore::new(max))))
+            .collect();
+
+        ConcurrencyLayer {
+            global: global.map(|max| Arc::new(Semaphore::new(max))),
+            services: Arc::new(services),
+        }
+    }
+}
+
+impl<S> Layer<S> for ConcurrencyLayer {
+    type Service = ConcurrencyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConcurrencyService {
+            inner,
+            global: self.global.clone(),
+            services: self.services.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConcurrencyService<S> {
+    inner: S,
+    global: Option<Arc<Semaphore>>,
+    services: Arc<HashMap<&'static str, Arc<Semaphore>>>,
+}
+
+impl<S> ConcurrencyService<S> {
+    // 先取服务的许可再取全局的, 任何一个取不到都拒绝
+    #[allow(clippy::result_large_err)]
+    fn acquire(&self, path: &str) -> Result<Vec<OwnedSemaphorePermit>, Status> {
+        // 路径形如 "/hello.Greeter/SayHello"
+        let service = path.split('/').nth(1).unwrap_or_default();
+        if EXEMPT_SERVICES.contains(&service) {
+            return Ok(Vec::new());
+        }
+
+        self.services
+            .get(service)
+            .into_iter()
+            .chain(&self.global)
+            .map(|semaphore| {
+                semaphore.clone().try_acquire_owned().map_err(|_| {
+                    tracing::warn!(service, "request shed, too many in flight");
+                    Status::resource_exhausted("server is overloaded, retry later")
+                })
+            })
+            .collect()
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for ConcurrencyService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let permits = match self.acquire(request.uri().path()) {
+            Ok(permits) => permits,
+            Err(status) => {
+                let response = status.to_http();
+                return Box::pin(async move { Ok(response) });
+            }
+        };
+        if permits.is_empty() {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(response.map(|body| {
+                BoxBody::new(PermitBody {
+                    inner: body,
+                    _permits: permits,
+                })
+            }))
+        })
+    }
+}
+
+// 响应体结束 (或被丢弃) 时释放许可
+struct PermitBody {
+    inner: BoxBody,
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+impl Body for PermitBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tonic::{body::empty_body, Code};
+    use tower::service_fn;
+
+    use super::*;
+
+    fn layer(
+        global: Option<usize>,
+        services: &[(&'static str, usize)],
+    ) -> impl FnMut(&str) -> http::Response<BoxBody> {
+        let mut service = ConcurrencyLayer::new(global, services.iter().copied()).layer(
+            service_fn(|_: http::Request<()>| async {
+                Ok::<_, Infallible>(http::Response::new(empty_body()))
+            }),
+        );
+        move |path| {
+            let request = http::Request::builder().uri(path).body(()).unwrap();
+            block_on(service.call(request)).unwrap()
+        }
+    }
+
+    // 内层服务立即返回, 响应 future 不会等待
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn code(response: &http::Response<BoxBody>) -> Code {
+        Status::from_header_map(response.headers()).map_or(Code::Ok, |status| status.code())
+    }
+
+    #[test]
+    fn global_limit_sheds_excess_requests() {
+        let mut call = layer(Some(2), &[]);
+        let first = call("/hello.Greeter/SayHello");
+        let second = call("/voting.Voting/Vote");
+        assert_eq!(code(&first), Code::Ok);
+        assert_eq!(code(&second), Code::Ok);
+        assert_eq!(
+            code(&call("/hello.Greeter/SayHello")),
+            Code::ResourceExhausted
+        );
+
+        // 响应体被丢弃后许可归还
+        drop(first);
+        assert_eq!(code(&call("/hello.Greeter/SayHello")), Code::Ok);
+    }
+
+    #[test]
+    fn service_limit_only_applies_to_its_service() {
+        let mut call = layer(None, &[("hello.Greeter", 1)]);
+        let _held = call("/hello.Greeter/SayHello");
+        assert_eq!(
+            code(&call("/hello.Greeter/SayHello")),
+            Code::ResourceExhausted
+        );
+        assert_eq!(code(&call("/voting.Voting/Vote")), Code::Ok);
+    }
+
+    #[test]
+    fn exempt_services_are_not_limited() {
+        let mut call = layer(Some(1), &[]);
+        let _held = call("/hello.Greeter/SayHello");
+        assert_eq!(
+            code(&call("/hello.Greeter/SayHello")),
+            Code::ResourceExhausted
+        );
+        assert_eq!(code(&call("/grpc.health.v1.Health/Check")), Code::Ok);
+    }
+}
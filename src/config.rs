@@ -0,0 +1,793 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Deserializer};
+use tracing::level_filters::LevelFilter;
+
+use crate::dedup::DEFAULT_FP_RATE;
+
+// tonic 默认的解码上限
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+const DEFAULT_SNAPSHOT_SECS: u64 = 30;
+const DEFAULT_VOTE_WINDOW_SECS: u64 = 60;
+const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 600;
+const DEFAULT_DRAIN_SECS: u64 = 30;
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_MAX_IN_FLIGHT: usize = 1024;
+// 按每天预计的投票数估算重复投票过滤器的大小
+const DEFAULT_EXPECTED_DAILY_VOTES: usize = 100_000;
+
+// 内置的方法超时 (毫秒), 0 表示不限制: 一元调用较短, 有结束的流较长, 订阅和聊天一类的流不限制
+const DEFAULT_METHOD_TIMEOUTS: &[(&str, u64)] = &[
+    ("voting.Voting/Vote", 5_000),
+    ("voting.Voting/StreamVotes", 300_000),
+    ("voting.Voting/WatchVotes", 0),
+    ("voting.Voting/TopUrls", 300_000),
+    ("voting.Voting/GetRecentReasons", 300_000),
+    ("voting.Voting/GetVoteHistory", 300_000),
+    ("hello.Greeter/SayHello", 5_000),
+    ("hello.Greeter/SayHelloStream", 300_000),
+    ("hello.Greeter/LotsOfGreetings", 300_000),
+    ("hello.Greeter/GreetChat", 0),
+    ("hello.Greeter/WatchGreetings", 0),
+    ("tutorial.RouteGuide/ListFeatures", 300_000),
+    ("tutorial.RouteGuide/ListFeaturesAtTime", 300_000),
+    ("tutorial.RouteGuide/RecordRoute", 300_000),
+    ("tutorial.RouteGuide/RouteChat", 0),
+    ("tutorial.RouteGuide/InterpolateRoute", 300_000),
+    ("tutorial.RouteGuide/SearchFeatures", 300_000),
+    ("tutorial.RouteGuide/ImportFeatures", 300_000),
+    ("tutorial.RouteGuide/ExportFeatures", 300_000),
+    ("tutorial.RouteGuide/WatchFeatures", 0),
+    ("tutorial.RouteGuide/ListPendingCorrections", 300_000),
+    ("grpc.health.v1.Health/Watch", 0),
+    (
+        "grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+        0,
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServiceName {
+    Voting,
+    Greeter,
+    RouteGuide,
+    Conversation,
+}
+
+// 命令行参数, 没有给出的参数从对应的环境变量读取; 给出的参数覆盖配置文件中的值
+#[derive(Debug, Clone, Default, Parser)]
+#[command(
+    name = "server",
+    about = "Voting, Greeter, RouteGuide and Conversation gRPC server"
+)]
+pub struct Args {
+    /// TOML config file; flags and environment variables override its values
+    #[arg(long, env = "SERVER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on; port 0 picks a free port [default: [::1]:8080]
+    #[arg(long, env = "SERVER_LISTEN")]
+    pub listen: Option<SocketAddr>,
+
+    /// JSON file with the RouteGuide features [default: route_guide_db.json in the crate directory]
+    #[arg(long, env = "ROUTE_GUIDE_DB")]
+    pub feature_db: Option<PathBuf>,
+
+    /// PEM certificate chain; enables TLS
+    #[arg(long, env = "SERVER_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key matching --tls-cert
+    #[arg(long, env = "SERVER_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle; clients must present a certificate signed by it
+    #[arg(long, env = "SERVER_TLS_CLIENT_CA", requires = "tls_cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Keep votes in this SQLite database (needs the sqlite feature)
+    #[arg(long, env = "VOTING_DB", conflicts_with = "voting_snapshot")]
+    pub voting_db: Option<PathBuf>,
+
+    /// Keep votes in memory and write them to this JSON file periodically
+    #[arg(long, env = "VOTING_SNAPSHOT")]
+    pub voting_snapshot: Option<PathBuf>,
+
+    /// Seconds between vote snapshots [default: 30]
+    #[arg(long, env = "VOTING_SNAPSHOT_SECS")]
+    pub voting_snapshot_secs: Option<u64>,
+
+    /// Seconds before a user may vote on the same url again; 0 disables the limit [default: 60]
+    #[arg(long, env = "VOTING_WINDOW_SECS")]
+    pub voting_window_secs: Option<u64>,
+
+    /// Seconds to remember an x-idempotency-key and its response; 0 ignores the header [default: 600]
+    #[arg(long, env = "VOTING_IDEMPOTENCY_TTL_SECS")]
+    pub voting_idempotency_ttl_secs: Option<u64>,
+
+    /// Reject votes without an x-user-id header instead of counting them anonymously
+    #[arg(long, env = "VOTING_REQUIRE_USER_ID")]
+    pub voting_require_user_id: bool,
+
+    /// Reject anonymous votes repeated by the same voter_id on the same day
+    #[arg(long, env = "VOTING_DUPLICATE_FILTER")]
+    pub voting_duplicate_filter: bool,
+
+    /// Embed the request number as an invisible watermark in SayHello replies
+    #[arg(long, env = "GREETER_WATERMARK")]
+    pub greeter_watermark: bool,
+
+    /// Send an empty RouteChat note after this many quiet seconds; 0 disables keepalives [default: 0]
+    #[arg(long, env = "ROUTE_CHAT_KEEPALIVE_SECS")]
+    pub route_chat_keepalive_secs: Option<u64>,
+
+    /// Drop a RouteChat note that repeats the last one stored at its location
+    #[arg(long, env = "ROUTE_CHAT_DEDUPE")]
+    pub route_chat_dedupe: bool,
+
+    /// Largest request or response message, in bytes [default: 4194304]
+    #[arg(long, env = "SERVER_MAX_MESSAGE_SIZE")]
+    pub max_message_size: Option<usize>,
+
+    /// Most requests handled at once before new ones get RESOURCE_EXHAUSTED; 0 means no limit [default: 1024]
+    #[arg(long, env = "SERVER_MAX_IN_FLIGHT")]
+    pub max_in_flight: Option<usize>,
+
+    /// Seconds to wait for in-flight requests on shutdown [default: 30]
+    #[arg(long, env = "SERVER_DRAIN_SECS")]
+    pub drain_secs: Option<u64>,
+
+    /// Services to serve, comma separated [default: all]
+    #[arg(long, env = "SERVER_SERVICES", value_enum, value_delimiter = ',')]
+    pub services: Option<Vec<ServiceName>>,
+
+    /// Most verbose level to log: off, error, warn, info, debug or trace [default: info]
+    #[arg(long, env = "SERVER_LOG_LEVEL")]
+    pub log_level: Option<LevelFilter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteBackend {
+    // 只保存在内存中, 重启后丢失
+    #[default]
+    Memory,
+    // 保存在内存中, 定期写入 path 指向的 JSON 文件
+    Snapshot,
+    // 保存在 path 指向的 SQLite 数据库
+    Sqlite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VoteStoreConfig {
+    pub backend: VoteBackend,
+    pub path: Option<PathBuf>,
+    pub snapshot_secs: u64,
+    // 同一用户对同一 url 两次投票之间至少间隔的秒数, 0 表示不限制
+    pub window_secs: u64,
+    // 记住 x-idempotency-key 及其结果的秒数, 0 表示忽略该 header
+    pub idempotency_ttl_secs: u64,
+}
+
+impl Default for VoteStoreConfig {
+    fn default() -> Self {
+        VoteStoreConfig {
+            backend: VoteBackend::Memory,
+            path: None,
+            snapshot_secs: DEFAULT_SNAPSHOT_SECS,
+            window_secs: DEFAULT_VOTE_WINDOW_SECS,
+            idempotency_ttl_secs: DEFAULT_IDEMPOTENCY_TTL_SECS,
+        }
+    }
+}
+
+impl VoteStoreConfig {
+    pub fn vote_window(&self) -> Option<Duration> {
+        (self.window_secs > 0).then(|| Duration::from_secs(self.window_secs))
+    }
+
+    pub fn idempotency_ttl(&self) -> Option<Duration> {
+        (self.idempotency_ttl_secs > 0).then(|| Duration::from_secs(self.idempotency_ttl_secs))
+    }
+}
+
+// 投票服务的行为, 存储在 VoteStoreConfig 中配置
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VotingConfig {
+    // 为 false 时没有 x-user-id 的投票按匿名票计数
+    pub require_user_id: bool,
+    // 用布隆过滤器拒绝当天重复的匿名投票, 匿名投票必须带 voter_id; 少量首次投票会被误判为重复
+    pub duplicate_filter: bool,
+    pub expected_daily_votes: usize,
+    // 布隆过滤器的误判率
+    pub fp_rate: f64,
+}
+
+impl Default for VotingConfig {
+    fn default() -> Self {
+        VotingConfig {
+            require_user_id: false,
+            duplicate_filter: false,
+            expected_daily_votes: DEFAULT_EXPECTED_DAILY_VOTES,
+            fp_rate: DEFAULT_FP_RATE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GreeterConfig {
+    // 在 SayHello 的 content 中用零宽字符嵌入 request_number, 用于追踪内容泄露
+    pub watermark: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteGuideConfig {
+    // route_chat 的入站流安静这么多秒后发送一条空的保活留言, 0 表示不发送
+    pub keepalive_secs: u64,
+    // 与该位置最后一条留言的位置和内容都相同的留言既不保存也不回放, 用于过滤客户端重试造成的重复
+    pub dedupe_notes: bool,
+}
+
+impl RouteGuideConfig {
+    pub fn keepalive(&self) -> Option<Duration> {
+        (self.keepalive_secs > 0).then(|| Duration::from_secs(self.keepalive_secs))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_message_size: usize,
+    // 关闭时等待进行中请求的秒数
+    pub drain_secs: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            drain_secs: DEFAULT_DRAIN_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    // 没有单独配置的方法的上限 (毫秒), 0 表示不限制
+    pub default_ms: u64,
+    // "package.Service/Method" -> 毫秒, 0 表示不限制; 覆盖 DEFAULT_METHOD_TIMEOUTS 中的值
+    pub methods: BTreeMap<String, u64>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            default_ms: DEFAULT_TIMEOUT_MS,
+            methods: BTreeMap::new(),
+        }
+    }
+}
+
+fn limit(ms: u64) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+impl Timeouts {
+    pub fn default_limit(&self) -> Option<Duration> {
+        limit(self.default_ms)
+    }
+
+    // 内置的值加上配置的覆盖
+    pub fn method_limits(&self) -> impl Iterator<Item = (String, Option<Duration>)> + '_ {
+        DEFAULT_METHOD_TIMEOUTS
+            .iter()
+            .filter(|(method, _)| !self.methods.contains_key(*method))
+            .map(|(method, ms)| (method.to_string(), *ms))
+            .chain(
+                self.methods
+                    .iter()
+                    .map(|(method, ms)| (method.clone(), *ms)),
+            )
+            .map(|(method, ms)| (method, limit(ms)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Concurrency {
+    // 所有服务同时处理的请求数上限, 0 表示不限制
+    pub max_in_flight: usize,
+    // 单个服务的上限, 与全局上限同时生效
+    pub services: BTreeMap<ServiceName, usize>,
+}
+
+impl Default for Concurrency {
+    fn default() -> Self {
+        Concurrency {
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            services: BTreeMap::new(),
+        }
+    }
+}
+
+impl Concurrency {
+    pub fn global_limit(&self) -> Option<usize> {
+        (self.max_in_flight > 0).then_some(self.max_in_flight)
+    }
+}
+
+// 服务端配置; 健康检查和反射总是启用
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    pub feature_db: Option<PathBuf>,
+    pub tls: Option<TlsFiles>,
+    pub votes: VoteStoreConfig,
+    pub voting: VotingConfig,
+    pub greeter: GreeterConfig,
+    pub route_guide: RouteGuideConfig,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub concurrency: Concurrency,
+    pub services: Vec<ServiceName>,
+    #[serde(deserialize_with = "level_filter")]
+    pub log_level: LevelFilter,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: "[::1]:8080".parse().unwrap(),
+            feature_db: None,
+            tls: None,
+            votes: VoteStoreConfig::default(),
+            voting: VotingConfig::default(),
+            greeter: GreeterConfig::default(),
+            route_guide: RouteGuideConfig::default(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            concurrency: Concurrency::default(),
+            services: vec![
+                ServiceName::Voting,
+                ServiceName::Greeter,
+                ServiceName::RouteGuide,
+                ServiceName::Conversation,
+            ],
+            log_level: LevelFilter::INFO,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(&'static str),
+    InvalidMethod(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {}", path.display(), e),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+            ConfigError::InvalidMethod(method) => write!(
+                f,
+                "invalid config: timeouts.methods key {:?} is not \"package.Service/Method\"",
+                method
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    // 先读 args.config 指向的文件 (没有时使用默认值), 再用 args 中给出的值覆盖
+    pub fn from_args(args: Args) -> Result<Self, ConfigError> {
+        let config = match &args.config {
+            Some(path) => Self::load(path)?,
+            None => ServerConfig::default(),
+        };
+
+        config.with_overrides(args).validate()
+    }
+
+    // 只解析文件, 不检查字段之间的约束
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.into(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.into(), e))
+    }
+
+    pub fn with_overrides(mut self, args: Args) -> Self {
+        if let Some(listen) = args.listen {
+            self.listen = listen;
+        }
+        if let Some(feature_db) = args.feature_db {
+            self.feature_db = Some(feature_db);
+        }
+        // clap 已经保证 cert 和 key 同时给出
+        if let (Some(cert), Some(key)) = (args.tls_cert, args.tls_key) {
+            self.tls = Some(TlsFiles {
+                cert,
+                key,
+                client_ca: args.tls_client_ca,
+            });
+        }
+        if let Some(path) = args.voting_db {
+            self.votes.backend = VoteBackend::Sqlite;
+            self.votes.path = Some(path);
+        }
+        if let Some(path) = args.voting_snapshot {
+            self.votes.backend = VoteBackend::Snapshot;
+            self.votes.path = Some(path);
+        }
+        if let Some(secs) = args.voting_snapshot_secs {
+            self.votes.snapshot_secs = secs;
+        }
+        if let Some(secs) = args.voting_window_secs {
+            self.votes.window_secs = secs;
+        }
+        if let Some(secs) = args.voting_idempotency_ttl_secs {
+            self.votes.idempotency_ttl_secs = secs;
+        }
+        if args.voting_require_user_id {
+            self.voting.require_user_id = true;
+        }
+        if args.voting_duplicate_filter {
+            self.voting.duplicate_filter = true;
+        }
+        if args.greeter_watermark {
+            self.greeter.watermark = true;
+        }
+        if let Some(secs) = args.route_chat_keepalive_secs {
+            self.route_guide.keepalive_secs = secs;
+        }
+        if args.route_chat_dedupe {
+            self.route_guide.dedupe_notes = true;
+        }
+        if let Some(max_message_size) = args.max_message_size {
+            self.limits.max_message_size = max_message_size;
+        }
+        if let Some(max_in_flight) = args.max_in_flight {
+            self.concurrency.max_in_flight = max_in_flight;
+        }
+        if let Some(drain_secs) = args.drain_secs {
+            self.limits.drain_secs = drain_secs;
+        }
+        if let Some(services) = args.services {
+            self.services = services;
+        }
+        if let Some(log_level) = args.log_level {
+            self.log_level = log_level;
+        }
+        self
+    }
+
+    pub fn validate(self) -> Result<Self, ConfigError> {
+        match (self.votes.backend, &self.votes.path) {
+            (VoteBackend::Sqlite, None) => {
+                return Err(ConfigError::Invalid(
+                    "votes.path is required when votes.backend = \"sqlite\"",
+                ))
+            }
+            (VoteBackend::Snapshot, None) => {
+                return Err(ConfigError::Invalid(
+                    "votes.path is required when votes.backend = \"snapshot\"",
+                ))
+            }
+            (VoteBackend::Memory, Some(_)) => {
+                return Err(ConfigError::Invalid(
+                    "votes.path is only used by the \"snapshot\" and \"sqlite\" backends",
+                ))
+            }
+            _ => {}
+        }
+        if self.votes.snapshot_secs == 0 {
+            return Err(ConfigError::Invalid("votes.snapshot_secs must be positive"));
+        }
+        if self.voting.expected_daily_votes == 0 {
+            return Err(ConfigError::Invalid(
+                "voting.expected_daily_votes must be positive",
+            ));
+        }
+        if !(self.voting.fp_rate > 0.0 && self.voting.fp_rate < 1.0) {
+            return Err(ConfigError::Invalid(
+                "voting.fp_rate must be between 0 and 1",
+            ));
+        }
+        if self.limits.max_message_size == 0 {
+            return Err(ConfigError::Invalid(
+                "limits.max_message_size must be positive",
+            ));
+        }
+        if let Some(method) = self.timeouts.methods.keys().find(|method| {
+            !method.split_once('/').is_some_and(|(service, name)| {
+                service.contains('.') && !name.is_empty() && !name.contains('/')
+            })
+        }) {
+            return Err(ConfigError::InvalidMethod(method.clone()));
+        }
+        if self.concurrency.services.values().any(|max| *max == 0) {
+            return Err(ConfigError::Invalid(
+                "concurrency.services limits must be positive",
+            ));
+        }
+        if self.services.is_empty() {
+            return Err(ConfigError::Invalid("services must not be empty"));
+        }
+
+        Ok(self)
+    }
+
+    pub fn enabled(&self, service: ServiceName) -> bool {
+        self.services.contains(&service)
+    }
+}
+
+// "info"、"debug" 等, 与 --log-level 相同
+fn level_filter<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LevelFilter, D::Error> {
+    let level = String::deserialize(deserializer)?;
+    level.parse().map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_filter_is_off_by_default() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert!(!config.voting.duplicate_filter);
+
+        let args = Args {
+            voting_duplicate_filter: true,
+            ..Default::default()
+        };
+        assert!(
+            ServerConfig::from_args(args)
+                .unwrap()
+                .voting
+                .duplicate_filter
+        );
+    }
+
+    #[test]
+    fn require_user_id_is_off_by_default() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert!(!config.voting.require_user_id);
+
+        let args = Args {
+            voting_require_user_id: true,
+            ..Default::default()
+        };
+        assert!(
+            ServerConfig::from_args(args)
+                .unwrap()
+                .voting
+                .require_user_id
+        );
+    }
+
+    #[test]
+    fn greeter_watermark_is_off_by_default() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert!(!config.greeter.watermark);
+
+        let args = Args {
+            greeter_watermark: true,
+            ..Default::default()
+        };
+        assert!(ServerConfig::from_args(args).unwrap().greeter.watermark);
+    }
+
+    #[test]
+    fn route_chat_keepalive_is_off_by_default() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert_eq!(config.route_guide.keepalive(), None);
+
+        let args = Args {
+            route_chat_keepalive_secs: Some(15),
+            ..Default::default()
+        };
+        let config = ServerConfig::from_args(args).unwrap();
+        assert_eq!(
+            config.route_guide.keepalive(),
+            Some(Duration::from_secs(15))
+        );
+
+        let path = temp_config("[route_guide]\nkeepalive_secs = 0\n");
+        let config = ServerConfig::load(&path).unwrap();
+        assert_eq!(config.route_guide.keepalive(), None);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn vote_window_defaults_to_a_minute() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert_eq!(config.votes.vote_window(), Some(Duration::from_secs(60)));
+
+        let args = Args {
+            voting_window_secs: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            ServerConfig::from_args(args).unwrap().votes.vote_window(),
+            None
+        );
+
+        let path = temp_config("[votes]\nwindow_secs = 5\n");
+        let config = ServerConfig::load(&path).unwrap();
+        assert_eq!(config.votes.vote_window(), Some(Duration::from_secs(5)));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn idempotency_ttl_defaults_to_ten_minutes() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert_eq!(
+            config.votes.idempotency_ttl(),
+            Some(Duration::from_secs(600))
+        );
+
+        let args = Args {
+            voting_idempotency_ttl_secs: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            ServerConfig::from_args(args)
+                .unwrap()
+                .votes
+                .idempotency_ttl(),
+            None
+        );
+    }
+
+    #[test]
+    fn route_chat_dedupe_is_off_by_default() {
+        let config = ServerConfig::from_args(Args::default()).unwrap();
+        assert!(!config.route_guide.dedupe_notes);
+
+        let args = Args {
+            route_chat_dedupe: true,
+            ..Default::default()
+        };
+        assert!(
+            ServerConfig::from_args(args)
+                .unwrap()
+                .route_guide
+                .dedupe_notes
+        );
+    }
+
+    #[test]
+    fn fp_rate_must_be_a_probability() {
+        for fp_rate in [0.0, 1.0, -0.5] {
+            let config = ServerConfig {
+                voting: VotingConfig {
+                    fp_rate,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "fp_rate {}", fp_rate);
+        }
+    }
+
+    fn temp_config(text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("server-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn example_config_loads() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("server.example.toml");
+        let config = ServerConfig::load(&path).unwrap().validate().unwrap();
+        assert_eq!(config.listen, "[::1]:8080".parse().unwrap());
+        assert_eq!(config.votes.backend, VoteBackend::Snapshot);
+        assert_eq!(
+            config.concurrency.services.get(&ServiceName::RouteGuide),
+            Some(&256)
+        );
+        assert!(config
+            .timeouts
+            .method_limits()
+            .any(|(method, limit)| method == "hello.Greeter/SayHello"
+                && limit == Some(Duration::from_millis(5000))));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let path = temp_config("[limits]\ndrain_seconds = 5\n");
+        let error = ServerConfig::load(&path).unwrap_err();
+        assert!(matches!(error, ConfigError::Parse(..)), "{}", error);
+        fs::remove_file(path).unwrap();
+    }
+
+    // 环境变量是整个进程共享的, 所有读环境变量的断言都放在这一个测试里
+    #[test]
+    fn flags_override_env_which_overrides_file() {
+        let path = temp_config(
+            "listen = \"127.0.0.1:1000\"\n[limits]\ndrain_secs = 5\nmax_message_size = 1000\n",
+        );
+        std::env::set_var("SERVER_LISTEN", "127.0.0.1:2000");
+        std::env::set_var("SERVER_DRAIN_SECS", "7");
+
+        let args = Args::try_parse_from([
+            "server".as_ref(),
+            "--config".as_ref(),
+            path.as_os_str(),
+            "--listen".as_ref(),
+            "127.0.0.1:3000".as_ref(),
+        ])
+        .unwrap();
+        let config = ServerConfig::from_args(args).unwrap();
+        std::env::remove_var("SERVER_LISTEN");
+        std::env::remove_var("SERVER_DRAIN_SECS");
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(config.listen, "127.0.0.1:3000".parse().unwrap());
+        assert_eq!(config.limits.drain_secs, 7);
+        assert_eq!(config.limits.max_message_size, 1000);
+    }
+
+    #[test]
+    fn tls_cert_requires_key() {
+        let error = Args::try_parse_from(["server", "--tls-cert", "server.pem"]).unwrap_err();
+        assert_eq!(
+            error.kind(),
+            clap::error::ErrorKind::MissingRequiredArgument
+        );
+
+        let path = temp_config("[tls]\ncert = \"server.pem\"\n");
+        assert!(matches!(
+            ServerConfig::load(&path),
+            Err(ConfigError::Parse(..))
+        ));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn backend_path_is_checked() {
+        let path = temp_config("[votes]\nbackend = \"sqlite\"\n");
+        let config = ServerConfig::load(&path).unwrap();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid(_))));
+        fs::remove_file(path).unwrap();
+
+        let config = ServerConfig {
+            timeouts: Timeouts {
+                methods: [("SayHello".to_string(), 10)].into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidMethod(method)) if method == "SayHello"
+        ));
+    }
+}
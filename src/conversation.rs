@@ -0,0 +1,211 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use proto::{
+    conversation_server::Conversation, ChatRequest, ChatResponse, EndSessionRequest, Session,
+    SessionSummary, StartRequest,
+};
+
+pub mod proto {
+    include!("../protos/conversation.rs");
+}
+
+#[derive(Debug)]
+pub struct ConversationState {
+    user: String,
+    turn: u32,
+    last_active: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversationService {
+    sessions: Arc<DashMap<Uuid, ConversationState>>,
+    // 会话在无活动超过 session_ttl 后过期
+    session_ttl: Duration,
+}
+
+impl ConversationService {
+    pub fn new(session_ttl: Duration) -> Self {
+        ConversationService {
+            sessions: Arc::new(DashMap::new()),
+            session_ttl,
+        }
+    }
+
+    // 清理所有已过期的会话
+    fn purge_expired(&self) {
+        let ttl = self.session_ttl;
+        self.sessions
+            .retain(|_, state| state.last_active.elapsed() < ttl);
+    }
+
+    // 每隔 interval 清理一次过期的会话, 没有新会话时也不会一直占用内存; shutdown 取消后停止
+    pub fn spawn_purger(&self, interval: Duration, shutdown: CancellationToken) -> JoinHandle<()> {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => service.purge_expired(),
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        })
+    }
+}
+
+fn invalid_session_id(e: uuid::Error) -> Status {
+    Status::invalid_argument(format!("invalid session_id: {}", e))
+}
+
+#[tonic::async_trait]
+impl Conversation for ConversationService {
+    async fn start_session(
+        &self,
+        request: Request<StartRequest>,
+    ) -> Result<Response<Session>, Status> {
+        self.purge_expired();
+
+        let session_id = Uuid::new_v4();
+        self.sessions.insert(
+            session_id,
+            ConversationState {
+                user: request.into_inner().user,
+                turn: 0,
+                last_active: Instant::now(),
+            },
+        );
+        println!("StartSession = {}", session_id);
+
+        Ok(Response::new(Session {
+            session_id: session_id.to_string(),
+        }))
+    }
+
+    async fn chat(&self, request: Request<ChatRequest>) -> Result<Response<ChatResponse>, Status> {
+        let req = request.into_inner();
+        let session_id = Uuid::parse_str(&req.session_id).map_err(invalid_session_id)?;
+
+        let mut state = self
+            .sessions
+            .get_mut(&session_id)
+            .ok_or_else(|| Status::not_found(format!("session {} not found", session_id)))?;
+
+        if state.last_active.elapsed() >= self.session_ttl {
+            drop(state);
+            self.sessions.remove(&session_id);
+            return Err(Status::not_found(format!("session {} expired", session_id)));
+        }
+
+        state.turn += 1;
+        state.last_active = Instant::now();
+
+        Ok(Response::new(ChatResponse {
+            reply: format!("{} said: {}", state.user, req.message),
+            turn: state.turn,
+        }))
+    }
+
+    async fn end_session(
+        &self,
+        request: Request<EndSessionRequest>,
+    ) -> Result<Response<SessionSummary>, Status> {
+        let session_id =
+            Uuid::parse_str(&request.get_ref().session_id).map_err(invalid_session_id)?;
+
+        match self.sessions.remove(&session_id) {
+            Some((_, state)) if state.last_active.elapsed() < self.session_ttl => {
+                Ok(Response::new(SessionSummary {
+                    turn_count: state.turn,
+                }))
+            }
+            Some(_) => Err(Status::not_found(format!("session {} expired", session_id))),
+            None => Err(Status::not_found(format!(
+                "session {} not found",
+                session_id
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    async fn start(service: &ConversationService) -> String {
+        let request = Request::new(StartRequest {
+            user: "alice".to_string(),
+        });
+        service
+            .start_session(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .session_id
+    }
+
+    async fn chat(service: &ConversationService, session_id: &str) -> Result<u32, Status> {
+        let request = Request::new(ChatRequest {
+            session_id: session_id.to_string(),
+            message: "hi".to_string(),
+        });
+        Ok(service.chat(request).await?.into_inner().turn)
+    }
+
+    async fn end(service: &ConversationService, session_id: &str) -> Result<u32, Status> {
+        let request = Request::new(EndSessionRequest {
+            session_id: session_id.to_string(),
+        });
+        Ok(service.end_session(request).await?.into_inner().turn_count)
+    }
+
+    #[tokio::test]
+    async fn session_lifecycle() {
+        let service = ConversationService::new(Duration::from_secs(60));
+        let session_id = start(&service).await;
+        assert_eq!(chat(&service, &session_id).await.unwrap(), 1);
+        assert_eq!(chat(&service, &session_id).await.unwrap(), 2);
+        assert_eq!(end(&service, &session_id).await.unwrap(), 2);
+
+        let status = chat(&service, &session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        let status = chat(&service, "not-a-uuid").await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn idle_session_expires() {
+        let service = ConversationService::new(Duration::from_millis(50));
+        let session_id = start(&service).await;
+        tokio::time::sleep(Duration::from_millis(80)).await;
+
+        let status = chat(&service, &session_id).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert!(status.message().contains("expired"));
+    }
+
+    #[tokio::test]
+    async fn purger_removes_expired_sessions_until_shutdown() {
+        let service = ConversationService::new(Duration::from_millis(50));
+        start(&service).await;
+        let shutdown = CancellationToken::new();
+        let purger = service.spawn_purger(Duration::from_millis(10), shutdown.clone());
+
+        // 没有新的请求, 过期的会话也会被清理
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(service.sessions.is_empty());
+
+        shutdown.cancel();
+        purger.await.unwrap();
+    }
+}
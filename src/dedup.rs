@@ -0,0 +1,88 @@
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bloomfilter::Bloom;
+
+pub const DEFAULT_FP_RATE: f64 = 0.01;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug)]
+struct DailyBloom {
+    // 自 UNIX 纪元起的天数(UTC)
+    day: u64,
+    bloom: Bloom<String>,
+}
+
+// 不依赖存储的重复投票过滤器, 以 voter_id + url + 日期为键
+// 布隆过滤器可能误判, 少量首次投票也会被当作重复投票
+#[derive(Debug)]
+pub struct DuplicateFilter {
+    state: Mutex<DailyBloom>,
+}
+
+impl DuplicateFilter {
+    // 根据 expected_daily_votes 和误判率 fp_rate 估算过滤器大小
+    pub fn new(expected_daily_votes: usize, fp_rate: f64) -> Self {
+        DuplicateFilter {
+            state: Mutex::new(DailyBloom {
+                day: today(),
+                bloom: Bloom::new_for_fp_rate(expected_daily_votes.max(1), fp_rate),
+            }),
+        }
+    }
+
+    // 记录一次投票, 当天已经记录过(或误判)时返回 true
+    pub fn check_and_record(&self, voter_id: &str, url: &str) -> bool {
+        let day = today();
+        let mut state = self.state.lock().unwrap();
+        // 过了 UTC 零点就清空
+        if state.day != day {
+            state.day = day;
+            state.bloom.clear();
+        }
+
+        let key = format!("{}\n{}\n{}", voter_id, url, day);
+        state.bloom.check_and_set(&key)
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / SECONDS_PER_DAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_vote_is_detected() {
+        let filter = DuplicateFilter::new(100, DEFAULT_FP_RATE);
+        assert!(!filter.check_and_record("alice", "https://example.com"));
+        assert!(filter.check_and_record("alice", "https://example.com"));
+        assert!(!filter.check_and_record("bob", "https://example.com"));
+    }
+
+    #[test]
+    fn false_positive_rate_is_bounded() {
+        const VOTES: usize = 100_000;
+        // 检查时也会记录, 样本太大会让过滤器超出预计的容量
+        const SAMPLE: usize = 5_000;
+        let filter = DuplicateFilter::new(VOTES, DEFAULT_FP_RATE);
+        for voter in 0..VOTES {
+            filter.check_and_record(&format!("voter-{}", voter), "https://example.com");
+        }
+
+        let false_positives = (0..SAMPLE)
+            .filter(|voter| {
+                filter.check_and_record(&format!("other-{}", voter), "https://example.com")
+            })
+            .count();
+        let rate = false_positives as f64 / SAMPLE as f64;
+        assert!(rate < DEFAULT_FP_RATE * 2.0, "false positive rate {}", rate);
+    }
+}
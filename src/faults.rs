@@ -0,0 +1,122 @@
+// 按请求 metadata 注入错误, 用于测试客户端的重试; 只在 GreetService 开启时生效
+use rand::Rng;
+use tonic::{metadata::MetadataMap, Code, Status};
+
+// 要返回的错误码名称, 如 "unavailable" 或 "DEADLINE_EXCEEDED"
+pub const FAIL_WITH_HEADER: &str = "x-fail-with";
+// 返回错误的概率 (百分比, 0 ~ 100), 默认 100
+pub const FAIL_PROBABILITY_HEADER: &str = "x-fail-probability";
+
+// 除 OK 以外的所有错误码
+const CODES: &[(&str, Code)] = &[
+    ("cancelled", Code::Cancelled),
+    ("unknown", Code::Unknown),
+    ("invalid_argument", Code::InvalidArgument),
+    ("deadline_exceeded", Code::DeadlineExceeded),
+    ("not_found", Code::NotFound),
+    ("already_exists", Code::AlreadyExists),
+    ("permission_denied", Code::PermissionDenied),
+    ("resource_exhausted", Code::ResourceExhausted),
+    ("failed_precondition", Code::FailedPrecondition),
+    ("aborted", Code::Aborted),
+    ("out_of_range", Code::OutOfRange),
+    ("unimplemented", Code::Unimplemented),
+    ("internal", Code::Internal),
+    ("unavailable", Code::Unavailable),
+    ("data_loss", Code::DataLoss),
+    ("unauthenticated", Code::Unauthenticated),
+];
+
+// 不区分大小写
+fn code_from_name(name: &str) -> Option<Code> {
+    CODES
+        .iter()
+        .find(|(code_name, _)| code_name.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+}
+
+// 没有 x-fail-with 时返回 None; 错误码名称或概率无法解析时返回 INVALID_ARGUMENT
+pub fn injected_fault(metadata: &MetadataMap) -> Option<Status> {
+    let name = metadata.get(FAIL_WITH_HEADER)?;
+    let Some(code) = name.to_str().ok().and_then(code_from_name) else {
+        return Some(Status::invalid_argument(format!(
+            "unknown {}: {:?}",
+            FAIL_WITH_HEADER, name
+        )));
+    };
+
+    let probability = match metadata.get(FAIL_PROBABILITY_HEADER) {
+        None => 100,
+        Some(value) => match value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+        {
+            Some(probability) if probability <= 100 => probability,
+            _ => {
+                return Some(Status::invalid_argument(format!(
+                    "invalid {}: {:?}, expected 0 to 100",
+                    FAIL_PROBABILITY_HEADER, value
+                )))
+            }
+        },
+    };
+    if rand::thread_rng().gen_range(0..100) >= probability {
+        return None;
+    }
+
+    Some(Status::new(
+        code,
+        format!("injected by {}", FAIL_WITH_HEADER),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail_with(name: &str, probability: Option<&str>) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(FAIL_WITH_HEADER, name.parse().unwrap());
+        if let Some(probability) = probability {
+            metadata.insert(FAIL_PROBABILITY_HEADER, probability.parse().unwrap());
+        }
+        metadata
+    }
+
+    #[test]
+    fn named_code_is_returned_every_time_by_default() {
+        for (name, code) in [
+            ("unavailable", Code::Unavailable),
+            ("INTERNAL", Code::Internal),
+            ("Deadline_Exceeded", Code::DeadlineExceeded),
+        ] {
+            for _ in 0..20 {
+                let status = injected_fault(&fail_with(name, Some("100"))).unwrap();
+                assert_eq!(status.code(), code, "{name}");
+            }
+            assert_eq!(injected_fault(&fail_with(name, None)).unwrap().code(), code);
+        }
+    }
+
+    #[test]
+    fn zero_probability_never_fails() {
+        for _ in 0..20 {
+            assert!(injected_fault(&fail_with("unavailable", Some("0"))).is_none());
+        }
+        assert!(injected_fault(&MetadataMap::new()).is_none());
+    }
+
+    #[test]
+    fn unknown_code_or_probability_is_invalid() {
+        for metadata in [
+            fail_with("ok", None),
+            fail_with("teapot", None),
+            fail_with("unavailable", Some("101")),
+            fail_with("unavailable", Some("half")),
+        ] {
+            let status = injected_fault(&metadata).unwrap();
+            assert_eq!(status.code(), Code::InvalidArgument, "{metadata:?}");
+        }
+    }
+}
@@ -0,0 +1,195 @@
+// route_guide_db.json 的文件格式, 服务端的存储和客户端的导入导出共用
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::routeguide::{feature::Category, Feature, Point};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PointJson {
+    pub latitude: i32,
+    pub longitude: i32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub floor: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeatureJson {
+    pub name: String,
+    pub location: PointJson,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub valid_from: i64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub valid_until: i64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub elevation: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // Category 的名称, 如 "PARK"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub category: String,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+// 从 route_guide_db.json 格式的文件读取 feature
+pub fn load_json(path: &Path) -> Result<Vec<Feature>, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let decoded: Vec<FeatureJson> = serde_json::from_reader(BufReader::new(file))?;
+
+    decoded
+        .into_iter()
+        .map(|feature| {
+            let category = match feature.category.as_str() {
+                "" => Category::Unspecified,
+                name => Category::from_str_name(name)
+                    .ok_or_else(|| format!("unknown category: {:?}", name))?,
+            };
+
+            Ok(Feature {
+                name: feature.name,
+                location: Some(Point {
+                    latitude: feature.location.latitude,
+                    longitude: feature.location.longitude,
+                    floor: feature.location.floor,
+                    ..Default::default()
+                }),
+                valid_from: feature.valid_from,
+                valid_until: feature.valid_until,
+                elevation: feature.elevation,
+                tags: feature.tags,
+                category: category.into(),
+            })
+        })
+        .collect()
+}
+
+// 先写临时文件再改名, 避免写到一半时留下损坏的文件; 没有 location 的 feature 不保存
+pub fn save_json<'a>(
+    path: &Path,
+    features: impl IntoIterator<Item = &'a Feature>,
+) -> io::Result<()> {
+    let encoded: Vec<FeatureJson> = features
+        .into_iter()
+        .filter_map(|feature| {
+            let location = feature.location.as_ref()?;
+            Some(FeatureJson {
+                name: feature.name.clone(),
+                location: PointJson {
+                    latitude: location.latitude,
+                    longitude: location.longitude,
+                    floor: location.floor,
+                },
+                valid_from: feature.valid_from,
+                valid_until: feature.valid_until,
+                elevation: feature.elevation,
+                tags: feature.tags.clone(),
+                category: match feature.category() {
+                    Category::Unspecified => String::new(),
+                    category => category.as_str_name().to_string(),
+                },
+            })
+        })
+        .collect();
+
+    // 改名前先落盘, 崩溃后要么是旧文件要么是完整的新文件; 残留的临时文件不会被读取
+    let tmp = path.with_extension("json.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer_pretty(&mut writer, &encoded)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("features-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    fn load_text(text: &str) -> Result<Vec<Feature>, String> {
+        let path = temp_path();
+        fs::write(&path, text).unwrap();
+        let result = load_json(&path).map_err(|e| e.to_string());
+        fs::remove_file(path).unwrap();
+        result
+    }
+
+    #[test]
+    fn sample_file_parses() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("route_guide_db.json");
+        let features = load_json(&path).unwrap();
+
+        assert_eq!(features.len(), 19);
+        assert_eq!(features[0].name, "Patriots Path, Mendham, NJ 07945, USA");
+        assert_eq!(
+            features[0].location,
+            Some(Point {
+                latitude: 407838351,
+                longitude: -746143763,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_documents_are_errors() {
+        let truncated = load_text(r#"[{"name": "a", "location": {"latitude": 1"#).unwrap_err();
+        assert!(truncated.contains("EOF"), "{}", truncated);
+
+        let missing = load_text(r#"[{"name": "a"}]"#).unwrap_err();
+        assert!(missing.contains("missing field `location`"), "{}", missing);
+
+        let category = load_text(
+            r#"[{"name": "a", "location": {"latitude": 1, "longitude": 2}, "category": "CASTLE"}]"#,
+        )
+        .unwrap_err();
+        assert_eq!(category, "unknown category: \"CASTLE\"");
+    }
+
+    #[test]
+    fn missing_file_is_an_io_error() {
+        let error = load_json(&temp_path()).unwrap_err();
+        assert!(error.downcast_ref::<io::Error>().is_some(), "{}", error);
+    }
+
+    #[test]
+    fn save_then_load_round_trips() {
+        let feature = Feature {
+            name: "library".to_string(),
+            location: Some(Point {
+                latitude: 10,
+                longitude: 20,
+                floor: 3,
+                ..Default::default()
+            }),
+            valid_from: 1_000,
+            valid_until: 2_000,
+            elevation: 12.5,
+            tags: vec!["quiet".to_string()],
+            category: Category::Park.into(),
+        };
+        let nowhere = Feature {
+            name: "nowhere".to_string(),
+            ..Default::default()
+        };
+        let path = temp_path();
+
+        save_json(&path, [&feature, &nowhere]).unwrap();
+        assert_eq!(load_json(&path).unwrap(), [feature]);
+        assert!(!path.with_extension("json.tmp").exists());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1146 @@
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    featurejson::{load_json, save_json},
+    routeguide::{Feature, Point, Rectangle},
+};
+
+// 统计某个位置上的 feature 个数, 见 FeatureStore::feature_counter
+pub type FeatureCounter = Arc<dyn Fn(&Point) -> usize + Send + Sync>;
+
+// feature 的存储, 读取返回的 feature 以 Arc 共享, 修改后之前返回的结果不受影响
+pub trait FeatureStore: Debug + Send + Sync {
+    fn all(&self) -> Vec<Arc<Feature>>;
+
+    // 位置与 point 完全相同的 feature
+    fn at(&self, point: &Point) -> Vec<Arc<Feature>>;
+
+    // 只取一次快照, 之后的调用不加锁也不受修改影响; 用于在 rayon 线程中逐点查找
+    fn feature_counter(&self) -> FeatureCounter;
+
+    // 位置在 rect 内的 feature, 保持数据集原有顺序
+    fn in_rect(&self, rect: &Rectangle) -> Vec<Arc<Feature>>;
+
+    // point 周围 radius 米范围内可能命中的 feature, 调用方仍需用 calc_distance 精确判断
+    fn near(&self, point: &Point, radius: i32) -> Vec<Arc<Feature>>;
+
+    // 同一位置已经有 feature 时返回 StoreError::AlreadyExists
+    fn add(&self, feature: Feature) -> Result<(), StoreError>;
+
+    // 一次写入加入一批 feature, 返回加入的 feature; 与已有 feature 位置相同的跳过
+    fn add_all(&self, features: Vec<Feature>) -> Result<Vec<Arc<Feature>>, StoreError>;
+
+    // 删除位置与 point 完全相同的 feature 并返回, 没有时返回 StoreError::NotFound
+    fn remove(&self, point: &Point) -> Result<Vec<Arc<Feature>>, StoreError>;
+
+    // 用 feature 替换位置与 original 完全相同的 feature, 返回被替换的 feature;
+    // original 没有 feature 时返回 StoreError::NotFound, 移动到的位置已经有 feature 时返回 StoreError::AlreadyExists
+    fn replace(&self, original: &Point, feature: Feature) -> Result<Arc<Feature>, StoreError>;
+
+    // 把位置与 location 完全相同的 feature 改名, 返回改名的个数
+    fn rename(&self, location: &Point, name: &str) -> Result<usize, StoreError>;
+
+    // 最后一次修改的 unix 毫秒时间戳, 0 表示启动后还没有修改过
+    fn last_modified(&self) -> i64;
+
+    // 把还没有保存的修改写入存储, 关闭服务前调用
+    fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn get(&self, point: &Point) -> Option<Arc<Feature>> {
+        self.at(point).into_iter().next()
+    }
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    AlreadyExists,
+    NotFound,
+    Io(io::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::AlreadyExists => write!(f, "a feature already exists at this location"),
+            StoreError::NotFound => write!(f, "no feature at this location"),
+            StoreError::Io(e) => write!(f, "failed to save features: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+// 网格边长: 0.1 度 (坐标按 1e7 缩放)
+const CELL_SIZE: i32 = 1_000_000;
+// 纬度方向每个网格大约 11km
+const CELL_METERS: f64 = 11_132.0;
+
+type Cell = (i32, i32);
+// 180 度 (坐标按 1e7 缩放)
+const HALF_TURN: i64 = 1_800_000_000;
+
+// feature 在 at_time (unix 毫秒) 时是否有效: valid_from <= at_time < valid_until, 0 表示不限
+pub fn valid_at(feature: &Feature, at_time: i64) -> bool {
+    (feature.valid_from == 0 || feature.valid_from <= at_time)
+        && (feature.valid_until == 0 || at_time < feature.valid_until)
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+fn cell_of(point: &Point) -> Cell {
+    (
+        point.latitude.div_euclid(CELL_SIZE),
+        point.longitude.div_euclid(CELL_SIZE),
+    )
+}
+
+// lo 为西南角, hi 为东北角; lo.longitude > hi.longitude 时矩形跨越 ±180° 经线
+// 缺少 lo 或 hi 的矩形不包含任何点; 楼层只比较设置了的 lo_floor / hi_floor
+pub fn in_rang(point: &Point, rect: &Rectangle) -> bool {
+    let (Some(lo), Some(hi)) = (rect.lo.as_ref(), rect.hi.as_ref()) else {
+        return false;
+    };
+
+    let top = cmp::max(lo.latitude, hi.latitude);
+    let bottom = cmp::min(lo.latitude, hi.latitude);
+
+    let in_longitude = if lo.longitude > hi.longitude {
+        point.longitude >= lo.longitude || point.longitude <= hi.longitude
+    } else {
+        point.longitude >= lo.longitude && point.longitude <= hi.longitude
+    };
+
+    let (lo_floor, hi_floor) = match (rect.lo_floor, rect.hi_floor) {
+        (Some(lo), Some(hi)) => (cmp::min(lo, hi), cmp::max(lo, hi)),
+        (lo, hi) => (lo.unwrap_or(i32::MIN), hi.unwrap_or(i32::MAX)),
+    };
+
+    in_longitude
+        && point.latitude >= bottom
+        && point.latitude <= top
+        && point.floor >= lo_floor
+        && point.floor <= hi_floor
+}
+
+// 按经纬度网格分桶的 feature 索引
+#[derive(Debug, Default, Clone)]
+struct FeatureIndex {
+    // 以 Arc 共享, 发送给流时只需增加引用计数
+    // 按写入序号排列, 删除时其余 feature 的序号不变, 只需移除对应的索引项
+    features: BTreeMap<usize, Arc<Feature>>,
+    next: usize,
+    cells: HashMap<Cell, Vec<usize>>,
+    // 精确坐标 (latitude, longitude, floor) 到 feature 序号的映射
+    points: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl FeatureIndex {
+    fn new(features: Vec<Feature>) -> Self {
+        let mut index = FeatureIndex::default();
+        for feature in features {
+            index.insert(feature);
+        }
+
+        index
+    }
+
+    fn insert(&mut self, feature: Feature) -> usize {
+        let i = self.next;
+        self.next += 1;
+        if let Some(location) = feature.location.as_ref() {
+            self.index(i, location);
+        }
+        self.features.insert(i, Arc::new(feature));
+
+        i
+    }
+
+    fn index(&mut self, i: usize, location: &Point) {
+        self.cells.entry(cell_of(location)).or_default().push(i);
+        self.points
+            .entry((location.latitude, location.longitude, location.floor))
+            .or_default()
+            .push(i);
+    }
+
+    // 从网格和坐标索引中移除单个序号, 清空的桶一并删除
+    fn unindex(&mut self, i: usize, location: &Point) {
+        let cell = cell_of(location);
+        if let Some(indexes) = self.cells.get_mut(&cell) {
+            indexes.retain(|&j| j != i);
+            if indexes.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+
+        let key = (location.latitude, location.longitude, location.floor);
+        if let Some(indexes) = self.points.get_mut(&key) {
+            indexes.retain(|&j| j != i);
+            if indexes.is_empty() {
+                self.points.remove(&key);
+            }
+        }
+    }
+
+    // 与 rect 有重叠的网格中的 feature, 保持数据集原有顺序; 调用方仍需做精确的范围判断
+    // 经度方向与 in_rang 一致, lo.longitude > hi.longitude 表示跨越 ±180° 经线
+    fn candidates(&self, rect: &Rectangle) -> Vec<&Arc<Feature>> {
+        let (Some(lo), Some(hi)) = (rect.lo.as_ref(), rect.hi.as_ref()) else {
+            return vec![];
+        };
+
+        let lo_lat = cmp::min(lo.latitude, hi.latitude).div_euclid(CELL_SIZE);
+        let hi_lat = cmp::max(lo.latitude, hi.latitude).div_euclid(CELL_SIZE);
+        // lo 在 hi 东边时矩形跨越 ±180° 经线, 经度拆成两段
+        let lng_ranges = if lo.longitude > hi.longitude {
+            vec![
+                (
+                    lo.longitude.div_euclid(CELL_SIZE),
+                    i32::MAX.div_euclid(CELL_SIZE),
+                ),
+                (
+                    i32::MIN.div_euclid(CELL_SIZE),
+                    hi.longitude.div_euclid(CELL_SIZE),
+                ),
+            ]
+        } else {
+            vec![(
+                lo.longitude.div_euclid(CELL_SIZE),
+                hi.longitude.div_euclid(CELL_SIZE),
+            )]
+        };
+
+        let in_cells = |(lat, lng): &Cell| {
+            (lo_lat..=hi_lat).contains(lat)
+                && lng_ranges
+                    .iter()
+                    .any(|&(lo_lng, hi_lng)| (lo_lng..=hi_lng).contains(lng))
+        };
+
+        // 矩形覆盖的网格比已占用的网格还多时, 直接遍历已占用的网格
+        let span: i64 = lng_ranges
+            .iter()
+            .map(|&(lo_lng, hi_lng)| (hi_lat - lo_lat + 1) as i64 * (hi_lng - lo_lng + 1) as i64)
+            .sum();
+        let mut indexes: Vec<usize> = if span > self.cells.len() as i64 {
+            self.cells
+                .iter()
+                .filter(|(cell, _)| in_cells(cell))
+                .flat_map(|(_, indexes)| indexes.iter().copied())
+                .collect()
+        } else {
+            lng_ranges
+                .iter()
+                .flat_map(|&(lo_lng, hi_lng)| {
+                    (lo_lat..=hi_lat)
+                        .flat_map(move |lat| (lo_lng..=hi_lng).map(move |lng| (lat, lng)))
+                })
+                .filter_map(|cell| self.cells.get(&cell))
+                .flat_map(|indexes| indexes.iter().copied())
+                .collect()
+        };
+        indexes.sort_unstable();
+
+        indexes.into_iter().map(|i| &self.features[&i]).collect()
+    }
+
+    fn at<'a>(&'a self, point: &Point) -> impl Iterator<Item = &'a Arc<Feature>> + 'a {
+        self.at_indexes(point).iter().map(|i| &self.features[i])
+    }
+
+    fn rename(&mut self, location: &Point, name: &str) -> usize {
+        let Some(indexes) =
+            self.points
+                .get(&(location.latitude, location.longitude, location.floor))
+        else {
+            return 0;
+        };
+
+        for i in indexes {
+            if let Some(feature) = self.features.get_mut(i) {
+                Arc::make_mut(feature).name = name.to_string();
+            }
+        }
+
+        indexes.len()
+    }
+
+    fn push(&mut self, feature: Feature) -> Result<(), StoreError> {
+        if let Some(location) = feature.location.as_ref() {
+            if self.at(location).next().is_some() {
+                return Err(StoreError::AlreadyExists);
+            }
+        }
+
+        self.insert(feature);
+
+        Ok(())
+    }
+
+    fn push_all(&mut self, features: Vec<Feature>) -> Vec<Arc<Feature>> {
+        features
+            .into_iter()
+            .filter_map(|feature| {
+                let i = self.next;
+                self.push(feature).ok()?;
+                self.features.get(&i).cloned()
+            })
+            .collect()
+    }
+
+    // 只移除该坐标下的 feature 及其索引项, 其余 feature 的序号不受影响
+    fn remove(&mut self, point: &Point) -> Result<Vec<Arc<Feature>>, StoreError> {
+        let indexes: Vec<usize> = self.at_indexes(point).to_vec();
+        if indexes.is_empty() {
+            return Err(StoreError::NotFound);
+        }
+
+        let mut removed = Vec::with_capacity(indexes.len());
+        for i in indexes {
+            self.unindex(i, point);
+            removed.extend(self.features.remove(&i));
+        }
+
+        Ok(removed)
+    }
+
+    fn at_indexes(&self, point: &Point) -> &[usize] {
+        self.points
+            .get(&(point.latitude, point.longitude, point.floor))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    // 新旧位置在同一次写入中切换
+    fn replace(&mut self, original: &Point, feature: Feature) -> Result<Arc<Feature>, StoreError> {
+        let key = (original.latitude, original.longitude, original.floor);
+        let Some(&i) = self.points.get(&key).and_then(|indexes| indexes.first()) else {
+            return Err(StoreError::NotFound);
+        };
+
+        let moved = feature
+            .location
+            .as_ref()
+            .is_none_or(|location| (location.latitude, location.longitude, location.floor) != key);
+        if moved {
+            if let Some(location) = feature.location.as_ref() {
+                if self.at(location).next().is_some() {
+                    return Err(StoreError::AlreadyExists);
+                }
+            }
+        }
+
+        // 位置变化时把序号从旧坐标移到新坐标, feature 在列表中的顺序不变
+        if moved {
+            self.unindex(i, original);
+            if let Some(location) = feature.location.as_ref() {
+                self.index(i, location);
+            }
+        }
+        let old = std::mem::replace(
+            self.features.get_mut(&i).expect("indexed feature"),
+            Arc::new(feature),
+        );
+
+        Ok(old)
+    }
+
+    fn near(&self, point: &Point, radius: i32) -> Vec<&Arc<Feature>> {
+        let lat_cells = (radius as f64 / CELL_METERS).ceil() as i32;
+        let cos_lat = (point.latitude as f64 / 1e7).to_radians().cos().max(0.01);
+        let lng_cells = (radius as f64 / (CELL_METERS * cos_lat)).ceil() as i64;
+
+        // 超出 ±180° 的经度绕到另一侧, 得到跨越经线的矩形; 覆盖整圈时不限经度
+        let lng_span = lng_cells.saturating_mul(CELL_SIZE as i64);
+        let (lo_lng, hi_lng) = if lng_span >= HALF_TURN {
+            (-HALF_TURN as i32, HALF_TURN as i32)
+        } else {
+            let wrap = |lng: i64| ((lng + HALF_TURN).rem_euclid(2 * HALF_TURN) - HALF_TURN) as i32;
+            let longitude = point.longitude as i64;
+            (wrap(longitude - lng_span), wrap(longitude + lng_span))
+        };
+
+        self.candidates(&Rectangle {
+            lo: Some(Point {
+                latitude: point
+                    .latitude
+                    .saturating_sub(lat_cells.saturating_mul(CELL_SIZE)),
+                longitude: lo_lng,
+                ..Default::default()
+            }),
+            hi: Some(Point {
+                latitude: point
+                    .latitude
+                    .saturating_add(lat_cells.saturating_mul(CELL_SIZE)),
+                longitude: hi_lng,
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+}
+
+// 内存中的 feature 存储, 修改时写时复制, 不影响正在读取的快照
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    index: RwLock<Arc<FeatureIndex>>,
+    // 最后一次修改的 unix 毫秒时间戳, 0 表示启动后还没有修改过
+    modified_at: AtomicI64,
+}
+
+impl InMemoryStore {
+    pub fn new(features: Vec<Feature>) -> Self {
+        InMemoryStore {
+            index: RwLock::new(Arc::new(FeatureIndex::new(features))),
+            modified_at: AtomicI64::new(0),
+        }
+    }
+
+    fn snapshot(&self) -> Arc<FeatureIndex> {
+        self.index.read().unwrap().clone()
+    }
+
+    // 整体替换全部 feature, 已经取得快照的读取不受影响
+    fn replace_all(&self, features: Vec<Feature>) {
+        self.replace_index(FeatureIndex::new(features));
+    }
+
+    fn replace_index(&self, index: FeatureIndex) {
+        *self.index.write().unwrap() = Arc::new(index);
+        self.modified_at.store(now_millis(), Ordering::Relaxed);
+    }
+
+    // 在写锁内修改索引
+    fn update<R>(
+        &self,
+        f: impl FnOnce(&mut FeatureIndex) -> Result<R, StoreError>,
+    ) -> Result<R, StoreError> {
+        let mut index = self.index.write().unwrap();
+        let result = f(Arc::make_mut(&mut index))?;
+        self.modified_at.store(now_millis(), Ordering::Relaxed);
+        Ok(result)
+    }
+}
+
+impl FeatureStore for InMemoryStore {
+    fn all(&self) -> Vec<Arc<Feature>> {
+        self.snapshot().features.values().cloned().collect()
+    }
+
+    fn last_modified(&self) -> i64 {
+        self.modified_at.load(Ordering::Relaxed)
+    }
+
+    fn at(&self, point: &Point) -> Vec<Arc<Feature>> {
+        self.snapshot().at(point).cloned().collect()
+    }
+
+    fn feature_counter(&self) -> FeatureCounter {
+        let index = self.snapshot();
+        Arc::new(move |point| index.at(point).count())
+    }
+
+    fn in_rect(&self, rect: &Rectangle) -> Vec<Arc<Feature>> {
+        self.snapshot()
+            .candidates(rect)
+            .into_iter()
+            .filter(|feature| {
+                feature
+                    .location
+                    .as_ref()
+                    .is_some_and(|location| in_rang(location, rect))
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn near(&self, point: &Point, radius: i32) -> Vec<Arc<Feature>> {
+        self.snapshot()
+            .near(point, radius)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    fn add(&self, feature: Feature) -> Result<(), StoreError> {
+        self.update(|index| index.push(feature))
+    }
+
+    fn add_all(&self, features: Vec<Feature>) -> Result<Vec<Arc<Feature>>, StoreError> {
+        self.update(|index| Ok(index.push_all(features)))
+    }
+
+    fn remove(&self, point: &Point) -> Result<Vec<Arc<Feature>>, StoreError> {
+        self.update(|index| index.remove(point))
+    }
+
+    fn replace(&self, original: &Point, feature: Feature) -> Result<Arc<Feature>, StoreError> {
+        self.update(|index| index.replace(original, feature))
+    }
+
+    fn rename(&self, location: &Point, name: &str) -> Result<usize, StoreError> {
+        self.update(|index| Ok(index.rename(location, name)))
+    }
+}
+
+// 每次修改后把全部 feature 写回 JSON 文件的存储
+#[derive(Debug)]
+pub struct JsonFileStore {
+    path: PathBuf,
+    memory: InMemoryStore,
+    // 保证写文件的顺序与修改的顺序一致; 同时记录本进程最后一次读写后文件的修改时间
+    save: Mutex<Option<SystemTime>>,
+}
+
+impl JsonFileStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.into();
+        let modified = modified_time(&path);
+        let features = load_json(&path)?;
+
+        Ok(JsonFileStore {
+            path,
+            memory: InMemoryStore::new(features),
+            save: Mutex::new(modified),
+        })
+    }
+
+    // 文件在本进程之外被修改时重新读取, 返回是否重新读取了;
+    // 解析失败时继续使用原来的数据, 同一次修改只报告一次错误
+    pub fn reload_if_changed(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut save = self.save.lock().unwrap();
+        let modified = fs::metadata(&self.path)?.modified()?;
+        if *save == Some(modified) {
+            return Ok(false);
+        }
+
+        *save = Some(modified);
+        let features = load_json(&self.path)?;
+        self.memory.replace_all(features);
+
+        Ok(true)
+    }
+
+    // 每隔 interval 检查一次文件的修改时间, shutdown 取消后停止
+    pub fn watch(
+        self: Arc<Self>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.cancelled() => break,
+                }
+                match self.reload_if_changed() {
+                    Ok(true) => {
+                        tracing::info!(path = %self.path.display(), "feature file reloaded")
+                    }
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!(
+                        path = %self.path.display(),
+                        error = %e,
+                        "failed to reload feature file, keeping previous features"
+                    ),
+                }
+            }
+        })
+    }
+
+    // 在副本上修改并先写入文件, 写入成功后才替换内存中的数据; 写入失败时内存和文件都保持原样.
+    // 所有修改都持有 save, 所以副本不会覆盖别的修改; 写文件时不持有读写锁, 不阻塞读取
+    fn persist<R>(
+        &self,
+        f: impl FnOnce(&mut FeatureIndex) -> Result<R, StoreError>,
+    ) -> Result<R, StoreError> {
+        let mut save = self.save.lock().unwrap();
+        let mut index = FeatureIndex::clone(&self.memory.snapshot());
+        let result = f(&mut index)?;
+        save_json(&self.path, index.features.values().map(Arc::as_ref))?;
+        *save = modified_time(&self.path);
+        self.memory.replace_index(index);
+        Ok(result)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl FeatureStore for JsonFileStore {
+    fn all(&self) -> Vec<Arc<Feature>> {
+        self.memory.all()
+    }
+
+    fn last_modified(&self) -> i64 {
+        self.memory.last_modified()
+    }
+
+    fn at(&self, point: &Point) -> Vec<Arc<Feature>> {
+        self.memory.at(point)
+    }
+
+    fn feature_counter(&self) -> FeatureCounter {
+        self.memory.feature_counter()
+    }
+
+    fn in_rect(&self, rect: &Rectangle) -> Vec<Arc<Feature>> {
+        self.memory.in_rect(rect)
+    }
+
+    fn near(&self, point: &Point, radius: i32) -> Vec<Arc<Feature>> {
+        self.memory.near(point, radius)
+    }
+
+    fn add(&self, feature: Feature) -> Result<(), StoreError> {
+        self.persist(|index| index.push(feature))
+    }
+
+    fn add_all(&self, features: Vec<Feature>) -> Result<Vec<Arc<Feature>>, StoreError> {
+        self.persist(|index| Ok(index.push_all(features)))
+    }
+
+    fn remove(&self, point: &Point) -> Result<Vec<Arc<Feature>>, StoreError> {
+        self.persist(|index| index.remove(point))
+    }
+
+    fn replace(&self, original: &Point, feature: Feature) -> Result<Arc<Feature>, StoreError> {
+        self.persist(|index| index.replace(original, feature))
+    }
+
+    fn rename(&self, location: &Point, name: &str) -> Result<usize, StoreError> {
+        self.persist(|index| Ok(index.rename(location, name)))
+    }
+
+    // 每次修改都已经保存过, 这里再完整写一遍, 确保文件与内存中的数据一致
+    fn flush(&self) -> Result<(), StoreError> {
+        let mut save = self.save.lock().unwrap();
+        save_json(&self.path, self.memory.all().iter().map(Arc::as_ref))?;
+        *save = modified_time(&self.path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn point(latitude: i32, longitude: i32, floor: i32) -> Point {
+        Point {
+            latitude,
+            longitude,
+            floor,
+            ..Default::default()
+        }
+    }
+
+    fn rect(lo_floor: Option<i32>, hi_floor: Option<i32>) -> Rectangle {
+        Rectangle {
+            lo: Some(point(0, 0, 0)),
+            hi: Some(point(10, 10, 0)),
+            lo_floor,
+            hi_floor,
+        }
+    }
+
+    #[test]
+    fn unset_floors_match_every_floor() {
+        for floor in [-3, 0, 7] {
+            assert!(in_rang(&point(5, 5, floor), &rect(None, None)));
+        }
+    }
+
+    #[test]
+    fn same_floor() {
+        let rect = rect(Some(0), Some(0));
+        assert!(in_rang(&point(5, 5, 0), &rect));
+        assert!(!in_rang(&point(5, 5, 1), &rect));
+    }
+
+    #[test]
+    fn floor_range() {
+        // 顺序颠倒的范围与正常的相同
+        for rect in [rect(Some(-1), Some(2)), rect(Some(2), Some(-1))] {
+            assert!(in_rang(&point(5, 5, -1), &rect));
+            assert!(in_rang(&point(5, 5, 2), &rect));
+            assert!(!in_rang(&point(5, 5, 3), &rect));
+            assert!(!in_rang(&point(5, 5, -2), &rect));
+        }
+    }
+
+    #[test]
+    fn open_floor_range() {
+        assert!(in_rang(&point(5, 5, 9), &rect(Some(1), None)));
+        assert!(!in_rang(&point(5, 5, 0), &rect(Some(1), None)));
+        assert!(in_rang(&point(5, 5, -9), &rect(None, Some(-1))));
+        assert!(!in_rang(&point(5, 5, 0), &rect(None, Some(-1))));
+    }
+
+    // 东经 170° 到西经 170°, 跨越日期变更线的 20° 窄带
+    fn date_line_band() -> Rectangle {
+        Rectangle {
+            lo: Some(point(0, 1_700_000_000, 0)),
+            hi: Some(point(10, -1_700_000_000, 0)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn antimeridian_band_includes_both_sides_of_the_date_line() {
+        let band = date_line_band();
+        for longitude in [
+            1_799_999_999,
+            1_800_000_000,
+            -1_800_000_000,
+            -1_799_999_999,
+            1_700_000_000,
+            -1_700_000_000,
+        ] {
+            assert!(in_rang(&point(5, longitude, 0), &band), "{}", longitude);
+        }
+    }
+
+    #[test]
+    fn antimeridian_band_excludes_the_complement() {
+        let band = date_line_band();
+        for longitude in [1_699_999_999, -1_699_999_999, 0, -746_143_763] {
+            assert!(!in_rang(&point(5, longitude, 0), &band), "{}", longitude);
+        }
+        // 纬度仍然按普通区间判断
+        assert!(!in_rang(&point(11, 1_799_999_999, 0), &band));
+    }
+
+    #[test]
+    fn ordered_rect_does_not_wrap() {
+        let rect = Rectangle {
+            lo: Some(point(0, -1_700_000_000, 0)),
+            hi: Some(point(10, 1_700_000_000, 0)),
+            ..Default::default()
+        };
+        assert!(in_rang(&point(5, 0, 0), &rect));
+        assert!(!in_rang(&point(5, 1_799_999_999, 0), &rect));
+        assert!(!in_rang(&point(5, -1_799_999_999, 0), &rect));
+    }
+
+    #[test]
+    fn store_lists_features_across_the_date_line() {
+        let feature = |name: &str, longitude| Feature {
+            name: name.to_string(),
+            location: Some(point(5, longitude, 0)),
+            ..Default::default()
+        };
+        let store = InMemoryStore::new(vec![
+            feature("fiji", 1_799_999_999),
+            feature("greenwich", 0),
+            feature("samoa", -1_799_999_999),
+        ]);
+
+        let names: Vec<_> = store
+            .in_rect(&date_line_band())
+            .iter()
+            .map(|feature| feature.name.clone())
+            .collect();
+        assert_eq!(names, ["fiji", "samoa"]);
+    }
+
+    #[test]
+    fn multi_floor_features() {
+        let store = InMemoryStore::new(vec![
+            Feature {
+                name: "lobby".to_string(),
+                location: Some(point(5, 5, 0)),
+                ..Default::default()
+            },
+            Feature {
+                name: "office".to_string(),
+                location: Some(point(5, 5, 3)),
+                ..Default::default()
+            },
+        ]);
+
+        let names = |rect: &Rectangle| -> Vec<String> {
+            store
+                .in_rect(rect)
+                .iter()
+                .map(|feature| feature.name.clone())
+                .collect()
+        };
+        assert_eq!(names(&rect(None, None)), ["lobby", "office"]);
+        assert_eq!(names(&rect(Some(1), Some(5))), ["office"]);
+    }
+
+    // 正负 180 度附近也放一些, 覆盖跨越经线的矩形
+    fn random_features(rng: &mut StdRng, count: usize) -> Vec<Feature> {
+        (0..count)
+            .map(|i| {
+                let longitude = if i % 10 == 0 {
+                    rng.gen_range(1_795_000_000..=1_800_000_000) * if i % 20 == 0 { -1 } else { 1 }
+                } else {
+                    rng.gen_range(-750_000_000..-730_000_000)
+                };
+                Feature {
+                    name: format!("feature {}", i),
+                    location: Some(point(
+                        rng.gen_range(400_000_000..420_000_000),
+                        longitude,
+                        rng.gen_range(-1..=1),
+                    )),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn random_point(rng: &mut StdRng) -> Point {
+        let longitude = if rng.gen_bool(0.2) {
+            rng.gen_range(1_790_000_000..=1_800_000_000) * if rng.gen_bool(0.5) { -1 } else { 1 }
+        } else {
+            rng.gen_range(-760_000_000..-720_000_000)
+        };
+        point(rng.gen_range(395_000_000..425_000_000), longitude, 0)
+    }
+
+    #[test]
+    fn grid_matches_brute_force_scan() {
+        let mut rng = StdRng::seed_from_u64(257);
+        let store = InMemoryStore::new(random_features(&mut rng, 5_000));
+        let all = store.all();
+
+        for _ in 0..500 {
+            let rect = Rectangle {
+                lo: Some(random_point(&mut rng)),
+                hi: Some(random_point(&mut rng)),
+                lo_floor: rng.gen_bool(0.3).then_some(0),
+                hi_floor: None,
+            };
+            let expected: Vec<_> = all
+                .iter()
+                .filter(|feature| in_rang(feature.location.as_ref().unwrap(), &rect))
+                .cloned()
+                .collect();
+            assert_eq!(store.in_rect(&rect), expected, "{:?}", rect);
+        }
+    }
+
+    #[test]
+    fn near_includes_every_feature_within_radius() {
+        let mut rng = StdRng::seed_from_u64(2572);
+        let store = InMemoryStore::new(random_features(&mut rng, 5_000));
+        let all = store.all();
+        for _ in 0..200 {
+            let center = all[rng.gen_range(0..all.len())].location.clone().unwrap();
+            let radius = rng.gen_range(100..50_000);
+            let candidates = store.near(&center, radius);
+            for feature in &all {
+                if crate::calc_distance(feature.location.as_ref().unwrap(), &center) <= radius {
+                    assert!(
+                        candidates
+                            .iter()
+                            .any(|candidate| Arc::ptr_eq(candidate, feature)),
+                        "{} missing within {}m of {:?}",
+                        feature.name,
+                        radius,
+                        center
+                    );
+                }
+            }
+        }
+    }
+
+    fn feature(name: &str, latitude: i32) -> Feature {
+        Feature {
+            name: name.to_string(),
+            location: Some(point(latitude, 0, 0)),
+            ..Default::default()
+        }
+    }
+
+    fn temp_store_path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("featurestore-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("features.json");
+        save_json(&path, &[feature("first", 1)]).unwrap();
+        path
+    }
+
+    fn names(store: &dyn FeatureStore) -> Vec<String> {
+        store
+            .all()
+            .iter()
+            .map(|feature| feature.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn changes_survive_reopen() {
+        let path = temp_store_path();
+        let store = JsonFileStore::open(&path).unwrap();
+        store.add(feature("second", 2)).unwrap();
+        store.rename(&point(1, 0, 0), "renamed").unwrap();
+        drop(store);
+
+        // 写到一半崩溃留下的临时文件不会被读取
+        fs::write(path.with_extension("json.tmp"), "[{\"name\": \"torn").unwrap();
+        let store = JsonFileStore::open(&path).unwrap();
+        assert_eq!(names(&store), ["renamed", "second"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failed_save_keeps_memory_unchanged() {
+        let path = temp_store_path();
+        let store = JsonFileStore::open(&path).unwrap();
+        // 临时文件的位置被目录占用, 写入失败
+        fs::create_dir(path.with_extension("json.tmp")).unwrap();
+
+        assert!(matches!(
+            store.add(feature("second", 2)),
+            Err(StoreError::Io(_))
+        ));
+        assert_eq!(names(&store), ["first"]);
+        assert!(store.get(&point(2, 0, 0)).is_none());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn remove_drops_only_the_entries_at_that_point() {
+        let mut index = FeatureIndex::new(vec![feature("a", 1), feature("b", 2), feature("c", 3)]);
+
+        let removed = index.remove(&point(2, 0, 0)).unwrap();
+        assert_eq!(removed[0].name, "b");
+        // 其余 feature 的序号不变, 被删除的序号也不再留在任何桶中
+        assert_eq!(index.features.keys().copied().collect::<Vec<_>>(), [0, 2]);
+        assert!(!index.points.contains_key(&(2, 0, 0)));
+        assert!(index.cells.values().flatten().all(|&i| i != 1));
+        assert_eq!(index.at(&point(3, 0, 0)).next().unwrap().name, "c");
+        assert!(matches!(
+            index.remove(&point(2, 0, 0)),
+            Err(StoreError::NotFound)
+        ));
+
+        // 重新加入的 feature 排在最后
+        index.push(feature("b", 2)).unwrap();
+        let names: Vec<_> = index
+            .candidates(&lat_band(0, 4))
+            .into_iter()
+            .map(|feature| feature.name.clone())
+            .collect();
+        assert_eq!(names, ["a", "c", "b"]);
+    }
+
+    #[test]
+    fn replace_moves_the_index_entry() {
+        let mut index = FeatureIndex::new(vec![feature("a", 1), feature("b", 2), feature("c", 3)]);
+
+        let old = index.replace(&point(2, 0, 0), feature("b2", 40)).unwrap();
+        assert_eq!(old.name, "b");
+        // 旧坐标不再命中, 新坐标命中同一个序号
+        assert!(index.at(&point(2, 0, 0)).next().is_none());
+        assert!(!index.points.contains_key(&(2, 0, 0)));
+        assert_eq!(index.at_indexes(&point(40, 0, 0)), [1]);
+        assert_eq!(
+            index.cells.values().flatten().filter(|&&i| i == 1).count(),
+            1
+        );
+        assert!(index.cells[&cell_of(&point(40, 0, 0))].contains(&1));
+
+        // 原地改名只替换 feature, 不改动索引
+        index.replace(&point(1, 0, 0), feature("a2", 1)).unwrap();
+        assert_eq!(index.at_indexes(&point(1, 0, 0)), [0]);
+        let names: Vec<_> = index.features.values().map(|f| f.name.clone()).collect();
+        assert_eq!(names, ["a2", "b2", "c"]);
+
+        assert!(matches!(
+            index.replace(&point(1, 0, 0), feature("a3", 3)),
+            Err(StoreError::AlreadyExists)
+        ));
+        assert!(matches!(
+            index.replace(&point(2, 0, 0), feature("x", 5)),
+            Err(StoreError::NotFound)
+        ));
+    }
+
+    // 显式推后修改时间, 不依赖文件系统的时间精度
+    fn touch(path: &Path, secs: u64) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    async fn wait_for(mut done: impl FnMut() -> bool) {
+        for _ in 0..250 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("condition not reached");
+    }
+
+    #[tokio::test]
+    async fn watcher_reloads_the_file_and_keeps_serving_on_parse_errors() {
+        let path = temp_store_path();
+        let store = Arc::new(JsonFileStore::open(&path).unwrap());
+        let shutdown = CancellationToken::new();
+        let watcher = store
+            .clone()
+            .watch(Duration::from_millis(10), shutdown.clone());
+        let before = store.all();
+
+        save_json(&path, &[feature("edited", 2)]).unwrap();
+        touch(&path, 1);
+        wait_for(|| store.get(&point(2, 0, 0)).is_some()).await;
+        assert_eq!(names(store.as_ref()), ["edited"]);
+        // 重新读取前取得的快照不受影响
+        assert_eq!(before[0].name, "first");
+
+        // 解析失败时继续使用原来的数据
+        fs::write(&path, "[{\"name\": ").unwrap();
+        touch(&path, 2);
+        wait_for(|| *store.save.lock().unwrap() == modified_time(&path)).await;
+        assert!(store.reload_if_changed().is_ok_and(|reloaded| !reloaded));
+        assert_eq!(names(store.as_ref()), ["edited"]);
+
+        shutdown.cancel();
+        watcher.await.unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    fn conformance_features() -> Vec<Feature> {
+        vec![feature("a", 1), feature("b", 2), feature("c", 3)]
+    }
+
+    fn lat_band(lo: i32, hi: i32) -> Rectangle {
+        Rectangle {
+            lo: Some(point(lo, -1, 0)),
+            hi: Some(point(hi, 1, 0)),
+            ..Default::default()
+        }
+    }
+
+    // 每个 FeatureStore 实现都要满足的行为; store 中是 conformance_features()
+    fn check_conformance(store: &dyn FeatureStore) {
+        assert_eq!(names(store), ["a", "b", "c"]);
+        assert_eq!(store.last_modified(), 0);
+
+        // 查找按完整的 (latitude, longitude, floor) 匹配
+        assert_eq!(store.get(&point(2, 0, 0)).unwrap().name, "b");
+        assert!(store.get(&point(2, 0, 1)).is_none());
+        assert_eq!(store.at(&point(2, 0, 0)).len(), 1);
+        let in_rect: Vec<_> = store
+            .in_rect(&lat_band(1, 2))
+            .iter()
+            .map(|f| f.name.clone())
+            .collect();
+        assert_eq!(in_rect, ["a", "b"]);
+        assert!(store
+            .near(&point(1, 0, 0), 100)
+            .iter()
+            .any(|f| f.name == "a"));
+
+        // 计数器是快照, 不受之后的修改影响
+        let counter = store.feature_counter();
+        assert!(matches!(
+            store.add(feature("a again", 1)),
+            Err(StoreError::AlreadyExists)
+        ));
+        store.add(feature("d", 4)).unwrap();
+        assert!(store.last_modified() > 0);
+        assert_eq!(counter(&point(3, 0, 0)), 1);
+        assert_eq!(counter(&point(4, 0, 0)), 0);
+        assert_eq!(store.feature_counter()(&point(4, 0, 0)), 1);
+
+        let added = store
+            .add_all(vec![feature("e", 5), feature("b again", 2)])
+            .unwrap();
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0].name, "e");
+
+        assert!(matches!(
+            store.remove(&point(9, 0, 0)),
+            Err(StoreError::NotFound)
+        ));
+        let removed = store.remove(&point(2, 0, 0)).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].name, "b");
+        assert!(store.get(&point(2, 0, 0)).is_none());
+        assert!(store.in_rect(&lat_band(2, 2)).is_empty());
+        assert_eq!(store.get(&point(3, 0, 0)).unwrap().name, "c");
+
+        assert!(matches!(
+            store.replace(&point(9, 0, 0), feature("x", 9)),
+            Err(StoreError::NotFound)
+        ));
+        assert!(matches!(
+            store.replace(&point(1, 0, 0), feature("onto c", 3)),
+            Err(StoreError::AlreadyExists)
+        ));
+        let old = store.replace(&point(1, 0, 0), feature("a2", 1)).unwrap();
+        assert_eq!(old.name, "a");
+        store.replace(&point(3, 0, 0), feature("c", 30)).unwrap();
+        assert!(store.get(&point(3, 0, 0)).is_none());
+        assert_eq!(store.get(&point(30, 0, 0)).unwrap().name, "c");
+
+        assert_eq!(store.rename(&point(4, 0, 0), "d2").unwrap(), 1);
+        assert_eq!(store.rename(&point(99, 0, 0), "nobody").unwrap(), 0);
+        store.flush().unwrap();
+
+        assert_eq!(names(store), ["a2", "c", "d2", "e"]);
+    }
+
+    #[test]
+    fn in_memory_store_conforms() {
+        check_conformance(&InMemoryStore::new(conformance_features()));
+    }
+
+    #[test]
+    fn json_file_store_conforms() {
+        let path = temp_store_path();
+        save_json(&path, &conformance_features()).unwrap();
+
+        check_conformance(&JsonFileStore::open(&path).unwrap());
+        // 每次修改都已经写入文件
+        assert_eq!(
+            names(&JsonFileStore::open(&path).unwrap()),
+            ["a2", "c", "d2", "e"]
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
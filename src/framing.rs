@@ -0,0 +1,419 @@
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::Mutex;
+
+use crate::tcp_client::TcpClient;
+use crate::ws_client::WebSocketClient;
+
+pub trait CallbackBack<Args = (usize, Vec<u8>)> {
+    fn name(&self) -> &str;
+    fn init(&mut self);
+    fn header_len(&self) -> usize;
+    fn protocol_len(&self) -> usize;
+    fn callback(&self, args: Args);
+}
+
+// 帧头: protocol_len 字节的协议号, 之后是 header_len - protocol_len 字节的 body 长度, 均为大端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameFormat {
+    protocol_len: usize,
+    length_len: usize,
+}
+
+impl FrameFormat {
+    // 两个字段只支持 2, 4, 8 字节
+    pub fn new(header_len: usize, protocol_len: usize) -> Result<Self> {
+        let width = |len: usize| matches!(len, 2 | 4 | 8);
+        let length_len = header_len.saturating_sub(protocol_len);
+        if !width(protocol_len) || !width(length_len) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "unsupported frame header: header_len {}, protocol_len {}",
+                    header_len, protocol_len
+                ),
+            ));
+        }
+
+        Ok(Self {
+            protocol_len,
+            length_len,
+        })
+    }
+
+    pub fn of<T: CallbackBack + ?Sized>(router: &T) -> Result<Self> {
+        Self::new(router.header_len(), router.protocol_len())
+    }
+
+    pub fn header_len(&self) -> usize {
+        self.protocol_len + self.length_len
+    }
+
+    pub fn encode(&self, protocol: usize, data: &[u8]) -> Result<Vec<u8>> {
+        let mut frame = Vec::with_capacity(self.header_len() + data.len());
+        put_uint(&mut frame, protocol, self.protocol_len)?;
+        put_uint(&mut frame, data.len(), self.length_len)?;
+        frame.extend_from_slice(data);
+        Ok(frame)
+    }
+
+    // buf 中有完整的一帧时返回 (protocol, body, 消耗的字节数)
+    pub fn decode(&self, buf: &[u8]) -> Option<(usize, Vec<u8>, usize)> {
+        let header_len = self.header_len();
+        if buf.len() < header_len {
+            return None;
+        }
+
+        let protocol = get_uint(&buf[..self.protocol_len]);
+        let body_len = get_uint(&buf[self.protocol_len..header_len]);
+        let end = header_len.checked_add(body_len)?;
+        (buf.len() >= end).then(|| (protocol, buf[header_len..end].to_vec(), end))
+    }
+}
+
+fn put_uint(buf: &mut Vec<u8>, value: usize, len: usize) -> Result<()> {
+    let bytes = (value as u64).to_be_bytes();
+    if len < 8 && (value as u64) >> (len * 8) != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} does not fit in {} bytes", value, len),
+        ));
+    }
+    buf.extend_from_slice(&bytes[8 - len..]);
+    Ok(())
+}
+
+fn get_uint(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64) as usize
+}
+
+// 底层连接拆成读写两半, 读的一半交给后台任务
+pub trait Transport: Send + 'static {
+    type Reader: ChunkReader;
+    type Writer: ChunkWriter;
+
+    fn split(self) -> (Self::Reader, Self::Writer);
+}
+
+#[tonic::async_trait]
+pub trait ChunkReader: Send + 'static {
+    // 把收到的字节追加到 buf, 连接关闭时返回 false
+    async fn read_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool>;
+}
+
+#[tonic::async_trait]
+pub trait ChunkWriter: Send + 'static {
+    // chunk 总是一个完整的帧
+    async fn write_chunk(&mut self, chunk: Vec<u8>) -> Result<()>;
+}
+
+// 与传输方式无关的分帧连接: 后台任务把每个完整的帧交给 router.callback
+pub struct FramedConnection<S: Transport, T> {
+    pub addr: String,
+    pub router: Arc<T>,
+    closed: Arc<AtomicBool>,
+    format: FrameFormat,
+    writer: Mutex<S::Writer>,
+}
+
+impl<S, T> FramedConnection<S, T>
+where
+    S: Transport,
+    T: CallbackBack + Send + Sync + 'static,
+{
+    pub fn new(addr: impl Into<String>, stream: S, mut router: T) -> Result<Self> {
+        router.init();
+        let format = FrameFormat::of(&router)?;
+        let addr = addr.into();
+        let router = Arc::new(router);
+        let closed = Arc::new(AtomicBool::new(false));
+        let (reader, writer) = stream.split();
+
+        tracing::debug!(name = router.name(), %addr, "framed connection start listening");
+        tokio::spawn(read_frames(reader, format, router.clone(), closed.clone()));
+
+        Ok(Self {
+            addr,
+            router,
+            closed,
+            format,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub async fn write(&self, protocol: usize, data: &[u8]) -> Result<()> {
+        if self.is_closed() {
+            return Err(Error::new(ErrorKind::NotConnected, "connection closed"));
+        }
+
+        let frame = self.format.encode(protocol, data)?;
+        self.writer.lock().await.write_chunk(frame).await
+    }
+}
+
+async fn read_frames<R, T>(
+    mut reader: R,
+    format: FrameFormat,
+    router: Arc<T>,
+    closed: Arc<AtomicBool>,
+) where
+    R: ChunkReader,
+    T: CallbackBack + Send + Sync + 'static,
+{
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_chunk(&mut buf).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                tracing::warn!(name = router.name(), error = %e, "framed connection read failed");
+                break;
+            }
+        }
+
+        let mut consumed = 0;
+        while let Some((protocol, body, len)) = format.decode(&buf[consumed..]) {
+            router.callback((protocol, body));
+            consumed += len;
+        }
+        buf.drain(..consumed);
+    }
+
+    closed.store(true, Ordering::Release);
+}
+
+// 按 URL 的 scheme 选择传输方式: tcp://host:port 或 ws://host:port/path
+pub enum Connection<T> {
+    Tcp(TcpClient<T>),
+    WebSocket(WebSocketClient<T>),
+}
+
+impl<T> Connection<T>
+where
+    T: CallbackBack + Send + Sync + 'static,
+{
+    pub async fn new(url: &str, router: T) -> Result<Self> {
+        if let Some(addr) = url.strip_prefix("tcp://") {
+            return Ok(Connection::Tcp(TcpClient::connect(addr, router).await?));
+        }
+        if url.starts_with("ws://") {
+            return Ok(Connection::WebSocket(
+                WebSocketClient::connect(url, router).await?,
+            ));
+        }
+
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unsupported connection url: {}", url),
+        ))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Connection::Tcp(client) => client.is_closed(),
+            Connection::WebSocket(client) => client.is_closed(),
+        }
+    }
+
+    pub async fn write(&self, protocol: usize, data: &[u8]) -> Result<()> {
+        match self {
+            Connection::Tcp(client) => client.write(protocol, data).await,
+            Connection::WebSocket(client) => client.write(protocol, data).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use super::*;
+
+    // 2 字节协议号 + 4 字节长度
+    struct Recorder {
+        initialized: bool,
+        frames: mpsc::UnboundedSender<(usize, Vec<u8>)>,
+    }
+
+    impl CallbackBack for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn init(&mut self) {
+            self.initialized = true;
+        }
+
+        fn header_len(&self) -> usize {
+            6
+        }
+
+        fn protocol_len(&self) -> usize {
+            2
+        }
+
+        fn callback(&self, args: (usize, Vec<u8>)) {
+            assert!(self.initialized);
+            let _ = self.frames.send(args);
+        }
+    }
+
+    fn recorder() -> (Recorder, mpsc::UnboundedReceiver<(usize, Vec<u8>)>) {
+        let (frames, rx) = mpsc::unbounded_channel();
+        let recorder = Recorder {
+            initialized: false,
+            frames,
+        };
+        (recorder, rx)
+    }
+
+    // 原样返回收到的字节
+    async fn tcp_echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.into_split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        format!("tcp://{}", address)
+    }
+
+    // 原样返回收到的 binary 消息
+    async fn ws_echo_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    while let Some(Ok(message)) = ws.next().await {
+                        if message.is_binary() && ws.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        format!("ws://{}/frames", address)
+    }
+
+    async fn round_trip(url: &str) {
+        let (router, mut frames) = recorder();
+        let connection = Connection::new(url, router).await.unwrap();
+
+        connection.write(7, b"hello").await.unwrap();
+        connection.write(65_535, b"").await.unwrap();
+        let large = vec![0xab; 20_000];
+        connection.write(1, &large).await.unwrap();
+
+        for expected in [(7, b"hello".to_vec()), (65_535, Vec::new()), (1, large)] {
+            let frame = tokio::time::timeout(Duration::from_secs(5), frames.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(frame, expected);
+        }
+        assert!(!connection.is_closed());
+    }
+
+    #[tokio::test]
+    async fn tcp_round_trip() {
+        let url = tcp_echo_server().await;
+        round_trip(&url).await;
+        assert!(matches!(
+            Connection::new(&url, recorder().0).await.unwrap(),
+            Connection::Tcp(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn websocket_round_trip() {
+        let url = ws_echo_server().await;
+        round_trip(&url).await;
+        assert!(matches!(
+            Connection::new(&url, recorder().0).await.unwrap(),
+            Connection::WebSocket(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn writes_fail_once_the_peer_hangs_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { drop(listener.accept().await) });
+
+        let connection = Connection::new(&url, recorder().0).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !connection.is_closed() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let error = connection.write(1, b"late").await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn unknown_scheme_is_rejected() {
+        let error = Connection::new("udp://127.0.0.1:1", recorder().0)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn frames_round_trip_through_every_width() {
+        for (header_len, protocol_len) in [(4, 2), (6, 2), (6, 4), (8, 4), (12, 4), (16, 8)] {
+            let format = FrameFormat::new(header_len, protocol_len).unwrap();
+            let frame = format.encode(258, b"body").unwrap();
+            assert_eq!(frame.len(), header_len + 4);
+            assert_eq!(
+                format.decode(&frame),
+                Some((258, b"body".to_vec(), frame.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn decode_waits_for_a_complete_frame() {
+        let format = FrameFormat::new(6, 2).unwrap();
+        let frame = format.encode(3, b"abc").unwrap();
+        assert_eq!(frame, [0, 3, 0, 0, 0, 3, b'a', b'b', b'c']);
+        for len in 0..frame.len() {
+            assert_eq!(format.decode(&frame[..len]), None);
+        }
+
+        // 之后的字节属于下一帧
+        let mut two = frame.clone();
+        two.extend_from_slice(&frame);
+        assert_eq!(format.decode(&two), Some((3, b"abc".to_vec(), frame.len())));
+    }
+
+    #[test]
+    fn invalid_formats_and_oversized_values_are_rejected() {
+        assert!(FrameFormat::new(5, 2).is_err());
+        assert!(FrameFormat::new(2, 2).is_err());
+        assert!(FrameFormat::new(6, 3).is_err());
+
+        let format = FrameFormat::new(4, 2).unwrap();
+        assert!(format.encode(65_536, b"").is_err());
+        assert!(format.encode(1, &vec![0; 65_536]).is_err());
+    }
+}
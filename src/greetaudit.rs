@@ -0,0 +1,218 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::featurestore::now_millis;
+
+// 缓冲满时丢弃记录, 不阻塞请求
+const ENTRY_BUFFER: usize = 4096;
+// 记录中 content 最多保留的字符数
+const MAX_AUDIT_CONTENT_CHARS: usize = 256;
+
+#[derive(Debug, Serialize)]
+struct AuditEntry {
+    timestamp_millis: i64,
+    // 没有对端地址时为 null
+    peer: Option<String>,
+    content_bytes: usize,
+    content: String,
+    truncated: bool,
+}
+
+// 每个问候追加一行 JSON 到 path; 文件超过 max_bytes 时改名为 path.1、path.2 ... 后写新文件
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    entries: mpsc::Sender<AuditEntry>,
+}
+
+impl AuditLog {
+    // 打开文件失败时返回错误; 之后写文件失败只记录日志
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let writer = AuditWriter::open(path.into(), max_bytes)?;
+        let (entries, receiver) = mpsc::channel(ENTRY_BUFFER);
+        // 写文件是阻塞的, 放在单独的线程里, 所有 AuditLog 被 drop 后结束
+        std::thread::spawn(move || writer.run(receiver));
+
+        Ok(AuditLog { entries })
+    }
+
+    pub fn record(&self, peer: Option<SocketAddr>, content: &str) {
+        let truncated = content.chars().count() > MAX_AUDIT_CONTENT_CHARS;
+        let entry = AuditEntry {
+            timestamp_millis: now_millis(),
+            peer: peer.map(|peer| peer.to_string()),
+            content_bytes: content.len(),
+            content: content.chars().take(MAX_AUDIT_CONTENT_CHARS).collect(),
+            truncated,
+        };
+        if let Err(e) = self.entries.try_send(entry) {
+            tracing::warn!(error = %e, "audit entry dropped");
+        }
+    }
+}
+
+#[derive(Debug)]
+struct AuditWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    // 当前文件的字节数
+    written: u64,
+    // 下一次改名使用的后缀
+    next_suffix: u64,
+}
+
+impl AuditWriter {
+    fn open(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        // 接着已有的后缀继续编号, 不覆盖之前改名的文件
+        let mut next_suffix = 1;
+        while suffixed(&path, next_suffix).exists() {
+            next_suffix += 1;
+        }
+
+        Ok(AuditWriter {
+            path,
+            max_bytes,
+            file,
+            written,
+            next_suffix,
+        })
+    }
+
+    fn run(mut self, mut receiver: mpsc::Receiver<AuditEntry>) {
+        while let Some(entry) = receiver.blocking_recv() {
+            if let Err(e) = self.append(&entry) {
+                tracing::warn!(path = %self.path.display(), error = %e, "audit write failed");
+            }
+        }
+    }
+
+    fn append(&mut self, entry: &AuditEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        // 当前文件为空时即使一行就超过 max_bytes 也直接写入
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+
+        self.file.write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = suffixed(&self.path, self.next_suffix);
+        fs::rename(&self.path, &rotated)?;
+        self.next_suffix += 1;
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        tracing::info!(path = %rotated.display(), "audit log rotated");
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// "audit.jsonl" -> "audit.jsonl.1"
+fn suffixed(path: &Path, suffix: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", suffix));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audit-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    // 等到 path 中有 lines 行, 最多等 5 秒
+    fn wait_for_lines(path: &Path, lines: usize) -> Vec<serde_json::Value> {
+        for _ in 0..500 {
+            let entries: Vec<serde_json::Value> = fs::read_to_string(path)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            if entries.len() >= lines {
+                return entries;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("{} never reached {} lines", path.display(), lines);
+    }
+
+    #[test]
+    fn each_record_is_a_json_line() {
+        let dir = temp_dir();
+        let path = dir.join("audit.jsonl");
+        let audit = AuditLog::open(&path, 1 << 20).unwrap();
+        let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        audit.record(Some(peer), "hello");
+        audit.record(None, "你好");
+        let long = "x".repeat(MAX_AUDIT_CONTENT_CHARS + 10);
+        audit.record(Some(peer), &long);
+
+        let entries = wait_for_lines(&path, 3);
+        assert_eq!(entries[0]["peer"], "127.0.0.1:4000");
+        assert_eq!(entries[0]["content"], "hello");
+        assert_eq!(entries[0]["content_bytes"], 5);
+        assert_eq!(entries[0]["truncated"], false);
+        assert!(entries[0]["timestamp_millis"].as_i64().unwrap() > 0);
+        assert!(entries[1]["peer"].is_null());
+        assert_eq!(entries[1]["content_bytes"], 6);
+        // 过长的 content 被截断, content_bytes 仍是原来的长度
+        assert_eq!(entries[2]["truncated"], true);
+        assert_eq!(
+            entries[2]["content"].as_str().unwrap().len(),
+            MAX_AUDIT_CONTENT_CHARS
+        );
+        assert_eq!(entries[2]["content_bytes"], long.len());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn full_file_is_rotated_with_a_numeric_suffix() {
+        let dir = temp_dir();
+        let path = dir.join("audit.jsonl");
+        // 每行都超过上限, 所以每个文件只有一行
+        let audit = AuditLog::open(&path, 10).unwrap();
+        for content in ["one", "two", "three"] {
+            audit.record(None, content);
+        }
+
+        wait_for_lines(&suffixed(&path, 2), 1);
+        let current = wait_for_lines(&path, 1);
+        assert_eq!(current[0]["content"], "three");
+        assert_eq!(wait_for_lines(&suffixed(&path, 1), 1)[0]["content"], "one");
+        assert_eq!(wait_for_lines(&suffixed(&path, 2), 1)[0]["content"], "two");
+
+        // 重新打开时接着已有的后缀编号
+        drop(audit);
+        let audit = AuditLog::open(&path, 10).unwrap();
+        audit.record(None, "four");
+        assert_eq!(
+            wait_for_lines(&suffixed(&path, 3), 1)[0]["content"],
+            "three"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+// 同一对端 (按 ip, 换端口重连也算同一个) 在 window 内最多发送 max_repeats 次相同的 content;
+// 计数从第一次出现开始, 过了 window 重新计数, 被拒绝的请求不会延长 window
+#[derive(Debug)]
+pub struct RepeatLimiter {
+    max_repeats: u32,
+    window: Duration,
+    // (对端, content) -> (window 开始时间, 已接受的次数); 没有对端地址的请求共用 None
+    repeats: DashMap<(Option<IpAddr>, String), (Instant, u32)>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RepeatLimiter {
+    pub fn new(max_repeats: u32, window: Duration) -> Self {
+        RepeatLimiter {
+            max_repeats,
+            window,
+            repeats: DashMap::new(),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    // 允许时计入本次请求; 否则返回到 window 结束还需要等待的时间
+    pub fn check(&self, peer: Option<IpAddr>, content: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.sweep(now);
+
+        match self.repeats.entry((peer, content.to_string())) {
+            Entry::Occupied(mut repeats) => {
+                let (start, count) = repeats.get_mut();
+                let elapsed = now.duration_since(*start);
+                if elapsed >= self.window {
+                    *start = now;
+                    *count = 1;
+                } else if *count >= self.max_repeats {
+                    return Err(self.window - elapsed);
+                } else {
+                    *count += 1;
+                }
+            }
+            Entry::Vacant(repeats) => {
+                repeats.insert((now, 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    // 每过一个 window 清理一次已经过期的记录, 避免记录无限增长
+    fn sweep(&self, now: Instant) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if now.duration_since(*last_sweep) < self.window {
+                return;
+            }
+            *last_sweep = now;
+        }
+
+        self.repeats
+            .retain(|_, (start, _)| now.duration_since(*start) < self.window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const PEER: Option<IpAddr> = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+
+    #[test]
+    fn fourth_identical_greeting_is_rejected() {
+        let limiter = RepeatLimiter::new(3, Duration::from_millis(100));
+        for _ in 0..3 {
+            limiter.check(PEER, "hello").unwrap();
+        }
+        let retry_after = limiter.check(PEER, "hello").unwrap_err();
+        assert!(retry_after <= Duration::from_millis(100));
+
+        // 不同的 content 和不同的对端分别计数
+        limiter.check(PEER, "hello 2").unwrap();
+        limiter.check(None, "hello").unwrap();
+    }
+
+    #[test]
+    fn count_restarts_after_the_window() {
+        let limiter = RepeatLimiter::new(1, Duration::from_millis(50));
+        limiter.check(PEER, "hello").unwrap();
+        assert!(limiter.check(PEER, "hello").is_err());
+
+        sleep(Duration::from_millis(60));
+        limiter.check(PEER, "hello").unwrap();
+        assert!(limiter.check(PEER, "hello").is_err());
+    }
+
+    #[test]
+    fn sweep_drops_expired_windows() {
+        let limiter = RepeatLimiter::new(3, Duration::from_millis(50));
+        limiter.check(PEER, "a").unwrap();
+        limiter.check(PEER, "b").unwrap();
+        assert_eq!(limiter.repeats.len(), 2);
+
+        sleep(Duration::from_millis(60));
+        limiter.check(PEER, "c").unwrap();
+        let contents: Vec<_> = limiter
+            .repeats
+            .iter()
+            .map(|entry| entry.key().1.clone())
+            .collect();
+        assert_eq!(contents, ["c"]);
+    }
+}
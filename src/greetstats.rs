@@ -0,0 +1,135 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::Mutex,
+};
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: u64,
+    // 对端的问候数和最后一次出现的序号
+    peers: HashMap<SocketAddr, (u64, u64)>,
+    // 最后一次出现的序号 -> 对端, 最小的是最久没有出现的
+    seen: BTreeMap<u64, SocketAddr>,
+    next_seen: u64,
+}
+
+// 按对端地址统计问候数, 最多记住 max_peers 个对端, 超过时淘汰最久没有出现的;
+// 被淘汰的对端再次出现时从 0 开始计数, 总数不受影响
+#[derive(Debug)]
+pub struct PeerCounter {
+    max_peers: usize,
+    counts: Mutex<Counts>,
+}
+
+impl PeerCounter {
+    pub fn new(max_peers: usize) -> Self {
+        PeerCounter {
+            max_peers: max_peers.max(1),
+            counts: Mutex::new(Counts::default()),
+        }
+    }
+
+    // 没有对端地址 (如不经过网络的调用) 时只计入总数
+    pub fn record(&self, peer: Option<SocketAddr>) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total += 1;
+        let Some(peer) = peer else {
+            return;
+        };
+
+        let seen = counts.next_seen;
+        counts.next_seen += 1;
+        let previous = counts.peers.get(&peer).copied();
+        let count = match previous {
+            Some((count, last_seen)) => {
+                counts.seen.remove(&last_seen);
+                count
+            }
+            None => {
+                if counts.peers.len() >= self.max_peers {
+                    if let Some((_, oldest)) = counts.seen.pop_first() {
+                        counts.peers.remove(&oldest);
+                    }
+                }
+                0
+            }
+        };
+        counts.peers.insert(peer, (count + 1, seen));
+        counts.seen.insert(seen, peer);
+    }
+
+    // 总数、记住的对端数和问候数最多的 limit 个对端, 问候数相同时最近出现的在前
+    pub fn snapshot(&self, limit: usize) -> (u64, usize, Vec<(SocketAddr, u64)>) {
+        let counts = self.counts.lock().unwrap();
+        let mut peers: Vec<_> = counts
+            .peers
+            .iter()
+            .map(|(peer, (count, seen))| (*peer, *count, *seen))
+            .collect();
+        peers.sort_by(|(_, a, a_seen), (_, b, b_seen)| b.cmp(a).then(b_seen.cmp(a_seen)));
+        let top = peers
+            .into_iter()
+            .take(limit)
+            .map(|(peer, count, _)| (peer, count))
+            .collect();
+
+        (counts.total, counts.peers.len(), top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16) -> Option<SocketAddr> {
+        Some(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    #[test]
+    fn counts_each_peer_and_the_total() {
+        let counter = PeerCounter::new(10);
+        for port in [1, 2, 1, 3, 1, 2] {
+            counter.record(peer(port));
+        }
+        // 没有对端地址的请求只计入总数
+        counter.record(None);
+
+        let (total, unique, top) = counter.snapshot(2);
+        assert_eq!((total, unique), (7, 3));
+        assert_eq!(top, [(peer(1).unwrap(), 3), (peer(2).unwrap(), 2)]);
+    }
+
+    #[test]
+    fn ties_put_the_most_recent_peer_first() {
+        let counter = PeerCounter::new(10);
+        for port in [1, 2, 3] {
+            counter.record(peer(port));
+        }
+
+        let (_, _, top) = counter.snapshot(10);
+        let ports: Vec<_> = top.iter().map(|(peer, _)| peer.port()).collect();
+        assert_eq!(ports, [3, 2, 1]);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_seen_peer() {
+        let counter = PeerCounter::new(2);
+        counter.record(peer(1));
+        counter.record(peer(1));
+        counter.record(peer(2));
+        // 1 比 2 更近出现过, 加入 3 时淘汰 2
+        counter.record(peer(1));
+        counter.record(peer(3));
+
+        let (total, unique, top) = counter.snapshot(10);
+        assert_eq!((total, unique), (5, 2));
+        assert_eq!(top, [(peer(1).unwrap(), 3), (peer(3).unwrap(), 1)]);
+
+        // 被淘汰的对端再次出现时从 0 开始计数; 这时最久没有出现的是 1, 即使它的问候数最多
+        counter.record(peer(2));
+        let (total, unique, top) = counter.snapshot(10);
+        assert_eq!((total, unique), (6, 2));
+        assert_eq!(top, [(peer(2).unwrap(), 1), (peer(3).unwrap(), 1)]);
+    }
+}
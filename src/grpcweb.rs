@@ -0,0 +1,232 @@
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tonic::{
+    body::{empty_body, BoxBody},
+    codegen::{
+        http::{self, header, HeaderName, HeaderValue, StatusCode},
+        BoxFuture, Service,
+    },
+    server::NamedService,
+    transport::Body,
+};
+use tonic_web::{GrpcWebLayer, GrpcWebService};
+use tower::Layer;
+use tower_http::cors::{AllowOrigin, Cors, CorsLayer};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// 与 tonic_web::enable 相同
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const EXPOSED_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+const ALLOWED_HEADERS: [&str; 4] = ["x-grpc-web", "content-type", "x-user-agent", "grpc-timeout"];
+
+// 让浏览器不经过代理直接调用服务; allowed_origins 为 None 时接受任意 Origin
+#[derive(Debug, Clone, Default)]
+pub struct GrpcWebConfig {
+    allowed_origins: Option<Arc<Vec<HeaderValue>>>,
+}
+
+impl GrpcWebConfig {
+    // 无法作为 header 的 origin 会被忽略
+    pub fn with_allowed_origins<I, O>(origins: I) -> Self
+    where
+        I: IntoIterator<Item = O>,
+        O: AsRef<str>,
+    {
+        let origins = origins
+            .into_iter()
+            .filter_map(|origin| HeaderValue::from_str(origin.as_ref().trim()).ok())
+            .collect();
+
+        GrpcWebConfig {
+            allowed_origins: Some(Arc::new(origins)),
+        }
+    }
+
+    pub fn enable<S>(&self, service: S) -> GrpcWeb<S>
+    where
+        S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+        S: Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<BoxError> + Send,
+    {
+        let allow_origin = match &self.allowed_origins {
+            Some(origins) => AllowOrigin::list(origins.iter().cloned()),
+            None => AllowOrigin::mirror_request(),
+        };
+        let cors = CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_credentials(true)
+            .max_age(MAX_AGE)
+            .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+            .allow_headers(ALLOWED_HEADERS.map(HeaderName::from_static));
+
+        GrpcWeb {
+            inner: cors.layer(GrpcWebLayer::new().layer(service)),
+            allowed_origins: self.allowed_origins.clone(),
+        }
+    }
+}
+
+// CORS 只能让浏览器丢弃响应, 请求仍然会被处理; 所以不允许的 Origin 直接返回 403, 不会调用服务
+#[derive(Debug, Clone)]
+pub struct GrpcWeb<S> {
+    inner: Cors<GrpcWebService<S>>,
+    allowed_origins: Option<Arc<Vec<HeaderValue>>>,
+}
+
+impl<S> GrpcWeb<S> {
+    fn is_allowed<B>(&self, request: &http::Request<B>) -> bool {
+        match (&self.allowed_origins, request.headers().get(header::ORIGIN)) {
+            (Some(origins), Some(origin)) => origins.contains(origin),
+            // 没有 Origin 的不是浏览器发出的跨域请求
+            _ => true,
+        }
+    }
+}
+
+impl<S> Service<http::Request<Body>> for GrpcWeb<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+    S: Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        if !self.is_allowed(&request) {
+            tracing::warn!(origin = ?request.headers().get(header::ORIGIN), "grpc-web origin rejected");
+            let mut response = http::Response::new(empty_body());
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            return Box::pin(async move { Ok(response) });
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
+
+impl<S: NamedService> NamedService for GrpcWeb<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use tonic::codegen::{Body as _, Bytes};
+    use tonic_health::pb::{
+        health_check_response::ServingStatus, HealthCheckRequest, HealthCheckResponse,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const ALLOWED: &str = "http://allowed.test";
+
+    // 单个 protobuf 消息的 grpc-web 帧: 标志位 + 4 字节长度 + 消息
+    fn frame(message: &impl Message) -> Bytes {
+        let message = message.encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&message);
+        frame.into()
+    }
+
+    fn health_check(origin: &str) -> http::Request<Body> {
+        http::Request::post("/grpc.health.v1.Health/Check")
+            .header(header::ORIGIN, origin)
+            .header(header::CONTENT_TYPE, "application/grpc-web+proto")
+            .header("x-grpc-web", "1")
+            .body(Body::from(frame(&HealthCheckRequest::default())))
+            .unwrap()
+    }
+
+    fn preflight(origin: &str) -> http::Request<Body> {
+        http::Request::options("/grpc.health.v1.Health/Check")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type,x-grpc-web",
+            )
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn call(request: http::Request<Body>) -> http::Response<BoxBody> {
+        let (_, health_service) = tonic_health::server::health_reporter();
+        GrpcWebConfig::with_allowed_origins([ALLOWED])
+            .enable(health_service)
+            .oneshot(request)
+            .await
+            .unwrap()
+    }
+
+    async fn body_bytes(response: http::Response<BoxBody>) -> Vec<u8> {
+        let mut body = response.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            bytes.extend_from_slice(&chunk.unwrap());
+        }
+        bytes
+    }
+
+    #[tokio::test]
+    async fn grpc_web_post_reaches_the_service() {
+        let response = call(health_check(ALLOWED)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            ALLOWED
+        );
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/grpc-web+proto"
+        );
+
+        // 先是消息帧, 然后是编码在响应体里的 trailers 帧
+        let bytes = body_bytes(response).await;
+        assert_eq!(bytes[0], 0);
+        let len = u32::from_be_bytes(bytes[1..5].try_into().unwrap()) as usize;
+        let reply = HealthCheckResponse::decode(&bytes[5..5 + len]).unwrap();
+        assert_eq!(reply.status(), ServingStatus::Serving);
+
+        let trailers = &bytes[5 + len..];
+        assert_eq!(trailers[0], 0x80);
+        assert!(String::from_utf8_lossy(&trailers[5..]).contains("grpc-status:0"));
+    }
+
+    #[tokio::test]
+    async fn preflight_from_a_disallowed_origin_is_rejected() {
+        let response = call(preflight("http://evil.test")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // 同样的预检来自允许的 Origin 时可以通过
+        let response = call(preflight(ALLOWED)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            ALLOWED
+        );
+    }
+
+    #[tokio::test]
+    async fn post_from_a_disallowed_origin_never_reaches_the_service() {
+        let response = call(health_check("http://evil.test")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(body_bytes(response).await.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use tonic::server::NamedService;
+use tonic_health::{server::HealthReporter, ServingStatus};
+
+// 整体状态使用的服务名
+const OVERALL: &str = "";
+
+// grpc.health.v1.Health 中各服务的状态; 以后的管理接口可以用它单独切换某个服务
+#[derive(Clone)]
+pub struct HealthHandle {
+    reporter: HealthReporter,
+    // 注册过的服务名, 关闭时全部切换为 NOT_SERVING
+    services: Vec<&'static str>,
+}
+
+impl HealthHandle {
+    pub fn new(reporter: HealthReporter) -> Self {
+        HealthHandle {
+            reporter,
+            services: Vec::new(),
+        }
+    }
+
+    // 服务加入 Server 后调用, 报告为 SERVING
+    pub async fn register<S: NamedService>(&mut self) {
+        self.services.push(S::NAME);
+        self.reporter.set_serving::<S>().await;
+    }
+
+    // 未注册的服务也可以设置, 客户端查询时按设置返回
+    pub async fn set_serving(&mut self, service: &str, serving: bool) {
+        let status = if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        self.reporter.set_service_status(service, status).await;
+    }
+
+    // 开始关闭时调用, 在连接断开之前让负载均衡摘掉这个实例
+    pub async fn shutting_down(&mut self) {
+        for service in self.services.clone() {
+            self.set_serving(service, false).await;
+        }
+        self.set_serving(OVERALL, false).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::transport::{server::TcpIncoming, Channel, Endpoint, Server};
+    use tonic_health::pb::{
+        health_check_response::ServingStatus as Reported, health_client::HealthClient,
+        HealthCheckRequest,
+    };
+
+    use super::*;
+
+    struct Dummy;
+
+    impl NamedService for Dummy {
+        const NAME: &'static str = "test.Dummy";
+    }
+
+    async fn health_client() -> (HealthHandle, HealthClient<Channel>) {
+        let (reporter, health_service) = tonic_health::server::health_reporter();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(health_service)
+                .serve_with_incoming(incoming),
+        );
+        let channel = Endpoint::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+
+        (HealthHandle::new(reporter), HealthClient::new(channel))
+    }
+
+    async fn status(client: &mut HealthClient<Channel>, service: &str) -> Reported {
+        client
+            .check(HealthCheckRequest {
+                service: service.to_string(),
+            })
+            .await
+            .unwrap()
+            .into_inner()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn status_toggles_between_serving_and_not_serving() {
+        let (mut health, mut client) = health_client().await;
+        health.register::<Dummy>().await;
+        assert_eq!(status(&mut client, Dummy::NAME).await, Reported::Serving);
+
+        health.set_serving(Dummy::NAME, false).await;
+        assert_eq!(status(&mut client, Dummy::NAME).await, Reported::NotServing);
+        health.set_serving(Dummy::NAME, true).await;
+        assert_eq!(status(&mut client, Dummy::NAME).await, Reported::Serving);
+    }
+
+    #[tokio::test]
+    async fn shutting_down_marks_every_service_not_serving() {
+        let (mut health, mut client) = health_client().await;
+        health.register::<Dummy>().await;
+        assert_eq!(status(&mut client, OVERALL).await, Reported::Serving);
+
+        health.shutting_down().await;
+        for service in [Dummy::NAME, OVERALL] {
+            assert_eq!(
+                status(&mut client, service).await,
+                Reported::NotServing,
+                "{service:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::time::Duration;
+
+use moka::future::Cache;
+use serde::Deserialize;
+use tonic::{metadata::MetadataMap, Status};
+
+#[derive(Debug, Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+}
+
+// 通过 OAuth2 introspection 接口校验引用令牌(非 JWT), 结果缓存 cache_ttl
+#[derive(Debug, Clone)]
+pub struct TokenIntrospector {
+    url: String,
+    client: reqwest::Client,
+    cache: Cache<String, bool>,
+}
+
+impl TokenIntrospector {
+    pub fn new(url: impl Into<String>, cache_ttl: Duration) -> Self {
+        TokenIntrospector {
+            url: url.into(),
+            client: reqwest::Client::new(),
+            cache: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(cache_ttl)
+                .build(),
+        }
+    }
+
+    // 从环境变量 OAUTH2_INTROSPECTION_URL / OAUTH2_INTROSPECTION_CACHE_TTL 构建, 未配置时返回 None
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("OAUTH2_INTROSPECTION_URL").ok()?;
+        let cache_ttl = std::env::var("OAUTH2_INTROSPECTION_CACHE_TTL")
+            .ok()
+            .and_then(|ttl| ttl.parse().ok())
+            .unwrap_or(60);
+
+        Some(Self::new(url, Duration::from_secs(cache_ttl)))
+    }
+
+    // 校验请求中的 bearer 令牌, JWT 格式的令牌不在这里处理
+    pub async fn authorize(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+
+        if is_jwt(token) {
+            return Ok(());
+        }
+
+        if self.is_active(token).await {
+            Ok(())
+        } else {
+            Err(Status::unauthenticated("token is not active"))
+        }
+    }
+
+    async fn is_active(&self, token: &str) -> bool {
+        if let Some(active) = self.cache.get(token).await {
+            return active;
+        }
+
+        match self.introspect(token).await {
+            Ok(active) => {
+                self.cache.insert(token.to_string(), active).await;
+                active
+            }
+            // 网络故障且没有缓存时放行
+            Err(e) => {
+                tracing::warn!(error = %e, "token introspection failed, failing open");
+                true
+            }
+        }
+    }
+
+    async fn introspect(&self, token: &str) -> Result<bool, reqwest::Error> {
+        let response: IntrospectionResponse = self
+            .client
+            .post(&self.url)
+            .form(&[("token", token)])
+            .timeout(Duration::from_secs(5))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.active)
+    }
+}
+
+fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{extract::State, routing::post, Form, Json, Router};
+    use tonic::Code;
+
+    use super::*;
+
+    // 只有 "active-token" 有效的 introspection 服务, 返回 url 和调用次数
+    fn mock_introspection_server() -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/introspect",
+                post(
+                    |State(calls): State<Arc<AtomicUsize>>,
+                     Form(form): Form<HashMap<String, String>>| async move {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        let active = form.get("token").map(String::as_str) == Some("active-token");
+                        Json(serde_json::json!({ "active": active }))
+                    },
+                ),
+            )
+            .with_state(calls.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/introspect", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        (url, calls)
+    }
+
+    fn bearer(token: &str) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        metadata
+    }
+
+    #[tokio::test]
+    async fn active_token_is_accepted() {
+        let (url, _) = mock_introspection_server();
+        let introspector = TokenIntrospector::new(url, Duration::from_secs(60));
+        introspector
+            .authorize(&bearer("active-token"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn inactive_token_is_rejected() {
+        let (url, _) = mock_introspection_server();
+        let introspector = TokenIntrospector::new(url, Duration::from_secs(60));
+        let status = introspector
+            .authorize(&bearer("revoked-token"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn responses_are_cached_until_ttl() {
+        let (url, calls) = mock_introspection_server();
+        let introspector = TokenIntrospector::new(url, Duration::from_millis(200));
+        for _ in 0..3 {
+            introspector
+                .authorize(&bearer("active-token"))
+                .await
+                .unwrap();
+        }
+        // 失效的令牌同样被缓存
+        for _ in 0..2 {
+            assert!(introspector
+                .authorize(&bearer("revoked-token"))
+                .await
+                .is_err());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        introspector
+            .authorize(&bearer("active-token"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_fails_open() {
+        // 端口 1 上没有服务, 连接被拒绝
+        let introspector =
+            TokenIntrospector::new("http://127.0.0.1:1/introspect", Duration::from_secs(60));
+        introspector.authorize(&bearer("any-token")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn cached_result_is_used_when_the_server_goes_away() {
+        let (url, calls) = mock_introspection_server();
+        let introspector = TokenIntrospector::new(url, Duration::from_secs(60));
+        assert!(introspector
+            .authorize(&bearer("revoked-token"))
+            .await
+            .is_err());
+
+        // 指向不可达的地址, 缓存中的结果仍然生效, 不会放行
+        let introspector = TokenIntrospector {
+            url: "http://127.0.0.1:1/introspect".to_string(),
+            ..introspector
+        };
+        assert!(introspector
+            .authorize(&bearer("revoked-token"))
+            .await
+            .is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn missing_token_and_jwt_skip_introspection() {
+        let (url, calls) = mock_introspection_server();
+        let introspector = TokenIntrospector::new(url, Duration::from_secs(60));
+        let status = introspector
+            .authorize(&MetadataMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        introspector
+            .authorize(&bearer("header.payload.signature"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
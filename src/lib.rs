@@ -0,0 +1,7477 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::Duration;
+use std::{
+    cmp,
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use dashmap::DashMap;
+use moka::future::Cache;
+use prost::Message;
+use rayon::prelude::*;
+use regex::Regex;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+use tonic::{
+    metadata::{KeyAndValueRef, MetadataMap, MetadataValue},
+    server::NamedService,
+    service::interceptor::InterceptedService,
+    transport::{server::TcpIncoming, Server, ServerTlsConfig},
+    Code, Request, Response, Status, Streaming,
+};
+use url::Url;
+use uuid::Uuid;
+
+use apikeys::{ApiKeyLayer, ApiKeyName};
+use auth::{BearerAuth, Principal};
+use concurrency::ConcurrencyLayer;
+use config::{ServerConfig, ServiceName, VoteBackend, VoteStoreConfig};
+use conversation::{proto::conversation_server::ConversationServer, ConversationService};
+use dedup::DuplicateFilter;
+use featurestore::{FeatureStore, InMemoryStore, JsonFileStore, StoreError};
+use google_rpc::{bad_request, BadRequest};
+use greet::{
+    greeter_server::{Greeter, GreeterServer},
+    Empty as GreetEmpty, GreetStats, GreetingSummary, HelloReq, HelloResp, PeerGreetCount,
+};
+use greetaudit::AuditLog;
+use greetlimit::RepeatLimiter;
+use greetstats::PeerCounter;
+use grpcweb::GrpcWebConfig;
+use health::HealthHandle;
+use introspection::TokenIntrospector;
+use moderation::{KeywordToxicityScorer, ToxicityScorer};
+use notes::{ChatRoom, Location};
+use peerlimit::PeerLimiter;
+use routeguide::{
+    feature::Category,
+    feature_event::EventType,
+    route_guide_server::{RouteGuide, RouteGuideServer},
+    search_request::Mode as SearchMode,
+    ApproveCorrectionRequest, Correction, CorrectionRequest, CorrectionResponse, Empty,
+    ExportRequest, Feature, FeatureCount, FeatureEvent, FeatureStats, FieldChange, ImportSummary,
+    InterpolateRequest, ListFeaturesRequest, NearestFeature, Point, Rectangle, RouteIssue,
+    RouteNote, RouteSummary, SearchRequest, SnapToRoadRequest, SnapToRoadResponse,
+    UpdateFeatureRequest, UpdateFeatureResponse, ValidateRouteRequest, ValidateRouteResponse,
+    WatchRequest,
+};
+use shutdown::drain;
+use timeouts::TimeoutLayer;
+use tls::{ClientCertInterceptor, ClientCommonName};
+use votelimit::VoteRateLimiter;
+#[cfg(feature = "sqlite")]
+use votestore::SqliteVoteStore;
+use votestore::{InMemoryVoteStore, Tally, VoteStore, VoteStoreError};
+use votewindow::VoteWindows;
+use voting::{
+    top_urls_request::Order as TopOrder,
+    voting_request::Vote,
+    voting_server::{Voting, VotingServer},
+    BatchVotingRequest, BatchVotingResponse, RecentReasonsRequest, ResetRequest, ResetResponse,
+    TopUrlsRequest, VoteCountRequest, VoteCountResponse, VoteEvent, VoteHistoryRequest, VoteReason,
+    VoteStreamSummary, VotingRequest, VotingResponse, VotingResult,
+};
+use watermark::embed_watermark;
+use webhook::WebhookNotifier;
+
+mod apikeys;
+mod auth;
+mod concurrency;
+pub mod config;
+mod conversation;
+mod dedup;
+mod faults;
+mod featurejson;
+mod featurestore;
+pub mod framing;
+mod greetaudit;
+mod greetlimit;
+mod greetstats;
+mod grpcweb;
+mod health;
+mod introspection;
+mod locale;
+mod moderation;
+mod notes;
+mod peerlimit;
+pub mod shutdown;
+pub mod tcp_client;
+mod timeouts;
+mod tls;
+mod votelimit;
+mod votestore;
+mod votewindow;
+pub mod watermark;
+mod webhook;
+pub mod ws_client;
+
+pub mod voting {
+    include!("../protos/voting.rs");
+}
+
+pub mod greet {
+    include!("../protos/hello.rs");
+}
+
+pub mod routeguide {
+    include!("../protos/tutorial.rs");
+}
+
+// build.rs 生成的所有 proto 的描述
+#[cfg(feature = "reflection")]
+const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/netsrv_descriptor.bin"));
+
+pub mod google_rpc {
+    include!("../protos/google.rpc.rs");
+}
+
+#[derive(Debug)]
+pub struct VotingService {
+    // 未配置时不校验投票人身份
+    introspector: Option<TokenIntrospector>,
+    // 未配置时不检查重复投票
+    duplicates: Option<DuplicateFilter>,
+    votes: Arc<dyn VoteStore>,
+    // 每个 url 最近 MAX_RECENT_REASONS 条投票理由, 新的在前
+    reasons: DashMap<String, VecDeque<VoteReason>>,
+    // 按时间分桶的票数, 用于 window_seconds 查询
+    windows: Arc<VoteWindows>,
+    // 每个用户最近 history_limit 次投票, 新的在前
+    history: DashMap<String, VecDeque<VoteEvent>>,
+    history_limit: usize,
+    // 为 true 时拒绝没有 x-user-id 的投票
+    require_user_id: bool,
+    // 未配置时不限制同一用户对同一 url 的投票频率
+    rate_limit: Option<VoteRateLimiter>,
+    // (用户, idempotency key) 对应的请求和第一次的结果, 未配置时忽略 x-idempotency-key;
+    // 不同用户的 key 互不影响, 匿名投票共用 None
+    idempotency: Option<IdempotencyCache>,
+    // 未配置时 ResetVotes 总是返回 PERMISSION_DENIED
+    admin_token: Option<String>,
+    // 未配置时不发送越过阈值的通知
+    webhook: Option<WebhookNotifier>,
+}
+
+impl Default for VotingService {
+    fn default() -> Self {
+        VotingService::new(Arc::new(InMemoryVoteStore::default()))
+    }
+}
+
+impl VotingService {
+    pub fn new(votes: Arc<dyn VoteStore>) -> Self {
+        VotingService {
+            introspector: None,
+            duplicates: None,
+            votes,
+            reasons: DashMap::new(),
+            windows: Arc::new(VoteWindows::default()),
+            history: DashMap::new(),
+            history_limit: DEFAULT_HISTORY_PER_USER,
+            require_user_id: false,
+            rate_limit: None,
+            idempotency: None,
+            admin_token: None,
+            webhook: None,
+        }
+    }
+
+    // 计数保存在内存中, 启动时从 path 恢复, 之后每隔 interval 写回, 直到 shutdown 取消; 需要在 tokio 运行时中调用
+    pub fn with_snapshot(
+        path: impl Into<PathBuf>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> Self {
+        let votes = Arc::new(InMemoryVoteStore::with_snapshot(path));
+        votes.clone().spawn_flusher(interval, shutdown);
+        VotingService::new(votes)
+    }
+
+    // 把计数写入存储, 关闭服务前调用
+    pub fn flush(&self) -> Result<(), VoteStoreError> {
+        self.votes.flush()
+    }
+
+    // 定期清理超出最长窗口的分桶, 直到 shutdown 取消
+    pub fn spawn_window_pruner(
+        &self,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> JoinHandle<()> {
+        self.windows.clone().spawn_pruner(interval, shutdown)
+    }
+
+    pub fn with_webhook(mut self, webhook: Option<WebhookNotifier>) -> Self {
+        self.webhook = webhook;
+        self
+    }
+
+    pub fn with_admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+
+    // 请求带有 "x-admin-token: <admin_token>" 时才是管理员
+    fn is_admin(&self, metadata: &MetadataMap) -> bool {
+        let Some(admin_token) = self.admin_token.as_deref() else {
+            return false;
+        };
+
+        non_empty_header(metadata, ADMIN_TOKEN_HEADER).is_some_and(|token| token == admin_token)
+    }
+
+    pub fn with_history_limit(mut self, history_limit: usize) -> Self {
+        self.history_limit = history_limit.max(1);
+        self
+    }
+
+    pub fn with_introspector(mut self, introspector: Option<TokenIntrospector>) -> Self {
+        self.introspector = introspector;
+        self
+    }
+
+    pub fn with_duplicate_filter(mut self, duplicates: Option<DuplicateFilter>) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    pub fn with_required_user_id(mut self, require_user_id: bool) -> Self {
+        self.require_user_id = require_user_id;
+        self
+    }
+
+    pub fn with_vote_window(mut self, window: Option<Duration>) -> Self {
+        self.rate_limit = window.map(VoteRateLimiter::new);
+        self
+    }
+
+    pub fn with_idempotency_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.idempotency = ttl.map(|ttl| {
+            Cache::builder()
+                .max_capacity(IDEMPOTENCY_CACHE_CAPACITY)
+                .time_to_live(ttl)
+                .build()
+        });
+        self
+    }
+
+    // vote 与 stream_votes 共用的单票逻辑, user_id 为请求中的 x-user-id
+    #[allow(clippy::result_large_err)]
+    fn apply_vote(
+        &self,
+        user_id: Option<&str>,
+        req: &VotingRequest,
+    ) -> Result<VotingResponse, Status> {
+        let vote = self.check_vote(user_id, req)?;
+        let tally = match user_id {
+            Some(user_id) => self
+                .votes
+                .record_user(user_id, &req.url, vote)
+                .map_err(vote_store_status)?,
+            None => Some(
+                self.votes
+                    .record(&req.url, vote)
+                    .map_err(vote_store_status)?,
+            ),
+        };
+
+        self.recorded_vote(user_id, req, vote, tally)
+    }
+
+    // 计票前的校验, 包括频率限制和重复投票检查
+    #[allow(clippy::result_large_err)]
+    fn check_vote(&self, user_id: Option<&str>, req: &VotingRequest) -> Result<Vote, Status> {
+        if let Some(status) = invalid_vote_url(&req.url) {
+            return Err(status);
+        }
+        if req.reason.chars().count() > MAX_REASON_CHARS {
+            return Err(bad_request(
+                "reason",
+                &format!("must be at most {} characters", MAX_REASON_CHARS),
+            ));
+        }
+        let vote = parse_vote(req.vote)?;
+
+        match user_id {
+            Some(user_id) => {
+                if let Some(Err(retry_after)) = self
+                    .rate_limit
+                    .as_ref()
+                    .map(|limit| limit.check(user_id, &req.url))
+                {
+                    return Err(rate_limited("voting on this url too often", retry_after));
+                }
+            }
+            None if self.require_user_id => {
+                return Err(Status::unauthenticated("missing x-user-id"));
+            }
+            None => {
+                if let Some(duplicates) = &self.duplicates {
+                    if req.voter_id.is_empty() {
+                        return Err(Status::invalid_argument("missing field: voter_id"));
+                    }
+                    if duplicates.check_and_record(&req.voter_id, &req.url) {
+                        return Err(Status::already_exists("vote already recorded"));
+                    }
+                }
+            }
+        }
+
+        Ok(vote)
+    }
+
+    // 计票后更新时间窗口、通知、投票历史和理由; tally 为 None 表示与该用户上一次投的票相同
+    #[allow(clippy::result_large_err)]
+    fn recorded_vote(
+        &self,
+        user_id: Option<&str>,
+        req: &VotingRequest,
+        vote: Vote,
+        tally: Option<Tally>,
+    ) -> Result<VotingResponse, Status> {
+        let tally = tally.ok_or_else(|| Status::already_exists("vote already recorded"))?;
+
+        self.windows.record(&req.url, vote);
+        if let Some(webhook) = &self.webhook {
+            webhook.publish(&req.url, tally);
+        }
+        if let Some(user_id) = user_id {
+            let mut history = self.history.entry(user_id.to_string()).or_default();
+            history.push_front(VoteEvent {
+                url: req.url.clone(),
+                vote: req.vote,
+                timestamp_millis: featurestore::now_millis(),
+            });
+            history.truncate(self.history_limit);
+        }
+        if !req.reason.is_empty() {
+            let mut reasons = self.reasons.entry(req.url.clone()).or_default();
+            reasons.push_front(VoteReason {
+                reason: req.reason.clone(),
+                vote: req.vote,
+                timestamp_millis: featurestore::now_millis(),
+            });
+            reasons.truncate(MAX_RECENT_REASONS);
+        }
+
+        Ok(voting_response(&req.url, vote, tally, false))
+    }
+}
+
+// confirmation 由结构化的字段生成, revoked 为 true 时是 unvote 的结果
+fn voting_response(url: &str, vote: Vote, tally: Tally, revoked: bool) -> VotingResponse {
+    let action = match (vote, revoked) {
+        (Vote::Up, false) => "upvoted",
+        (Vote::Down, false) => "downvoted",
+        (Vote::Abstain, false) => "abstained",
+        (Vote::Up, true) => "revoked upvote",
+        (Vote::Down, true) => "revoked downvote",
+        (Vote::Abstain, true) => "revoked abstention",
+    };
+
+    VotingResponse {
+        confirmation: format!("{} for {}", action, url),
+        up_count: tally.up,
+        down_count: tally.down,
+        abstain_count: tally.abstain,
+        url: url.to_string(),
+        vote: vote.into(),
+        accepted: true,
+    }
+}
+
+// 新增的票值在服务端支持之前返回 UNIMPLEMENTED, 并给出收到的值
+#[allow(clippy::result_large_err)]
+fn parse_vote(value: i32) -> Result<Vote, Status> {
+    Vote::from_i32(value)
+        .ok_or_else(|| Status::unimplemented(format!("unsupported vote value {}", value)))
+}
+
+fn vote_count(url: String, tally: Tally) -> VoteCountResponse {
+    VoteCountResponse {
+        url,
+        up_count: tally.up,
+        down_count: tally.down,
+        abstain_count: tally.abstain,
+        total: tally.total(),
+    }
+}
+
+fn vote_store_status(e: VoteStoreError) -> Status {
+    Status::internal(e.to_string())
+}
+
+// watch_votes 发送缓冲, 客户端读得慢时中间的计数会被合并
+const WATCH_VOTES_BUFFER: usize = 16;
+
+// 第一条是订阅时的计数, 之后只发送最新的计数
+async fn forward_vote_counts(
+    mut receiver: watch::Receiver<Tally>,
+    url: String,
+    tx: mpsc::Sender<Result<VoteCountResponse, Status>>,
+) {
+    loop {
+        let count = vote_count(url.clone(), *receiver.borrow_and_update());
+        if tx.send(Ok(count)).await.is_err() {
+            return;
+        }
+
+        tokio::select! {
+            changed = receiver.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            // 客户端断开后不再等待下一次投票
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+// top_urls 一次最多返回的 url 数
+const MAX_TOP_URLS: u32 = 1000;
+
+// retry-after 为需要等待的秒数, 向上取整
+fn rate_limited(message: &str, retry_after: Duration) -> Status {
+    let mut status = Status::resource_exhausted(message);
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    status
+        .metadata_mut()
+        .insert("retry-after", MetadataValue::from(seconds));
+    status
+}
+
+// 每个用户默认保留的投票记录条数
+const DEFAULT_HISTORY_PER_USER: usize = 100;
+
+// 投票理由的最大字符数
+const MAX_REASON_CHARS: usize = 500;
+
+// 每个 url 保留的投票理由条数
+const MAX_RECENT_REASONS: usize = 20;
+
+// 一次 batch_vote 最多处理的票数
+const MAX_BATCH_VOTES: usize = 1000;
+
+// 标识投票用户的 metadata, 同一用户对同一 url 只算最后一次投的票
+const USER_ID_HEADER: &str = "x-user-id";
+
+const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+// 重试时带上相同的 key, 在 ttl 内返回第一次的结果而不重复计票
+const IDEMPOTENCY_KEY_HEADER: &str = "x-idempotency-key";
+
+// 最多记住的 idempotency key 数
+const IDEMPOTENCY_CACHE_CAPACITY: u64 = 100_000;
+
+type IdempotencyCache = Cache<(Option<String>, String), Arc<(VotingRequest, VotingResponse)>>;
+
+// 依次使用客户端证书的 CN、bearer 令牌对应的 principal, 都没有时才使用 x-user-id
+fn user_id<T>(request: &Request<T>) -> Option<&str> {
+    let extensions = request.extensions();
+    if let Some(ClientCommonName(common_name)) = extensions.get() {
+        return Some(common_name);
+    }
+    if let Some(Principal(principal)) = extensions.get() {
+        return Some(principal);
+    }
+
+    non_empty_header(request.metadata(), USER_ID_HEADER)
+}
+
+fn non_empty_header<'a>(metadata: &'a MetadataMap, name: &str) -> Option<&'a str> {
+    metadata
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+}
+
+#[tonic::async_trait]
+impl Voting for VotingService {
+    async fn vote(
+        &self,
+        request: Request<VotingRequest>,
+    ) -> Result<Response<VotingResponse>, Status> {
+        if let Some(introspector) = &self.introspector {
+            introspector.authorize(request.metadata()).await?;
+        }
+
+        let user_id = user_id(&request);
+        let req = request.get_ref();
+        let key = non_empty_header(request.metadata(), IDEMPOTENCY_KEY_HEADER);
+        let Some((responses, key)) = self.idempotency.as_ref().zip(key) else {
+            return Ok(Response::new(self.apply_vote(user_id, req)?));
+        };
+
+        // 同一个 key 的并发请求只有一个会计票, 失败的结果不缓存, 可以用同一个 key 重试
+        let entry = responses
+            .entry((user_id.map(str::to_string), key.to_string()))
+            .or_try_insert_with(async {
+                self.apply_vote(user_id, req)
+                    .map(|response| Arc::new((req.clone(), response)))
+            })
+            .await
+            .map_err(|status| Status::clone(&status))?;
+        let fresh = entry.is_fresh();
+        let (original, response) = &*entry.into_value();
+        if !fresh && original != req {
+            return Err(Status::aborted(format!(
+                "{} was already used for a different vote",
+                IDEMPOTENCY_KEY_HEADER
+            )));
+        }
+
+        Ok(Response::new(response.clone()))
+    }
+
+    async fn unvote(
+        &self,
+        request: Request<VotingRequest>,
+    ) -> Result<Response<VotingResponse>, Status> {
+        if let Some(introspector) = &self.introspector {
+            introspector.authorize(request.metadata()).await?;
+        }
+
+        let req: &VotingRequest = request.get_ref();
+        if let Some(status) = invalid_vote_url(&req.url) {
+            return Err(status);
+        }
+        let vote = parse_vote(req.vote)?;
+
+        let no_votes =
+            || Status::failed_precondition(format!("no votes to revoke for {}", req.url));
+        let revoked = match user_id(&request) {
+            // 只撤销该用户自己投的票
+            Some(user_id) => self.votes.unvote_user(user_id, &req.url, vote),
+            None if self.require_user_id => {
+                return Err(Status::unauthenticated("missing x-user-id"));
+            }
+            None => self.votes.unvote(&req.url, vote),
+        };
+        let tally = revoked.map_err(vote_store_status)?.ok_or_else(no_votes)?;
+        if let Some(webhook) = &self.webhook {
+            webhook.publish(&req.url, tally);
+        }
+
+        Ok(Response::new(voting_response(&req.url, vote, tally, true)))
+    }
+
+    async fn batch_vote(
+        &self,
+        request: Request<BatchVotingRequest>,
+    ) -> Result<Response<BatchVotingResponse>, Status> {
+        if let Some(introspector) = &self.introspector {
+            introspector.authorize(request.metadata()).await?;
+        }
+
+        let user_id = user_id(&request);
+        let votes = &request.get_ref().votes;
+        if votes.len() > MAX_BATCH_VOTES {
+            return Err(Status::invalid_argument(format!(
+                "batch of {} votes exceeds the limit of {}",
+                votes.len(),
+                MAX_BATCH_VOTES
+            )));
+        }
+
+        // 先逐票校验, 通过校验的票在一次 record_batch 中计入;
+        // 同一批中同一用户对同一 url 的票与依次调用 vote 的结果相同
+        #[allow(clippy::result_large_err)]
+        let checked: Vec<_> = votes
+            .iter()
+            .map(|vote| self.check_vote(user_id, vote))
+            .collect();
+        let accepted: Vec<_> = votes
+            .iter()
+            .zip(&checked)
+            .filter_map(|(req, vote)| Some((req.url.as_str(), *vote.as_ref().ok()?)))
+            .collect();
+        let mut tallies = self
+            .votes
+            .record_batch(user_id, &accepted)
+            .map_err(vote_store_status)?
+            .into_iter();
+
+        #[allow(clippy::result_large_err)]
+        let results = votes
+            .iter()
+            .zip(checked)
+            .enumerate()
+            .map(|(index, (req, checked))| {
+                let index = index as u32;
+                let recorded = checked.and_then(|vote| {
+                    let tally = tallies.next().flatten();
+                    self.recorded_vote(user_id, req, vote, tally)
+                });
+                match recorded {
+                    Ok(vote) => VotingResult {
+                        index,
+                        message: vote.confirmation,
+                        up_count: vote.up_count,
+                        down_count: vote.down_count,
+                        abstain_count: vote.abstain_count,
+                        accepted: vote.accepted,
+                        ..Default::default()
+                    },
+                    Err(status) => VotingResult {
+                        index,
+                        code: status.code() as i32,
+                        message: status.message().to_string(),
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+
+        Ok(Response::new(BatchVotingResponse { results }))
+    }
+
+    async fn stream_votes(
+        &self,
+        request: Request<Streaming<VotingRequest>>,
+    ) -> Result<Response<VoteStreamSummary>, Status> {
+        if let Some(introspector) = &self.introspector {
+            introspector.authorize(request.metadata()).await?;
+        }
+
+        let user_id = user_id(&request).map(str::to_string);
+        let mut stream = request.into_inner();
+        let mut summary = VoteStreamSummary::default();
+        let now = Instant::now();
+
+        // 每票收到即计入, 客户端中途断开时已处理的票保留
+        while let Some(next) = stream.next().await {
+            let vote = match next {
+                Ok(vote) => vote,
+                Err(status) => {
+                    tracing::warn!(accepted = summary.accepted, error = %status, "stream_votes ended early");
+                    break;
+                }
+            };
+            match self.apply_vote(user_id.as_deref(), &vote) {
+                Ok(_) => summary.accepted += 1,
+                Err(status) => {
+                    summary.rejected += 1;
+                    *summary
+                        .rejected_by_reason
+                        .entry(format!("{:?}", status.code()))
+                        .or_default() += 1;
+                }
+            }
+        }
+        summary.elapsed_time_millis = now.elapsed().as_millis() as i64;
+
+        Ok(Response::new(summary))
+    }
+
+    type WatchVotesStream =
+        Pin<Box<dyn Stream<Item = Result<VoteCountResponse, Status>> + Send + 'static>>;
+
+    async fn watch_votes(
+        &self,
+        request: Request<VoteCountRequest>,
+    ) -> Result<Response<Self::WatchVotesStream>, Status> {
+        let url = request.into_inner().url;
+        if let Some(status) = invalid_vote_url(&url) {
+            return Err(status);
+        }
+
+        let receiver = self.votes.watch(&url).map_err(vote_store_status)?;
+
+        let (tx, rx) = mpsc::channel(WATCH_VOTES_BUFFER);
+        tokio::spawn(forward_vote_counts(receiver, url, tx));
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchVotesStream
+        ))
+    }
+
+    type TopUrlsStream = Self::WatchVotesStream;
+
+    async fn top_urls(
+        &self,
+        request: Request<TopUrlsRequest>,
+    ) -> Result<Response<Self::TopUrlsStream>, Status> {
+        let req = request.into_inner();
+        if req.limit == 0 || req.limit > MAX_TOP_URLS {
+            return Err(Status::invalid_argument(format!(
+                "limit must be between 1 and {}",
+                MAX_TOP_URLS
+            )));
+        }
+        let order = TopOrder::from_i32(req.order)
+            .ok_or_else(|| Status::invalid_argument("unknown order"))?;
+
+        let counts = self
+            .votes
+            .top_n(req.limit as usize, order)
+            .map_err(vote_store_status)?
+            .into_iter()
+            .map(|(url, tally)| vote_count(url, tally));
+
+        Ok(Response::new(
+            Box::pin(tokio_stream::iter(counts.map(Ok))) as Self::TopUrlsStream
+        ))
+    }
+
+    type GetRecentReasonsStream =
+        Pin<Box<dyn Stream<Item = Result<VoteReason, Status>> + Send + 'static>>;
+
+    async fn get_recent_reasons(
+        &self,
+        request: Request<RecentReasonsRequest>,
+    ) -> Result<Response<Self::GetRecentReasonsStream>, Status> {
+        let url = request.into_inner().url;
+        if let Some(status) = invalid_vote_url(&url) {
+            return Err(status);
+        }
+
+        let reasons: Vec<_> = self
+            .reasons
+            .get(&url)
+            .map(|reasons| reasons.iter().cloned().collect())
+            .unwrap_or_default();
+
+        Ok(Response::new(
+            Box::pin(tokio_stream::iter(reasons.into_iter().map(Ok)))
+                as Self::GetRecentReasonsStream,
+        ))
+    }
+
+    type GetVoteHistoryStream =
+        Pin<Box<dyn Stream<Item = Result<VoteEvent, Status>> + Send + 'static>>;
+
+    async fn get_vote_history(
+        &self,
+        request: Request<VoteHistoryRequest>,
+    ) -> Result<Response<Self::GetVoteHistoryStream>, Status> {
+        let req = request.into_inner();
+        let limit = match req.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+
+        let events: Vec<_> = self
+            .history
+            .get(&req.user_id)
+            .map(|history| history.iter().take(limit).cloned().collect())
+            .unwrap_or_default();
+
+        Ok(Response::new(
+            Box::pin(tokio_stream::iter(events.into_iter().map(Ok))) as Self::GetVoteHistoryStream,
+        ))
+    }
+
+    async fn reset_votes(
+        &self,
+        request: Request<ResetRequest>,
+    ) -> Result<Response<ResetResponse>, Status> {
+        if !self.is_admin(request.metadata()) {
+            return Err(Status::permission_denied("admin token required"));
+        }
+
+        // 投票理由和投票历史是已经发生的记录, 不清空
+        let url = request.get_ref().url.as_deref();
+        let cleared_count = self.votes.reset(url).map_err(vote_store_status)?;
+        self.windows.clear(url);
+        if let Some(webhook) = &self.webhook {
+            webhook.reset(url);
+        }
+        tracing::info!(url, cleared_count, "votes reset");
+
+        Ok(Response::new(ResetResponse { cleared_count }))
+    }
+
+    async fn get_vote_count(
+        &self,
+        request: Request<VoteCountRequest>,
+    ) -> Result<Response<VoteCountResponse>, Status> {
+        let req = request.into_inner();
+        let window_secs = u64::from(req.window_seconds);
+        if window_secs > votewindow::MAX_WINDOW_SECS {
+            return Err(Status::invalid_argument(format!(
+                "window_seconds must be at most {}",
+                votewindow::MAX_WINDOW_SECS
+            )));
+        }
+
+        let tally = self.votes.get(&req.url).map_err(vote_store_status)?;
+        let tally = match tally {
+            None if req.require_known => {
+                return Err(Status::not_found(format!("no votes for {}", req.url)))
+            }
+            _ if window_secs > 0 => self.windows.count(&req.url, window_secs),
+            tally => tally.unwrap_or_default(),
+        };
+
+        Ok(Response::new(vote_count(req.url, tally)))
+    }
+}
+
+// 投票 url 的最大长度
+const MAX_VOTE_URL_LEN: usize = 2048;
+
+// url 必须是 http 或 https 的绝对地址, 不合法时返回带 BadRequest 详情的 INVALID_ARGUMENT
+fn invalid_vote_url(url: &str) -> Option<Status> {
+    let violation = if url.is_empty() {
+        "must not be empty".to_string()
+    } else if url.len() > MAX_VOTE_URL_LEN {
+        format!("must be at most {} bytes", MAX_VOTE_URL_LEN)
+    } else {
+        match Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => return None,
+            Ok(parsed) => format!(
+                "unsupported scheme {:?}, expected http or https",
+                parsed.scheme()
+            ),
+            Err(e) => format!("not an absolute url: {}", e),
+        }
+    };
+
+    Some(bad_request("url", &violation))
+}
+
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+// 和 tonic-types 一样把 google.rpc.BadRequest 放在 google.rpc.Status 的 details 里
+fn bad_request(field: &str, description: &str) -> Status {
+    let message = format!("invalid {}: {}", field, description);
+    let violations = BadRequest {
+        field_violations: vec![bad_request::FieldViolation {
+            field: field.to_string(),
+            description: description.to_string(),
+        }],
+    };
+    let details = google_rpc::Status {
+        code: Code::InvalidArgument as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: BAD_REQUEST_TYPE_URL.to_string(),
+            value: violations.encode_to_vec(),
+        }],
+    };
+
+    Status::with_details(
+        Code::InvalidArgument,
+        message,
+        details.encode_to_vec().into(),
+    )
+}
+
+// toxicity_score 超过该值的问候会被拒绝
+const TOXICITY_THRESHOLD: f32 = 0.8;
+
+pub struct GreetService {
+    scorer: Arc<dyn ToxicityScorer + Send + Sync>,
+    // 每个响应的水印 id, 也作为 request_number 返回
+    next_request_id: AtomicU64,
+    // 启动时的主机名, 随每个响应返回
+    hostname: String,
+    // say_hello_stream 相邻两条问候之间的间隔
+    stream_delay: Duration,
+    // content 的最大字节数
+    max_content_bytes: usize,
+    // HelloReq.delay_ms 的上限
+    max_delay: Duration,
+    // 为 true 时按 x-fail-with 返回错误, 默认关闭
+    fault_injection: bool,
+    // 带 name 时的问候, {name} 和 {content} 会被替换
+    template: String,
+    // 每个对端的请求数, get_greet_stats 本身不计入
+    peers: PeerCounter,
+    // 限制同一对端重复发送相同的 content, 默认不限制
+    repeat_limit: Option<RepeatLimiter>,
+    // say_hello 的响应, 转发给 watch_greetings 的订阅者
+    greetings: broadcast::Sender<HelloResp>,
+    // 记录每个 say_hello 和 say_hello_stream 请求, 默认不记录
+    audit: Option<AuditLog>,
+    // 为 true 时在 say_hello 的 content 中嵌入 request_id 水印, 默认关闭
+    watermark: bool,
+}
+
+// 订阅者最多落后的问候数, 超过后丢弃最旧的
+const GREETING_WATCH_CAPACITY: usize = 256;
+
+// 最多记住的对端数
+const DEFAULT_MAX_TRACKED_PEERS: usize = 1024;
+// get_greet_stats 返回的对端数
+const TOP_GREET_PEERS: usize = 5;
+
+const DEFAULT_GREETING_TEMPLATE: &str = "Hello, {name}! {content}";
+const MAX_NAME_CHARS: usize = 64;
+
+const DEFAULT_MAX_CONTENT_BYTES: usize = 4 * 1024;
+
+// 请求中带该前缀的 metadata 原样放回响应中
+const ECHO_HEADER_PREFIX: &str = "x-echo-";
+// 最多回显的 metadata 个数和总字节数 (key 加编码后的 value)
+const MAX_ECHO_HEADERS: usize = 16;
+const MAX_ECHO_BYTES: usize = 4 * 1024;
+
+const ACCEPT_LANGUAGE: &str = "accept-language";
+const CONTENT_LANGUAGE: &str = "content-language";
+
+// say_hello_stream 最多返回的问候数
+const MAX_GREETING_COUNT: u32 = 10_000;
+
+// say_hello_stream 发送通道的容量
+const GREETING_STREAM_BUFFER: usize = 16;
+
+impl GreetService {
+    pub fn new(scorer: impl ToxicityScorer + Send + Sync + 'static) -> Self {
+        GreetService {
+            scorer: Arc::new(scorer),
+            next_request_id: AtomicU64::new(1),
+            hostname: hostname(),
+            stream_delay: Duration::from_millis(100),
+            max_content_bytes: DEFAULT_MAX_CONTENT_BYTES,
+            max_delay: Duration::from_secs(10),
+            fault_injection: false,
+            template: DEFAULT_GREETING_TEMPLATE.to_string(),
+            peers: PeerCounter::new(DEFAULT_MAX_TRACKED_PEERS),
+            repeat_limit: None,
+            greetings: broadcast::channel(GREETING_WATCH_CAPACITY).0,
+            audit: None,
+            watermark: false,
+        }
+    }
+
+    pub fn with_watermark(mut self, watermark: bool) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    pub fn with_audit_log(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    // 被拒绝的请求也会记录
+    fn audit(&self, peer: Option<SocketAddr>, content: &str) {
+        if let Some(audit) = &self.audit {
+            audit.record(peer, content);
+        }
+    }
+
+    // (max_repeats, window): 同一对端在 window 内最多发送 max_repeats 次相同的 content
+    pub fn with_repeat_limit(mut self, limit: Option<(u32, Duration)>) -> Self {
+        self.repeat_limit =
+            limit.map(|(max_repeats, window)| RepeatLimiter::new(max_repeats, window));
+        self
+    }
+
+    // 超过限制时返回带 retry-after 的 RESOURCE_EXHAUSTED
+    fn repeated_content(&self, peer: Option<SocketAddr>, content: &str) -> Option<Status> {
+        let limit = self.repeat_limit.as_ref()?;
+        limit
+            .check(peer.map(|peer| peer.ip()), content)
+            .err()
+            .map(|retry_after| rate_limited("same greeting sent too often", retry_after))
+    }
+
+    pub fn with_max_tracked_peers(mut self, max_tracked_peers: usize) -> Self {
+        self.peers = PeerCounter::new(max_tracked_peers);
+        self
+    }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    // 只应在测试环境打开
+    pub fn with_fault_injection(mut self, fault_injection: bool) -> Self {
+        self.fault_injection = fault_injection;
+        self
+    }
+
+    // 关闭时忽略 x-fail-with
+    fn injected_fault(&self, metadata: &MetadataMap) -> Option<Status> {
+        if !self.fault_injection {
+            return None;
+        }
+
+        faults::injected_fault(metadata)
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_max_content_bytes(mut self, max_content_bytes: usize) -> Self {
+        self.max_content_bytes = max_content_bytes;
+        self
+    }
+
+    pub fn with_stream_delay(mut self, stream_delay: Duration) -> Self {
+        self.stream_delay = stream_delay;
+        self
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check_toxicity(&self, content: &str) -> Result<f32, Status> {
+        check_toxicity(&*self.scorer, content)
+    }
+}
+
+// 复制请求中 ECHO_HEADER_PREFIX 开头的 metadata (包括 -bin), 同一个 key 的多个值都保留;
+// 超过 MAX_ECHO_HEADERS 或 MAX_ECHO_BYTES 时返回 RESOURCE_EXHAUSTED
+#[allow(clippy::result_large_err)]
+fn echo_headers(metadata: &MetadataMap) -> Result<MetadataMap, Status> {
+    let mut echoed = MetadataMap::new();
+    let (mut count, mut bytes) = (0, 0);
+    for entry in metadata.iter() {
+        let (key, size) = match &entry {
+            KeyAndValueRef::Ascii(key, value) => (key.as_str(), value.as_encoded_bytes().len()),
+            KeyAndValueRef::Binary(key, value) => (key.as_str(), value.as_encoded_bytes().len()),
+        };
+        if !key.starts_with(ECHO_HEADER_PREFIX) {
+            continue;
+        }
+
+        count += 1;
+        bytes += key.len() + size;
+        if count > MAX_ECHO_HEADERS || bytes > MAX_ECHO_BYTES {
+            return Err(Status::resource_exhausted(format!(
+                "at most {} {}* headers and {} bytes can be echoed",
+                MAX_ECHO_HEADERS, ECHO_HEADER_PREFIX, MAX_ECHO_BYTES
+            )));
+        }
+        match entry {
+            KeyAndValueRef::Ascii(key, value) => {
+                echoed.append(key.clone(), value.clone());
+            }
+            KeyAndValueRef::Binary(key, value) => {
+                echoed.append_bin(key.clone(), value.clone());
+            }
+        }
+    }
+
+    Ok(echoed)
+}
+
+// 空的 name 表示没有 name; 否则只能包含字母、空格和连字符, 且不能只有空白
+fn invalid_name(name: &str) -> Option<Status> {
+    let violation = if name.is_empty() {
+        return None;
+    } else if name.trim().is_empty() {
+        "must not be blank".to_string()
+    } else if name.chars().count() > MAX_NAME_CHARS {
+        format!("must be at most {} characters", MAX_NAME_CHARS)
+    } else if !name
+        .chars()
+        .all(|c| c.is_alphabetic() || c == ' ' || c == '-')
+    {
+        "may only contain letters, spaces and hyphens".to_string()
+    } else {
+        return None;
+    };
+
+    Some(bad_request("name", &violation))
+}
+
+// 先替换 {name}, content 中的 "{name}" 不会被替换
+fn render_greeting(template: &str, name: &str, content: &str) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{content}", content)
+}
+
+// 依次取环境变量 HOSTNAME、/etc/hostname, 都没有时为 "unknown"
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// content 为空 (或只有空白) 或超过 max_bytes 字节时返回带 BadRequest 详情的 INVALID_ARGUMENT
+fn invalid_content(content: &str, max_bytes: usize) -> Option<Status> {
+    if content.trim().is_empty() {
+        return Some(bad_request("content", "must not be empty"));
+    }
+    if content.len() > max_bytes {
+        return Some(bad_request(
+            "content",
+            &format!("is {} bytes, at most {} allowed", content.len(), max_bytes),
+        ));
+    }
+
+    None
+}
+
+// 超过 TOXICITY_THRESHOLD 时返回 INVALID_ARGUMENT, 否则返回分数
+#[allow(clippy::result_large_err)]
+fn check_toxicity(
+    scorer: &(dyn ToxicityScorer + Send + Sync),
+    content: &str,
+) -> Result<f32, Status> {
+    let toxicity_score = scorer.score(content);
+    if toxicity_score > TOXICITY_THRESHOLD {
+        tracing::warn!(toxicity_score, content, "greeting rejected");
+        return Err(Status::invalid_argument("content policy violation"));
+    }
+
+    Ok(toxicity_score)
+}
+
+#[tonic::async_trait]
+impl Greeter for GreetService {
+    // 带 accept-language 时用选中的语言问候, 并在响应的 content-language 中返回该语言; 否则原样返回内容.
+    // 请求中 x-echo- 开头的 metadata 会出现在响应的 metadata 中
+    async fn say_hello(&self, request: Request<HelloReq>) -> Result<Response<HelloResp>, Status> {
+        let peer = request.remote_addr();
+        self.peers.record(peer);
+        self.audit(peer, &request.get_ref().content);
+        if let Some(status) = self.injected_fault(request.metadata()) {
+            return Err(status);
+        }
+        let echoed = echo_headers(request.metadata())?;
+        let locale = request.metadata().get(ACCEPT_LANGUAGE).map(|value| {
+            value
+                .to_str()
+                .map_or(locale::DEFAULT_LOCALE, locale::negotiate)
+        });
+        let api_key = request.extensions().get::<ApiKeyName>().cloned();
+        let req = request.into_inner();
+        let hello_str = req.content;
+        println!("greet from client: {}", hello_str);
+
+        if let Some(status) = invalid_content(&hello_str, self.max_content_bytes) {
+            return Err(status);
+        }
+        if let Some(status) = self.repeated_content(peer, &hello_str) {
+            return Err(status);
+        }
+        if let Some(status) = invalid_name(&req.name) {
+            return Err(status);
+        }
+        let delay = Duration::from_millis(req.delay_ms.into());
+        if delay > self.max_delay {
+            return Err(Status::invalid_argument(format!(
+                "delay_ms must be at most {}",
+                self.max_delay.as_millis()
+            )));
+        }
+        let toxicity_score = self.check_toxicity(&hello_str)?;
+        // 客户端超时后 tonic 会丢弃这个 future, 不会继续等待
+        if !delay.is_zero() {
+            time::sleep(delay).await;
+        }
+
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let api_key = api_key.as_ref().map(|ApiKeyName(name)| name.as_str());
+        if self.watermark {
+            tracing::info!(request_id, api_key, "greeting watermarked");
+        }
+
+        // 带 name 时使用模板, 不再按 accept-language 问候
+        let (content, locale) = match (req.name.as_str(), locale) {
+            ("", Some(locale)) => (locale::greet(locale, &hello_str), Some(locale)),
+            ("", None) => (hello_str, None),
+            (name, _) => (render_greeting(&self.template, name, &hello_str), None),
+        };
+        let resp = HelloResp {
+            content: if self.watermark {
+                embed_watermark(&content, request_id)
+            } else {
+                content
+            },
+            toxicity_score,
+            timestamp_millis: featurestore::now_millis(),
+            hostname: self.hostname.clone(),
+            request_number: request_id,
+            ..Default::default()
+        };
+        // 没有订阅者时 send 返回错误, 忽略即可
+        let _ = self.greetings.send(resp.clone());
+        let mut response = Response::new(resp);
+        *response.metadata_mut() = echoed;
+        if let Some(locale) = locale {
+            response
+                .metadata_mut()
+                .insert(CONTENT_LANGUAGE, MetadataValue::from_static(locale));
+        }
+
+        Ok(response)
+    }
+
+    type SayHelloStreamStream =
+        Pin<Box<dyn Stream<Item = Result<HelloResp, Status>> + Send + 'static>>;
+
+    async fn say_hello_stream(
+        &self,
+        request: Request<HelloReq>,
+    ) -> Result<Response<Self::SayHelloStreamStream>, Status> {
+        let peer = request.remote_addr();
+        self.peers.record(peer);
+        self.audit(peer, &request.get_ref().content);
+        if let Some(status) = self.injected_fault(request.metadata()) {
+            return Err(status);
+        }
+        let req = request.into_inner();
+        println!("greet stream from client: {} x{}", req.content, req.count);
+
+        if req.count > MAX_GREETING_COUNT {
+            return Err(Status::invalid_argument(format!(
+                "count must be at most {}",
+                MAX_GREETING_COUNT
+            )));
+        }
+        if let Some(status) = invalid_content(&req.content, self.max_content_bytes) {
+            return Err(status);
+        }
+        if let Some(status) = self.repeated_content(peer, &req.content) {
+            return Err(status);
+        }
+        let toxicity_score = self.check_toxicity(&req.content)?;
+
+        let (tx, rx) = mpsc::channel(GREETING_STREAM_BUFFER);
+        let delay = self.stream_delay;
+        let hostname = self.hostname.clone();
+        tokio::spawn(async move {
+            for n in 1..=req.count {
+                if n > 1 {
+                    time::sleep(delay).await;
+                }
+                let resp = HelloResp {
+                    content: format!("hello #{} {}", n, req.content),
+                    toxicity_score,
+                    timestamp_millis: featurestore::now_millis(),
+                    hostname: hostname.clone(),
+                    ..Default::default()
+                };
+                // 客户端断开后不再继续
+                if tx.send(Ok(resp)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::SayHelloStreamStream
+        ))
+    }
+
+    async fn lots_of_greetings(
+        &self,
+        request: Request<Streaming<HelloReq>>,
+    ) -> Result<Response<GreetingSummary>, Status> {
+        println!("LotsOfGreetings");
+        self.peers.record(request.remote_addr());
+        if let Some(status) = self.injected_fault(request.metadata()) {
+            return Err(status);
+        }
+
+        let mut stream = request.into_inner();
+        let mut summary = GreetingSummary::default();
+        let mut longest_chars = 0;
+        while let Some(next) = stream.next().await {
+            // details 中放的是已收到部分的汇总, 不再是 BadRequest
+            let failure = match next {
+                Ok(req) => match invalid_content(&req.content, self.max_content_bytes) {
+                    None => Ok(req),
+                    Some(status) => Err(status),
+                },
+                Err(status) => Err(status),
+            };
+            let req = match failure {
+                Ok(req) => req,
+                Err(status) => {
+                    tracing::warn!(greetings = summary.greeting_count, error = %status, "lots_of_greetings failed mid-stream");
+                    return Err(Status::with_details(
+                        status.code(),
+                        status.message(),
+                        summary.encode_to_vec().into(),
+                    ));
+                }
+            };
+
+            summary.greeting_count += 1;
+            summary.total_bytes += req.content.len() as u64;
+            let chars = req.content.chars().count();
+            if summary.greeting_count == 1 || chars > longest_chars {
+                longest_chars = chars;
+                summary.longest_content = req.content;
+            }
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    type GreetChatStream = Pin<Box<dyn Stream<Item = Result<HelloResp, Status>> + Send + 'static>>;
+
+    // 与 route_chat 不同, 只回复发送者本人, 也不重放之前的消息
+    async fn greet_chat(
+        &self,
+        request: Request<Streaming<HelloReq>>,
+    ) -> Result<Response<Self::GreetChatStream>, Status> {
+        println!("GreetChat");
+        self.peers.record(request.remote_addr());
+        if let Some(status) = self.injected_fault(request.metadata()) {
+            return Err(status);
+        }
+
+        let mut stream = request.into_inner();
+        let (tx, rx) = mpsc::channel(GREETING_STREAM_BUFFER);
+        let scorer = self.scorer.clone();
+        let max_content_bytes = self.max_content_bytes;
+        let hostname = self.hostname.clone();
+        tokio::spawn(async move {
+            let mut sequence = 0;
+            while let Some(next) = stream.next().await {
+                let scored = match next {
+                    Ok(req) => match invalid_content(&req.content, max_content_bytes) {
+                        None => check_toxicity(&*scorer, &req.content).map(|score| (req, score)),
+                        Some(status) => Err(status),
+                    },
+                    Err(status) => Err(status),
+                };
+                let resp = match scored {
+                    Ok((req, toxicity_score)) => {
+                        sequence += 1;
+                        HelloResp {
+                            content: format!("hello, {}", req.content),
+                            toxicity_score,
+                            sequence,
+                            timestamp_millis: featurestore::now_millis(),
+                            hostname: hostname.clone(),
+                            ..Default::default()
+                        }
+                    }
+                    // 出错后以该错误结束
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                // 客户端断开后不再继续
+                if tx.send(Ok(resp)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::GreetChatStream
+        ))
+    }
+
+    async fn get_greet_stats(
+        &self,
+        _request: Request<GreetEmpty>,
+    ) -> Result<Response<GreetStats>, Status> {
+        let (total_requests, unique_peers, top_peers) = self.peers.snapshot(TOP_GREET_PEERS);
+
+        Ok(Response::new(GreetStats {
+            total_requests,
+            unique_peers: unique_peers as u64,
+            top_peers: top_peers
+                .into_iter()
+                .map(|(peer, count)| PeerGreetCount {
+                    peer: peer.to_string(),
+                    count,
+                })
+                .collect(),
+        }))
+    }
+
+    type WatchGreetingsStream =
+        Pin<Box<dyn Stream<Item = Result<HelloResp, Status>> + Send + 'static>>;
+
+    async fn watch_greetings(
+        &self,
+        _request: Request<GreetEmpty>,
+    ) -> Result<Response<Self::WatchGreetingsStream>, Status> {
+        println!("WatchGreetings");
+
+        let (tx, rx) = mpsc::channel(GREETING_STREAM_BUFFER);
+        tokio::spawn(forward_greetings(self.greetings.subscribe(), tx));
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchGreetingsStream
+        ))
+    }
+}
+
+// 落后的订阅者收到一条只有 missed 的消息, 而不是被断开
+async fn forward_greetings(
+    mut receiver: broadcast::Receiver<HelloResp>,
+    tx: mpsc::Sender<Result<HelloResp, Status>>,
+) {
+    loop {
+        let resp = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(resp) => resp,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "watch_greetings subscriber lagged");
+                    HelloResp {
+                        missed,
+                        ..Default::default()
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // 客户端断开后不再等待下一条问候
+            _ = tx.closed() => return,
+        };
+
+        if tx.send(Ok(resp)).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RouteGuideService {
+    features: Arc<dyn FeatureStore>,
+    // 待审核的纠错建议, 最多保留 MAX_PENDING_CORRECTIONS 条
+    corrections: Mutex<VecDeque<Correction>>,
+    // 纠错审核接口需要的管理员令牌, 未配置时审核接口不可用
+    admin_token: Option<String>,
+    // get_feature 的匹配半径(米), 0 表示精确匹配
+    tolerance: i32,
+    // list_features 发送通道的容量
+    channel_capacity: usize,
+    // record_route 等待下一个点的最长时间
+    idle_timeout: Duration,
+    // 所有 route_chat 流共享的留言
+    chat: Arc<ChatRoom>,
+    // 设置时, route_chat 客户端在该时间内没有发送留言就向它发送一条空的保活留言
+    keepalive: Option<Duration>,
+    // 是否丢弃与同一位置最后一条留言重复的留言(客户端重试造成)
+    dedupe_notes: bool,
+    // feature 的增删改, 由 watch_features 转发给订阅者
+    feature_events: broadcast::Sender<FeatureChange>,
+    // 每个连接同时进行的 record_route 数
+    route_limiter: Arc<PeerLimiter>,
+}
+
+// 广播给 watch_features 的一次修改, previous 为 UPDATED 修改前的位置
+#[derive(Debug, Clone)]
+struct FeatureChange {
+    event_type: EventType,
+    feature: Arc<Feature>,
+    previous: Option<Point>,
+}
+
+impl FeatureChange {
+    // 没有 rect 或者修改前后的位置有一个在 rect 内
+    fn is_within(&self, rect: Option<&Rectangle>) -> bool {
+        let Some(rect) = rect else {
+            return true;
+        };
+
+        self.feature
+            .location
+            .iter()
+            .chain(self.previous.iter())
+            .any(|location| featurestore::in_rang(location, rect))
+    }
+}
+
+impl RouteGuideService {
+    fn new(features: Arc<dyn FeatureStore>) -> Self {
+        RouteGuideService {
+            features,
+            corrections: Mutex::new(VecDeque::new()),
+            admin_token: None,
+            tolerance: 0,
+            channel_capacity: 5,
+            idle_timeout: Duration::from_secs(30),
+            chat: Arc::new(ChatRoom::new(50, 1024)),
+            keepalive: None,
+            dedupe_notes: false,
+            feature_events: broadcast::channel(FEATURE_EVENT_CAPACITY).0,
+            route_limiter: Arc::new(PeerLimiter::new(4)),
+        }
+    }
+
+    fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity.max(1);
+        self
+    }
+
+    fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    // route_chat 每个位置保留的留言数和最多保留的位置数
+    fn with_note_limits(mut self, notes_per_location: usize, max_note_locations: usize) -> Self {
+        self.chat = Arc::new(ChatRoom::new(notes_per_location, max_note_locations));
+        self
+    }
+
+    fn with_max_routes_per_peer(mut self, max_routes_per_peer: usize) -> Self {
+        self.route_limiter = Arc::new(PeerLimiter::new(max_routes_per_peer));
+        self
+    }
+
+    fn with_note_dedupe(mut self, dedupe_notes: bool) -> Self {
+        self.dedupe_notes = dedupe_notes;
+        self
+    }
+
+    fn with_keepalive(mut self, keepalive: Option<Duration>) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    fn with_tolerance(mut self, tolerance: i32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    fn with_admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token;
+        self
+    }
+
+    // 与 VotingService 相同, 请求带有 "x-admin-token: <admin_token>" 时才是管理员;
+    // authorization 头留给 BearerAuth
+    fn is_admin(&self, metadata: &MetadataMap) -> bool {
+        let Some(admin_token) = self.admin_token.as_deref() else {
+            return false;
+        };
+
+        non_empty_header(metadata, ADMIN_TOKEN_HEADER).is_some_and(|token| token == admin_token)
+    }
+
+    // 没有订阅者时发送失败, 可以忽略
+    fn publish(&self, event_type: EventType, feature: Arc<Feature>, previous: Option<Point>) {
+        let _ = self.feature_events.send(FeatureChange {
+            event_type,
+            feature,
+            previous,
+        });
+    }
+
+    // 一批 feature 只获取一次写锁, 与已有 feature 位置相同的计为 duplicate
+    fn import_batch(
+        &self,
+        batch: &mut Vec<Feature>,
+        summary: &mut ImportSummary,
+    ) -> Result<(), StoreError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let total = batch.len() as i32;
+        let added = self.features.add_all(std::mem::take(batch))?;
+        summary.accepted += added.len() as i32;
+        summary.duplicate += total - added.len() as i32;
+        for feature in added {
+            self.publish(EventType::Added, feature, None);
+        }
+
+        Ok(())
+    }
+
+    // 在 tolerance 半径内查找离 point 最近的 feature
+    fn find_feature(&self, point: &Point) -> Option<Arc<Feature>> {
+        if self.tolerance <= 0 {
+            return self.features.get(point);
+        }
+
+        self.nearest_within(point, self.tolerance)
+    }
+
+    // radius 米范围内离 point 最近的 feature
+    fn nearest_within(&self, point: &Point, radius: i32) -> Option<Arc<Feature>> {
+        self.features
+            .near(point, radius)
+            .into_iter()
+            .filter_map(|feature| {
+                let distance = calc_distance(feature.location.as_ref()?, point);
+                Some((feature, distance))
+            })
+            .filter(|(_, distance)| *distance <= radius)
+            .min_by_key(|(_, distance)| *distance)
+            .map(|(feature, _)| feature)
+    }
+
+    // 点多时放到阻塞线程池里用 rayon 并行计算, 避免占用异步运行时; 连同 points 一起返回以便复用
+    async fn summarize_chunk(
+        &self,
+        points: Vec<Point>,
+        carried: bool,
+    ) -> Result<(Vec<Point>, RouteTotals), Status> {
+        let count = self.features.feature_counter();
+        if points.len() <= PARALLEL_ROUTE_THRESHOLD {
+            let totals = summarize_route(&*count, &points, carried);
+            return Ok((points, totals));
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let totals = summarize_route_parallel(&*count, &points, carried);
+            (points, totals)
+        })
+        .await
+        .map_err(|e| Status::internal(format!("route summary failed: {}", e)))
+    }
+}
+
+#[tonic::async_trait]
+impl RouteGuide for RouteGuideService {
+    async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        println!("GetFeature = {:?}", request);
+
+        match self.find_feature(request.get_ref()) {
+            Some(feature) => Ok(Response::new(Arc::unwrap_or_clone(feature))),
+            None => Ok(Response::new(Feature::default())),
+        }
+    }
+
+    async fn get_nearest_feature(
+        &self,
+        request: Request<Point>,
+    ) -> Result<Response<NearestFeature>, Status> {
+        println!("GetNearestFeature = {:?}", request);
+
+        let point = request.get_ref();
+        let features = self.features.all();
+        let nearest = features
+            .iter()
+            .filter_map(|feature| {
+                let location = feature.location.as_ref()?;
+                Some((feature, calc_distance(location, point)))
+            })
+            .min_by_key(|(_, distance)| *distance);
+
+        match nearest {
+            Some((feature, distance)) => Ok(Response::new(NearestFeature {
+                feature: Some(Feature::clone(feature)),
+                distance_meters: distance as f64,
+            })),
+            None => Err(Status::not_found("feature store is empty")),
+        }
+    }
+
+    type ListFeaturesStream = Pin<Box<dyn Stream<Item = Result<Feature, Status>> + Send + 'static>>;
+
+    async fn list_features(
+        &self,
+        request: Request<ListFeaturesRequest>,
+    ) -> Result<Response<Self::ListFeaturesStream>, Status> {
+        println!("ListFeatures = {:?}", request);
+
+        let deadline = grpc_timeout(request.metadata()).map(|timeout| Instant::now() + timeout);
+        let req = request.into_inner();
+        let rect = req
+            .rect
+            .ok_or_else(|| Status::invalid_argument("missing field: rect"))?;
+        if let Some(status) = missing_corner(&rect) {
+            return Err(status);
+        }
+        let order_from = req.order_from;
+        let offset = parse_page_token(&req.page_token).ok_or_else(|| {
+            Status::invalid_argument(format!("invalid page_token: {:?}", req.page_token))
+        })?;
+        // page_size 为 0 表示不分页
+        let page_size = req.page_size as usize;
+        let at_time = req.at_time;
+        let category = req.category;
+        let tag = req.tag;
+        let name_filter = match req.name_filter.as_str() {
+            "" => None,
+            pattern => Some(
+                Regex::new(pattern)
+                    .map_err(|e| Status::invalid_argument(format!("invalid name_filter: {}", e)))?,
+            ),
+        };
+
+        let (tx, rx) = mpsc::channel::<Result<Arc<Feature>, Status>>(self.channel_capacity);
+        let features = self.features.clone();
+        tokio::spawn(async move {
+            let filtered = features
+                .in_rect(&rect)
+                .into_iter()
+                .filter(|feature| {
+                    name_filter
+                        .as_ref()
+                        .is_none_or(|filter| filter.is_match(&feature.name))
+                })
+                .filter(|feature| at_time == 0 || featurestore::valid_at(feature, at_time))
+                .filter(|feature| {
+                    category == i32::from(Category::Unspecified) || feature.category == category
+                })
+                .filter(|feature| tag.is_empty() || feature.tags.contains(&tag));
+            // 指定 order_from 时需要先收集全部结果再按距离排序, 否则边扫描边发送
+            let mut matched: Box<dyn Iterator<Item = Arc<Feature>> + Send> = match &order_from {
+                None => Box::new(filtered.skip(offset)),
+                Some(from) => {
+                    let mut sorted: Vec<_> = filtered.collect();
+                    sorted.sort_by_cached_key(|feature| {
+                        feature
+                            .location
+                            .as_ref()
+                            .map_or(i32::MAX, |location| calc_distance(location, from))
+                    });
+                    Box::new(sorted.into_iter().skip(offset))
+                }
+            };
+            let mut sent = 0;
+            let mut next_page_token = String::new();
+
+            loop {
+                // 客户端已断开, 剩下的 feature 不必再做范围判断
+                if tx.is_closed() {
+                    println!(" /// client disconnected, stop sending");
+                    return;
+                }
+
+                // trailers 中的 token 指向第一个没有发出的 feature, 客户端可以从这里继续
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    println!(" /// deadline exceeded, stop sending");
+                    let mut trailers = MetadataMap::new();
+                    trailers.insert(
+                        NEXT_PAGE_TOKEN,
+                        (offset + sent).to_string().parse().unwrap(),
+                    );
+                    let _ = tx
+                        .send(Err(Status::with_metadata(
+                            Code::DeadlineExceeded,
+                            "list_features deadline exceeded",
+                            trailers,
+                        )))
+                        .await;
+                    return;
+                }
+
+                let Some(feature) = matched.next() else {
+                    break;
+                };
+                if page_size > 0 && sent == page_size {
+                    next_page_token = (offset + sent).to_string();
+                    break;
+                }
+
+                println!(" => send {:?}", feature);
+                let delivered = match deadline {
+                    Some(deadline) => {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        match tokio::time::timeout(left, tx.send(Ok(feature))).await {
+                            Ok(delivered) => delivered.is_ok(),
+                            // 通道一直是满的, 这个 feature 没有发出, 不计入 sent; 下一轮循环会结束流
+                            Err(_) => continue,
+                        }
+                    }
+                    None => tx.send(Ok(feature)).await.is_ok(),
+                };
+                if !delivered {
+                    println!(" /// client disconnected, stop sending");
+                    return;
+                }
+                sent += 1;
+            }
+
+            // 以 OK 状态结束流, 下一页的 token 放在 trailers 里, 最后一页为空
+            let mut trailers = MetadataMap::new();
+            trailers.insert(NEXT_PAGE_TOKEN, next_page_token.parse().unwrap());
+            let _ = tx
+                .send(Err(Status::with_metadata(Code::Ok, "", trailers)))
+                .await;
+
+            println!(" /// done sending");
+        });
+
+        // 只在最终编码前才把 Arc<Feature> 展开成 Feature
+        #[allow(clippy::result_large_err)]
+        let output = ReceiverStream::new(rx).map(|feature| feature.map(Arc::unwrap_or_clone));
+
+        Ok(Response::new(Box::pin(output) as Self::ListFeaturesStream))
+    }
+
+    type ListFeaturesAtTimeStream = Self::ListFeaturesStream;
+
+    async fn list_features_at_time(
+        &self,
+        mut request: Request<ListFeaturesRequest>,
+    ) -> Result<Response<Self::ListFeaturesAtTimeStream>, Status> {
+        if request.get_ref().at_time == 0 {
+            request.get_mut().at_time = featurestore::now_millis();
+        }
+
+        self.list_features(request).await
+    }
+
+    async fn record_route(
+        &self,
+        request: Request<Streaming<Point>>,
+    ) -> Result<Response<RouteSummary>, Status> {
+        println!("RecordRoute");
+
+        // 直到处理结束(包括客户端中途断开)才归还名额
+        let _permit = match request.remote_addr() {
+            Some(peer) => Some(self.route_limiter.try_acquire(peer).ok_or_else(|| {
+                Status::resource_exhausted("too many concurrent record_route streams")
+            })?),
+            None => None,
+        };
+
+        let mut stream = request.into_inner();
+        let now = Instant::now();
+
+        // 中途出错时仍然汇总已收到的点, 放在错误的 details 里返回
+        let mut failure = None;
+        let mut bounds = None;
+        let mut totals = RouteTotals::default();
+        // 当前块的点, 第一个点为上一块的最后一个点时 carried 为 true
+        let mut chunk = Vec::new();
+        let mut carried = false;
+        loop {
+            let Ok(next) = tokio::time::timeout(self.idle_timeout, stream.next()).await else {
+                failure = Some(Status::deadline_exceeded(format!(
+                    "no points received for {} seconds",
+                    self.idle_timeout.as_secs_f64()
+                )));
+                break;
+            };
+            let point = match next {
+                None => break,
+                Some(Ok(point)) => point,
+                Some(Err(status)) => {
+                    failure = Some(status);
+                    break;
+                }
+            };
+            println!(" ==> Point = {:?}", point);
+            if let Some(reason) = invalid_coordinate(&point) {
+                let index = totals.point_count + chunk.len() - usize::from(carried);
+                failure = Some(Status::invalid_argument(format!(
+                    "point {}: {}",
+                    index, reason
+                )));
+                break;
+            }
+            extend_bounds(&mut bounds, &point);
+            chunk.push(point);
+
+            if chunk.len() >= ROUTE_CHUNK_SIZE {
+                let (mut points, chunk_totals) = self
+                    .summarize_chunk(std::mem::take(&mut chunk), carried)
+                    .await?;
+                totals.add(chunk_totals);
+                // 留下最后一个点, 与下一块的第一个点计算距离
+                points.drain(..points.len() - 1);
+                chunk = points;
+                carried = true;
+            }
+        }
+        let (_, chunk_totals) = self.summarize_chunk(chunk, carried).await?;
+        totals.add(chunk_totals);
+
+        let mut summary = totals.into_summary();
+        let elapsed = now.elapsed();
+        summary.elapsed_time = elapsed.as_secs() as i32;
+        summary.elapsed_time_millis = elapsed.as_millis() as i64;
+        summary.bounds = bounds;
+
+        if let Some(status) = failure {
+            tracing::warn!(points = summary.point_count, error = %status, "record_route failed mid-stream");
+            return Err(Status::with_details(
+                status.code(),
+                status.message(),
+                summary.encode_to_vec().into(),
+            ));
+        }
+
+        Ok(Response::new(summary))
+    }
+
+    type RouteChatStream = Pin<Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + 'static>>;
+
+    async fn route_chat(
+        &self,
+        request: Request<Streaming<RouteNote>>,
+    ) -> Result<Response<Self::RouteChatStream>, Status> {
+        println!("RouteChat");
+
+        let history_limit = match request.metadata().get(HISTORY_LIMIT) {
+            None => None,
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .ok_or_else(|| {
+                        Status::invalid_argument(format!("invalid {}: {:?}", HISTORY_LIMIT, value))
+                    })?,
+            ),
+        };
+        let mut stream = request.into_inner();
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
+        let chat = self.chat.clone();
+        let dedupe = self.dedupe_notes;
+        let mut keepalive = self.keepalive.map(|period| {
+            let mut interval = time::interval_at(time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+            interval
+        });
+
+        tokio::spawn(async move {
+            let member = chat.join();
+            // 已订阅的位置, 每个位置一个转发其他客户端留言的任务
+            let mut forwarders: HashMap<Location, JoinHandle<()>> = HashMap::new();
+
+            'inbound: loop {
+                let next = match keepalive.as_mut() {
+                    None => stream.next().await,
+                    Some(interval) => tokio::select! {
+                        next = stream.next() => {
+                            interval.reset();
+                            next
+                        }
+                        _ = interval.tick() => {
+                            // 保活留言没有 location 也没有内容
+                            if tx.send(Ok(RouteNote::default())).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    },
+                };
+                let Some(note) = next else {
+                    break;
+                };
+                let note = match note {
+                    Ok(note) => note,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+
+                let Some(location) = note
+                    .location
+                    .as_ref()
+                    .map(|location| (location.latitude, location.longitude))
+                else {
+                    let _ = tx
+                        .send(Err(Status::invalid_argument("missing field: location")))
+                        .await;
+                    break;
+                };
+
+                let subscribed = forwarders.contains_key(&location);
+                let (replay, receiver) = chat.post(member, location, note, subscribed, dedupe);
+                if let Some(receiver) = receiver {
+                    let forwarder = tokio::spawn(forward_notes(member, receiver, tx.clone()));
+                    forwarders.insert(location, forwarder);
+                }
+
+                // replay 的最后一条是刚收到的留言, 总会回显; history_limit 只限制之前的留言
+                let skip = history_limit.map_or(0, |limit| replay.len().saturating_sub(limit + 1));
+                for note in replay.into_iter().skip(skip) {
+                    if tx.send(Ok(note)).await.is_err() {
+                        break 'inbound;
+                    }
+                }
+            }
+
+            // 客户端发送结束后不再转发, 等转发任务退出后清理无人订阅的通道
+            for forwarder in forwarders.into_values() {
+                forwarder.abort();
+                let _ = forwarder.await;
+            }
+            chat.leave();
+        });
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::RouteChatStream
+        ))
+    }
+
+    type InterpolateRouteStream =
+        Pin<Box<dyn Stream<Item = Result<Point, Status>> + Send + 'static>>;
+
+    async fn interpolate_route(
+        &self,
+        request: Request<InterpolateRequest>,
+    ) -> Result<Response<Self::InterpolateRouteStream>, Status> {
+        println!("InterpolateRoute = {:?}", request);
+
+        let req = request.into_inner();
+        let from = req
+            .from
+            .ok_or_else(|| Status::invalid_argument("missing field: from"))?;
+        let to = req
+            .to
+            .ok_or_else(|| Status::invalid_argument("missing field: to"))?;
+        for (field, point) in [("from", &from), ("to", &to)] {
+            if let Some(reason) = invalid_coordinate(point) {
+                return Err(Status::invalid_argument(format!("{}: {}", field, reason)));
+            }
+        }
+        if !req.step_meters.is_finite() || req.step_meters <= 0.0 {
+            return Err(Status::invalid_argument("step_meters must be positive"));
+        }
+
+        let points = interpolate_great_circle(&from, &to, req.step_meters).ok_or_else(|| {
+            Status::invalid_argument(format!(
+                "route would have more than {} points, or from and to are antipodal",
+                MAX_INTERPOLATED_POINTS
+            ))
+        })?;
+        let output = tokio_stream::iter(points.into_iter().map(Ok));
+
+        Ok(Response::new(
+            Box::pin(output) as Self::InterpolateRouteStream
+        ))
+    }
+
+    async fn snap_to_road(
+        &self,
+        request: Request<SnapToRoadRequest>,
+    ) -> Result<Response<SnapToRoadResponse>, Status> {
+        println!("SnapToRoad");
+
+        let req = request.into_inner();
+        let radius = req.snap_radius_m.min(i32::MAX as u32) as i32;
+        let mut snapped_count = 0;
+
+        let snapped = req
+            .raw_points
+            .into_iter()
+            .map(|raw| {
+                let Some(location) = self
+                    .nearest_within(&raw, radius)
+                    .and_then(|feature| feature.location.clone())
+                else {
+                    return raw;
+                };
+
+                let moved = (location.latitude, location.longitude, location.floor)
+                    != (raw.latitude, raw.longitude, raw.floor);
+                if moved {
+                    snapped_count += 1;
+                }
+
+                // 只替换位置, 保留原始时间戳
+                Point {
+                    timestamp: raw.timestamp,
+                    ..location
+                }
+            })
+            .collect();
+
+        Ok(Response::new(SnapToRoadResponse {
+            snapped,
+            snapped_count,
+        }))
+    }
+
+    type SearchFeaturesStream = Self::ListFeaturesStream;
+
+    async fn search_features(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<Self::SearchFeaturesStream>, Status> {
+        println!("SearchFeatures = {:?}", request);
+
+        let req = request.into_inner();
+        if req.query.is_empty() {
+            return Err(Status::invalid_argument("query must not be empty"));
+        }
+        let mode = req.mode();
+        let fold = |text: &str| {
+            if req.case_sensitive {
+                text.to_string()
+            } else {
+                text.to_lowercase()
+            }
+        };
+        let query = fold(&req.query);
+        // limit 为 0 表示不限制
+        let limit = match req.limit {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+
+        let matched: Vec<_> = self
+            .features
+            .all()
+            .into_iter()
+            .filter(|feature| {
+                let name = fold(&feature.name);
+                match mode {
+                    SearchMode::Substring => name.contains(&query),
+                    SearchMode::Prefix => name.starts_with(&query),
+                    SearchMode::Exact => name == query,
+                }
+            })
+            .take(limit)
+            .map(Arc::unwrap_or_clone)
+            .collect();
+        let output = tokio_stream::iter(matched.into_iter().map(Ok));
+
+        Ok(Response::new(Box::pin(output) as Self::SearchFeaturesStream))
+    }
+
+    async fn get_feature_stats(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<FeatureStats>, Status> {
+        println!("GetFeatureStats = {:?}", request);
+
+        let features = self.features.all();
+        let mut stats = FeatureStats {
+            total: features.len() as i64,
+            last_modified_millis: self.features.last_modified(),
+            ..Default::default()
+        };
+        for feature in &features {
+            if let Some(location) = &feature.location {
+                extend_bounds(&mut stats.bounds, location);
+            }
+            *stats
+                .per_category
+                .entry(feature.category().as_str_name().to_string())
+                .or_default() += 1;
+        }
+
+        Ok(Response::new(stats))
+    }
+
+    async fn count_features(
+        &self,
+        request: Request<Rectangle>,
+    ) -> Result<Response<FeatureCount>, Status> {
+        println!("CountFeatures = {:?}", request);
+
+        let rect = request.get_ref();
+        if let Some(status) = missing_corner(rect) {
+            return Err(status);
+        }
+
+        let matched = self.features.in_rect(rect);
+        let mut bounds = None;
+        for location in matched
+            .iter()
+            .filter_map(|feature| feature.location.as_ref())
+        {
+            extend_bounds(&mut bounds, location);
+        }
+
+        Ok(Response::new(FeatureCount {
+            count: matched.len() as i64,
+            bounds,
+        }))
+    }
+
+    async fn add_feature(&self, request: Request<Feature>) -> Result<Response<Feature>, Status> {
+        println!("AddFeature = {:?}", request);
+
+        let feature = request.into_inner();
+        if feature.location.is_none() {
+            return Err(Status::invalid_argument("missing field: location"));
+        }
+        if feature.name.trim().is_empty() {
+            return Err(Status::invalid_argument("name must not be empty"));
+        }
+
+        self.features.add(feature.clone()).map_err(store_status)?;
+        self.publish(EventType::Added, Arc::new(feature.clone()), None);
+
+        Ok(Response::new(feature))
+    }
+
+    async fn delete_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
+        println!("DeleteFeature = {:?}", request);
+
+        let removed = self
+            .features
+            .remove(request.get_ref())
+            .map_err(store_status)?;
+        for feature in &removed {
+            self.publish(EventType::Deleted, feature.clone(), None);
+        }
+        let feature = removed
+            .into_iter()
+            .next()
+            .map(Arc::unwrap_or_clone)
+            .unwrap_or_default();
+
+        Ok(Response::new(feature))
+    }
+
+    async fn update_feature(
+        &self,
+        request: Request<UpdateFeatureRequest>,
+    ) -> Result<Response<UpdateFeatureResponse>, Status> {
+        println!("UpdateFeature = {:?}", request);
+
+        let request = request.into_inner();
+        let original = request
+            .original
+            .ok_or_else(|| Status::invalid_argument("missing field: original"))?;
+        let replacement = request
+            .replacement
+            .ok_or_else(|| Status::invalid_argument("missing field: replacement"))?;
+        if replacement.location.is_none() {
+            return Err(Status::invalid_argument(
+                "missing field: replacement.location",
+            ));
+        }
+        if replacement.name.trim().is_empty() {
+            return Err(Status::invalid_argument(
+                "replacement.name must not be empty",
+            ));
+        }
+
+        let old = self
+            .features
+            .replace(&original, replacement.clone())
+            .map_err(store_status)?;
+        let changes = diff_features(&old, &replacement);
+        self.publish(
+            EventType::Updated,
+            Arc::new(replacement.clone()),
+            old.location.clone(),
+        );
+
+        Ok(Response::new(UpdateFeatureResponse {
+            feature: Some(replacement),
+            changes,
+        }))
+    }
+
+    async fn import_features(
+        &self,
+        request: Request<Streaming<Feature>>,
+    ) -> Result<Response<ImportSummary>, Status> {
+        println!("ImportFeatures");
+
+        let mut stream = request.into_inner();
+        let mut summary = ImportSummary::default();
+        let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        while let Some(feature) = stream.next().await {
+            let feature = feature?;
+            summary.total_bytes += feature.encoded_len() as i64;
+
+            if feature.location.is_none() {
+                summary.missing_location += 1;
+            } else if feature.name.trim().is_empty() {
+                summary.empty_name += 1;
+            } else {
+                batch.push(feature);
+            }
+
+            if batch.len() >= IMPORT_BATCH_SIZE {
+                self.import_batch(&mut batch, &mut summary)
+                    .map_err(store_status)?;
+            }
+        }
+        self.import_batch(&mut batch, &mut summary)
+            .map_err(store_status)?;
+
+        summary.rejected = summary.missing_location + summary.duplicate + summary.empty_name;
+        println!(" ==> ImportSummary = {:?}", summary);
+
+        Ok(Response::new(summary))
+    }
+
+    type ExportFeaturesStream = Self::ListFeaturesStream;
+
+    async fn export_features(
+        &self,
+        request: Request<ExportRequest>,
+    ) -> Result<Response<Self::ExportFeaturesStream>, Status> {
+        println!("ExportFeatures = {:?}", request);
+
+        let rect = request.into_inner().rect;
+        if let Some(status) = rect.as_ref().and_then(missing_corner) {
+            return Err(status);
+        }
+
+        // 先取快照再发送, 导出过程中的修改不会出现在本次导出里
+        let snapshot = self.features.all();
+        #[allow(clippy::result_large_err)]
+        let output = tokio_stream::iter(snapshot)
+            .filter(move |feature| {
+                rect.as_ref().is_none_or(|rect| {
+                    feature
+                        .location
+                        .as_ref()
+                        .is_some_and(|location| featurestore::in_rang(location, rect))
+                })
+            })
+            .map(|feature| Ok(Arc::unwrap_or_clone(feature)));
+
+        Ok(Response::new(Box::pin(output) as Self::ExportFeaturesStream))
+    }
+
+    type WatchFeaturesStream =
+        Pin<Box<dyn Stream<Item = Result<FeatureEvent, Status>> + Send + 'static>>;
+
+    async fn watch_features(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchFeaturesStream>, Status> {
+        println!("WatchFeatures = {:?}", request);
+
+        let rect = request.into_inner().rect;
+        if let Some(status) = rect.as_ref().and_then(missing_corner) {
+            return Err(status);
+        }
+
+        let (tx, rx) = mpsc::channel(self.channel_capacity);
+        let receiver = self.feature_events.subscribe();
+        tokio::spawn(forward_feature_events(receiver, rect, tx));
+
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::WatchFeaturesStream
+        ))
+    }
+
+    async fn suggest_feature_correction(
+        &self,
+        request: Request<CorrectionRequest>,
+    ) -> Result<Response<CorrectionResponse>, Status> {
+        println!("SuggestFeatureCorrection = {:?}", request);
+
+        let req = request.into_inner();
+        let location = req
+            .feature_location
+            .ok_or_else(|| Status::invalid_argument("missing field: feature_location"))?;
+        if req.suggested_name.trim().is_empty() {
+            return Err(Status::invalid_argument("suggested_name must not be empty"));
+        }
+
+        // 记录 feature 的实际位置, 批准时按该位置精确改名
+        let feature_location = self
+            .find_feature(&location)
+            .and_then(|feature| feature.location.clone())
+            .ok_or_else(|| Status::not_found("no feature at feature_location"))?;
+
+        let correction_id = Uuid::new_v4().to_string();
+        let mut corrections = self.corrections.lock().unwrap();
+        if corrections.len() >= MAX_PENDING_CORRECTIONS {
+            corrections.pop_front();
+        }
+        corrections.push_back(Correction {
+            correction_id: correction_id.clone(),
+            feature_location: Some(feature_location),
+            suggested_name: req.suggested_name,
+            correction_reason: req.correction_reason,
+        });
+
+        Ok(Response::new(CorrectionResponse { correction_id }))
+    }
+
+    type ListPendingCorrectionsStream =
+        Pin<Box<dyn Stream<Item = Result<Correction, Status>> + Send + 'static>>;
+
+    async fn list_pending_corrections(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<Self::ListPendingCorrectionsStream>, Status> {
+        println!("ListPendingCorrections");
+
+        if !self.is_admin(request.metadata()) {
+            return Err(Status::permission_denied("admin token required"));
+        }
+
+        let pending: Vec<_> = self.corrections.lock().unwrap().iter().cloned().collect();
+        let output = tokio_stream::iter(pending.into_iter().map(Ok));
+
+        Ok(Response::new(
+            Box::pin(output) as Self::ListPendingCorrectionsStream
+        ))
+    }
+
+    async fn approve_correction(
+        &self,
+        request: Request<ApproveCorrectionRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        println!("ApproveCorrection = {:?}", request);
+
+        if !self.is_admin(request.metadata()) {
+            return Err(Status::permission_denied("admin token required"));
+        }
+
+        let correction_id = Uuid::parse_str(&request.get_ref().correction_id)
+            .map_err(|e| Status::invalid_argument(format!("invalid correction_id: {}", e)))?
+            .to_string();
+
+        let correction = {
+            let mut corrections = self.corrections.lock().unwrap();
+            let index = corrections
+                .iter()
+                .position(|correction| correction.correction_id == correction_id)
+                .ok_or_else(|| {
+                    Status::not_found(format!("correction {} not found", correction_id))
+                })?;
+            corrections.remove(index).unwrap()
+        };
+
+        let location = correction.feature_location.unwrap_or_default();
+        let renamed = self
+            .features
+            .rename(&location, &correction.suggested_name)
+            .map_err(store_status)?;
+        for feature in self.features.at(&location) {
+            self.publish(EventType::Updated, feature, Some(location.clone()));
+        }
+        tracing::info!(
+            correction_id,
+            renamed,
+            name = %correction.suggested_name,
+            "feature correction approved"
+        );
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn validate_route(
+        &self,
+        request: Request<ValidateRouteRequest>,
+    ) -> Result<Response<ValidateRouteResponse>, Status> {
+        println!("ValidateRoute");
+
+        let issues = validate_route(request.get_ref());
+
+        Ok(Response::new(ValidateRouteResponse {
+            valid: issues.is_empty(),
+            issues,
+        }))
+    }
+}
+
+impl Eq for Point {}
+
+// 把其他客户端在同一位置的留言转发到 tx, 落后太多时跳过丢失的留言
+async fn forward_notes(
+    member: u64,
+    mut receiver: broadcast::Receiver<(u64, RouteNote)>,
+    tx: mpsc::Sender<Result<RouteNote, Status>>,
+) {
+    loop {
+        match receiver.recv().await {
+            Ok((from, note)) => {
+                if from != member && tx.send(Ok(note)).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(skipped, "route_chat subscriber lagged");
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+// 落后的订阅者收到一条 lagged 事件, 告诉它丢了多少个修改
+async fn forward_feature_events(
+    mut receiver: broadcast::Receiver<FeatureChange>,
+    rect: Option<Rectangle>,
+    tx: mpsc::Sender<Result<FeatureEvent, Status>>,
+) {
+    loop {
+        let event = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(change) if change.is_within(rect.as_ref()) => FeatureEvent {
+                    event_type: change.event_type.into(),
+                    feature: Some(Arc::unwrap_or_clone(change.feature)),
+                    ..Default::default()
+                },
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "watch_features subscriber lagged");
+                    FeatureEvent {
+                        lagged: true,
+                        missed,
+                        ..Default::default()
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // 客户端断开后不再等待下一个修改
+            _ = tx.closed() => return,
+        };
+
+        if tx.send(Ok(event)).await.is_err() {
+            return;
+        }
+    }
+}
+
+// rect.lo 或 rect.hi 未设置时返回 INVALID_ARGUMENT
+fn missing_corner(rect: &Rectangle) -> Option<Status> {
+    if rect.lo.is_none() {
+        return Some(Status::invalid_argument("missing field: rect.lo"));
+    }
+    if rect.hi.is_none() {
+        return Some(Status::invalid_argument("missing field: rect.hi"));
+    }
+
+    None
+}
+
+// 把 point 并入 bounds, lo 为最小的经纬度, hi 为最大的经纬度, lo_floor 和 hi_floor 为经过的楼层范围
+fn extend_bounds(bounds: &mut Option<Rectangle>, point: &Point) {
+    if let Some(Rectangle {
+        lo: Some(lo),
+        hi: Some(hi),
+        lo_floor,
+        hi_floor,
+    }) = bounds
+    {
+        lo.latitude = cmp::min(lo.latitude, point.latitude);
+        lo.longitude = cmp::min(lo.longitude, point.longitude);
+        hi.latitude = cmp::max(hi.latitude, point.latitude);
+        hi.longitude = cmp::max(hi.longitude, point.longitude);
+        *lo_floor = Some(lo_floor.map_or(point.floor, |floor| cmp::min(floor, point.floor)));
+        *hi_floor = Some(hi_floor.map_or(point.floor, |floor| cmp::max(floor, point.floor)));
+        return;
+    }
+
+    let corner = Point {
+        latitude: point.latitude,
+        longitude: point.longitude,
+        ..Default::default()
+    };
+    *bounds = Some(Rectangle {
+        lo: Some(corner.clone()),
+        hi: Some(corner),
+        lo_floor: Some(point.floor),
+        hi_floor: Some(point.floor),
+    });
+}
+
+// 纬度超出 ±90° 或经度超出 ±180° 时返回原因
+fn invalid_coordinate(point: &Point) -> Option<String> {
+    if !(-900_000_000..=900_000_000).contains(&point.latitude) {
+        return Some(format!("latitude {} out of range", point.latitude));
+    }
+    if !(-1_800_000_000..=1_800_000_000).contains(&point.longitude) {
+        return Some(format!("longitude {} out of range", point.longitude));
+    }
+
+    None
+}
+
+fn store_status(e: StoreError) -> Status {
+    match e {
+        StoreError::AlreadyExists => Status::already_exists(e.to_string()),
+        StoreError::NotFound => Status::not_found(e.to_string()),
+        StoreError::Io(_) => Status::internal(e.to_string()),
+    }
+}
+
+// 广播 feature 修改的通道容量, 订阅者落后超过该数量时会收到 lagged 事件
+const FEATURE_EVENT_CAPACITY: usize = 256;
+
+// ImportFeatures 每批写入的 feature 数
+const IMPORT_BATCH_SIZE: usize = 500;
+
+// 最多保留的待审核纠错建议数, 超出时丢弃最早的
+const MAX_PENDING_CORRECTIONS: usize = 1000;
+
+// 超过该点数的路线并行计算距离和 feature 数
+const PARALLEL_ROUTE_THRESHOLD: usize = 100;
+
+// record_route 每收到这么多点汇总一次, 不缓存整条路线
+const ROUTE_CHUNK_SIZE: usize = 4096;
+
+// record_route 的累计结果, 距离等超出 i32 时在 into_summary 中截断
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct RouteTotals {
+    point_count: usize,
+    feature_count: usize,
+    distance: i64,
+    floor_changes: usize,
+}
+
+impl RouteTotals {
+    fn add(&mut self, other: RouteTotals) {
+        self.point_count += other.point_count;
+        self.feature_count += other.feature_count;
+        self.distance += other.distance;
+        self.floor_changes += other.floor_changes;
+    }
+
+    fn into_summary(self) -> RouteSummary {
+        RouteSummary {
+            point_count: self.point_count.min(i32::MAX as usize) as i32,
+            feature_count: self.feature_count.min(i32::MAX as usize) as i32,
+            distance: self.distance.min(i32::MAX as i64) as i32,
+            elapsed_time: 0,
+            elapsed_time_millis: 0,
+            bounds: None,
+            floor_changes: self.floor_changes as u32,
+        }
+    }
+}
+
+// carried 为 true 时 points[0] 是上一块的最后一个点, 只用于计算两块之间的距离和楼层变化
+fn summarize_route(
+    count: &(dyn Fn(&Point) -> usize + Sync),
+    points: &[Point],
+    carried: bool,
+) -> RouteTotals {
+    let distance: i64 = points
+        .windows(2)
+        .map(|pair| calc_distance(&pair[0], &pair[1]) as i64)
+        .sum();
+    let new_points = &points[usize::from(carried).min(points.len())..];
+    let feature_count: usize = new_points.iter().map(count).sum();
+    let floor_changes = points
+        .windows(2)
+        .filter(|pair| pair[0].floor != pair[1].floor)
+        .count();
+
+    RouteTotals {
+        point_count: new_points.len(),
+        feature_count,
+        distance,
+        floor_changes,
+    }
+}
+
+fn summarize_route_parallel(
+    count: &(dyn Fn(&Point) -> usize + Sync),
+    points: &[Point],
+    carried: bool,
+) -> RouteTotals {
+    let distance: i64 = (1..points.len())
+        .into_par_iter()
+        .map(|i| calc_distance(&points[i - 1], &points[i]) as i64)
+        .sum();
+    let new_points = &points[usize::from(carried).min(points.len())..];
+    let feature_count: usize = new_points.par_iter().map(count).sum();
+    let floor_changes = (1..points.len())
+        .into_par_iter()
+        .filter(|&i| points[i - 1].floor != points[i].floor)
+        .count();
+
+    RouteTotals {
+        point_count: new_points.len(),
+        feature_count,
+        distance,
+        floor_changes,
+    }
+}
+
+// list_features 在 trailers 中返回下一页 token 的 key
+const NEXT_PAGE_TOKEN: &str = "x-next-page-token";
+
+// route_chat 请求 metadata 中限制回放历史条数的 key, 不设置时回放全部历史
+const HISTORY_LIMIT: &str = "x-history-limit";
+
+// page_token 为下一页在匹配结果中的偏移量, 空字符串表示第一页
+fn parse_page_token(page_token: &str) -> Option<usize> {
+    if page_token.is_empty() {
+        return Some(0);
+    }
+
+    page_token.parse().ok()
+}
+
+// 解析 grpc-timeout 请求头, 格式为 "<数字><单位>", 单位为 H/M/S/m/u/n
+fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let value = metadata.get("grpc-timeout")?.to_str().ok()?;
+    if value.len() < 2 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+fn format_location(location: Option<&Point>) -> String {
+    match location {
+        Some(point) => format!("{},{}", point.latitude, point.longitude),
+        None => String::new(),
+    }
+}
+
+// 逐字段比较两个 feature, 返回发生变化的字段
+fn diff_features(old: &Feature, new: &Feature) -> Vec<FieldChange> {
+    let mut changes = vec![];
+
+    if old.name != new.name {
+        changes.push(FieldChange {
+            field_name: "name".to_string(),
+            old_value: old.name.clone(),
+            new_value: new.name.clone(),
+        });
+    }
+
+    let old_location = format_location(old.location.as_ref());
+    let new_location = format_location(new.location.as_ref());
+    if old_location != new_location {
+        changes.push(FieldChange {
+            field_name: "location".to_string(),
+            old_value: old_location,
+            new_value: new_location,
+        });
+    }
+
+    if old.elevation != new.elevation {
+        changes.push(FieldChange {
+            field_name: "elevation".to_string(),
+            old_value: old.elevation.to_string(),
+            new_value: new.elevation.to_string(),
+        });
+    }
+
+    if old.category != new.category {
+        changes.push(FieldChange {
+            field_name: "category".to_string(),
+            old_value: old.category().as_str_name().to_string(),
+            new_value: new.category().as_str_name().to_string(),
+        });
+    }
+
+    if old.tags != new.tags {
+        changes.push(FieldChange {
+            field_name: "tags".to_string(),
+            old_value: old.tags.join(","),
+            new_value: new.tags.join(","),
+        });
+    }
+
+    for change in &changes {
+        tracing::info!(
+            field = %change.field_name,
+            old = %change.old_value,
+            new = %change.new_value,
+            "feature changed"
+        );
+    }
+
+    changes
+}
+
+// 检查相邻两点之间的跳点距离和隐含速度
+fn validate_route(req: &ValidateRouteRequest) -> Vec<RouteIssue> {
+    let mut issues = vec![];
+
+    for (i, pair) in req.points.windows(2).enumerate() {
+        let (prev, point) = (&pair[0], &pair[1]);
+        let index = (i + 1) as u32;
+        let distance = calc_distance(prev, point);
+
+        if req.max_jump_m > 0 && distance as u32 > req.max_jump_m {
+            issues.push(RouteIssue {
+                index,
+                description: format!(
+                    "jump of {}m from point {} exceeds {}m",
+                    distance, i, req.max_jump_m
+                ),
+            });
+        }
+
+        if req.max_speed_m_per_s <= 0.0 || prev.timestamp == 0 || point.timestamp == 0 {
+            continue;
+        }
+
+        let elapsed_ms = point.timestamp - prev.timestamp;
+        if elapsed_ms <= 0 {
+            issues.push(RouteIssue {
+                index,
+                description: format!("timestamp does not advance from point {}", i),
+            });
+            continue;
+        }
+
+        let speed = distance as f32 / (elapsed_ms as f32 / 1000.0);
+        if speed > req.max_speed_m_per_s {
+            issues.push(RouteIssue {
+                index,
+                description: format!(
+                    "speed of {:.1}m/s from point {} exceeds {}m/s",
+                    speed, i, req.max_speed_m_per_s
+                ),
+            });
+        }
+    }
+
+    issues
+}
+
+// InterpolateRoute 最多返回的点数
+const MAX_INTERPOLATED_POINTS: usize = 10_000;
+
+// 沿大圆从 from 每隔 step_meters 取一个点, 最后一个点正好是 to;
+// 点数超过 MAX_INTERPOLATED_POINTS 或两点互为对跖点(大圆不唯一)时返回 None
+fn interpolate_great_circle(from: &Point, to: &Point, step_meters: f64) -> Option<Vec<Point>> {
+    const CORD_FACTOR: f64 = 1e7;
+    const R: f64 = 6_371_000.0;
+
+    let unit = |point: &Point| {
+        let lat = (point.latitude as f64 / CORD_FACTOR).to_radians();
+        let lng = (point.longitude as f64 / CORD_FACTOR).to_radians();
+        [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()]
+    };
+    let a = unit(from);
+    let b = unit(to);
+
+    let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+    let sin_omega = (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt();
+    // 两点之间的圆心角
+    let omega = sin_omega.atan2(dot);
+    let distance = R * omega;
+
+    if distance < 1.0 {
+        return Some(vec![to.clone()]);
+    }
+    if sin_omega < 1e-12 {
+        return None;
+    }
+
+    let steps = (distance / step_meters).floor();
+    if steps + 2.0 > MAX_INTERPOLATED_POINTS as f64 {
+        return None;
+    }
+    let steps = steps as usize;
+
+    let mut points: Vec<Point> = (0..=steps)
+        .map(|i| {
+            let t = (i as f64 * step_meters / distance).min(1.0);
+            let wa = ((1.0 - t) * omega).sin() / sin_omega;
+            let wb = (t * omega).sin() / sin_omega;
+            let [x, y, z] = [
+                wa * a[0] + wb * b[0],
+                wa * a[1] + wb * b[1],
+                wa * a[2] + wb * b[2],
+            ];
+            Point {
+                latitude: (z.atan2((x * x + y * y).sqrt()).to_degrees() * CORD_FACTOR).round()
+                    as i32,
+                longitude: (y.atan2(x).to_degrees() * CORD_FACTOR).round() as i32,
+                floor: from.floor,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    // 剩下不足 1 米时用 to 代替最后一个点, 否则把 to 追加在最后
+    if distance - steps as f64 * step_meters < 1.0 {
+        points.pop();
+    }
+    points.push(to.clone());
+
+    Some(points)
+}
+
+// 每层楼的高度(米), 跨楼层时计入距离
+const FLOOR_HEIGHT_M: i32 = 3;
+
+fn calc_distance(p1: &Point, p2: &Point) -> i32 {
+    const CORD_FACTOR: f64 = 1e7;
+    const R: f64 = 6_371_000.0;
+
+    let lat1 = p1.latitude as f64 / CORD_FACTOR;
+    let lat2 = p2.latitude as f64 / CORD_FACTOR;
+    let lng1 = p1.longitude as f64 / CORD_FACTOR;
+    let lng2 = p2.longitude as f64 / CORD_FACTOR;
+
+    let lat_rad1 = lat1.to_radians();
+    let lat_rad2 = lat2.to_radians();
+
+    let delta_lat = (lat2 - lat1).to_radians();
+    let delta_lng = (lng2 - lng1).to_radians();
+
+    let a = (delta_lat / 2f64).sin() * (delta_lat / 2f64).sin()
+        + (lat_rad1).cos() * (lat_rad2).cos() * (delta_lng / 2f64).sin() * (delta_lng / 2f64).sin();
+
+    let c = 2f64 * a.sqrt().atan2((1f64 - a).sqrt());
+
+    (R * c) as i32 + FLOOR_HEIGHT_M * (p1.floor - p2.floor).abs()
+}
+
+// path 为 ServerConfig.feature_db, 依次取 --feature-db、环境变量 ROUTE_GUIDE_DB、配置文件中的 feature_db;
+// 都没有设置时使用项目目录下的 route_guide_db.json, 文件不存在时使用内置的数据
+fn open_store(
+    path: Option<&Path>,
+    shutdown: &CancellationToken,
+) -> Result<Arc<dyn FeatureStore>, Box<dyn std::error::Error>> {
+    let path = path
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("route_guide_db.json"));
+
+    if !path.exists() {
+        tracing::warn!(path = %path.display(), "feature file not found, using built-in features");
+        return Ok(Arc::new(InMemoryStore::new(load())));
+    }
+
+    let store = JsonFileStore::open(&path)
+        .map_err(|e| format!("failed to load {}: {}", path.display(), e))?;
+    let store = Arc::new(store);
+    // 设置 ROUTE_GUIDE_RELOAD_SECS 时定期检查文件, 修改后自动重新读取
+    let reload_secs = std::env::var("ROUTE_GUIDE_RELOAD_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .filter(|secs| *secs > 0);
+    if let Some(secs) = reload_secs {
+        store
+            .clone()
+            .watch(Duration::from_secs(secs), shutdown.clone());
+    }
+
+    Ok(store)
+}
+
+// 设置 SERVER_AUTH_TOKENS 时所有服务 (健康检查和反射除外) 都要求该文件中的 bearer 令牌
+fn auth_from_env() -> std::io::Result<BearerAuth> {
+    match std::env::var_os("SERVER_AUTH_TOKENS") {
+        Some(path) => BearerAuth::load(Path::new(&path)),
+        None => Ok(BearerAuth::default()),
+    }
+}
+
+// 设置 SERVER_API_KEYS 时所有服务 (健康检查和反射除外) 都要求该文件中的 x-api-key, 并按 key 限流
+fn api_keys_from_env() -> std::io::Result<ApiKeyLayer> {
+    match std::env::var_os("SERVER_API_KEYS") {
+        Some(path) => ApiKeyLayer::load(Path::new(&path)),
+        None => Ok(ApiKeyLayer::default()),
+    }
+}
+
+// 设置 GRPC_WEB_ALLOWED_ORIGINS (逗号分隔) 时只接受来自这些 Origin 的 grpc-web 请求, 否则接受任意 Origin
+fn grpc_web_from_env() -> GrpcWebConfig {
+    match std::env::var("GRPC_WEB_ALLOWED_ORIGINS") {
+        Ok(origins) => GrpcWebConfig::with_allowed_origins(origins.split(',')),
+        Err(_) => GrpcWebConfig::default(),
+    }
+}
+
+fn authenticated<S: NamedService>(
+    service: S,
+    auth: &BearerAuth,
+) -> InterceptedService<S, BearerAuth> {
+    InterceptedService::new(service, auth.for_service(S::NAME))
+}
+
+// 包括健康检查和反射本身
+#[cfg(feature = "reflection")]
+fn reflection_service() -> Result<
+    tonic_reflection::server::ServerReflectionServer<
+        impl tonic_reflection::server::ServerReflection,
+    >,
+    tonic_reflection::server::Error,
+> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+}
+
+// ServiceName 对应的 gRPC 服务名, 与请求路径中的第一段相同
+fn grpc_service_name(service: ServiceName) -> &'static str {
+    match service {
+        ServiceName::Voting => VotingServer::<VotingService>::NAME,
+        ServiceName::Greeter => GreeterServer::<GreetService>::NAME,
+        ServiceName::RouteGuide => RouteGuideServer::<RouteGuideService>::NAME,
+        ServiceName::Conversation => ConversationServer::<ConversationService>::NAME,
+    }
+}
+
+fn concurrency_layer(config: &ServerConfig) -> ConcurrencyLayer {
+    let services = config
+        .concurrency
+        .services
+        .iter()
+        .map(|(service, max)| (grpc_service_name(*service), *max));
+
+    ConcurrencyLayer::new(config.concurrency.global_limit(), services)
+}
+
+// 配置了 tls (--tls-cert/--tls-key、SERVER_TLS_CERT/SERVER_TLS_KEY 或配置文件的 [tls]) 时使用 TLS,
+// 否则使用明文; 再给出 client_ca 时只接受由其中的 CA 签发了客户端证书的连接
+fn tls_config(config: &ServerConfig) -> Result<Option<ServerTlsConfig>, tls::TlsError> {
+    match &config.tls {
+        Some(files) => Ok(Some(tls::server_tls_config(
+            &files.cert,
+            &files.key,
+            files.client_ca.as_deref(),
+        )?)),
+        None => Ok(None),
+    }
+}
+
+// 设置 GREETER_AUDIT_LOG 时把问候记录追加到该文件, 超过 GREETER_AUDIT_MAX_BYTES (默认 10 MiB) 后轮转
+fn audit_log_from_env() -> std::io::Result<Option<AuditLog>> {
+    let Ok(path) = std::env::var("GREETER_AUDIT_LOG") else {
+        return Ok(None);
+    };
+    let max_bytes = std::env::var("GREETER_AUDIT_MAX_BYTES")
+        .ok()
+        .and_then(|max_bytes| max_bytes.parse().ok())
+        .unwrap_or(DEFAULT_AUDIT_MAX_BYTES);
+
+    AuditLog::open(path, max_bytes).map(Some)
+}
+
+const DEFAULT_AUDIT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+// 设置 VOTING_WEBHOOK_URL 时, url 的净票数达到 VOTING_WEBHOOK_THRESHOLD (默认 10) 后通知该地址
+fn webhook_from_env() -> Option<WebhookNotifier> {
+    let target = std::env::var("VOTING_WEBHOOK_URL").ok()?;
+    let threshold = std::env::var("VOTING_WEBHOOK_THRESHOLD")
+        .ok()
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(DEFAULT_WEBHOOK_THRESHOLD);
+
+    Some(WebhookNotifier::spawn(target, threshold))
+}
+
+const DEFAULT_WEBHOOK_THRESHOLD: u64 = 10;
+
+// snapshot 后端的计数保存在内存中, 每隔 snapshot_secs 秒写入 path 指向的 JSON 文件;
+// validate 已经保证 snapshot 和 sqlite 后端都有 path
+fn open_voting_service(
+    votes: &VoteStoreConfig,
+    shutdown: &CancellationToken,
+) -> Result<VotingService, Box<dyn std::error::Error>> {
+    match (votes.backend, &votes.path) {
+        (VoteBackend::Snapshot, Some(path)) => Ok(VotingService::with_snapshot(
+            path,
+            Duration::from_secs(votes.snapshot_secs),
+            shutdown.clone(),
+        )),
+        (VoteBackend::Sqlite, Some(path)) => Ok(VotingService::new(open_sqlite_vote_store(path)?)),
+        _ => Ok(VotingService::new(Arc::new(InMemoryVoteStore::default()))),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn open_sqlite_vote_store(path: &Path) -> Result<Arc<dyn VoteStore>, Box<dyn std::error::Error>> {
+    let store = SqliteVoteStore::open(path)
+        .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn open_sqlite_vote_store(_: &Path) -> Result<Arc<dyn VoteStore>, Box<dyn std::error::Error>> {
+    Err("votes.backend = \"sqlite\" requires the sqlite feature".into())
+}
+
+// 内置的 feature 数据
+pub fn load() -> Vec<Feature> {
+    vec![
+        crate::routeguide::Feature {
+            name: "Patriots Path, Mendham, NJ 07945, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 407838351,
+                longitude: -746143763,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "101 New Jersey 10, Whippany, NJ 07981, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 408122808,
+                longitude: -743999179,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "U.S. 6, Shohola, PA 18458, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 413628156,
+                longitude: -749015468,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "5 Conners Road, Kingston, NY 12401, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 419999544,
+                longitude: -740371136,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "Mid Hudson Psychiatric Center, New Hampton, NY 10958, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 414008389,
+                longitude: -743951297,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "287 Flugertown Road, Livingston Manor, NY 12758, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 419611318,
+                longitude: -746524769,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "4001 Tremley Point Road, Linden, NJ 07036, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 406109563,
+                longitude: -742186778,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "352 South Mountain Road, Wallkill, NY 12589, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 416802456,
+                longitude: -742370183,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "Bailey Turn Road, Harriman, NY 10926, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 412950425,
+                longitude: -741077389,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "193-199 Wawayanda Road, Hewitt, NJ 07421, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 412144655,
+                longitude: -743949739,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "406-496 Ward Avenue, Pine Bush, NY 12566, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 415736605,
+                longitude: -742847522,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "162 Merrill Road, Highland Mills, NY 10930, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 413843930,
+                longitude: -740501726,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "Clinton Road, West Milford, NJ 07480, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 410873075,
+                longitude: -744459023,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "16 Old Brook Lane, Warwick, NY 10990, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 412346009,
+                longitude: -744026814,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "3 Drake Lane, Pennington, NJ 08534, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 402948455,
+                longitude: -747903913,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "6324 8th Avenue, Brooklyn, NY 11220, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 406337092,
+                longitude: -740122226,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "1 Merck Access Road, Whitehouse Station, NJ 08889, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 406421967,
+                longitude: -747727624,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "78-98 Schalck Road, Narrowsburg, NY 12764, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 416318082,
+                longitude: -749677716,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        crate::routeguide::Feature {
+            name: "282 Lakeview Drive Road, Highland Lake, NY 12743, USA".to_string(),
+            location: Some(crate::routeguide::Point {
+                latitude: 415301720,
+                longitude: -748416257,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    ]
+}
+
+fn voting_service(
+    config: &ServerConfig,
+    shutdown: &CancellationToken,
+) -> Result<Arc<VotingService>, Box<dyn std::error::Error>> {
+    let voting = &config.voting;
+    let voting_service = open_voting_service(&config.votes, shutdown)?
+        .with_introspector(TokenIntrospector::from_env())
+        .with_duplicate_filter(
+            voting
+                .duplicate_filter
+                .then(|| DuplicateFilter::new(voting.expected_daily_votes, voting.fp_rate)),
+        )
+        .with_required_user_id(voting.require_user_id)
+        .with_vote_window(config.votes.vote_window())
+        .with_idempotency_ttl(config.votes.idempotency_ttl())
+        .with_admin_token(std::env::var("VOTING_ADMIN_TOKEN").ok())
+        .with_webhook(webhook_from_env());
+    voting_service.spawn_window_pruner(Duration::from_secs(60), shutdown.clone());
+
+    Ok(Arc::new(voting_service))
+}
+
+fn conversation_service(shutdown: &CancellationToken) -> ConversationService {
+    let conversation_service = ConversationService::new(Duration::from_secs(300));
+    conversation_service.spawn_purger(Duration::from_secs(60), shutdown.clone());
+    conversation_service
+}
+
+fn greet_service(config: &ServerConfig) -> Result<GreetService, Box<dyn std::error::Error>> {
+    let scorer = KeywordToxicityScorer::new(moderation::DEFAULT_RULES)?;
+
+    Ok(GreetService::new(scorer)
+        .with_fault_injection(std::env::var_os("GREETER_FAULT_INJECTION").is_some())
+        .with_watermark(config.greeter.watermark)
+        .with_audit_log(audit_log_from_env()?))
+}
+
+fn route_guide_service(
+    features: Arc<dyn FeatureStore>,
+    config: &ServerConfig,
+) -> RouteGuideService {
+    RouteGuideService::new(features)
+        .with_tolerance(10)
+        .with_channel_capacity(16)
+        .with_idle_timeout(Duration::from_secs(30))
+        .with_note_limits(50, 1024)
+        .with_keepalive(config.route_guide.keepalive())
+        .with_note_dedupe(config.route_guide.dedupe_notes)
+        .with_max_routes_per_peer(4)
+        .with_admin_token(std::env::var("ROUTEGUIDE_ADMIN_TOKEN").ok())
+}
+
+// 按 config 启动服务, 直到 shutdown 取消并且连接处理完; 绑定端口后通过 bound 发出实际监听的地址
+pub async fn run(
+    config: ServerConfig,
+    shutdown: CancellationToken,
+    bound: oneshot::Sender<SocketAddr>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // 配置错误在绑定端口之前报告
+    let tls = tls_config(&config)?;
+    let auth = auth_from_env()?;
+    let api_keys = api_keys_from_env()?;
+    // Voting、Greeter 和 RouteGuide 也可以通过 grpc-web 调用
+    let grpc_web = grpc_web_from_env();
+    let max_message_size = config.limits.max_message_size;
+
+    let (reporter, health_service) = tonic_health::server::health_reporter();
+    let mut health = HealthHandle::new(reporter);
+
+    let voting_service = if config.enabled(ServiceName::Voting) {
+        health.register::<VotingServer<VotingService>>().await;
+        Some(voting_service(&config, &shutdown)?)
+    } else {
+        None
+    };
+    let greet_service = if config.enabled(ServiceName::Greeter) {
+        health.register::<GreeterServer<GreetService>>().await;
+        Some(
+            GreeterServer::new(greet_service(&config)?)
+                .max_decoding_message_size(max_message_size)
+                .max_encoding_message_size(max_message_size),
+        )
+    } else {
+        None
+    };
+    let features = if config.enabled(ServiceName::RouteGuide) {
+        Some(open_store(config.feature_db.as_deref(), &shutdown)?)
+    } else {
+        None
+    };
+    let route_guide_service = if let Some(features) = &features {
+        health
+            .register::<RouteGuideServer<RouteGuideService>>()
+            .await;
+        Some(
+            RouteGuideServer::new(route_guide_service(features.clone(), &config))
+                .max_decoding_message_size(max_message_size)
+                .max_encoding_message_size(max_message_size),
+        )
+    } else {
+        None
+    };
+    let conversation_service = if config.enabled(ServiceName::Conversation) {
+        health
+            .register::<ConversationServer<ConversationService>>()
+            .await;
+        Some(
+            ConversationServer::new(conversation_service(&shutdown))
+                .max_decoding_message_size(max_message_size)
+                .max_encoding_message_size(max_message_size),
+        )
+    } else {
+        None
+    };
+
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls)?;
+    }
+    let router = server
+        .accept_http1(true)
+        // 最外层, 超过上限的请求不再经过其他检查
+        .layer(concurrency_layer(&config))
+        .layer(api_keys)
+        .layer(TimeoutLayer::new(
+            config.timeouts.default_limit(),
+            config.timeouts.method_limits(),
+        ))
+        .add_optional_service(voting_service.clone().map(|voting_service| {
+            let voting_service = VotingServer::from_arc(voting_service)
+                .max_decoding_message_size(max_message_size)
+                .max_encoding_message_size(max_message_size);
+            grpc_web.enable(authenticated(
+                InterceptedService::new(voting_service, ClientCertInterceptor),
+                &auth,
+            ))
+        }))
+        .add_optional_service(
+            greet_service.map(|greet_service| grpc_web.enable(authenticated(greet_service, &auth))),
+        )
+        .add_optional_service(
+            route_guide_service.map(|route_guide_service| {
+                grpc_web.enable(authenticated(route_guide_service, &auth))
+            }),
+        )
+        .add_optional_service(
+            conversation_service
+                .map(|conversation_service| authenticated(conversation_service, &auth)),
+        )
+        .add_service(health_service);
+    #[cfg(feature = "reflection")]
+    let router = router.add_service(reflection_service()?);
+
+    let listener = tokio::net::TcpListener::bind(config.listen).await?;
+    let address = listener.local_addr()?;
+    tracing::info!(%address, "listening");
+    let _ = bound.send(address);
+    let incoming = TcpIncoming::from_listener(listener, false, None)
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
+    // 先报告 NOT_SERVING, 再停止接受新连接, 进行中的请求最多再等 drain_deadline
+    let serve = router.serve_with_incoming_shutdown(incoming, {
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.cancelled().await;
+            health.shutting_down().await;
+        }
+    });
+    drain(
+        serve,
+        &shutdown,
+        Duration::from_secs(config.limits.drain_secs),
+    )
+    .await?;
+
+    if let Some(features) = features {
+        features.flush()?;
+    }
+    if let Some(voting_service) = voting_service {
+        voting_service.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use tonic::transport::{Channel, Endpoint};
+
+    use super::*;
+    use greet::greeter_client::GreeterClient;
+    use routeguide::route_guide_client::RouteGuideClient;
+    use tonic_health::pb::{
+        health_check_response::ServingStatus, health_client::HealthClient, HealthCheckRequest,
+    };
+    use voting::voting_client::VotingClient;
+
+    #[tokio::test]
+    async fn run_reports_bound_address() {
+        let config = ServerConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        };
+        let shutdown = CancellationToken::new();
+        let (bound, address) = oneshot::channel();
+        let server = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                run(config, shutdown, bound)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        });
+
+        let address = address.await.unwrap();
+        assert_ne!(address.port(), 0);
+
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = HealthClient::new(channel);
+        let response = client
+            .check(HealthCheckRequest {
+                service: GreeterServer::<GreetService>::NAME.to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.into_inner().status(), ServingStatus::Serving);
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "reflection")]
+    #[tokio::test]
+    async fn reflection_lists_every_service() {
+        use tonic_reflection::pb::{
+            server_reflection_client::ServerReflectionClient,
+            server_reflection_request::MessageRequest, server_reflection_response::MessageResponse,
+            ServerReflectionRequest,
+        };
+
+        let config = ServerConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        };
+        let shutdown = CancellationToken::new();
+        let (bound, address) = oneshot::channel();
+        let server = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                run(config, shutdown, bound)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+        });
+        let address = address.await.unwrap();
+
+        let channel = Endpoint::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let request = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(MessageRequest::ListServices(String::new())),
+        };
+        let mut responses = ServerReflectionClient::new(channel)
+            .server_reflection_info(tokio_stream::iter(vec![request]))
+            .await
+            .unwrap()
+            .into_inner();
+        let response = responses.message().await.unwrap().unwrap();
+        let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+            panic!("unexpected {:?}", response.message_response);
+        };
+        let names: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+        // 列表来自编译进来的描述符, 所以也包括没有注册的 web.Web
+        for service in [
+            VotingServer::<VotingService>::NAME,
+            GreeterServer::<GreetService>::NAME,
+            RouteGuideServer::<RouteGuideService>::NAME,
+            ConversationServer::<ConversationService>::NAME,
+            "grpc.health.v1.Health",
+            "grpc.reflection.v1alpha.ServerReflection",
+        ] {
+            assert!(
+                names.iter().any(|name| name == service),
+                "{service} in {names:?}"
+            );
+        }
+
+        shutdown.cancel();
+        server.await.unwrap().unwrap();
+    }
+
+    fn feature(name: &str, latitude: i32, longitude: i32, tags: &[&str]) -> Feature {
+        Feature {
+            name: name.to_string(),
+            location: Some(Point {
+                latitude,
+                longitude,
+                ..Default::default()
+            }),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diff_features_name_only() {
+        let changes = diff_features(&feature("a", 1, 2, &[]), &feature("b", 1, 2, &[]));
+        assert_eq!(
+            changes,
+            vec![FieldChange {
+                field_name: "name".to_string(),
+                old_value: "a".to_string(),
+                new_value: "b".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_features_location_only() {
+        let changes = diff_features(&feature("a", 1, 2, &[]), &feature("a", 3, 4, &[]));
+        assert_eq!(
+            changes,
+            vec![FieldChange {
+                field_name: "location".to_string(),
+                old_value: "1,2".to_string(),
+                new_value: "3,4".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_features_tag_added() {
+        let changes = diff_features(
+            &feature("a", 1, 2, &["park"]),
+            &feature("a", 1, 2, &["park", "cafe"]),
+        );
+        assert_eq!(
+            changes,
+            vec![FieldChange {
+                field_name: "tags".to_string(),
+                old_value: "park".to_string(),
+                new_value: "park,cafe".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_features_no_change() {
+        let old = feature("a", 1, 2, &["park"]);
+        assert!(diff_features(&old, &old.clone()).is_empty());
+    }
+
+    fn vote_request(user_id: Option<&str>, vote: Vote) -> Request<VotingRequest> {
+        let mut request = Request::new(VotingRequest {
+            url: "https://example.com/".to_string(),
+            vote: vote.into(),
+            ..Default::default()
+        });
+        if let Some(user_id) = user_id {
+            request
+                .metadata_mut()
+                .insert(USER_ID_HEADER, user_id.parse().unwrap());
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn repeated_user_vote_is_rejected() {
+        let service = VotingService::default();
+        service
+            .vote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap();
+
+        let status = service
+            .vote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn flipped_user_vote_moves_the_tally() {
+        let service = VotingService::default();
+        service
+            .vote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap();
+
+        let response = service
+            .vote(vote_request(Some("alice"), Vote::Down))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.up_count, response.down_count), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn users_vote_independently() {
+        let service = VotingService::default();
+        service
+            .vote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap();
+
+        let response = service
+            .vote(vote_request(Some("bob"), Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.up_count, response.down_count), (2, 0));
+    }
+
+    #[tokio::test]
+    async fn anonymous_votes_follow_require_user_id() {
+        let service = VotingService::default();
+        for _ in 0..2 {
+            service.vote(vote_request(None, Vote::Up)).await.unwrap();
+        }
+
+        let service = VotingService::default().with_required_user_id(true);
+        let status = service
+            .vote(vote_request(None, Vote::Up))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    fn vote_on(url: &str, vote: Vote) -> Request<VotingRequest> {
+        Request::new(VotingRequest {
+            url: url.to_string(),
+            vote: vote.into(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn alternating_votes_are_tallied() {
+        let service = VotingService::default();
+
+        let mut counts = Vec::new();
+        for vote in [Vote::Up, Vote::Down, Vote::Up, Vote::Down, Vote::Up] {
+            let response = service
+                .vote(vote_on("https://example.com/", vote))
+                .await
+                .unwrap()
+                .into_inner();
+            counts.push((response.up_count, response.down_count));
+        }
+        assert_eq!(counts, [(1, 0), (1, 1), (2, 1), (2, 2), (3, 2)]);
+    }
+
+    #[tokio::test]
+    async fn urls_are_tallied_independently() {
+        let service = VotingService::default();
+        service
+            .vote(vote_on("https://a.example/", Vote::Up))
+            .await
+            .unwrap();
+        service
+            .vote(vote_on("https://a.example/", Vote::Up))
+            .await
+            .unwrap();
+
+        let response = service
+            .vote(vote_on("https://b.example/", Vote::Down))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.up_count, response.down_count), (0, 1));
+        let tally = service.votes.get("https://a.example/").unwrap().unwrap();
+        assert_eq!((tally.up, tally.down), (2, 0));
+    }
+
+    fn count_request(url: &str, require_known: bool) -> Request<VoteCountRequest> {
+        Request::new(VoteCountRequest {
+            url: url.to_string(),
+            require_known,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn get_vote_count_reads_without_voting() {
+        let service = VotingService::default();
+        let url = "https://example.com/";
+
+        // 没有投过票的 url 返回 0, require_known 时返回 NOT_FOUND
+        let count = service
+            .get_vote_count(count_request(url, false))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((count.up_count, count.down_count, count.total), (0, 0, 0));
+        let status = service
+            .get_vote_count(count_request(url, true))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        for vote in [Vote::Up, Vote::Up, Vote::Down, Vote::Abstain] {
+            service.vote(vote_on(url, vote)).await.unwrap();
+        }
+        let count = service
+            .get_vote_count(count_request(url, true))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(count.url, url);
+        assert_eq!(
+            (count.up_count, count.down_count, count.abstain_count),
+            (2, 1, 1)
+        );
+        assert_eq!(count.total, 4);
+        // 读取不计票
+        assert_eq!(service.votes.get(url).unwrap().unwrap().total(), 4);
+    }
+
+    // 多线程运行时, 投票和读取真正并发进行
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn get_vote_count_sees_consistent_tallies_during_concurrent_votes() {
+        let service = Arc::new(VotingService::default());
+        let url = "https://example.com/";
+
+        let voters: Vec<_> = (0..8)
+            .map(|i| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    let vote = if i % 2 == 0 { Vote::Up } else { Vote::Down };
+                    for _ in 0..100 {
+                        service.vote(vote_on(url, vote)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // 每次读到的计数各项之和等于 total, 且 total 不会变小
+        let mut last_total = 0;
+        while voters.iter().any(|voter| !voter.is_finished()) {
+            let count = service
+                .get_vote_count(count_request(url, false))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(
+                count.up_count + count.down_count + count.abstain_count,
+                count.total
+            );
+            assert!(count.total >= last_total);
+            last_total = count.total;
+            tokio::task::yield_now().await;
+        }
+        for voter in voters {
+            voter.await.unwrap();
+        }
+
+        let count = service
+            .get_vote_count(count_request(url, false))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((count.up_count, count.down_count), (400, 400));
+    }
+
+    #[tokio::test]
+    async fn unvote_revokes_a_recorded_vote() {
+        let service = VotingService::default();
+        let url = "https://example.com/";
+        service.vote(vote_on(url, Vote::Up)).await.unwrap();
+        service.vote(vote_on(url, Vote::Down)).await.unwrap();
+
+        let response = service
+            .unvote(vote_on(url, Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.confirmation, format!("revoked upvote for {}", url));
+        assert_eq!((response.up_count, response.down_count), (0, 1));
+
+        // 没有可撤销的票时不会减到 0 以下
+        let status = service.unvote(vote_on(url, Vote::Up)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(status.message(), format!("no votes to revoke for {}", url));
+        let status = service
+            .unvote(vote_on("https://never.example/", Vote::Down))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn unvote_only_touches_the_callers_votes() {
+        let service = VotingService::default();
+        service
+            .vote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap();
+
+        // 匿名撤销和其他用户的撤销都不会减掉 alice 的票
+        let status = service
+            .unvote(vote_request(None, Vote::Up))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        let status = service
+            .unvote(vote_request(Some("bob"), Vote::Up))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+        assert_eq!(
+            service
+                .votes
+                .get("https://example.com/")
+                .unwrap()
+                .unwrap()
+                .up,
+            1
+        );
+
+        let response = service
+            .unvote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.up_count, 0);
+        // alice 撤销后可以重新投票
+        service
+            .vote(vote_request(Some("alice"), Vote::Up))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn interleaved_votes_and_unvotes_stay_consistent() {
+        let service = Arc::new(VotingService::default());
+        let url = "https://example.com/";
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let service = service.clone();
+                tokio::spawn(async move {
+                    for _ in 0..100 {
+                        service.vote(vote_on(url, Vote::Up)).await.unwrap();
+                        service.unvote(vote_on(url, Vote::Up)).await.unwrap();
+                    }
+                    // 每个任务最后留下一张反对票
+                    service.vote(vote_on(url, Vote::Down)).await.unwrap();
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let tally = service.votes.get(url).unwrap().unwrap();
+        assert_eq!((tally.up, tally.down), (0, 8));
+        let status = service.unvote(vote_on(url, Vote::Up)).await.unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition);
+    }
+
+    // status details 中唯一的 BadRequest 字段错误
+    fn field_violation(status: &Status) -> bad_request::FieldViolation {
+        let details = google_rpc::Status::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::InvalidArgument as i32);
+        assert_eq!(details.details.len(), 1);
+        assert_eq!(details.details[0].type_url, BAD_REQUEST_TYPE_URL);
+        let mut violations = BadRequest::decode(details.details[0].value.as_slice())
+            .unwrap()
+            .field_violations;
+        assert_eq!(violations.len(), 1);
+        violations.remove(0)
+    }
+
+    #[tokio::test]
+    async fn invalid_vote_urls_carry_bad_request_details() {
+        let service = VotingService::default();
+        let oversized = format!("https://example.com/{}", "a".repeat(MAX_VOTE_URL_LEN));
+        let cases = [
+            ("", "must not be empty"),
+            ("example.com/page", "not an absolute url"),
+            ("ftp://example.com/", "unsupported scheme \"ftp\""),
+            (oversized.as_str(), "must be at most 2048 bytes"),
+        ];
+
+        for (url, description) in cases {
+            let status = service.vote(vote_on(url, Vote::Up)).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{:?}", url);
+            let violation = field_violation(&status);
+            assert_eq!(violation.field, "url");
+            assert!(
+                violation.description.starts_with(description),
+                "{:?}: {}",
+                url,
+                violation.description
+            );
+        }
+        assert!(service.votes.top_n(10, TopOrder::Total).unwrap().is_empty());
+
+        let response = service
+            .vote(vote_on("http://example.com/page?q=1", Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.up_count, 1);
+    }
+
+    fn batch(votes: Vec<VotingRequest>, user_id: Option<&str>) -> Request<BatchVotingRequest> {
+        let mut request = Request::new(BatchVotingRequest { votes });
+        if let Some(user_id) = user_id {
+            request
+                .metadata_mut()
+                .insert(USER_ID_HEADER, user_id.parse().unwrap());
+        }
+        request
+    }
+
+    #[tokio::test]
+    async fn batch_vote_reports_each_entry() {
+        let service = VotingService::default();
+        let url = "https://example.com/";
+        let votes = vec![
+            vote_on(url, Vote::Up).into_inner(),
+            vote_on("", Vote::Up).into_inner(),
+            VotingRequest {
+                url: url.to_string(),
+                vote: 42,
+                ..Default::default()
+            },
+            // 与这一批中前一张票相同
+            vote_on(url, Vote::Up).into_inner(),
+            vote_on(url, Vote::Down).into_inner(),
+            vote_on("https://other.example/", Vote::Up).into_inner(),
+        ];
+
+        let results = service
+            .batch_vote(batch(votes, Some("alice")))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        let summary: Vec<_> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.index,
+                    Code::from_i32(result.code),
+                    result.up_count,
+                    result.down_count,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, Code::Ok, 1, 0),
+                (1, Code::InvalidArgument, 0, 0),
+                (2, Code::Unimplemented, 0, 0),
+                (3, Code::AlreadyExists, 0, 0),
+                (4, Code::Ok, 0, 1),
+                (5, Code::Ok, 1, 0),
+            ]
+        );
+        assert!(results[0].accepted);
+        assert_eq!(results[0].message, format!("upvoted for {}", url));
+        assert_eq!(results[1].message, "invalid url: must not be empty");
+
+        // 失败的票不影响同一批中的其他票
+        let tally = service.votes.get(url).unwrap().unwrap();
+        assert_eq!((tally.up, tally.down), (0, 1));
+        let history = service.history.get("alice").unwrap();
+        assert_eq!(history.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn batch_vote_enforces_the_batch_limit() {
+        let service = VotingService::default();
+        let votes = |count| vec![vote_on("https://example.com/", Vote::Up).into_inner(); count];
+
+        let status = service
+            .batch_vote(batch(votes(MAX_BATCH_VOTES + 1), None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            format!(
+                "batch of {} votes exceeds the limit of {}",
+                MAX_BATCH_VOTES + 1,
+                MAX_BATCH_VOTES
+            )
+        );
+        assert_eq!(service.votes.get("https://example.com/").unwrap(), None);
+
+        let results = service
+            .batch_vote(batch(votes(MAX_BATCH_VOTES), None))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert_eq!(results.len(), MAX_BATCH_VOTES);
+        assert_eq!(results.last().unwrap().up_count, MAX_BATCH_VOTES as u64);
+    }
+
+    async fn voting_client(service: Arc<VotingService>) -> VotingClient<Channel> {
+        VotingClient::new(
+            serve(Server::builder().add_service(VotingServer::from_arc(service))).await,
+        )
+    }
+
+    #[tokio::test]
+    async fn stream_votes_counts_rejections_by_reason() {
+        let service = Arc::new(VotingService::default());
+        let mut client = voting_client(service.clone()).await;
+        let url = "https://example.com/";
+        let votes: Vec<_> = (0..50)
+            .map(|i| match i {
+                10 | 20 => vote_on("not a url", Vote::Up).into_inner(),
+                30 => VotingRequest {
+                    url: url.to_string(),
+                    vote: 42,
+                    ..Default::default()
+                },
+                i if i % 2 == 0 => vote_on(url, Vote::Up).into_inner(),
+                _ => vote_on(url, Vote::Down).into_inner(),
+            })
+            .collect();
+
+        let summary = client
+            .stream_votes(tokio_stream::iter(votes))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.accepted, 47);
+        assert_eq!(summary.rejected, 3);
+        assert_eq!(
+            summary.rejected_by_reason,
+            HashMap::from([
+                ("InvalidArgument".to_string(), 2),
+                ("Unimplemented".to_string(), 1),
+            ])
+        );
+        assert!(summary.elapsed_time_millis >= 0);
+        let tally = service.votes.get(url).unwrap().unwrap();
+        assert_eq!((tally.up, tally.down), (22, 25));
+    }
+
+    #[tokio::test]
+    async fn stream_votes_keeps_votes_from_a_disconnected_client() {
+        let service = Arc::new(VotingService::default());
+        let mut client = voting_client(service.clone()).await;
+        let url = "https://example.com/";
+        let (tx, rx) = mpsc::channel(8);
+        let call = tokio::spawn(async move { client.stream_votes(ReceiverStream::new(rx)).await });
+
+        for _ in 0..5 {
+            tx.send(vote_on(url, Vote::Up).into_inner()).await.unwrap();
+        }
+        for _ in 0..250 {
+            if service
+                .votes
+                .get(url)
+                .unwrap()
+                .is_some_and(|tally| tally.up == 5)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // 客户端没有结束流就断开, 已经计入的票保留
+        call.abort();
+        drop(tx);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(service.votes.get(url).unwrap().unwrap().up, 5);
+
+        let mut client = voting_client(service.clone()).await;
+        let summary = client
+            .stream_votes(tokio_stream::iter(vec![
+                vote_on(url, Vote::Down).into_inner()
+            ]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.accepted, 1);
+        assert_eq!(service.votes.get(url).unwrap().unwrap().total(), 6);
+    }
+
+    #[tokio::test]
+    async fn watch_votes_pushes_each_change_to_the_url() {
+        let service = Arc::new(VotingService::default());
+        let url = "https://example.com/";
+        service.vote(vote_on(url, Vote::Up)).await.unwrap();
+        let mut client = voting_client(service.clone()).await;
+
+        let mut updates = client
+            .watch_votes(count_request(url, false))
+            .await
+            .unwrap()
+            .into_inner();
+        // 第一条是订阅时的计数
+        let first = updates.message().await.unwrap().unwrap();
+        assert_eq!((first.up_count, first.down_count), (1, 0));
+
+        let voter = tokio::spawn({
+            let service = service.clone();
+            async move {
+                service.vote(vote_on(url, Vote::Up)).await.unwrap();
+                service
+                    .vote(vote_on("https://other.example/", Vote::Up))
+                    .await
+                    .unwrap();
+                service.vote(vote_on(url, Vote::Down)).await.unwrap();
+                service.unvote(vote_on(url, Vote::Up)).await.unwrap();
+            }
+        });
+        voter.await.unwrap();
+
+        // 读得慢时中间的计数可能被合并, 但计数只会按修改的顺序出现, 最后一条是最新的计数
+        let expected = [(2, 0), (2, 1), (1, 1)];
+        let mut seen = Vec::new();
+        while seen.last() != Some(&(1, 1)) {
+            let update = tokio::time::timeout(Duration::from_secs(5), updates.message())
+                .await
+                .expect("vote update")
+                .unwrap()
+                .unwrap();
+            assert_eq!(update.url, url);
+            seen.push((update.up_count, update.down_count));
+        }
+        let mut remaining = expected.iter();
+        assert!(seen.iter().all(|count| remaining.any(|want| want == count)));
+    }
+
+    #[tokio::test]
+    async fn watch_votes_starts_from_zero_and_validates_the_url() {
+        let service = Arc::new(VotingService::default());
+        let mut client = voting_client(service.clone()).await;
+
+        let mut updates = client
+            .watch_votes(count_request("https://new.example/", false))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = updates.message().await.unwrap().unwrap();
+        assert_eq!(first.total, 0);
+        service
+            .vote(vote_on("https://new.example/", Vote::Abstain))
+            .await
+            .unwrap();
+        let update = updates.message().await.unwrap().unwrap();
+        assert_eq!(update.abstain_count, 1);
+
+        let status = client
+            .watch_votes(count_request("", false))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    async fn top_urls(
+        service: &VotingService,
+        limit: u32,
+        order: TopOrder,
+    ) -> Result<Vec<(String, u64, u64)>, Status> {
+        let stream = service
+            .top_urls(Request::new(TopUrlsRequest {
+                limit,
+                order: order.into(),
+            }))
+            .await?
+            .into_inner();
+        let counts = stream.collect::<Result<Vec<_>, _>>().await?;
+        Ok(counts
+            .into_iter()
+            .map(|count| (count.url, count.up_count, count.down_count))
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn top_urls_orders_by_score_then_url() {
+        let service = VotingService::default();
+        // 第 i 个 url 有 i % 5 张赞成票和 i % 3 张反对票, 00 没有票
+        for i in 0..12u64 {
+            let url = format!("https://example.com/{:02}", i);
+            for _ in 0..i % 5 {
+                service.vote(vote_on(&url, Vote::Up)).await.unwrap();
+            }
+            for _ in 0..i % 3 {
+                service.vote(vote_on(&url, Vote::Down)).await.unwrap();
+            }
+        }
+        let url = |i: u64| (format!("https://example.com/{:02}", i), i % 5, i % 3);
+
+        // 净票数: 09 为 4, 03 和 04 为 3, 06、07、08 为 1
+        let net = top_urls(&service, 5, TopOrder::Net).await.unwrap();
+        assert_eq!(net, [url(9), url(3), url(4), url(6), url(7)]);
+        // 总票数: 04 和 08 为 5, 02 和 09 为 4
+        let total = top_urls(&service, 3, TopOrder::Total).await.unwrap();
+        assert_eq!(total, [url(4), url(8), url(2)]);
+        // limit 超过 url 数时返回全部
+        let all = top_urls(&service, 1000, TopOrder::Total).await.unwrap();
+        assert_eq!(all.len(), 11);
+    }
+
+    #[tokio::test]
+    async fn top_urls_rejects_out_of_range_limits() {
+        let service = VotingService::default();
+        for limit in [0, MAX_TOP_URLS + 1] {
+            let status = top_urls(&service, limit, TopOrder::Net).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(status.message(), "limit must be between 1 and 1000");
+        }
+    }
+
+    fn keyed(mut request: Request<VotingRequest>, key: &str) -> Request<VotingRequest> {
+        request
+            .metadata_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, key.parse().unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn replayed_idempotency_key_returns_the_first_response() {
+        let service = VotingService::default().with_idempotency_ttl(Some(Duration::from_secs(60)));
+
+        let first = service
+            .vote(keyed(vote_request(None, Vote::Up), "retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        let replayed = service
+            .vote(keyed(vote_request(None, Vote::Up), "retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(replayed, first);
+        assert_eq!(first.up_count, 1);
+
+        // 重放没有计票, 不带 key 的投票照常计数
+        let unkeyed = service
+            .vote(vote_request(None, Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(unkeyed.up_count, 2);
+    }
+
+    #[tokio::test]
+    async fn reused_idempotency_key_with_a_different_vote_is_aborted() {
+        let service = VotingService::default().with_idempotency_ttl(Some(Duration::from_secs(60)));
+        service
+            .vote(keyed(vote_request(Some("alice"), Vote::Up), "retry-1"))
+            .await
+            .unwrap();
+
+        let status = service
+            .vote(keyed(vote_request(Some("alice"), Vote::Down), "retry-1"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Aborted);
+    }
+
+    #[tokio::test]
+    async fn idempotency_keys_are_scoped_per_user() {
+        let service = VotingService::default().with_idempotency_ttl(Some(Duration::from_secs(60)));
+        let alice = service
+            .vote(keyed(vote_request(Some("alice"), Vote::Up), "retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(alice.up_count, 1);
+
+        // bob 用了同一个 key, 得到的是自己的结果而不是 alice 的
+        let bob = service
+            .vote(keyed(vote_request(Some("bob"), Vote::Down), "retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((bob.up_count, bob.down_count), (1, 1));
+
+        let anonymous = service
+            .vote(keyed(vote_request(None, Vote::Up), "retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(anonymous.up_count, 2);
+    }
+
+    #[tokio::test]
+    async fn expired_idempotency_key_can_be_reused() {
+        let service =
+            VotingService::default().with_idempotency_ttl(Some(Duration::from_millis(100)));
+        service
+            .vote(keyed(vote_request(None, Vote::Up), "retry-1"))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let response = service
+            .vote(keyed(vote_request(None, Vote::Down), "retry-1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.up_count, response.down_count), (1, 1));
+    }
+
+    fn with_reason(url: &str, vote: Vote, reason: &str) -> Request<VotingRequest> {
+        Request::new(VotingRequest {
+            url: url.to_string(),
+            vote: vote.into(),
+            reason: reason.to_string(),
+            ..Default::default()
+        })
+    }
+
+    async fn recent_reasons(service: &VotingService, url: &str) -> Vec<VoteReason> {
+        let stream = service
+            .get_recent_reasons(Request::new(RecentReasonsRequest {
+                url: url.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        stream.collect::<Result<Vec<_>, _>>().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn oversized_reason_is_rejected() {
+        let service = VotingService::default();
+        let status = service
+            .vote(with_reason(
+                "https://example.com/",
+                Vote::Down,
+                &"x".repeat(MAX_REASON_CHARS + 1),
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(field_violation(&status).field, "reason");
+        // 被拒绝的票没有计数
+        assert_eq!(service.votes.get("https://example.com/").unwrap(), None);
+
+        // 按字符而不是字节计算长度
+        service
+            .vote(with_reason(
+                "https://example.com/",
+                Vote::Down,
+                &"票".repeat(MAX_REASON_CHARS),
+            ))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn recent_reasons_are_newest_first_and_capped() {
+        let service = VotingService::default();
+        let url = "https://example.com/";
+        for i in 0..MAX_RECENT_REASONS + 5 {
+            service
+                .vote(with_reason(url, Vote::Down, &format!("reason {i}")))
+                .await
+                .unwrap();
+        }
+        // 没有理由的票不记录
+        service.vote(vote_on(url, Vote::Up)).await.unwrap();
+
+        let reasons = recent_reasons(&service, url).await;
+        let texts: Vec<_> = reasons.iter().map(|r| r.reason.as_str()).collect();
+        let expected: Vec<_> = (5..MAX_RECENT_REASONS + 5)
+            .rev()
+            .map(|i| format!("reason {i}"))
+            .collect();
+        assert_eq!(texts, expected);
+        assert!(reasons.iter().all(|r| r.vote == Vote::Down as i32));
+        assert!(reasons
+            .windows(2)
+            .all(|pair| pair[0].timestamp_millis >= pair[1].timestamp_millis));
+
+        assert!(recent_reasons(&service, "https://other.example/")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn each_vote_value_has_its_own_count() {
+        let service = VotingService::default();
+        let url = "https://example.com/";
+
+        let expected = [
+            (Vote::Up, (1, 0, 0)),
+            (Vote::Down, (1, 1, 0)),
+            (Vote::Abstain, (1, 1, 1)),
+            (Vote::Abstain, (1, 1, 2)),
+        ];
+        for (vote, counts) in expected {
+            let response = service.vote(vote_on(url, vote)).await.unwrap().into_inner();
+            assert_eq!(response.vote(), vote);
+            assert_eq!(
+                (
+                    response.up_count,
+                    response.down_count,
+                    response.abstain_count
+                ),
+                counts,
+                "{vote:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_vote_value_is_unimplemented() {
+        let service = VotingService::default();
+        let status = service
+            .vote(Request::new(VotingRequest {
+                url: "https://example.com/".to_string(),
+                vote: 7,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert!(status.message().contains('7'), "{}", status.message());
+        assert_eq!(service.votes.get("https://example.com/").unwrap(), None);
+    }
+
+    fn user_vote_on(user_id: &str, url: &str, vote: Vote) -> Request<VotingRequest> {
+        let mut request = vote_on(url, vote);
+        request
+            .metadata_mut()
+            .insert(USER_ID_HEADER, user_id.parse().unwrap());
+        request
+    }
+
+    async fn history_urls(service: &VotingService, user_id: &str, limit: u32) -> Vec<String> {
+        let stream = service
+            .get_vote_history(Request::new(VoteHistoryRequest {
+                user_id: user_id.to_string(),
+                limit,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        stream
+            .map(|event| event.unwrap().url)
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[tokio::test]
+    async fn vote_history_is_newest_first_and_evicts_the_oldest() {
+        let service = VotingService::default().with_history_limit(3);
+        for i in 0..5 {
+            service
+                .vote(user_vote_on(
+                    "alice",
+                    &format!("https://{i}.example/"),
+                    Vote::Up,
+                ))
+                .await
+                .unwrap();
+        }
+        // 被拒绝的重复票和匿名票都不进入历史
+        service
+            .vote(user_vote_on("alice", "https://4.example/", Vote::Up))
+            .await
+            .unwrap_err();
+        service
+            .vote(vote_on("https://5.example/", Vote::Up))
+            .await
+            .unwrap();
+        service
+            .vote(user_vote_on("bob", "https://0.example/", Vote::Down))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            history_urls(&service, "alice", 0).await,
+            [
+                "https://4.example/",
+                "https://3.example/",
+                "https://2.example/"
+            ]
+        );
+        assert_eq!(
+            history_urls(&service, "alice", 2).await,
+            ["https://4.example/", "https://3.example/"]
+        );
+        assert_eq!(
+            history_urls(&service, "bob", 10).await,
+            ["https://0.example/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn vote_history_of_an_unknown_user_is_empty() {
+        let service = VotingService::default();
+        assert!(history_urls(&service, "nobody", 0).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn vote_response_has_structured_fields() {
+        let service = VotingService::default();
+        let url = "https://example.com/";
+
+        let up = service
+            .vote(vote_on(url, Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            up,
+            VotingResponse {
+                confirmation: format!("upvoted for {url}"),
+                up_count: 1,
+                down_count: 0,
+                abstain_count: 0,
+                url: url.to_string(),
+                vote: Vote::Up.into(),
+                accepted: true,
+            }
+        );
+
+        let down = service
+            .vote(vote_on(url, Vote::Down))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(down.confirmation, format!("downvoted for {url}"));
+        assert_eq!(down.vote(), Vote::Down);
+        assert!(down.accepted);
+        assert_eq!((down.up_count, down.down_count), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn rejected_vote_is_not_accepted() {
+        let service = VotingService::default();
+        let status = service
+            .vote(vote_on("not a url", Vote::Up))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // 批量投票中被拒绝的一票 accepted 为 false, 同时带有错误码
+        let results = service
+            .batch_vote(batch(
+                vec![
+                    vote_on("not a url", Vote::Up).into_inner(),
+                    vote_on("https://example.com/", Vote::Down).into_inner(),
+                ],
+                None,
+            ))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert!(!results[0].accepted);
+        assert_eq!(results[0].code, Code::InvalidArgument as i32);
+        assert!(results[1].accepted);
+        assert_eq!(results[1].code, 0);
+    }
+
+    fn reset(url: Option<&str>) -> ResetRequest {
+        ResetRequest {
+            url: url.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn reset_votes_requires_the_admin_token() {
+        let url = "https://example.com/";
+        let unconfigured = VotingService::default();
+        let status = unconfigured
+            .reset_votes(as_admin(reset(None), "secret"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        let service = VotingService::default().with_admin_token(Some("secret".to_string()));
+        service.vote(vote_on(url, Vote::Up)).await.unwrap();
+        for request in [Request::new(reset(None)), as_admin(reset(None), "guess")] {
+            let status = service.reset_votes(request).await.unwrap_err();
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+        assert_eq!(service.votes.get(url).unwrap().unwrap().up, 1);
+    }
+
+    #[tokio::test]
+    async fn reset_one_url_notifies_its_watchers() {
+        let service = VotingService::default().with_admin_token(Some("secret".to_string()));
+        let url = "https://example.com/";
+        service.vote(vote_on(url, Vote::Up)).await.unwrap();
+        service
+            .vote(vote_on("https://other.example/", Vote::Down))
+            .await
+            .unwrap();
+
+        let mut updates = service
+            .watch_votes(count_request(url, false))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(updates.next().await.unwrap().unwrap().total, 1);
+
+        let response = service
+            .reset_votes(as_admin(reset(Some(url)), "secret"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.cleared_count, 1);
+        let update = tokio::time::timeout(Duration::from_secs(5), updates.next())
+            .await
+            .expect("reset update")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (update.up_count, update.down_count, update.total),
+            (0, 0, 0)
+        );
+
+        // 其他 url 不受影响
+        assert_eq!(
+            top_urls(&service, 10, TopOrder::Total).await.unwrap(),
+            [("https://other.example/".to_string(), 0, 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn reset_all_clears_every_url() {
+        let service = VotingService::default().with_admin_token(Some("secret".to_string()));
+        for url in ["https://a.example/", "https://b.example/"] {
+            service.vote(vote_on(url, Vote::Up)).await.unwrap();
+        }
+
+        let response = service
+            .reset_votes(as_admin(reset(None), "secret"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.cleared_count, 2);
+        assert!(top_urls(&service, 10, TopOrder::Total)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // 清空后可以重新投票
+        let response = service
+            .vote(vote_on("https://a.example/", Vote::Up))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.up_count, 1);
+    }
+
+    fn point(latitude: i32, longitude: i32, floor: i32) -> Point {
+        Point {
+            latitude,
+            longitude,
+            floor,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extend_bounds_keeps_floors() {
+        let mut bounds = None;
+        for point in [point(10, 20, 2), point(30, 5, -1), point(20, 10, 4)] {
+            extend_bounds(&mut bounds, &point);
+        }
+
+        let bounds = bounds.unwrap();
+        assert_eq!(
+            (bounds.lo.unwrap(), bounds.hi.unwrap()),
+            (point(10, 5, 0), point(30, 20, 0))
+        );
+        assert_eq!((bounds.lo_floor, bounds.hi_floor), (Some(-1), Some(4)));
+    }
+
+    fn route(len: usize) -> Vec<Point> {
+        (0..len as i32)
+            .map(|i| {
+                point(
+                    400_000_000 + i * 1_000,
+                    -740_000_000 + (i % 97) * 5_000,
+                    i % 3,
+                )
+            })
+            .collect()
+    }
+
+    // 路线上每隔 10 个点放一个 feature
+    fn route_store(points: &[Point]) -> InMemoryStore {
+        InMemoryStore::new(
+            points
+                .iter()
+                .step_by(10)
+                .map(|location| Feature {
+                    name: "stop".to_string(),
+                    location: Some(location.clone()),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parallel_route_summary_matches_sequential() {
+        let points = route(10_000);
+        let count = route_store(&points).feature_counter();
+
+        let sequential = summarize_route(&*count, &points, false);
+        assert_eq!(
+            summarize_route_parallel(&*count, &points, false),
+            sequential
+        );
+        assert_eq!(sequential.point_count, 10_000);
+        assert_eq!(sequential.feature_count, 1_000);
+        assert_eq!(sequential.floor_changes, 9_999);
+    }
+
+    #[test]
+    fn feature_count_matches_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(258);
+        // 点落在一个小网格上, 路线经常经过 feature; 同一位置可以有多个 feature
+        let mut grid_point = || {
+            point(
+                rng.gen_range(0..20),
+                rng.gen_range(0..20),
+                rng.gen_range(0..2),
+            )
+        };
+        let features: Vec<_> = (0..150).map(|_| named("stop", grid_point())).collect();
+        let points: Vec<_> = (0..2_000).map(|_| grid_point()).collect();
+
+        let store = InMemoryStore::new(features.clone());
+        let linear: usize = points
+            .iter()
+            .map(|point| {
+                features
+                    .iter()
+                    .filter(|feature| feature.location.as_ref() == Some(point))
+                    .count()
+            })
+            .sum();
+        assert!(linear > 0);
+        let count = store.feature_counter();
+        assert_eq!(
+            summarize_route(&*count, &points, false).feature_count,
+            linear
+        );
+    }
+
+    #[test]
+    fn chunked_route_summary_matches_whole_route() {
+        let points = route(1_000);
+        let count = route_store(&points).feature_counter();
+
+        // 与 record_route 相同: 每块的第一个点是上一块的最后一个点
+        let mut totals = summarize_route(&*count, &points[..300], false);
+        for start in (299..points.len() - 1).step_by(300) {
+            let end = (start + 301).min(points.len());
+            totals.add(summarize_route(&*count, &points[start..end], true));
+        }
+        assert_eq!(totals, summarize_route(&*count, &points, false));
+    }
+
+    // cargo test --release --lib -- --ignored --nocapture bench_route_summary
+    #[test]
+    #[ignore]
+    fn bench_route_summary() {
+        let points = route(10_000);
+        let count = route_store(&points).feature_counter();
+        let time = |summarize: &dyn Fn() -> RouteTotals| {
+            let start = Instant::now();
+            for _ in 0..100 {
+                summarize();
+            }
+            start.elapsed() / 100
+        };
+
+        let sequential = time(&|| summarize_route(&*count, &points, false));
+        let parallel = time(&|| summarize_route_parallel(&*count, &points, false));
+        println!(
+            "10000 points: sequential {:?}, parallel {:?} ({:.1}x)",
+            sequential,
+            parallel,
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+
+    // 每个点往北约 100 米, 相隔 10 秒
+    fn timed_route(len: i32) -> Vec<Point> {
+        (0..len)
+            .map(|i| Point {
+                timestamp: 1_000_000 + i as i64 * 10_000,
+                ..point(400_000_000 + i * 9_000, -740_000_000, 0)
+            })
+            .collect()
+    }
+
+    async fn validate(points: Vec<Point>) -> ValidateRouteResponse {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())));
+        let request = Request::new(ValidateRouteRequest {
+            points,
+            max_speed_m_per_s: 30.0,
+            max_jump_m: 500,
+        });
+        service.validate_route(request).await.unwrap().into_inner()
+    }
+
+    #[tokio::test]
+    async fn validate_route_accepts_a_plausible_route() {
+        let response = validate(timed_route(5)).await;
+        assert!(response.valid);
+        assert!(response.issues.is_empty());
+    }
+
+    #[tokio::test]
+    async fn validate_route_reports_a_jump() {
+        let mut points = timed_route(5);
+        // 第 3 个点往北跳了约 1 公里, 用 100 秒走完, 速度没有超过上限
+        for point in &mut points[3..] {
+            point.latitude += 90_000;
+            point.timestamp += 100_000;
+        }
+
+        let response = validate(points).await;
+        assert!(!response.valid);
+        assert_eq!(response.issues.len(), 1);
+        assert_eq!(response.issues[0].index, 3);
+        assert!(response.issues[0].description.starts_with("jump of"));
+    }
+
+    #[tokio::test]
+    async fn validate_route_reports_a_speed_violation() {
+        let mut points = timed_route(5);
+        // 100 米只用了 1 秒
+        for point in &mut points[2..] {
+            point.timestamp -= 9_000;
+        }
+
+        let response = validate(points).await;
+        assert!(!response.valid);
+        assert_eq!(response.issues.len(), 1);
+        assert_eq!(response.issues[0].index, 2);
+        assert!(response.issues[0].description.starts_with("speed of"));
+    }
+
+    #[tokio::test]
+    async fn validate_route_accepts_an_empty_route() {
+        let response = validate(Vec::new()).await;
+        assert!(response.valid);
+        assert!(response.issues.is_empty());
+    }
+
+    fn hello(content: &str) -> Request<HelloReq> {
+        Request::new(HelloReq {
+            content: content.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn say_hello_watermark_follows_config() {
+        let plain = GreetService::new(KeywordToxicityScorer::new(&[]).unwrap());
+        let response = plain.say_hello(hello("hi there")).await.unwrap();
+        assert_eq!(response.get_ref().content, "hi there");
+
+        let marked =
+            GreetService::new(KeywordToxicityScorer::new(&[]).unwrap()).with_watermark(true);
+        let response = marked.say_hello(hello("hi there")).await.unwrap();
+        let response = response.get_ref();
+        assert_eq!(
+            watermark::extract_watermark(&response.content),
+            Some(response.request_number)
+        );
+    }
+
+    fn greeter() -> GreetService {
+        GreetService::new(KeywordToxicityScorer::new(&[]).unwrap())
+    }
+
+    async fn greeter_client(service: GreetService) -> GreeterClient<Channel> {
+        GreeterClient::new(serve(Server::builder().add_service(GreeterServer::new(service))).await)
+    }
+
+    #[tokio::test]
+    async fn say_hello_stream_sends_numbered_greetings_in_order() {
+        let mut client =
+            greeter_client(greeter().with_stream_delay(Duration::from_millis(1))).await;
+        let stream = client
+            .say_hello_stream(HelloReq {
+                content: "there".to_string(),
+                count: 5,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let contents: Vec<_> = stream
+            .map(|resp| resp.unwrap().content)
+            .collect::<Vec<_>>()
+            .await;
+        let expected: Vec<_> = (1..=5).map(|n| format!("hello #{n} there")).collect();
+        assert_eq!(contents, expected);
+
+        // count 为 0 时流直接结束
+        let mut empty = client
+            .say_hello_stream(HelloReq {
+                content: "there".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(empty.message().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn say_hello_stream_caps_the_count() {
+        let service = greeter();
+        let request = |count| {
+            Request::new(HelloReq {
+                content: "there".to_string(),
+                count,
+                ..Default::default()
+            })
+        };
+        let status = service
+            .say_hello_stream(request(MAX_GREETING_COUNT + 1))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(service
+            .say_hello_stream(request(MAX_GREETING_COUNT))
+            .await
+            .is_ok());
+    }
+
+    fn greetings(contents: &[&str]) -> Vec<HelloReq> {
+        contents
+            .iter()
+            .map(|content| HelloReq {
+                content: content.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lots_of_greetings_summarizes_the_stream() {
+        let mut client = greeter_client(greeter()).await;
+
+        let summary = client
+            .lots_of_greetings(tokio_stream::iter(Vec::new()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary, GreetingSummary::default());
+
+        // 按字符数比较, "你好世界" 有 12 个字节但只有 4 个字符
+        let summary = client
+            .lots_of_greetings(tokio_stream::iter(greetings(&[
+                "hi",
+                "你好世界",
+                "hello",
+                "abcde",
+            ])))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.greeting_count, 4);
+        assert_eq!(summary.total_bytes, 2 + 12 + 5 + 5);
+        assert_eq!(summary.longest_content, "hello");
+    }
+
+    #[tokio::test]
+    async fn lots_of_greetings_returns_the_partial_summary_on_error() {
+        let mut client = greeter_client(greeter()).await;
+        let status = client
+            .lots_of_greetings(tokio_stream::iter(greetings(&[
+                "hi", "hello", " ", "later",
+            ])))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let partial = GreetingSummary::decode(status.details()).unwrap();
+        assert_eq!(partial.greeting_count, 2);
+        assert_eq!(partial.total_bytes, 7);
+        assert_eq!(partial.longest_content, "hello");
+    }
+
+    #[tokio::test]
+    async fn greet_chat_answers_each_message_in_order() {
+        let mut client = greeter_client(greeter()).await;
+        let started = featurestore::now_millis();
+        let outbound = async_stream::stream! {
+            for i in 1..=5 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                yield HelloReq {
+                    content: format!("message {i}"),
+                    ..Default::default()
+                };
+            }
+        };
+
+        let replies: Vec<_> = client
+            .greet_chat(outbound)
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|reply| reply.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        // 客户端结束发送后服务端结束回复流
+        assert_eq!(replies.len(), 5);
+        for (i, reply) in replies.iter().enumerate() {
+            assert_eq!(reply.sequence, i as u64 + 1);
+            assert_eq!(reply.content, format!("hello, message {}", i + 1));
+            assert!(reply.timestamp_millis >= started);
+        }
+    }
+
+    #[tokio::test]
+    async fn greet_chat_ends_with_the_inbound_error() {
+        let mut client = greeter_client(greeter()).await;
+        let mut replies = client
+            .greet_chat(tokio_stream::iter(greetings(&[
+                "first",
+                "",
+                "never answered",
+            ])))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(replies.message().await.unwrap().unwrap().sequence, 1);
+        let status = replies.message().await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn say_hello_greets_in_the_accepted_language() {
+        let service = greeter();
+        let with_language = |value: MetadataValue<tonic::metadata::Ascii>| {
+            let mut request = hello("world");
+            request.metadata_mut().insert(ACCEPT_LANGUAGE, value);
+            request
+        };
+
+        let response = service
+            .say_hello(with_language(MetadataValue::from_static("fr, es;q=0.5")))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().content, "Hola, world");
+        assert_eq!(response.metadata().get(CONTENT_LANGUAGE).unwrap(), "es");
+
+        // 不是 ASCII 的值按默认语言处理
+        let response = service
+            .say_hello(with_language(
+                MetadataValue::try_from("中文".as_bytes()).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().content, "Hello, world");
+        assert_eq!(response.metadata().get(CONTENT_LANGUAGE).unwrap(), "en");
+
+        // 没有 accept-language 时原样返回
+        let response = service.say_hello(hello("world")).await.unwrap();
+        assert_eq!(response.get_ref().content, "world");
+        assert!(response.metadata().get(CONTENT_LANGUAGE).is_none());
+    }
+
+    #[tokio::test]
+    async fn say_hello_checks_the_content_size() {
+        let service = greeter().with_max_content_bytes(8);
+        for content in ["", " \t\n"] {
+            let status = service.say_hello(hello(content)).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(field_violation(&status).description, "must not be empty");
+        }
+
+        // 上限按字节计算
+        service.say_hello(hello("12345678")).await.unwrap();
+        let status = service.say_hello(hello("你好世")).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let violation = field_violation(&status);
+        assert_eq!(violation.field, "content");
+        assert_eq!(violation.description, "is 9 bytes, at most 8 allowed");
+    }
+
+    #[tokio::test]
+    async fn say_hello_numbers_requests_and_stamps_the_server() {
+        let service = greeter();
+        let before = featurestore::now_millis();
+        let mut numbers = Vec::new();
+        for _ in 0..3 {
+            let response = service.say_hello(hello("hi")).await.unwrap().into_inner();
+            assert!(response.timestamp_millis >= before);
+            assert!(response.timestamp_millis <= featurestore::now_millis());
+            assert_eq!(response.hostname, service.hostname);
+            assert!(!response.hostname.is_empty());
+            numbers.push(response.request_number);
+        }
+        assert_eq!(numbers, [1, 2, 3]);
+
+        // 被拒绝的请求不占用序号
+        service.say_hello(hello("")).await.unwrap_err();
+        let response = service.say_hello(hello("hi")).await.unwrap().into_inner();
+        assert_eq!(response.request_number, 4);
+    }
+
+    #[tokio::test]
+    async fn say_hello_echoes_x_echo_headers() {
+        let mut request = hello("hi");
+        let metadata = request.metadata_mut();
+        metadata.insert("x-echo-trace", "abc".parse().unwrap());
+        metadata.append("x-echo-trace", "def".parse().unwrap());
+        metadata.insert_bin("x-echo-blob-bin", MetadataValue::from_bytes(&[0, 159, 255]));
+        metadata.insert("x-other", "dropped".parse().unwrap());
+
+        let response = greeter().say_hello(request).await.unwrap();
+        let echoed = response.metadata();
+        let traces: Vec<_> = echoed.get_all("x-echo-trace").iter().collect();
+        assert_eq!(traces, ["abc", "def"]);
+        assert_eq!(
+            echoed
+                .get_bin("x-echo-blob-bin")
+                .unwrap()
+                .to_bytes()
+                .unwrap(),
+            &[0, 159, 255][..]
+        );
+        assert!(echoed.get("x-other").is_none());
+    }
+
+    #[tokio::test]
+    async fn say_hello_caps_echoed_headers() {
+        let service = greeter();
+        let mut too_many = hello("hi");
+        for i in 0..=MAX_ECHO_HEADERS {
+            too_many.metadata_mut().insert(
+                tonic::metadata::MetadataKey::from_bytes(format!("x-echo-{i}").as_bytes()).unwrap(),
+                "v".parse().unwrap(),
+            );
+        }
+        let status = service.say_hello(too_many).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let mut too_large = hello("hi");
+        too_large
+            .metadata_mut()
+            .insert("x-echo-big", "v".repeat(MAX_ECHO_BYTES).parse().unwrap());
+        let status = service.say_hello(too_large).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    fn delayed(delay_ms: u32) -> HelloReq {
+        HelloReq {
+            content: "hi".to_string(),
+            delay_ms,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn say_hello_waits_for_delay_ms_up_to_the_cap() {
+        let service = greeter().with_max_delay(Duration::from_millis(100));
+
+        let started = std::time::Instant::now();
+        service.say_hello(Request::new(delayed(50))).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let started = std::time::Instant::now();
+        let status = service
+            .say_hello(Request::new(delayed(101)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "delay_ms must be at most 100");
+        // 超过上限时不等待
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn say_hello_delay_past_the_client_deadline_times_out() {
+        let mut client = greeter_client(greeter()).await;
+        let mut request = Request::new(delayed(1_000));
+        request.set_timeout(Duration::from_millis(50));
+
+        let started = std::time::Instant::now();
+        let status = client.say_hello(request).await.unwrap_err();
+        // tonic 0.9 的服务端按 grpc-timeout 丢弃请求时返回 CANCELLED
+        assert_eq!(status.code(), Code::Cancelled, "{status:?}");
+        assert!(started.elapsed() < Duration::from_millis(1_000));
+    }
+
+    #[tokio::test]
+    async fn fault_injection_is_off_by_default() {
+        let failing = || {
+            let mut request = hello("hi");
+            request
+                .metadata_mut()
+                .insert(faults::FAIL_WITH_HEADER, "unavailable".parse().unwrap());
+            request
+        };
+
+        greeter().say_hello(failing()).await.unwrap();
+
+        let status = greeter()
+            .with_fault_injection(true)
+            .say_hello(failing())
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+    }
+
+    fn hello_named(name: &str, content: &str) -> Request<HelloReq> {
+        Request::new(HelloReq {
+            content: content.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn say_hello_fills_the_template_with_the_name() {
+        let response = greeter()
+            .say_hello(hello_named("Mary-Jane Watson", "nice to meet you"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.get_ref().content,
+            "Hello, Mary-Jane Watson! nice to meet you"
+        );
+
+        // content 中的 {name} 不会被替换
+        let service = greeter().with_template("{content} <- {name}");
+        let response = service
+            .say_hello(hello_named("Zoë", "{name}"))
+            .await
+            .unwrap();
+        assert_eq!(response.get_ref().content, "{name} <- Zoë");
+
+        // 没有 name 时原样返回
+        let response = service.say_hello(hello_named("", "plain")).await.unwrap();
+        assert_eq!(response.get_ref().content, "plain");
+    }
+
+    #[tokio::test]
+    async fn say_hello_rejects_invalid_names() {
+        let service = greeter();
+        for (name, description) in [
+            ("   ", "must not be blank"),
+            (
+                "Robert'); DROP",
+                "may only contain letters, spaces and hyphens",
+            ),
+            ("R2D2", "may only contain letters, spaces and hyphens"),
+        ] {
+            let status = service
+                .say_hello(hello_named(name, "hi"))
+                .await
+                .unwrap_err();
+            let violation = field_violation(&status);
+            assert_eq!(violation.field, "name");
+            assert_eq!(violation.description, description, "{name:?}");
+        }
+
+        let longest = "a".repeat(MAX_NAME_CHARS);
+        service
+            .say_hello(hello_named(&longest, "hi"))
+            .await
+            .unwrap();
+        let status = service
+            .say_hello(hello_named(&format!("{longest}a"), "hi"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            field_violation(&status).description,
+            "must be at most 64 characters"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_greet_stats_counts_connected_peers() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(GreeterServer::new(greeter().with_max_tracked_peers(2)))
+                .serve_with_incoming(incoming),
+        );
+
+        // 每个连接是一个不同的对端端口
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            let channel = Endpoint::from_shared(format!("http://{address}"))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            clients.push(GreeterClient::new(channel));
+        }
+        for (i, client) in clients.iter_mut().enumerate() {
+            for _ in 0..=i {
+                client.say_hello(hello("hi").into_inner()).await.unwrap();
+            }
+        }
+
+        let stats = clients[0]
+            .get_greet_stats(GreetEmpty {})
+            .await
+            .unwrap()
+            .into_inner();
+        // 最多记住 2 个对端, 第一个连接被淘汰; get_greet_stats 本身不计入
+        assert_eq!(stats.total_requests, 6);
+        assert_eq!(stats.unique_peers, 2);
+        let counts: Vec<_> = stats.top_peers.iter().map(|peer| peer.count).collect();
+        assert_eq!(counts, [3, 2]);
+        assert!(stats
+            .top_peers
+            .iter()
+            .all(|peer| peer.peer.starts_with("127.0.0.1:")));
+    }
+
+    #[tokio::test]
+    async fn repeated_greeting_is_throttled_with_retry_after() {
+        let service = greeter().with_repeat_limit(Some((3, Duration::from_millis(100))));
+        for _ in 0..3 {
+            service.say_hello(hello("same")).await.unwrap();
+        }
+        let status = service.say_hello(hello("same")).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "1");
+
+        service.say_hello(hello("different")).await.unwrap();
+        // 默认不限制
+        let unlimited = greeter();
+        for _ in 0..10 {
+            unlimited.say_hello(hello("same")).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn watch_greetings_sees_greetings_from_another_client() {
+        let channel = serve(Server::builder().add_service(GreeterServer::new(greeter()))).await;
+        let mut watcher = GreeterClient::new(channel.clone())
+            .watch_greetings(GreetEmpty {})
+            .await
+            .unwrap()
+            .into_inner();
+
+        let greeter = tokio::spawn(async move {
+            let mut client = GreeterClient::new(channel);
+            for i in 1..=3 {
+                client
+                    .say_hello(hello(&format!("greeting {i}")).into_inner())
+                    .await
+                    .unwrap();
+            }
+        });
+        greeter.await.unwrap();
+
+        for i in 1..=3 {
+            let resp = tokio::time::timeout(Duration::from_secs(5), watcher.message())
+                .await
+                .expect("watched greeting")
+                .unwrap()
+                .unwrap();
+            assert_eq!(resp.content, format!("greeting {i}"));
+            assert_eq!(resp.missed, 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn lagging_greeting_watcher_is_told_how_many_it_missed() {
+        let service = greeter();
+        let mut watcher = service
+            .watch_greetings(Request::new(GreetEmpty {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let sent = GREETING_WATCH_CAPACITY as u64 * 2;
+        for i in 0..sent {
+            service.say_hello(hello(&format!("hi {i}"))).await.unwrap();
+        }
+
+        // 收到的问候加上 missed 正好是发送的问候数
+        let (mut received, mut missed) = (0, 0);
+        while received + missed < sent {
+            let resp = tokio::time::timeout(Duration::from_secs(5), watcher.next())
+                .await
+                .expect("watched greeting")
+                .unwrap()
+                .unwrap();
+            if resp.missed > 0 {
+                assert!(resp.content.is_empty());
+                missed += resp.missed;
+            } else {
+                received += 1;
+            }
+        }
+        assert!(missed > 0);
+        assert_eq!(received + missed, sent);
+    }
+
+    #[tokio::test]
+    async fn greetings_are_written_to_the_audit_log() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+        let service = greeter().with_audit_log(Some(AuditLog::open(&path, 1 << 20).unwrap()));
+        service.say_hello(hello("first")).await.unwrap();
+        // 被拒绝的请求也会记录
+        service.say_hello(hello(" ")).await.unwrap_err();
+
+        let mut lines = Vec::new();
+        for _ in 0..500 {
+            lines = std::fs::read_to_string(&path)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect();
+            if lines.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let contents: Vec<_> = lines.iter().map(|line| line["content"].clone()).collect();
+        assert_eq!(contents, ["first", " "]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_features_deadline_counts_only_sent_features() {
+        let features = (0..10)
+            .map(|i| Feature {
+                name: format!("feature {}", i),
+                location: Some(point(i, i, 0)),
+                ..Default::default()
+            })
+            .collect();
+        let service =
+            RouteGuideService::new(Arc::new(InMemoryStore::new(features))).with_channel_capacity(1);
+        let mut request = Request::new(ListFeaturesRequest {
+            rect: Some(Rectangle {
+                lo: Some(point(0, 0, 0)),
+                hi: Some(point(100, 100, 0)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        request
+            .metadata_mut()
+            .insert("grpc-timeout", "100m".parse().unwrap());
+        let mut stream = service.list_features(request).await.unwrap().into_inner();
+
+        // 不读取, 让发送方在通道满的时候到期
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let mut received = 0;
+        let status = loop {
+            match stream.next().await.unwrap() {
+                Ok(_) => received += 1,
+                Err(status) => break status,
+            }
+        };
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert_eq!(
+            status.metadata().get(NEXT_PAGE_TOKEN).unwrap(),
+            received.to_string().as_str()
+        );
+    }
+
+    fn named(name: &str, location: Point) -> Feature {
+        Feature {
+            name: name.to_string(),
+            location: Some(location),
+            ..Default::default()
+        }
+    }
+
+    // 纬度方向 1 米大约是 90 个单位
+    async fn feature_name(service: &RouteGuideService, latitude: i32) -> String {
+        let request = Request::new(point(latitude, -740_000_000, 0));
+        service
+            .get_feature(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .name
+    }
+
+    fn tolerance_service(features: Vec<Feature>, tolerance: i32) -> RouteGuideService {
+        RouteGuideService::new(Arc::new(InMemoryStore::new(features))).with_tolerance(tolerance)
+    }
+
+    #[tokio::test]
+    async fn get_feature_exact_match() {
+        let features = vec![named("a", point(400_000_000, -740_000_000, 0))];
+        for tolerance in [0, 10] {
+            let service = tolerance_service(features.clone(), tolerance);
+            assert_eq!(feature_name(&service, 400_000_000).await, "a");
+        }
+        // 不设 tolerance 时必须完全相同
+        let service = tolerance_service(features, 0);
+        assert_eq!(feature_name(&service, 400_000_001).await, "");
+    }
+
+    // Patriots Path 的位置, 纬度方向 450 个单位大约是 5 米
+    const PATRIOTS_PATH: (i32, i32) = (407838351, -746143763);
+
+    async fn dataset_feature_name(service: &RouteGuideService, latitude: i32) -> String {
+        let request = Request::new(point(latitude, PATRIOTS_PATH.1, 0));
+        service
+            .get_feature(request)
+            .await
+            .unwrap()
+            .into_inner()
+            .name
+    }
+
+    #[tokio::test]
+    async fn get_feature_within_tolerance() {
+        let service = tolerance_service(load(), 10);
+        assert_eq!(
+            dataset_feature_name(&service, PATRIOTS_PATH.0 + 450).await,
+            "Patriots Path, Mendham, NJ 07945, USA"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_feature_outside_tolerance() {
+        let service = tolerance_service(load(), 10);
+        assert_eq!(
+            dataset_feature_name(&service, PATRIOTS_PATH.0 + 45_000).await,
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn get_feature_prefers_the_closest_match() {
+        let service = tolerance_service(
+            vec![
+                named("near", point(400_000_500, -740_000_000, 0)),
+                named("nearer", point(400_000_200, -740_000_000, 0)),
+            ],
+            10,
+        );
+        assert_eq!(feature_name(&service, 400_000_000).await, "nearer");
+    }
+
+    #[tokio::test]
+    async fn get_feature_tie_prefers_dataset_order() {
+        let north = named("north", point(400_000_500, -740_000_000, 0));
+        let south = named("south", point(399_999_500, -740_000_000, 0));
+
+        let service = tolerance_service(vec![north.clone(), south.clone()], 10);
+        assert_eq!(feature_name(&service, 400_000_000).await, "north");
+        let service = tolerance_service(vec![south, north], 10);
+        assert_eq!(feature_name(&service, 400_000_000).await, "south");
+    }
+
+    async fn nearest(service: &RouteGuideService, latitude: i32, longitude: i32) -> NearestFeature {
+        service
+            .get_nearest_feature(Request::new(point(latitude, longitude, 0)))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn get_nearest_feature_probes_the_bundled_dataset() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+
+        // 正好在 feature 上
+        let on = nearest(&service, 407838351, -746143763).await;
+        assert_eq!(
+            on.feature.unwrap().name,
+            "Patriots Path, Mendham, NJ 07945, USA"
+        );
+        assert_eq!(on.distance_meters, 0.0);
+
+        // 往北大约 1 公里
+        let north = nearest(&service, 408212808, -743999179).await;
+        assert_eq!(
+            north.feature.unwrap().name,
+            "101 New Jersey 10, Whippany, NJ 07981, USA"
+        );
+        assert!((900.0..1100.0).contains(&north.distance_meters));
+
+        // Kingston 附近
+        let kingston = nearest(&service, 420000000, -740000000).await;
+        assert_eq!(
+            kingston.feature.unwrap().name,
+            "5 Conners Road, Kingston, NY 12401, USA"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_nearest_feature_on_empty_store_is_not_found() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())));
+        let status = service
+            .get_nearest_feature(Request::new(point(0, 0, 0)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    // 在随机端口上运行 router, 返回连接好的 channel; 测试结束时服务随运行时一起停止
+    async fn serve(router: tonic::transport::server::Router) -> Channel {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(router.serve_with_incoming(incoming));
+
+        Endpoint::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap()
+    }
+
+    fn whole_map() -> Rectangle {
+        Rectangle {
+            lo: Some(point(-900_000_000, -1_800_000_000, 0)),
+            hi: Some(point(900_000_000, 1_800_000_000, 0)),
+            ..Default::default()
+        }
+    }
+
+    // 读完 list_features 的流, 返回收到的 feature 和 trailers 中的下一页 token
+    async fn list(
+        service: &RouteGuideService,
+        request: ListFeaturesRequest,
+    ) -> Result<(Vec<Feature>, String), Status> {
+        let mut stream = service
+            .list_features(Request::new(request))
+            .await?
+            .into_inner();
+        let mut features = Vec::new();
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(feature) => features.push(feature),
+                Err(status) if status.code() == Code::Ok => {
+                    let token = status.metadata().get(NEXT_PAGE_TOKEN).unwrap();
+                    return Ok((features, token.to_str().unwrap().to_string()));
+                }
+                Err(status) => return Err(status),
+            }
+        }
+
+        Ok((features, String::new()))
+    }
+
+    fn numbered_features(count: i32) -> Vec<Feature> {
+        (0..count)
+            .map(|i| named(&format!("feature {}", i), point(i, i, 0)))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn list_features_streams_thousands_of_features() {
+        let service =
+            RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(5_000))))
+                .with_channel_capacity(64);
+        let (features, next_page_token) = list(
+            &service,
+            ListFeaturesRequest {
+                rect: Some(whole_map()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(features.len(), 5_000);
+        assert_eq!(features[4_999].name, "feature 4999");
+        assert_eq!(next_page_token, "");
+    }
+
+    fn page(page_size: u32, page_token: &str) -> ListFeaturesRequest {
+        ListFeaturesRequest {
+            rect: Some(whole_map()),
+            page_size,
+            page_token: page_token.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn feature_names(features: &[Feature]) -> Vec<&str> {
+        features
+            .iter()
+            .map(|feature| feature.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn list_features_pages_until_an_empty_token() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(10))));
+
+        let (first, token) = list(&service, page(4, "")).await.unwrap();
+        assert_eq!(
+            feature_names(&first),
+            ["feature 0", "feature 1", "feature 2", "feature 3"]
+        );
+        assert_eq!(token, "4");
+        let (second, token) = list(&service, page(4, &token)).await.unwrap();
+        assert_eq!(second.len(), 4);
+        assert_eq!(token, "8");
+        let (last, token) = list(&service, page(4, &token)).await.unwrap();
+        assert_eq!(feature_names(&last), ["feature 8", "feature 9"]);
+        assert_eq!(token, "");
+    }
+
+    #[tokio::test]
+    async fn list_features_full_last_page_has_an_empty_token() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(8))));
+        let (last, token) = list(&service, page(4, "4")).await.unwrap();
+        assert_eq!(last.len(), 4);
+        assert_eq!(token, "");
+    }
+
+    #[tokio::test]
+    async fn list_features_page_size_zero_is_unlimited() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(10))));
+        let (features, token) = list(&service, page(0, "")).await.unwrap();
+        assert_eq!(features.len(), 10);
+        assert_eq!(token, "");
+    }
+
+    #[tokio::test]
+    async fn list_features_rejects_an_invalid_page_token() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(10))));
+        for token in ["abc", "-1"] {
+            let status = list(&service, page(4, token)).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+    }
+
+    fn name_filter(pattern: &str) -> ListFeaturesRequest {
+        ListFeaturesRequest {
+            rect: Some(whole_map()),
+            name_filter: pattern.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn list_features_name_filter_matches() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let (features, _) = list(&service, name_filter("Road, .*NY")).await.unwrap();
+        assert!(!features.is_empty());
+        assert!(features
+            .iter()
+            .all(|feature| feature.name.contains("Road") && feature.name.contains("NY")));
+        assert!(feature_names(&features).contains(&"5 Conners Road, Kingston, NY 12401, USA"));
+
+        // 空的过滤条件不过滤
+        let (all, _) = list(&service, name_filter("")).await.unwrap();
+        assert_eq!(all.len(), load().len());
+    }
+
+    #[tokio::test]
+    async fn list_features_name_filter_without_matches() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let (features, _) = list(&service, name_filter("^Atlantis")).await.unwrap();
+        assert!(features.is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_features_rejects_an_invalid_name_filter() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let status = list(&service, name_filter("(unclosed")).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status.message().starts_with("invalid name_filter"));
+    }
+
+    #[tokio::test]
+    async fn list_features_orders_by_distance_from_a_point() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let request = ListFeaturesRequest {
+            rect: Some(whole_map()),
+            order_from: Some(point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0)),
+            ..Default::default()
+        };
+        let (features, _) = list(&service, request).await.unwrap();
+
+        let names = feature_names(&features);
+        assert_eq!(names.len(), load().len());
+        assert_eq!(
+            names[..4],
+            [
+                "Patriots Path, Mendham, NJ 07945, USA",
+                "101 New Jersey 10, Whippany, NJ 07981, USA",
+                "1 Merck Access Road, Whitehouse Station, NJ 08889, USA",
+                "Clinton Road, West Milford, NJ 07480, USA",
+            ]
+        );
+        assert_eq!(names[18], "5 Conners Road, Kingston, NY 12401, USA");
+
+        // 不指定 order_from 时保持数据集的顺序
+        let (unordered, _) = list(&service, page(0, "")).await.unwrap();
+        assert_eq!(unordered, load());
+    }
+
+    // cargo test --release --lib -- --ignored --nocapture bench_feature_channel
+    #[tokio::test]
+    #[ignore]
+    async fn bench_feature_channel() {
+        let features: Vec<_> = (0..5_000)
+            .map(|i| Arc::new(named(&"x".repeat(10_000), point(i, i, 0))))
+            .collect();
+
+        let start = Instant::now();
+        let (tx, mut rx) = mpsc::channel(64);
+        let send = tokio::spawn({
+            let features = features.clone();
+            async move {
+                for feature in features {
+                    tx.send(feature).await.unwrap();
+                }
+            }
+        });
+        while rx.recv().await.is_some() {}
+        send.await.unwrap();
+        let shared = start.elapsed();
+
+        let start = Instant::now();
+        let (tx, mut rx) = mpsc::channel(64);
+        let send = tokio::spawn(async move {
+            for feature in &features {
+                tx.send(Feature::clone(feature)).await.unwrap();
+            }
+        });
+        while rx.recv().await.is_some() {}
+        send.await.unwrap();
+        let cloned = start.elapsed();
+
+        println!(
+            "5000 features with 10KB names: Arc {:?}, clone {:?} ({:.1}x)",
+            shared,
+            cloned,
+            cloned.as_secs_f64() / shared.as_secs_f64()
+        );
+    }
+
+    #[tokio::test]
+    async fn list_features_survives_client_disconnect() {
+        let service =
+            RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(1_000))))
+                .with_channel_capacity(1);
+        let mut client = RouteGuideClient::new(
+            serve(Server::builder().add_service(RouteGuideServer::new(service))).await,
+        );
+        let request = || ListFeaturesRequest {
+            rect: Some(whole_map()),
+            ..Default::default()
+        };
+
+        // 读一条后断开
+        let mut stream = client.list_features(request()).await.unwrap().into_inner();
+        assert!(stream.message().await.unwrap().is_some());
+        drop(stream);
+
+        // 服务继续处理之后的请求
+        let mut stream = client.list_features(request()).await.unwrap().into_inner();
+        let mut received = 0;
+        while stream.message().await.unwrap().is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 1_000);
+        let feature = client.get_feature(point(7, 7, 0)).await.unwrap();
+        assert_eq!(feature.into_inner().name, "feature 7");
+    }
+
+    #[tokio::test]
+    async fn missing_fields_are_rejected_and_the_server_stays_up() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(numbered_features(10))));
+        let mut client = RouteGuideClient::new(
+            serve(Server::builder().add_service(RouteGuideServer::new(service))).await,
+        );
+
+        let status = client
+            .list_features(ListFeaturesRequest {
+                rect: Some(Rectangle {
+                    lo: None,
+                    ..whole_map()
+                }),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "missing field: rect.lo");
+
+        let note = RouteNote {
+            location: None,
+            message: "nowhere".to_string(),
+        };
+        let mut notes = client
+            .route_chat(tokio_stream::iter(vec![note]))
+            .await
+            .unwrap()
+            .into_inner();
+        let status = notes.message().await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "missing field: location");
+
+        // 之后的请求照常处理
+        let mut stream = client
+            .list_features(ListFeaturesRequest {
+                rect: Some(whole_map()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut received = 0;
+        while stream.message().await.unwrap().is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 10);
+
+        let note = RouteNote {
+            location: Some(point(1, 1, 0)),
+            message: "here".to_string(),
+        };
+        let mut notes = client
+            .route_chat(tokio_stream::iter(vec![note]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(notes.message().await.unwrap().unwrap().message, "here");
+    }
+
+    fn as_admin<T>(message: T, token: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request
+            .metadata_mut()
+            .insert(ADMIN_TOKEN_HEADER, token.parse().unwrap());
+        request
+    }
+
+    async fn pending_corrections(
+        service: &RouteGuideService,
+        token: &str,
+    ) -> Result<Vec<Correction>, Status> {
+        let stream = service
+            .list_pending_corrections(as_admin(Empty {}, token))
+            .await?
+            .into_inner();
+        stream.collect::<Result<Vec<_>, _>>().await
+    }
+
+    #[tokio::test]
+    async fn feature_correction_lifecycle() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())))
+            .with_admin_token(Some("secret".to_string()));
+        let renamed = "Patriots Path, Mendham, NJ 07945";
+
+        let response = service
+            .suggest_feature_correction(Request::new(CorrectionRequest {
+                feature_location: Some(point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0)),
+                suggested_name: renamed.to_string(),
+                correction_reason: "official trail name".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // 非管理员不能查看或批准; authorization 头不算管理员令牌
+        for token in ["wrong", ""] {
+            let status = pending_corrections(&service, token).await.unwrap_err();
+            assert_eq!(status.code(), Code::PermissionDenied);
+        }
+        let mut request = Request::new(Empty {});
+        request
+            .metadata_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        let status = service
+            .list_pending_corrections(request)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let approve = || ApproveCorrectionRequest {
+            correction_id: response.correction_id.clone(),
+        };
+        let status = service
+            .approve_correction(as_admin(approve(), "wrong"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_ne!(
+            dataset_feature_name(&service, PATRIOTS_PATH.0).await,
+            renamed
+        );
+
+        let pending = pending_corrections(&service, "secret").await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].correction_id, response.correction_id);
+        assert_eq!(pending[0].suggested_name, renamed);
+
+        service
+            .approve_correction(as_admin(approve(), "secret"))
+            .await
+            .unwrap();
+        assert_eq!(
+            dataset_feature_name(&service, PATRIOTS_PATH.0).await,
+            renamed
+        );
+        assert!(pending_corrections(&service, "secret")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // 同一条建议不能批准两次
+        let status = service
+            .approve_correction(as_admin(approve(), "secret"))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn corrections_need_a_known_feature() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let status = service
+            .suggest_feature_correction(Request::new(CorrectionRequest {
+                feature_location: Some(point(1, 1, 0)),
+                suggested_name: "nowhere".to_string(),
+                correction_reason: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // 没有配置管理员令牌时任何令牌都被拒绝
+        let status = pending_corrections(&service, "secret").await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    async fn route_client(service: RouteGuideService) -> RouteGuideClient<Channel> {
+        RouteGuideClient::new(
+            serve(Server::builder().add_service(RouteGuideServer::new(service))).await,
+        )
+    }
+
+    #[tokio::test]
+    async fn record_route_reports_elapsed_milliseconds() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+
+        let points = async_stream::stream! {
+            for i in 0..3 {
+                if i > 0 {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                yield point(400_000_000 + i * 9_000, -740_000_000, 0);
+            }
+        };
+        let summary = client.record_route(points).await.unwrap().into_inner();
+
+        assert_eq!(summary.point_count, 3);
+        assert!(
+            (200..2_000).contains(&summary.elapsed_time_millis),
+            "{}",
+            summary.elapsed_time_millis
+        );
+        // 整秒字段保留兼容, 不到一秒时为 0
+        assert_eq!(summary.elapsed_time, 0);
+    }
+
+    #[tokio::test]
+    async fn record_route_accepts_coordinates_on_the_boundary() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let points = vec![
+            point(900_000_000, 1_800_000_000, 0),
+            point(-900_000_000, -1_800_000_000, 0),
+            point(0, 0, 0),
+        ];
+
+        let summary = client
+            .record_route(tokio_stream::iter(points))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.point_count, 3);
+    }
+
+    #[tokio::test]
+    async fn record_route_rejects_out_of_range_coordinates() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let cases = [
+            (
+                point(900_000_001, 0, 0),
+                "point 1: latitude 900000001 out of range",
+            ),
+            (
+                point(-900_000_001, 0, 0),
+                "point 1: latitude -900000001 out of range",
+            ),
+            (
+                point(0, 1_800_000_001, 0),
+                "point 1: longitude 1800000001 out of range",
+            ),
+            (
+                point(0, i32::MIN, 0),
+                "point 1: longitude -2147483648 out of range",
+            ),
+        ];
+
+        for (bad, message) in cases {
+            let points = vec![point(0, 0, 0), bad, point(1, 1, 0)];
+            let status = client
+                .record_route(tokio_stream::iter(points))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(status.message(), message);
+            // 越界点之前的点仍然被汇总
+            let partial = RouteSummary::decode(status.details()).unwrap();
+            assert_eq!(partial.point_count, 1);
+        }
+    }
+
+    // 把 points 编码成 gRPC 帧, 像客户端流一样交给 record_route; Err 表示客户端流出错
+    #[allow(clippy::result_large_err)]
+    fn point_stream(
+        points: impl Stream<Item = Result<Point, Status>> + Send + 'static,
+    ) -> Request<Streaming<Point>> {
+        use tonic::codec::{Codec, ProstCodec};
+
+        let frames = points.map(|point| {
+            point.map(|point| {
+                let message = point.encode_to_vec();
+                let mut frame = vec![0];
+                frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&message);
+                axum::body::Bytes::from(frame)
+            })
+        });
+        let decoder = ProstCodec::<Point, Point>::default().decoder();
+        let body = axum::body::StreamBody::new(frames);
+        Request::new(Streaming::new_request(decoder, body, None, None))
+    }
+
+    #[tokio::test]
+    async fn record_route_returns_a_partial_summary_when_the_stream_fails() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())));
+        let points = tokio_stream::iter(vec![
+            Ok(point(400_000_000, -740_000_000, 0)),
+            Ok(point(400_009_000, -740_000_000, 0)),
+            Err(Status::unavailable("client went away")),
+            Ok(point(400_018_000, -740_000_000, 0)),
+        ]);
+
+        let status = service
+            .record_route(point_stream(points))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "client went away");
+        let partial = RouteSummary::decode(status.details()).unwrap();
+        assert_eq!(partial.point_count, 2);
+        assert!(partial.distance > 0);
+    }
+
+    const HOUR_MS: i64 = 3_600_000;
+
+    fn temporal_features(now: i64) -> Vec<Feature> {
+        let window = |name: &str, i, valid_from, valid_until| Feature {
+            valid_from,
+            valid_until,
+            ..named(name, point(i, i, 0))
+        };
+        vec![
+            window("always", 0, 0, 0),
+            window("expired", 1, 0, now - 24 * HOUR_MS),
+            window("active", 2, now - HOUR_MS, now + HOUR_MS),
+            window("upcoming", 3, now + 24 * HOUR_MS, 0),
+            window("since last week", 4, now - 7 * 24 * HOUR_MS, 0),
+        ]
+    }
+
+    fn at_time(at_time: i64) -> ListFeaturesRequest {
+        ListFeaturesRequest {
+            rect: Some(whole_map()),
+            at_time,
+            ..Default::default()
+        }
+    }
+
+    async fn names_at(service: &RouteGuideService, time: i64) -> Vec<String> {
+        let (features, _) = list(service, at_time(time)).await.unwrap();
+        features.into_iter().map(|feature| feature.name).collect()
+    }
+
+    #[tokio::test]
+    async fn list_features_at_time_defaults_to_now() {
+        let now = featurestore::now_millis();
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(temporal_features(now))));
+
+        let stream = service
+            .list_features_at_time(Request::new(at_time(0)))
+            .await
+            .unwrap()
+            .into_inner();
+        let names: Vec<_> = stream
+            .filter_map(|item| item.ok())
+            .map(|feature| feature.name)
+            .collect()
+            .await;
+        assert_eq!(names, ["always", "active", "since last week"]);
+
+        // ListFeatures 不设置 at_time 时不按时间过滤
+        assert_eq!(names_at(&service, 0).await.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn list_features_in_the_past_and_future() {
+        let now = featurestore::now_millis();
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(temporal_features(now))));
+
+        assert_eq!(
+            names_at(&service, now - 30 * 24 * HOUR_MS).await,
+            ["always", "expired"]
+        );
+        assert_eq!(
+            names_at(&service, now + 30 * 24 * HOUR_MS).await,
+            ["always", "upcoming", "since last week"]
+        );
+    }
+
+    #[tokio::test]
+    async fn validity_windows_are_half_open() {
+        let now = featurestore::now_millis();
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(temporal_features(now))));
+
+        let active = |names: Vec<String>| names.iter().any(|name| name == "active");
+        assert!(active(names_at(&service, now - HOUR_MS).await));
+        assert!(active(names_at(&service, now + HOUR_MS - 1).await));
+        assert!(!active(names_at(&service, now + HOUR_MS).await));
+        assert!(!active(names_at(&service, now - HOUR_MS - 1).await));
+    }
+
+    #[tokio::test]
+    async fn record_route_reports_the_bounding_box() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())));
+        let points = vec![
+            point(407_838_351, -746_143_763, 0),
+            point(408_122_808, -743_999_179, 0),
+            point(413_628_156, -749_015_468, 0),
+        ];
+
+        let summary = service
+            .record_route(point_stream(tokio_stream::iter(points.into_iter().map(Ok))))
+            .await
+            .unwrap()
+            .into_inner();
+        let bounds = summary.bounds.unwrap();
+        assert_eq!(bounds.lo, Some(point(407_838_351, -749_015_468, 0)));
+        assert_eq!(bounds.hi, Some(point(413_628_156, -743_999_179, 0)));
+
+        // 空的流没有 bounds
+        let summary = service
+            .record_route(point_stream(tokio_stream::empty()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.point_count, 0);
+        assert_eq!(summary.bounds, None);
+    }
+
+    #[tokio::test]
+    async fn record_route_times_out_an_idle_stream() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())))
+            .with_idle_timeout(Duration::from_millis(100));
+        // 发送一个点后既不再发送也不结束
+        let points = tokio_stream::iter(vec![Ok(point(400_000_000, -740_000_000, 0))])
+            .chain(tokio_stream::pending());
+
+        let started = Instant::now();
+        let status = service
+            .record_route(point_stream(points))
+            .await
+            .unwrap_err();
+        let elapsed = started.elapsed();
+
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert_eq!(status.message(), "no points received for 0.1 seconds");
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+        let partial = RouteSummary::decode(status.details()).unwrap();
+        assert_eq!(partial.point_count, 1);
+    }
+
+    fn note(latitude: i32, longitude: i32, message: &str) -> RouteNote {
+        RouteNote {
+            location: Some(point(latitude, longitude, 0)),
+            message: message.to_string(),
+        }
+    }
+
+    // 发送 notes 后读完回复的流, 返回回复的内容
+    async fn chat(client: &mut RouteGuideClient<Channel>, notes: Vec<RouteNote>) -> Vec<String> {
+        let mut replies = client
+            .route_chat(tokio_stream::iter(notes))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut messages = Vec::new();
+        while let Some(note) = replies.message().await.unwrap() {
+            messages.push(note.message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn route_chat_keys_history_by_latitude_and_longitude() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+
+        let replies = chat(
+            &mut client,
+            vec![
+                note(10, 10, "first at (10, 10)"),
+                note(10, 99, "first at (10, 99)"),
+                note(10, 10, "second at (10, 10)"),
+                note(10, 99, "second at (10, 99)"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            replies,
+            [
+                "first at (10, 10)",
+                "first at (10, 99)",
+                "first at (10, 10)",
+                "second at (10, 10)",
+                "first at (10, 99)",
+                "second at (10, 99)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn route_chat_replays_only_the_capped_history() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())))
+            .with_channel_capacity(64)
+            .with_note_limits(50, 2);
+        let mut client = route_client(service).await;
+
+        let notes = (0..200).map(|i| note(1, 1, &i.to_string())).collect();
+        let replies = chat(&mut client, notes).await;
+        // 第 k 条留言回放 min(k, 50) 条
+        assert_eq!(
+            replies.len(),
+            (1..=200).map(|k: usize| k.min(50)).sum::<usize>()
+        );
+        let last: Vec<String> = (150..200).map(|i: i32| i.to_string()).collect();
+        assert_eq!(replies[replies.len() - 50..], last[..]);
+
+        // 超过两个位置时淘汰最久没有留言的 (1, 1)
+        chat(&mut client, vec![note(2, 2, "b"), note(3, 3, "c")]).await;
+        assert_eq!(
+            chat(&mut client, vec![note(1, 1, "again")]).await,
+            ["again"]
+        );
+    }
+
+    // 保持打开的 route_chat 流, 通过返回的 Sender 逐条发送
+    async fn open_chat(
+        client: &mut RouteGuideClient<Channel>,
+    ) -> (mpsc::Sender<RouteNote>, Streaming<RouteNote>) {
+        let (tx, rx) = mpsc::channel(16);
+        let replies = client
+            .route_chat(ReceiverStream::new(rx))
+            .await
+            .unwrap()
+            .into_inner();
+        (tx, replies)
+    }
+
+    async fn next_message(replies: &mut Streaming<RouteNote>) -> String {
+        time::timeout(Duration::from_secs(5), replies.message())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+            .message
+    }
+
+    #[tokio::test]
+    async fn route_chat_delivers_notes_to_other_clients() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let (a, mut a_replies) = open_chat(&mut client).await;
+        let (b, mut b_replies) = open_chat(&mut client).await;
+
+        a.send(note(5, 5, "a1")).await.unwrap();
+        assert_eq!(next_message(&mut a_replies).await, "a1");
+
+        b.send(note(5, 5, "b1")).await.unwrap();
+        assert_eq!(next_message(&mut b_replies).await, "a1");
+        assert_eq!(next_message(&mut b_replies).await, "b1");
+        assert_eq!(next_message(&mut a_replies).await, "b1");
+
+        // 其他位置的留言不会转发给 a, a 也不会收到自己的留言
+        b.send(note(6, 6, "b2")).await.unwrap();
+        assert_eq!(next_message(&mut b_replies).await, "b2");
+        a.send(note(5, 5, "a2")).await.unwrap();
+        assert_eq!(next_message(&mut b_replies).await, "a2");
+        for expected in ["a1", "b1", "a2"] {
+            assert_eq!(next_message(&mut a_replies).await, expected);
+        }
+
+        drop((a, b));
+        assert!(a_replies.message().await.unwrap().is_none());
+        assert!(b_replies.message().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn route_chat_survives_a_lagging_subscriber() {
+        // 只保留一条历史, 回放不会混入 b 的留言
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())))
+            .with_note_limits(1, 16);
+        let mut client = route_client(service).await;
+        let (a, mut a_replies) = open_chat(&mut client).await;
+        a.send(note(5, 5, "a1")).await.unwrap();
+        assert_eq!(next_message(&mut a_replies).await, "a1");
+
+        // a 不读, b 发送的留言超过广播通道的容量; 留言足够大, 填满 HTTP/2 的流控窗口
+        let padding = "x".repeat(32 * 1024);
+        let notes = (0..300)
+            .map(|i| note(5, 5, &format!("b{} {}", i, padding)))
+            .collect();
+        chat(&mut client, notes).await;
+
+        a.send(note(5, 5, "still here")).await.unwrap();
+        let mut forwarded = 0;
+        loop {
+            let message = next_message(&mut a_replies).await;
+            if message.starts_with('b') {
+                forwarded += 1;
+            } else if message == "still here" {
+                break;
+            }
+        }
+        assert!(forwarded < 300, "{}", forwarded);
+    }
+
+    #[tokio::test]
+    async fn route_chat_sends_keepalives_while_the_client_is_quiet() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())))
+            .with_keepalive(Some(Duration::from_millis(100)));
+        let mut client = route_client(service).await;
+        let (tx, mut replies) = open_chat(&mut client).await;
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            let keepalive = time::timeout(Duration::from_secs(2), replies.message())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(keepalive, RouteNote::default());
+        }
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(250), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+        // 留言照常回显
+        tx.send(note(1, 1, "hi")).await.unwrap();
+        assert_eq!(next_message(&mut replies).await, "hi");
+    }
+
+    #[tokio::test]
+    async fn route_chat_keepalive_is_off_by_default() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let (_tx, mut replies) = open_chat(&mut client).await;
+
+        assert!(time::timeout(Duration::from_millis(300), replies.message())
+            .await
+            .is_err());
+    }
+
+    // 在 (1, 1) 留 prior 条言后, 以给定的 x-history-limit 再留一条, 返回这条留言的回放
+    async fn replay_with_limit(prior: usize, history_limit: &str) -> Result<Vec<String>, Status> {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let notes = (0..prior)
+            .map(|i| note(1, 1, &format!("old {}", i)))
+            .collect();
+        chat(&mut client, notes).await;
+
+        let mut request = Request::new(tokio_stream::iter(vec![note(1, 1, "new")]));
+        request
+            .metadata_mut()
+            .insert(HISTORY_LIMIT, history_limit.parse().unwrap());
+        let mut replies = client.route_chat(request).await?.into_inner();
+        let mut messages = Vec::new();
+        while let Some(note) = replies.message().await? {
+            messages.push(note.message);
+        }
+        Ok(messages)
+    }
+
+    #[tokio::test]
+    async fn route_chat_history_limit_trims_the_replay() {
+        // 刚收到的留言总会回显, 不计入 limit
+        assert_eq!(replay_with_limit(3, "0").await.unwrap(), ["new"]);
+        assert_eq!(replay_with_limit(3, "1").await.unwrap(), ["old 2", "new"]);
+        assert_eq!(
+            replay_with_limit(3, "10").await.unwrap(),
+            ["old 0", "old 1", "old 2", "new"]
+        );
+    }
+
+    #[tokio::test]
+    async fn route_chat_rejects_an_invalid_history_limit() {
+        for value in ["-1", "many"] {
+            let status = replay_with_limit(0, value).await.unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument, "{}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn route_chat_dedupe_drops_repeated_notes() {
+        let service =
+            RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new()))).with_note_dedupe(true);
+        let mut client = route_client(service).await;
+
+        let retry = || note(1, 1, "retry");
+        let replies = chat(&mut client, vec![retry(), retry(), retry()]).await;
+        assert_eq!(replies, ["retry"]);
+        // 只保存了一份; 不同位置的相同内容不算重复
+        let replies = chat(&mut client, vec![note(1, 1, "next"), note(2, 2, "retry")]).await;
+        assert_eq!(replies, ["retry", "next", "retry"]);
+    }
+
+    #[tokio::test]
+    async fn route_chat_keeps_repeats_without_dedupe() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+
+        let retry = || note(1, 1, "retry");
+        let replies = chat(&mut client, vec![retry(), retry()]).await;
+        assert_eq!(replies, ["retry", "retry", "retry"]);
+    }
+
+    #[tokio::test]
+    async fn open_store_falls_back_to_builtin_features() {
+        let missing = std::env::temp_dir().join(format!("missing-{}.json", Uuid::new_v4()));
+        let store = open_store(Some(&missing), &CancellationToken::new()).unwrap();
+        assert_eq!(store.all().len(), load().len());
+
+        // 内置的数据与示例文件相同
+        let sample = Path::new(env!("CARGO_MANIFEST_DIR")).join("route_guide_db.json");
+        assert_eq!(featurejson::load_json(&sample).unwrap(), load());
+    }
+
+    #[tokio::test]
+    async fn open_store_reports_malformed_files() {
+        let path = std::env::temp_dir().join(format!("malformed-{}.json", Uuid::new_v4()));
+        std::fs::write(&path, "[{").unwrap();
+        let error = open_store(Some(&path), &CancellationToken::new())
+            .err()
+            .unwrap()
+            .to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            error.starts_with(&format!("failed to load {}: ", path.display())),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn added_features_are_visible_to_later_calls() {
+        let mut client =
+            route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(load())))).await;
+        let library = named("Library", point(407_000_000, -745_000_000, 0));
+
+        let added = client
+            .add_feature(library.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(added, library);
+
+        let found = client
+            .get_feature(point(407_000_000, -745_000_000, 0))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(found.name, "Library");
+        let mut stream = client
+            .list_features(ListFeaturesRequest {
+                rect: Some(whole_map()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut names = Vec::new();
+        while let Some(feature) = stream.message().await.unwrap() {
+            names.push(feature.name);
+        }
+        assert_eq!(names.len(), load().len() + 1);
+        assert!(names.contains(&"Library".to_string()));
+
+        let status = client.add_feature(library).await.unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn add_feature_requires_a_location_and_a_name() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())));
+        let invalid = [
+            (
+                Feature {
+                    name: "Nowhere".to_string(),
+                    ..Default::default()
+                },
+                "missing field: location",
+            ),
+            (named("  ", point(1, 1, 0)), "name must not be empty"),
+        ];
+
+        for (feature, message) in invalid {
+            let status = service
+                .add_feature(Request::new(feature))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(status.message(), message);
+        }
+        assert!(service.features.all().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deleted_features_are_gone_for_later_calls() {
+        let mut client =
+            route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(load())))).await;
+        let location = point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0);
+
+        let removed = client
+            .delete_feature(location.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(removed.name, "Patriots Path, Mendham, NJ 07945, USA");
+
+        // 该位置已没有 feature, GetFeature 返回无名 feature, 再次删除返回 NOT_FOUND
+        let found = client
+            .get_feature(location.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(found.name, "");
+        let status = client.delete_feature(location).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // 其余 feature 不受影响
+        let other = load().swap_remove(1);
+        let found = client
+            .get_feature(other.location.clone().unwrap())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(found, other);
+        let mut stream = client
+            .list_features(ListFeaturesRequest {
+                rect: Some(whole_map()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut count = 0;
+        while stream.message().await.unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, load().len() - 1);
+    }
+
+    #[tokio::test]
+    async fn deleting_an_empty_point_is_not_found() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+
+        let status = service
+            .delete_feature(Request::new(point(1, 1, 0)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(service.features.all().len(), load().len());
+    }
+
+    fn update(original: Point, replacement: Feature) -> Request<UpdateFeatureRequest> {
+        Request::new(UpdateFeatureRequest {
+            original: Some(original),
+            replacement: Some(replacement),
+        })
+    }
+
+    #[tokio::test]
+    async fn update_feature_renames_in_place() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(vec![named(
+            "Old",
+            point(1, 1, 0),
+        )])));
+
+        let response = service
+            .update_feature(update(point(1, 1, 0), named("New", point(1, 1, 0))))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.feature.unwrap().name, "New");
+        assert_eq!(service.find_feature(&point(1, 1, 0)).unwrap().name, "New");
+        assert_eq!(service.features.all().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn update_feature_moves_to_a_new_point() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(vec![
+            named("Moving", point(1, 1, 0)),
+            named("Staying", point(2, 2, 0)),
+        ])));
+
+        service
+            .update_feature(update(
+                point(1, 1, 0),
+                named("Moving", point(50_000_000, 50_000_000, 0)),
+            ))
+            .await
+            .unwrap();
+
+        // 新位置命中, 旧位置不再命中
+        assert_eq!(
+            service
+                .find_feature(&point(50_000_000, 50_000_000, 0))
+                .unwrap()
+                .name,
+            "Moving"
+        );
+        assert!(service.find_feature(&point(1, 1, 0)).is_none());
+        let status = service
+            .delete_feature(Request::new(point(1, 1, 0)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        let names: Vec<_> = service
+            .features
+            .all()
+            .iter()
+            .map(|feature| feature.name.clone())
+            .collect();
+        assert_eq!(names, ["Moving", "Staying"]);
+    }
+
+    #[tokio::test]
+    async fn update_feature_rejects_conflicts_and_invalid_replacements() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(vec![
+            named("A", point(1, 1, 0)),
+            named("B", point(2, 2, 0)),
+        ])));
+
+        let status = service
+            .update_feature(update(point(1, 1, 0), named("A", point(2, 2, 0))))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+        let status = service
+            .update_feature(update(point(3, 3, 0), named("C", point(4, 4, 0))))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        for replacement in [
+            Feature {
+                name: "A".to_string(),
+                ..Default::default()
+            },
+            named(" ", point(1, 1, 0)),
+        ] {
+            let status = service
+                .update_feature(update(point(1, 1, 0), replacement))
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+
+        // 失败的更新不改变 store
+        assert_eq!(service.find_feature(&point(1, 1, 0)).unwrap().name, "A");
+        assert_eq!(service.find_feature(&point(2, 2, 0)).unwrap().name, "B");
+    }
+
+    #[tokio::test]
+    async fn import_features_counts_each_rejection_reason() {
+        let store = Arc::new(InMemoryStore::new(vec![named("existing", point(5, 5, 0))]));
+        let mut client = route_client(RouteGuideService::new(store.clone())).await;
+
+        // 1000 个合法 feature, 其中 feature 5 与已有的 feature 同位置
+        let mut features = numbered_features(1_000);
+        features.push(Feature {
+            name: "nowhere".to_string(),
+            ..Default::default()
+        });
+        features.push(named(" ", point(-1, -1, 0)));
+        features.push(named("again", point(7, 7, 0)));
+        let total_bytes: i64 = features.iter().map(|f| f.encoded_len() as i64).sum();
+
+        let summary = client
+            .import_features(tokio_stream::iter(features))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.accepted, 999);
+        assert_eq!(summary.missing_location, 1);
+        assert_eq!(summary.empty_name, 1);
+        assert_eq!(summary.duplicate, 2);
+        assert_eq!(summary.rejected, 4);
+        assert_eq!(summary.total_bytes, total_bytes);
+
+        assert_eq!(store.all().len(), 1_000);
+        assert_eq!(store.get(&point(5, 5, 0)).unwrap().name, "existing");
+        assert_eq!(store.get(&point(7, 7, 0)).unwrap().name, "feature 7");
+    }
+
+    async fn export(
+        client: &mut RouteGuideClient<Channel>,
+        rect: Option<Rectangle>,
+    ) -> Vec<Feature> {
+        let mut stream = client
+            .export_features(ExportRequest { rect })
+            .await
+            .unwrap()
+            .into_inner();
+        let mut features = Vec::new();
+        while let Some(feature) = stream.message().await.unwrap() {
+            features.push(feature);
+        }
+        features
+    }
+
+    #[tokio::test]
+    async fn exported_features_import_into_an_empty_store() {
+        let mut source =
+            route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(load())))).await;
+        let exported = export(&mut source, None).await;
+        assert_eq!(exported, load());
+
+        // 导入到一个空 store 后再导出, 内容和顺序都不变
+        let mut target = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let summary = target
+            .import_features(tokio_stream::iter(exported.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(summary.accepted as usize, exported.len());
+        assert_eq!(export(&mut target, None).await, exported);
+    }
+
+    #[tokio::test]
+    async fn export_features_applies_the_rect_filter() {
+        let mut client =
+            route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(load())))).await;
+        let rect = Rectangle {
+            lo: Some(point(PATRIOTS_PATH.0 - 1, PATRIOTS_PATH.1 - 1, 0)),
+            hi: Some(point(PATRIOTS_PATH.0 + 1, PATRIOTS_PATH.1 + 1, 0)),
+            ..Default::default()
+        };
+
+        let exported = export(&mut client, Some(rect)).await;
+        assert_eq!(exported, load()[..1]);
+
+        let status = client
+            .export_features(ExportRequest {
+                rect: Some(Rectangle {
+                    lo: None,
+                    ..whole_map()
+                }),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    async fn watch(
+        client: &mut RouteGuideClient<Channel>,
+        rect: Option<Rectangle>,
+    ) -> Streaming<FeatureEvent> {
+        client
+            .watch_features(WatchRequest { rect })
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    async fn next_event(stream: &mut Streaming<FeatureEvent>) -> FeatureEvent {
+        tokio::time::timeout(Duration::from_secs(5), stream.message())
+            .await
+            .expect("feature event")
+            .unwrap()
+            .expect("stream open")
+    }
+
+    #[tokio::test]
+    async fn watchers_receive_changes_within_their_rect() {
+        let mut client = route_client(RouteGuideService::new(Arc::new(InMemoryStore::new(
+            Vec::new(),
+        ))))
+        .await;
+        let mut everything = watch(&mut client, None).await;
+        let mut nearby = watch(
+            &mut client,
+            Some(Rectangle {
+                lo: Some(point(0, 0, 0)),
+                hi: Some(point(10, 10, 0)),
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        let far = named("far", point(50_000_000, 50_000_000, 0));
+        let near = named("near", point(1, 1, 0));
+        client.add_feature(far.clone()).await.unwrap();
+        client.add_feature(near.clone()).await.unwrap();
+
+        let event = next_event(&mut everything).await;
+        assert_eq!(event.event_type(), EventType::Added);
+        assert_eq!(event.feature, Some(far));
+        assert_eq!(
+            next_event(&mut everything).await.feature,
+            Some(near.clone())
+        );
+        // 区域外的修改不会发给 nearby, 第一个事件就是区域内的 feature
+        let event = next_event(&mut nearby).await;
+        assert_eq!(event.event_type(), EventType::Added);
+        assert_eq!(event.feature, Some(near));
+
+        // 移出区域的修改按修改前的位置仍然发给 nearby
+        client
+            .update_feature(UpdateFeatureRequest {
+                original: Some(point(1, 1, 0)),
+                replacement: Some(named("moved", point(40_000_000, 40_000_000, 0))),
+            })
+            .await
+            .unwrap();
+        let event = next_event(&mut nearby).await;
+        assert_eq!(event.event_type(), EventType::Updated);
+        assert_eq!(event.feature.unwrap().name, "moved");
+        client
+            .delete_feature(point(40_000_000, 40_000_000, 0))
+            .await
+            .unwrap();
+        assert_eq!(
+            next_event(&mut everything).await.event_type(),
+            EventType::Updated
+        );
+        assert_eq!(
+            next_event(&mut everything).await.event_type(),
+            EventType::Deleted
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_watchers_are_told_how_many_events_they_missed() {
+        let (sender, receiver) = broadcast::channel(2);
+        for i in 0..5 {
+            sender
+                .send(FeatureChange {
+                    event_type: EventType::Added,
+                    feature: Arc::new(named(&i.to_string(), point(i, i, 0))),
+                    previous: None,
+                })
+                .unwrap();
+        }
+        let (tx, mut rx) = mpsc::channel(8);
+        let forward = tokio::spawn(forward_feature_events(receiver, None, tx));
+
+        let lagged = rx.recv().await.unwrap().unwrap();
+        assert!(lagged.lagged);
+        assert_eq!(lagged.missed, 3);
+        assert_eq!(lagged.feature, None);
+        // 之后继续转发还留在通道里的事件
+        assert_eq!(rx.recv().await.unwrap().unwrap().feature.unwrap().name, "3");
+        assert_eq!(rx.recv().await.unwrap().unwrap().feature.unwrap().name, "4");
+
+        drop(sender);
+        forward.await.unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+
+    fn categorized(name: &str, latitude: i32, category: Category, tags: &[&str]) -> Feature {
+        Feature {
+            category: category.into(),
+            ..feature(name, latitude, 0, tags)
+        }
+    }
+
+    #[tokio::test]
+    async fn list_features_filters_by_category_and_tag() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(vec![
+            categorized("park", 1, Category::Park, &["dogs", "shade"]),
+            categorized("road", 2, Category::Road, &["dogs"]),
+            categorized("garden", 3, Category::Park, &["flowers"]),
+            // 老数据没有分类和标签
+            feature("plain", 4, 0, &[]),
+        ])));
+        let names = |features: Vec<Feature>| -> Vec<String> {
+            features.into_iter().map(|feature| feature.name).collect()
+        };
+        let filtered = |category: Category, tag: &str| ListFeaturesRequest {
+            rect: Some(whole_map()),
+            category: category.into(),
+            tag: tag.to_string(),
+            ..Default::default()
+        };
+
+        let (features, _) = list(&service, filtered(Category::Park, "")).await.unwrap();
+        assert_eq!(names(features), ["park", "garden"]);
+        let (features, _) = list(&service, filtered(Category::Unspecified, "dogs"))
+            .await
+            .unwrap();
+        assert_eq!(names(features), ["park", "road"]);
+        let (features, _) = list(&service, filtered(Category::Park, "dogs"))
+            .await
+            .unwrap();
+        assert_eq!(names(features), ["park"]);
+        let (features, _) = list(&service, filtered(Category::Building, "dogs"))
+            .await
+            .unwrap();
+        assert!(features.is_empty());
+
+        // 不设置过滤条件时返回全部 feature
+        let (features, _) = list(&service, filtered(Category::Unspecified, ""))
+            .await
+            .unwrap();
+        assert_eq!(names(features), ["park", "road", "garden", "plain"]);
+    }
+
+    #[tokio::test]
+    async fn count_features_matches_the_listed_features() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let patriots_path = point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0);
+        let rects = [
+            whole_map(),
+            Rectangle {
+                lo: Some(point(400_000_000, -750_000_000, 0)),
+                hi: Some(point(420_000_000, -730_000_000, 0)),
+                ..Default::default()
+            },
+            Rectangle {
+                lo: Some(patriots_path.clone()),
+                hi: Some(patriots_path.clone()),
+                ..Default::default()
+            },
+            // 数据集中没有南半球的 feature
+            Rectangle {
+                lo: Some(point(-10, -10, 0)),
+                hi: Some(point(-1, -1, 0)),
+                ..Default::default()
+            },
+        ];
+
+        for rect in rects {
+            let (listed, _) = list(
+                &service,
+                ListFeaturesRequest {
+                    rect: Some(rect.clone()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            let count = service
+                .count_features(Request::new(rect.clone()))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(count.count as usize, listed.len(), "{:?}", rect);
+            if listed.is_empty() {
+                assert_eq!(count.bounds, None);
+            }
+        }
+
+        let count = service
+            .count_features(Request::new(Rectangle {
+                lo: Some(patriots_path.clone()),
+                hi: Some(patriots_path.clone()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(count.count, 1);
+        let bounds = count.bounds.unwrap();
+        assert_eq!(bounds.lo, Some(patriots_path.clone()));
+        assert_eq!(bounds.hi, Some(patriots_path));
+
+        let status = service
+            .count_features(Request::new(Rectangle {
+                hi: None,
+                ..whole_map()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    async fn interpolate(from: Point, to: Point, step_meters: f64) -> Result<Vec<Point>, Status> {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(Vec::new())));
+        let stream = service
+            .interpolate_route(Request::new(InterpolateRequest {
+                from: Some(from),
+                to: Some(to),
+                step_meters,
+            }))
+            .await?
+            .into_inner();
+        stream.collect::<Result<Vec<_>, _>>().await
+    }
+
+    #[tokio::test]
+    async fn interpolate_route_spaces_points_by_step() {
+        let from = point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0);
+        // 向正北约 1056 米
+        let to = point(PATRIOTS_PATH.0 + 95_000, PATRIOTS_PATH.1, 0);
+        let total = calc_distance(&from, &to);
+
+        let points = interpolate(from.clone(), to.clone(), 100.0).await.unwrap();
+        assert_eq!(points.len(), (total as f64 / 100.0).ceil() as usize + 1);
+        assert_eq!(points.first(), Some(&from));
+        assert_eq!(points.last(), Some(&to));
+        for (i, pair) in points.windows(2).enumerate() {
+            let step = calc_distance(&pair[0], &pair[1]);
+            if i + 2 == points.len() {
+                // 最后一段是剩下的距离
+                assert!(step <= 100, "last step {}", step);
+            } else {
+                assert!((99..=101).contains(&step), "step {} is {}", i, step);
+            }
+        }
+
+        // 起点和终点重合时只返回终点
+        let points = interpolate(from.clone(), from.clone(), 100.0)
+            .await
+            .unwrap();
+        assert_eq!(points, [from]);
+    }
+
+    #[tokio::test]
+    async fn interpolate_route_rejects_bad_steps_and_long_routes() {
+        let from = point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0);
+        let to = point(PATRIOTS_PATH.0 + 95_000, PATRIOTS_PATH.1, 0);
+
+        for step_meters in [0.0, -5.0, f64::NAN] {
+            let status = interpolate(from.clone(), to.clone(), step_meters)
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(status.message(), "step_meters must be positive");
+        }
+
+        // 1056 米每 0.1 米一个点, 超过 MAX_INTERPOLATED_POINTS
+        let status = interpolate(from, to, 0.1).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert!(status
+            .message()
+            .contains(&MAX_INTERPOLATED_POINTS.to_string()));
+    }
+
+    async fn search(
+        service: &RouteGuideService,
+        query: &str,
+        mode: SearchMode,
+        case_sensitive: bool,
+        limit: u32,
+    ) -> Result<Vec<String>, Status> {
+        let stream = service
+            .search_features(Request::new(SearchRequest {
+                query: query.to_string(),
+                mode: mode.into(),
+                case_sensitive,
+                limit,
+            }))
+            .await?
+            .into_inner();
+        let features = stream.collect::<Result<Vec<_>, _>>().await?;
+        Ok(features.into_iter().map(|feature| feature.name).collect())
+    }
+
+    #[tokio::test]
+    async fn search_features_matches_each_mode() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let clinton = "Clinton Road, West Milford, NJ 07480, USA";
+
+        let found = search(&service, "wawayanda", SearchMode::Substring, false, 0)
+            .await
+            .unwrap();
+        assert_eq!(found, ["193-199 Wawayanda Road, Hewitt, NJ 07421, USA"]);
+        let found = search(&service, "Clinton", SearchMode::Prefix, false, 0)
+            .await
+            .unwrap();
+        assert_eq!(found, [clinton]);
+        // 前缀模式不匹配名称中间的部分
+        let found = search(&service, "Road", SearchMode::Prefix, false, 0)
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+        let found = search(
+            &service,
+            &clinton.to_uppercase(),
+            SearchMode::Exact,
+            false,
+            0,
+        )
+        .await
+        .unwrap();
+        assert_eq!(found, [clinton]);
+        let found = search(&service, "Clinton Road", SearchMode::Exact, false, 0)
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+
+        // 区分大小写
+        let found = search(&service, "clinton", SearchMode::Prefix, true, 0)
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+        let found = search(&service, "Clinton", SearchMode::Prefix, true, 0)
+            .await
+            .unwrap();
+        assert_eq!(found, [clinton]);
+    }
+
+    #[tokio::test]
+    async fn search_features_applies_the_limit_in_dataset_order() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+
+        let all = search(&service, "road", SearchMode::Substring, false, 0)
+            .await
+            .unwrap();
+        let expected: Vec<_> = load()
+            .into_iter()
+            .map(|feature| feature.name)
+            .filter(|name| name.to_lowercase().contains("road"))
+            .collect();
+        assert_eq!(all, expected);
+        assert!(all.len() > 2);
+
+        let limited = search(&service, "road", SearchMode::Substring, false, 2)
+            .await
+            .unwrap();
+        assert_eq!(limited, all[..2]);
+
+        let status = search(&service, "", SearchMode::Substring, false, 0)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn feature_stats_cover_the_dataset_and_track_mutations() {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let stats = || async {
+            service
+                .get_feature_stats(Request::new(Empty {}))
+                .await
+                .unwrap()
+                .into_inner()
+        };
+
+        let before = stats().await;
+        let features = load();
+        assert_eq!(before.total as usize, features.len());
+        let latitudes = features
+            .iter()
+            .map(|f| f.location.as_ref().unwrap().latitude);
+        let longitudes = features
+            .iter()
+            .map(|f| f.location.as_ref().unwrap().longitude);
+        let bounds = before.bounds.clone().unwrap();
+        let (lo, hi) = (bounds.lo.unwrap(), bounds.hi.unwrap());
+        assert_eq!(lo.latitude, latitudes.clone().min().unwrap());
+        assert_eq!(hi.latitude, latitudes.max().unwrap());
+        assert_eq!(lo.longitude, longitudes.clone().min().unwrap());
+        assert_eq!(hi.longitude, longitudes.max().unwrap());
+        assert_eq!(before.per_category.values().sum::<i64>(), before.total);
+        // 启动后还没有修改过
+        assert_eq!(before.last_modified_millis, 0);
+
+        let started = featurestore::now_millis();
+        service
+            .add_feature(Request::new(named("South Pole", point(-900_000_000, 0, 0))))
+            .await
+            .unwrap();
+        let added = stats().await;
+        assert_eq!(added.total, before.total + 1);
+        assert_eq!(added.bounds.unwrap().lo.unwrap().latitude, -900_000_000);
+        assert!(added.last_modified_millis >= started);
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        service
+            .delete_feature(Request::new(point(-900_000_000, 0, 0)))
+            .await
+            .unwrap();
+        let deleted = stats().await;
+        assert_eq!(deleted.total, before.total);
+        assert_eq!(deleted.bounds, before.bounds);
+        assert!(deleted.last_modified_millis > added.last_modified_millis);
+    }
+
+    async fn snap(raw_points: Vec<Point>, snap_radius_m: u32) -> SnapToRoadResponse {
+        let service = RouteGuideService::new(Arc::new(InMemoryStore::new(load())));
+        let request = Request::new(SnapToRoadRequest {
+            raw_points,
+            snap_radius_m,
+        });
+        service.snap_to_road(request).await.unwrap().into_inner()
+    }
+
+    // Patriots Path 和 Whippany 附近的点, 离 feature 大约 5 米
+    fn noisy_points() -> Vec<Point> {
+        vec![
+            Point {
+                timestamp: 1_000,
+                ..point(PATRIOTS_PATH.0 + 450, PATRIOTS_PATH.1, 0)
+            },
+            Point {
+                timestamp: 2_000,
+                ..point(408122808 - 450, -743999179, 0)
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn snap_to_road_snaps_every_point() {
+        let response = snap(noisy_points(), 50).await;
+        assert_eq!(response.snapped_count, 2);
+        assert_eq!(
+            response.snapped,
+            [
+                Point {
+                    timestamp: 1_000,
+                    ..point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0)
+                },
+                Point {
+                    timestamp: 2_000,
+                    ..point(408122808, -743999179, 0)
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn snap_to_road_keeps_points_that_are_too_far() {
+        let response = snap(noisy_points(), 1).await;
+        assert_eq!(response.snapped_count, 0);
+        assert_eq!(response.snapped, noisy_points());
+    }
+
+    #[tokio::test]
+    async fn snap_to_road_point_on_a_feature_is_not_counted() {
+        let on = point(PATRIOTS_PATH.0, PATRIOTS_PATH.1, 0);
+        let response = snap(vec![on.clone()], 50).await;
+        assert_eq!(response.snapped_count, 0);
+        assert_eq!(response.snapped, [on]);
+    }
+
+    #[tokio::test]
+    async fn tls_errors_are_reported_before_binding() {
+        let config = ServerConfig {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            tls: Some(config::TlsFiles {
+                cert: "missing-cert.pem".into(),
+                key: "missing-key.pem".into(),
+                client_ca: None,
+            }),
+            ..Default::default()
+        };
+        let (bound, address) = oneshot::channel();
+
+        assert!(run(config, CancellationToken::new(), bound).await.is_err());
+        // 没有绑定端口, bound 没有发出地址就被丢弃
+        assert!(address.await.is_err());
+    }
+}
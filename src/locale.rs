@@ -0,0 +1,100 @@
+// say_hello 按 accept-language 选择问候语
+
+// 支持的语言, 第一个为默认语言
+const GREETINGS: &[(&str, &str)] = &[
+    ("en", "Hello"),
+    ("zh", "你好"),
+    ("es", "Hola"),
+    ("de", "Hallo"),
+];
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+// 从 "zh-CN,zh;q=0.9,en;q=0.8" 这样的列表中选出 q 最大的支持的语言, q 相同时取靠前的;
+// 只比较主标签, q=0 表示不接受, "*" 匹配默认语言; 无法解析的项会被忽略, 没有匹配时返回默认语言
+pub fn negotiate(accept_language: &str) -> &'static str {
+    let mut best: Option<(&'static str, f32)> = None;
+    for item in accept_language.split(',') {
+        let mut parts = item.split(';').map(str::trim);
+        let tag = parts.next().unwrap_or_default();
+        let Some(q) = parts.try_fold(1.0, |q, param| match param.split_once('=') {
+            Some((name, value)) if name.trim().eq_ignore_ascii_case("q") => value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|q| (0.0..=1.0).contains(q)),
+            _ => Some(q),
+        }) else {
+            continue;
+        };
+        if q == 0.0 {
+            continue;
+        }
+
+        let primary = tag.split('-').next().unwrap_or_default();
+        let locale = match primary {
+            "*" => Some(DEFAULT_LOCALE),
+            primary => GREETINGS
+                .iter()
+                .map(|(locale, _)| *locale)
+                .find(|locale| locale.eq_ignore_ascii_case(primary)),
+        };
+        if let Some(locale) = locale {
+            if best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((locale, q));
+            }
+        }
+    }
+
+    best.map_or(DEFAULT_LOCALE, |(locale, _)| locale)
+}
+
+// locale 不支持时使用默认语言
+pub fn greet(locale: &str, content: &str) -> String {
+    let greeting = GREETINGS
+        .iter()
+        .find(|(supported, _)| *supported == locale)
+        .unwrap_or(&GREETINGS[0])
+        .1;
+
+    format!("{}, {}", greeting, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_supported_locale_has_a_greeting() {
+        for (header, greeting) in [
+            ("en", "Hello, world"),
+            ("zh-CN", "你好, world"),
+            ("es", "Hola, world"),
+            ("DE-at", "Hallo, world"),
+        ] {
+            assert_eq!(greet(negotiate(header), "world"), greeting, "{header}");
+        }
+    }
+
+    #[test]
+    fn highest_q_wins_and_ties_keep_the_first() {
+        assert_eq!(negotiate("zh-CN,zh;q=0.9,en;q=0.8"), "zh");
+        assert_eq!(negotiate("en;q=0.5, es;q=0.9, de;q=0.7"), "es");
+        assert_eq!(negotiate("de;q=0.8, es;q=0.8"), "de");
+        // 不支持的语言被跳过
+        assert_eq!(negotiate("fr, es;q=0.1"), "es");
+        // q=0 表示不接受
+        assert_eq!(negotiate("es;q=0, de;q=0.1"), "de");
+        assert_eq!(negotiate("fr, *;q=0.5"), DEFAULT_LOCALE);
+    }
+
+    #[test]
+    fn unsupported_or_malformed_headers_fall_back_to_english() {
+        for header in ["fr", "", ";;;", "es;q=abc", "es;q=2", ",,,"] {
+            assert_eq!(negotiate(header), DEFAULT_LOCALE, "{header:?}");
+        }
+        // 无法解析的项只影响自己
+        assert_eq!(negotiate("es;q=abc, de;q=0.3"), "de");
+        assert_eq!(greet("fr", "world"), "Hello, world");
+    }
+}
@@ -0,0 +1,82 @@
+use regex::RegexSet;
+
+// 内容毒性评分, 0.0 表示正常, 1.0 表示完全违规
+pub trait ToxicityScorer {
+    fn score(&self, content: &str) -> f32;
+}
+
+// 问候使用的规则; 只按整词匹配. 不包含 "die" 这类在其他语言中是常用词的词 (德语的冠词)
+pub const DEFAULT_RULES: &[(&str, f32)] = &[
+    (r"(?i)\b(idiot|stupid|moron)\b", 0.5),
+    (r"(?i)\b(fuck|shit|bastard)\b", 0.9),
+    (r"(?i)\bkill\b", 0.6),
+];
+
+#[derive(Debug)]
+pub struct KeywordToxicityScorer {
+    patterns: RegexSet,
+    weights: Vec<f32>,
+}
+
+impl KeywordToxicityScorer {
+    // 每个规则带一个 0.0..=1.0 的权重, 命中多个规则时按 1 - ∏(1 - w) 叠加
+    pub fn new(rules: &[(&str, f32)]) -> Result<Self, regex::Error> {
+        let patterns = RegexSet::new(rules.iter().map(|(pattern, _)| *pattern))?;
+        let weights = rules
+            .iter()
+            .map(|(_, weight)| weight.clamp(0.0, 1.0))
+            .collect();
+
+        Ok(KeywordToxicityScorer { patterns, weights })
+    }
+}
+
+impl ToxicityScorer for KeywordToxicityScorer {
+    fn score(&self, content: &str) -> f32 {
+        let clean = self
+            .patterns
+            .matches(content)
+            .into_iter()
+            .fold(1.0, |clean, i| clean * (1.0 - self.weights[i]));
+
+        1.0 - clean
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(content: &str) -> f32 {
+        KeywordToxicityScorer::new(DEFAULT_RULES)
+            .unwrap()
+            .score(content)
+    }
+
+    #[test]
+    fn clean_content() {
+        assert_eq!(score("hello there"), 0.0);
+        // 德语冠词
+        assert_eq!(score("die Katze ist müde"), 0.0);
+    }
+
+    #[test]
+    fn whole_words_only() {
+        assert_eq!(score("what a skillful stupidity"), 0.0);
+        assert_eq!(score("you IDIOT"), 0.5);
+    }
+
+    #[test]
+    fn weights_combine() {
+        assert_eq!(score("stupid idiot"), 0.5);
+        assert!((score("stupid bastard") - 0.95).abs() < 1e-6);
+        assert!((score("kill the stupid bastard") - 0.98).abs() < 1e-6);
+    }
+
+    #[test]
+    fn weights_are_clamped() {
+        let scorer = KeywordToxicityScorer::new(&[("bad", 2.0), ("worse", -1.0)]).unwrap();
+        assert_eq!(scorer.score("bad"), 1.0);
+        assert_eq!(scorer.score("worse"), 0.0);
+    }
+}
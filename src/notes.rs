@@ -0,0 +1,251 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use tokio::sync::broadcast;
+
+use crate::routeguide::RouteNote;
+
+pub type Location = (i32, i32);
+
+// route_chat 中按位置保存的留言, 每个位置只保留最近 per_location 条,
+// 位置数超过 max_locations 时淘汰最久没有新留言的位置
+#[derive(Debug)]
+pub struct NoteHistory {
+    per_location: usize,
+    max_locations: usize,
+    // 单调递增的计数, 记录每个位置最后一次留言的先后
+    tick: u64,
+    locations: HashMap<Location, (u64, VecDeque<RouteNote>)>,
+}
+
+impl NoteHistory {
+    pub fn new(per_location: usize, max_locations: usize) -> Self {
+        NoteHistory {
+            per_location: per_location.max(1),
+            max_locations: max_locations.max(1),
+            tick: 0,
+            locations: HashMap::new(),
+        }
+    }
+
+    // note 与该位置最后一条留言的位置和内容都相同
+    pub fn is_repeat(&self, location: Location, note: &RouteNote) -> bool {
+        self.locations
+            .get(&location)
+            .and_then(|(_, notes)| notes.back())
+            .is_some_and(|last| last.location == note.location && last.message == note.message)
+    }
+
+    // 记录一条留言, 返回该位置保留的全部留言
+    pub fn push(&mut self, location: Location, note: RouteNote) -> &VecDeque<RouteNote> {
+        self.tick += 1;
+
+        if !self.locations.contains_key(&location) && self.locations.len() >= self.max_locations {
+            let oldest = self
+                .locations
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(location, _)| *location);
+            if let Some(oldest) = oldest {
+                self.locations.remove(&oldest);
+            }
+        }
+
+        let (last_used, notes) = self.locations.entry(location).or_default();
+        *last_used = self.tick;
+        notes.push_back(note);
+        while notes.len() > self.per_location {
+            notes.pop_front();
+        }
+
+        notes
+    }
+}
+
+type Broadcast = broadcast::Sender<(u64, RouteNote)>;
+
+// 每个位置广播通道的容量, 订阅者落后超过该数量时丢弃最旧的留言
+const BROADCAST_CAPACITY: usize = 64;
+
+#[derive(Debug)]
+struct ChatState {
+    history: NoteHistory,
+    channels: HashMap<Location, Broadcast>,
+}
+
+// 所有 route_chat 客户端共享的留言板, 在同一位置留过言的客户端会收到其他客户端在该位置的新留言
+#[derive(Debug)]
+pub struct ChatRoom {
+    state: Mutex<ChatState>,
+    next_member: AtomicU64,
+}
+
+impl ChatRoom {
+    pub fn new(per_location: usize, max_locations: usize) -> Self {
+        ChatRoom {
+            state: Mutex::new(ChatState {
+                history: NoteHistory::new(per_location, max_locations),
+                channels: HashMap::new(),
+            }),
+            next_member: AtomicU64::new(1),
+        }
+    }
+
+    // 每个 route_chat 流一个 id, 用于过滤自己广播出去的留言
+    pub fn join(&self) -> u64 {
+        self.next_member.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // 记录留言并广播, 返回需要回放给发送者的历史;
+    // subscribed 为 false 时同时返回该位置的订阅, 订阅先于广播建立, 不会漏掉留言;
+    // dedupe 为 true 时与该位置最后一条留言重复的留言既不记录也不广播, 回放为空
+    pub fn post(
+        &self,
+        member: u64,
+        location: Location,
+        note: RouteNote,
+        subscribed: bool,
+        dedupe: bool,
+    ) -> (
+        Vec<RouteNote>,
+        Option<broadcast::Receiver<(u64, RouteNote)>>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let repeat = dedupe && state.history.is_repeat(location, &note);
+        let replay = if repeat {
+            vec![]
+        } else {
+            state
+                .history
+                .push(location, note.clone())
+                .iter()
+                .cloned()
+                .collect()
+        };
+
+        let channel = state
+            .channels
+            .entry(location)
+            .or_insert_with(|| broadcast::channel(BROADCAST_CAPACITY).0);
+        let receiver = (!subscribed).then(|| channel.subscribe());
+        // 没有订阅者时发送失败, 可以忽略
+        if !repeat {
+            let _ = channel.send((member, note));
+        }
+
+        (replay, receiver)
+    }
+
+    // 清理已经没有订阅者的广播通道
+    pub fn leave(&self) {
+        self.state
+            .lock()
+            .unwrap()
+            .channels
+            .retain(|_, channel| channel.receiver_count() > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routeguide::Point;
+
+    fn note(location: Location, message: &str) -> RouteNote {
+        RouteNote {
+            location: Some(Point {
+                latitude: location.0,
+                longitude: location.1,
+                ..Default::default()
+            }),
+            message: message.to_string(),
+        }
+    }
+
+    fn messages(notes: &VecDeque<RouteNote>) -> Vec<&str> {
+        notes.iter().map(|note| note.message.as_str()).collect()
+    }
+
+    #[test]
+    fn keeps_the_last_notes_per_location() {
+        let mut history = NoteHistory::new(50, 8);
+        for i in 0..200 {
+            history.push((1, 1), note((1, 1), &i.to_string()));
+        }
+
+        let notes = history.push((1, 1), note((1, 1), "200"));
+        assert_eq!(notes.len(), 50);
+        assert_eq!(notes.front().unwrap().message, "151");
+        assert_eq!(notes.back().unwrap().message, "200");
+        assert_eq!(history.locations.len(), 1);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_location() {
+        let mut history = NoteHistory::new(10, 2);
+        history.push((1, 1), note((1, 1), "a"));
+        history.push((2, 2), note((2, 2), "b"));
+        // (1, 1) 有了新留言, (2, 2) 变成最久没有留言的位置
+        history.push((1, 1), note((1, 1), "a again"));
+        history.push((3, 3), note((3, 3), "c"));
+
+        assert_eq!(history.locations.len(), 2);
+        assert!(!history.locations.contains_key(&(2, 2)));
+        assert_eq!(
+            messages(history.push((1, 1), note((1, 1), "a3"))),
+            ["a", "a again", "a3"]
+        );
+        // 被淘汰的位置重新开始
+        assert_eq!(messages(history.push((2, 2), note((2, 2), "b2"))), ["b2"]);
+        assert!(!history.locations.contains_key(&(3, 3)));
+    }
+
+    #[test]
+    fn repeats_match_location_and_message() {
+        let mut history = NoteHistory::new(10, 10);
+        assert!(!history.is_repeat((1, 1), &note((1, 1), "a")));
+        history.push((1, 1), note((1, 1), "a"));
+
+        assert!(history.is_repeat((1, 1), &note((1, 1), "a")));
+        assert!(!history.is_repeat((1, 1), &note((1, 1), "b")));
+        assert!(!history.is_repeat((2, 2), &note((2, 2), "a")));
+        // 只和最后一条比较
+        history.push((1, 1), note((1, 1), "b"));
+        assert!(!history.is_repeat((1, 1), &note((1, 1), "a")));
+    }
+
+    #[test]
+    fn deduped_posts_are_not_broadcast() {
+        let room = ChatRoom::new(10, 10);
+        let (replay, subscriber) = room.post(1, (1, 1), note((1, 1), "a"), false, true);
+        assert_eq!(replay.len(), 1);
+        let mut subscriber = subscriber.unwrap();
+        assert_eq!(subscriber.try_recv().unwrap().1.message, "a");
+
+        let (replay, _) = room.post(2, (1, 1), note((1, 1), "a"), false, true);
+        assert!(replay.is_empty());
+        assert!(matches!(
+            subscriber.try_recv(),
+            Err(broadcast::error::TryRecvError::Empty)
+        ));
+
+        // 不开启 dedupe 时照常保存和广播
+        let (replay, _) = room.post(2, (1, 1), note((1, 1), "a"), false, false);
+        assert_eq!(replay.len(), 2);
+        assert_eq!(subscriber.try_recv().unwrap().0, 2);
+    }
+
+    #[test]
+    fn zero_limits_keep_at_least_one() {
+        let mut history = NoteHistory::new(0, 0);
+        history.push((1, 1), note((1, 1), "a"));
+        assert_eq!(messages(history.push((1, 1), note((1, 1), "b"))), ["b"]);
+        history.push((2, 2), note((2, 2), "c"));
+        assert_eq!(history.locations.len(), 1);
+    }
+}
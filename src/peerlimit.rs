@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// 限制每个连接(按对端地址区分)同时进行的调用数
+#[derive(Debug)]
+pub struct PeerLimiter {
+    max_per_peer: usize,
+    peers: Mutex<HashMap<SocketAddr, Arc<Semaphore>>>,
+}
+
+impl PeerLimiter {
+    pub fn new(max_per_peer: usize) -> Self {
+        PeerLimiter {
+            max_per_peer: max_per_peer.max(1),
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 不排队等待, 已经达到上限时返回 None; 返回的 permit 释放时归还名额
+    pub fn try_acquire(self: &Arc<Self>, peer: SocketAddr) -> Option<PeerPermit> {
+        let mut peers = self.peers.lock().unwrap();
+        let semaphore = peers
+            .entry(peer)
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_peer)));
+        let permit = semaphore.clone().try_acquire_owned().ok()?;
+
+        Some(PeerPermit {
+            permit: Some(permit),
+            peer,
+            limiter: self.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct PeerPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    peer: SocketAddr,
+    limiter: Arc<PeerLimiter>,
+}
+
+impl Drop for PeerPermit {
+    // 对端没有进行中的调用时删除它的信号量, 与 try_acquire 在同一把锁内进行
+    fn drop(&mut self) {
+        let mut peers = self.limiter.peers.lock().unwrap();
+        drop(self.permit.take());
+        let idle = peers
+            .get(&self.peer)
+            .is_some_and(|semaphore| semaphore.available_permits() == self.limiter.max_per_peer);
+        if idle {
+            peers.remove(&self.peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn tracked(limiter: &PeerLimiter) -> usize {
+        limiter.peers.lock().unwrap().len()
+    }
+
+    #[test]
+    fn each_peer_has_its_own_cap() {
+        let limiter = Arc::new(PeerLimiter::new(2));
+        let first = limiter.try_acquire(peer(1)).unwrap();
+        let _second = limiter.try_acquire(peer(1)).unwrap();
+        assert!(limiter.try_acquire(peer(1)).is_none());
+        // 其他对端不受影响
+        let _other = limiter.try_acquire(peer(2)).unwrap();
+
+        drop(first);
+        assert!(limiter.try_acquire(peer(1)).is_some());
+    }
+
+    #[test]
+    fn zero_cap_allows_one_call() {
+        let limiter = Arc::new(PeerLimiter::new(0));
+        let _held = limiter.try_acquire(peer(1)).unwrap();
+        assert!(limiter.try_acquire(peer(1)).is_none());
+    }
+
+    #[test]
+    fn idle_peers_are_forgotten() {
+        let limiter = Arc::new(PeerLimiter::new(2));
+        let first = limiter.try_acquire(peer(1)).unwrap();
+        let second = limiter.try_acquire(peer(1)).unwrap();
+        let other = limiter.try_acquire(peer(2)).unwrap();
+        assert_eq!(tracked(&limiter), 2);
+
+        drop(first);
+        assert_eq!(tracked(&limiter), 2);
+        drop(second);
+        assert_eq!(tracked(&limiter), 1);
+        drop(other);
+        assert_eq!(tracked(&limiter), 0);
+    }
+
+    #[test]
+    fn rejected_call_does_not_leak_the_peer() {
+        let limiter = Arc::new(PeerLimiter::new(1));
+        let held = limiter.try_acquire(peer(1)).unwrap();
+        assert!(limiter.try_acquire(peer(1)).is_none());
+
+        drop(held);
+        assert_eq!(tracked(&limiter), 0);
+    }
+}
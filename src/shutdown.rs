@@ -0,0 +1,114 @@
+use std::{future::Future, time::Duration};
+
+use tokio_util::sync::CancellationToken;
+
+// 收到 SIGINT 或 SIGTERM 时返回; 非 unix 平台只监听 Ctrl-C
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to listen for SIGTERM, only SIGINT will shut down");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// 运行 serve 直到结束; shutdown 取消后最多再等 deadline 让进行中的请求完成, 超时后不再等待剩下的连接
+pub async fn drain<E>(
+    serve: impl Future<Output = Result<(), E>>,
+    shutdown: &CancellationToken,
+    deadline: Duration,
+) -> Result<(), E> {
+    tokio::pin!(serve);
+    tokio::select! {
+        result = &mut serve => return result,
+        _ = shutdown.cancelled() => {}
+    }
+
+    match tokio::time::timeout(deadline, serve).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::warn!(
+                ?deadline,
+                "drain deadline exceeded, abandoning remaining connections"
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    use tokio::time::Instant;
+
+    use super::*;
+
+    // 收到 shutdown 后再过 work 才结束的 serve
+    fn serve(
+        shutdown: &CancellationToken,
+        work: Duration,
+        finished: &Arc<AtomicBool>,
+    ) -> impl Future<Output = Result<(), ()>> {
+        let shutdown = shutdown.clone();
+        let finished = finished.clone();
+        async move {
+            shutdown.cancelled().await;
+            tokio::time::sleep(work).await;
+            finished.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_finish_within_deadline() {
+        let shutdown = CancellationToken::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let serve = serve(&shutdown, Duration::from_millis(20), &finished);
+
+        shutdown.cancel();
+        drain(serve, &shutdown, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn in_flight_requests_are_abandoned_after_deadline() {
+        let shutdown = CancellationToken::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let serve = serve(&shutdown, Duration::from_secs(60), &finished);
+
+        let start = Instant::now();
+        shutdown.cancel();
+        drain(serve, &shutdown, Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn serve_result_is_returned_without_shutdown() {
+        let shutdown = CancellationToken::new();
+        let result = drain(async { Err("bind failed") }, &shutdown, Duration::ZERO).await;
+        assert_eq!(result, Err("bind failed"));
+        assert!(!shutdown.is_cancelled());
+    }
+}
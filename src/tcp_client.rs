@@ -0,0 +1,55 @@
+use std::io::Result;
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::timeout,
+};
+
+use crate::framing::{CallbackBack, ChunkReader, ChunkWriter, FramedConnection, Transport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BUFF_SIZE: usize = 8192;
+
+pub type TcpClient<T> = FramedConnection<TcpStream, T>;
+
+impl<T> FramedConnection<TcpStream, T>
+where
+    T: CallbackBack + Send + Sync + 'static,
+{
+    // addr 为 host:port
+    pub async fn connect(addr: &str, router: T) -> Result<Self> {
+        let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await??;
+        Self::new(addr, stream, router)
+    }
+}
+
+impl Transport for TcpStream {
+    type Reader = OwnedReadHalf;
+    type Writer = OwnedWriteHalf;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        self.into_split()
+    }
+}
+
+#[tonic::async_trait]
+impl ChunkReader for OwnedReadHalf {
+    async fn read_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool> {
+        let mut chunk = [0u8; MAX_BUFF_SIZE];
+        let n = self.read(&mut chunk).await?;
+        buf.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+}
+
+#[tonic::async_trait]
+impl ChunkWriter for OwnedWriteHalf {
+    async fn write_chunk(&mut self, chunk: Vec<u8>) -> Result<()> {
+        self.write_all(&chunk).await
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::{Instant, Sleep};
+use tonic::{
+    body::{empty_body, BoxBody},
+    codegen::{
+        http::{self, HeaderMap},
+        Body, BoxFuture, Bytes, Service,
+    },
+    Status,
+};
+use tower::Layer;
+
+fn deadline_exceeded(limit: Duration) -> Status {
+    Status::deadline_exceeded(format!(
+        "request exceeded the server limit of {}ms",
+        limit.as_millis()
+    ))
+}
+
+// 按方法限制处理时间, None 表示不限制. 一元调用和客户端流在返回响应之前计时,
+// 服务端流一直计时到响应发送完; 超时都返回 DEADLINE_EXCEEDED
+#[derive(Debug, Clone, Default)]
+pub struct TimeoutLayer {
+    default: Option<Duration>,
+    // "package.Service/Method" -> 上限
+    methods: Arc<HashMap<String, Option<Duration>>>,
+}
+
+impl TimeoutLayer {
+    pub fn new<I>(default: Option<Duration>, methods: I) -> Self
+    where
+        I: IntoIterator<Item = (String, Option<Duration>)>,
+    {
+        TimeoutLayer {
+            default,
+            methods: Arc::new(methods.into_iter().collect()),
+        }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = TimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimeoutService {
+            inner,
+            default: self.default,
+            methods: self.methods.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimeoutService<S> {
+    inner: S,
+    default: Option<Duration>,
+    methods: Arc<HashMap<String, Option<Duration>>>,
+}
+
+impl<S> TimeoutService<S> {
+    fn limit(&self, path: &str) -> Option<Duration> {
+        // 路径形如 "/hello.Greeter/SayHello"
+        let method = path.strip_prefix('/').unwrap_or(path);
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for TimeoutService<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let Some(limit) = self.limit(request.uri().path()) else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        let deadline = Instant::now() + limit;
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            match tokio::time::timeout_at(deadline, response).await {
+                Ok(Ok(response)) => Ok(response.map(|body| {
+                    BoxBody::new(TimeoutBody {
+                        inner: body,
+                        sleep: Box::pin(tokio::time::sleep_until(deadline)),
+                        limit,
+                        expired: false,
+                    })
+                })),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Ok(deadline_exceeded(limit).to_http()),
+            }
+        })
+    }
+}
+
+// 到期后结束响应体, 用 DEADLINE_EXCEEDED 作为 trailers
+struct TimeoutBody {
+    inner: BoxBody,
+    sleep: Pin<Box<Sleep>>,
+    limit: Duration,
+    expired: bool,
+}
+
+impl Body for TimeoutBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = self.get_mut();
+        if this.expired {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(data) = Pin::new(&mut this.inner).poll_data(cx) {
+            return Poll::Ready(data);
+        }
+        if this.sleep.as_mut().poll(cx).is_ready() {
+            // 丢弃原来的响应体, 产生数据的任务随之停止
+            this.inner = empty_body();
+            this.expired = true;
+            return Poll::Ready(None);
+        }
+
+        Poll::Pending
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let this = self.get_mut();
+        if this.expired {
+            let mut trailers = HeaderMap::new();
+            deadline_exceeded(this.limit).add_header(&mut trailers)?;
+            return Poll::Ready(Ok(Some(trailers)));
+        }
+
+        Pin::new(&mut this.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        !self.expired && self.inner.is_end_stream()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tonic::{transport, Code};
+    use tower::{service_fn, ServiceExt};
+
+    use super::*;
+
+    const LIMIT: Duration = Duration::from_millis(50);
+
+    fn request(path: &str) -> http::Request<()> {
+        http::Request::builder().uri(path).body(()).unwrap()
+    }
+
+    fn code(response: &http::Response<BoxBody>) -> Code {
+        Status::from_header_map(response.headers()).map_or(Code::Ok, |status| status.code())
+    }
+
+    // 内层服务等待 delay 后返回空响应
+    fn slow_unary(
+        layer: &TimeoutLayer,
+        delay: Duration,
+    ) -> impl Service<
+        http::Request<()>,
+        Response = http::Response<BoxBody>,
+        Error = Infallible,
+        Future = BoxFuture<http::Response<BoxBody>, Infallible>,
+    > {
+        layer.layer(service_fn(move |_: http::Request<()>| async move {
+            tokio::time::sleep(delay).await;
+            Ok::<_, Infallible>(http::Response::new(empty_body()))
+        }))
+    }
+
+    #[tokio::test]
+    async fn unary_call_exceeding_the_limit_is_cut_off() {
+        let layer = TimeoutLayer::new(Some(LIMIT), []);
+
+        let response = slow_unary(&layer, LIMIT * 10)
+            .oneshot(request("/hello.Greeter/SayHello"))
+            .await
+            .unwrap();
+        assert_eq!(code(&response), Code::DeadlineExceeded);
+
+        let response = slow_unary(&layer, Duration::ZERO)
+            .oneshot(request("/hello.Greeter/SayHello"))
+            .await
+            .unwrap();
+        assert_eq!(code(&response), Code::Ok);
+    }
+
+    #[tokio::test]
+    async fn streaming_body_ends_with_deadline_trailers() {
+        let layer = TimeoutLayer::new(Some(LIMIT), []);
+        // 发送一条消息后不再结束的流
+        let (mut sender, body) = transport::Body::channel();
+        sender
+            .send_data(Bytes::from_static(b"first"))
+            .await
+            .unwrap();
+        let mut body = Some(
+            body.map_err(|e| Status::internal(e.to_string()))
+                .boxed_unsync(),
+        );
+
+        let response = layer
+            .layer(service_fn(move |_: http::Request<()>| {
+                let response = http::Response::new(body.take().unwrap());
+                async move { Ok::<_, Infallible>(response) }
+            }))
+            .oneshot(request("/routeguide.RouteGuide/ListFeatures"))
+            .await
+            .unwrap();
+        assert_eq!(code(&response), Code::Ok);
+
+        let mut body = response.into_body();
+        let first = body.data().await.unwrap().unwrap();
+        assert_eq!(first, Bytes::from_static(b"first"));
+        assert!(body.data().await.is_none());
+
+        let trailers = body.trailers().await.unwrap().unwrap();
+        let status = Status::from_header_map(&trailers).unwrap();
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        // 原来的响应体已被丢弃
+        assert!(sender.send_data(Bytes::new()).await.is_err());
+    }
+
+    #[test]
+    fn method_limits_override_the_default() {
+        let layer = TimeoutLayer::new(
+            Some(LIMIT),
+            [
+                ("hello.Greeter/SayHello".to_string(), Some(LIMIT * 2)),
+                ("routeguide.RouteGuide/RouteChat".to_string(), None),
+            ],
+        );
+        let service = layer.layer(());
+
+        assert_eq!(service.limit("/hello.Greeter/SayHello"), Some(LIMIT * 2));
+        assert_eq!(service.limit("/routeguide.RouteGuide/RouteChat"), None);
+        // 只按完整的方法名匹配
+        assert_eq!(service.limit("/hello.Greeter/SayHelloAgain"), Some(LIMIT));
+        assert_eq!(service.limit("/hello.Greeter"), Some(LIMIT));
+        assert_eq!(service.limit("/voting.Voting/Vote"), Some(LIMIT));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use rustls::{sign, PrivateKey, SignatureScheme};
+use rustls_pemfile::Item;
+use tonic::{
+    service::Interceptor,
+    transport::{Certificate, Identity, ServerTlsConfig},
+    Request, Status,
+};
+
+// 用私钥签名、再用证书中的公钥验证, 以此检查两者是否配对
+const PROBE: &[u8] = b"netsrv tls key check";
+
+// 私钥可能使用的签名方案和对应的验证算法
+const SCHEMES: &[(SignatureScheme, &webpki::SignatureAlgorithm)] = &[
+    (
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        &webpki::ECDSA_P256_SHA256,
+    ),
+    (
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        &webpki::ECDSA_P384_SHA384,
+    ),
+    (SignatureScheme::ED25519, &webpki::ED25519),
+    (
+        SignatureScheme::RSA_PSS_SHA256,
+        &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    ),
+    (
+        SignatureScheme::RSA_PKCS1_SHA256,
+        &webpki::RSA_PKCS1_2048_8192_SHA256,
+    ),
+];
+
+#[derive(Debug)]
+pub enum TlsError {
+    Read(PathBuf, io::Error),
+    NoCertificate(PathBuf),
+    NoKey(PathBuf),
+    UnsupportedKey(PathBuf),
+    InvalidCertificate(PathBuf),
+    Mismatch { cert: PathBuf, key: PathBuf },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            TlsError::NoCertificate(path) => {
+                write!(f, "no PEM certificate found in {}", path.display())
+            }
+            TlsError::NoKey(path) => write!(f, "no PEM private key found in {}", path.display()),
+            TlsError::UnsupportedKey(path) => {
+                write!(f, "unsupported private key in {}", path.display())
+            }
+            TlsError::InvalidCertificate(path) => {
+                write!(f, "invalid certificate in {}", path.display())
+            }
+            TlsError::Mismatch { cert, key } => write!(
+                f,
+                "private key {} does not match certificate {}",
+                key.display(),
+                cert.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+// 读取 PEM 格式的证书链 (第一个为服务端证书) 和私钥; 在启动时检查, 而不是等到第一次握手才失败.
+// 指定 client_ca_path 时要求客户端出示由其中某个 CA 签发的证书, 否则握手失败
+pub fn server_tls_config(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca_path: Option<&Path>,
+) -> Result<ServerTlsConfig, TlsError> {
+    let cert_pem = read(cert_path)?;
+    let key_pem = read(key_path)?;
+
+    let cert = pem_items(cert_path, &cert_pem)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::X509Certificate(der) => Some(der),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoCertificate(cert_path.to_path_buf()))?;
+    let key = pem_items(key_path, &key_pem)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(der),
+            _ => None,
+        })
+        .ok_or_else(|| TlsError::NoKey(key_path.to_path_buf()))?;
+    check_key_pair(cert_path, &cert, key_path, key)?;
+
+    let config = ServerTlsConfig::new().identity(Identity::from_pem(cert_pem, key_pem));
+    let Some(client_ca_path) = client_ca_path else {
+        return Ok(config);
+    };
+    let ca_pem = read(client_ca_path)?;
+    let has_ca = pem_items(client_ca_path, &ca_pem)?
+        .iter()
+        .any(|item| matches!(item, Item::X509Certificate(_)));
+    if !has_ca {
+        return Err(TlsError::NoCertificate(client_ca_path.to_path_buf()));
+    }
+
+    Ok(config.client_ca_root(Certificate::from_pem(ca_pem)))
+}
+
+// 客户端证书 subject 中的第一个 CN, 由 ClientCertInterceptor 放入请求的 extensions
+#[derive(Debug, Clone)]
+pub struct ClientCommonName(pub String);
+
+// 没有客户端证书或证书没有 CN 时不修改请求
+#[derive(Debug, Clone, Copy)]
+pub struct ClientCertInterceptor;
+
+impl Interceptor for ClientCertInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let common_name = request
+            .peer_certs()
+            .and_then(|certs| certs.first().and_then(|cert| common_name(cert.get_ref())));
+        if let Some(common_name) = common_name {
+            request
+                .extensions_mut()
+                .insert(ClientCommonName(common_name));
+        }
+
+        Ok(request)
+    }
+}
+
+fn common_name(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?.as_str().ok()?;
+
+    Some(common_name.to_string()).filter(|common_name| !common_name.is_empty())
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsError> {
+    fs::read(path).map_err(|e| TlsError::Read(path.to_path_buf(), e))
+}
+
+fn pem_items(path: &Path, pem: &[u8]) -> Result<Vec<Item>, TlsError> {
+    rustls_pemfile::read_all(&mut &pem[..]).map_err(|e| TlsError::Read(path.to_path_buf(), e))
+}
+
+fn check_key_pair(
+    cert_path: &Path,
+    cert: &[u8],
+    key_path: &Path,
+    key: Vec<u8>,
+) -> Result<(), TlsError> {
+    let unsupported = || TlsError::UnsupportedKey(key_path.to_path_buf());
+    let key = sign::any_supported_type(&PrivateKey(key)).map_err(|_| unsupported())?;
+    let offered: Vec<_> = SCHEMES.iter().map(|(scheme, _)| *scheme).collect();
+    let signer = key.choose_scheme(&offered).ok_or_else(unsupported)?;
+    let signature = signer.sign(PROBE).map_err(|_| unsupported())?;
+    let (_, algorithm) = SCHEMES
+        .iter()
+        .find(|(scheme, _)| *scheme == signer.scheme())
+        .ok_or_else(unsupported)?;
+
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|_| TlsError::InvalidCertificate(cert_path.to_path_buf()))?;
+    cert.verify_signature(algorithm, PROBE, &signature)
+        .map_err(|_| TlsError::Mismatch {
+            cert: cert_path.to_path_buf(),
+            key: key_path.to_path_buf(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::transport::{Channel, ClientTlsConfig, Endpoint, Server};
+    use tonic_health::pb::{health_client::HealthClient, HealthCheckRequest};
+
+    use super::*;
+
+    // 写入临时目录, 返回 (证书路径, 私钥路径)
+    fn write_pair(cert: &rcgen::Certificate) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("tls-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn localhost_cert() -> rcgen::Certificate {
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap()
+    }
+
+    // 在 127.0.0.1 上启动只有健康检查服务的 TLS 服务端, 返回地址
+    async fn serve_tls(config: ServerTlsConfig) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let incoming =
+            tonic::transport::server::TcpIncoming::from_listener(listener, true, None).unwrap();
+        let (_, health) = tonic_health::server::health_reporter();
+        tokio::spawn(
+            Server::builder()
+                .tls_config(config)
+                .unwrap()
+                .add_service(health)
+                .serve_with_incoming(incoming),
+        );
+        address
+    }
+
+    async fn check(channel: Channel) -> Result<(), Status> {
+        HealthClient::new(channel)
+            .check(HealthCheckRequest::default())
+            .await
+            .map(|_| ())
+    }
+
+    fn endpoint(address: std::net::SocketAddr, tls: ClientTlsConfig) -> Endpoint {
+        Endpoint::from_shared(format!("https://localhost:{}", address.port()))
+            .unwrap()
+            .tls_config(tls.domain_name("localhost"))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn generated_certificate_serves_tls() {
+        let cert = localhost_cert();
+        let (cert_path, key_path) = write_pair(&cert);
+        let address = serve_tls(server_tls_config(&cert_path, &key_path, None).unwrap()).await;
+
+        let tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(cert.serialize_pem().unwrap()));
+        let channel = endpoint(address, tls).connect().await.unwrap();
+        check(channel).await.unwrap();
+
+        // 不信任该证书的客户端无法连接
+        let untrusted = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(
+            localhost_cert().serialize_pem().unwrap(),
+        ));
+        assert!(endpoint(address, untrusted).connect().await.is_err());
+
+        fs::remove_dir_all(cert_path.parent().unwrap()).unwrap();
+    }
+
+    fn client_ca() -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "test client ca");
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    // 由 ca 签发、CN 为 common_name 的客户端证书, 返回 (证书 PEM, 私钥 PEM)
+    fn client_cert(ca: &rcgen::Certificate, common_name: &str) -> (String, String) {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        (
+            cert.serialize_pem_with_signer(ca).unwrap(),
+            cert.serialize_private_key_pem(),
+        )
+    }
+
+    #[tokio::test]
+    async fn client_certificate_is_required_when_a_ca_is_configured() {
+        let server_cert = localhost_cert();
+        let (cert_path, key_path) = write_pair(&server_cert);
+        let ca = client_ca();
+        let ca_path = cert_path.with_file_name("client-ca.pem");
+        fs::write(&ca_path, ca.serialize_pem().unwrap()).unwrap();
+        let address =
+            serve_tls(server_tls_config(&cert_path, &key_path, Some(&ca_path)).unwrap()).await;
+        let trusted = || {
+            ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(server_cert.serialize_pem().unwrap()))
+        };
+
+        let (client_pem, client_key) = client_cert(&ca, "alice");
+        let with_cert = trusted().identity(Identity::from_pem(client_pem, client_key));
+        let channel = endpoint(address, with_cert).connect().await.unwrap();
+        check(channel).await.unwrap();
+
+        // TLS 1.3 中客户端可能在服务端拒绝之前就认为握手完成, 所以错误也可能出现在第一个请求上
+        let without_cert = async {
+            let channel = endpoint(address, trusted())
+                .connect()
+                .await
+                .map_err(|e| e.to_string())?;
+            check(channel).await.map_err(|e| e.to_string())
+        };
+        assert!(without_cert.await.is_err());
+
+        // 由其他 CA 签发的证书同样被拒绝
+        let (other_pem, other_key) = client_cert(&client_ca(), "mallory");
+        let other = async {
+            let tls = trusted().identity(Identity::from_pem(other_pem, other_key));
+            let channel = endpoint(address, tls)
+                .connect()
+                .await
+                .map_err(|e| e.to_string())?;
+            check(channel).await.map_err(|e| e.to_string())
+        };
+        assert!(other.await.is_err());
+
+        fs::remove_dir_all(cert_path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn common_name_comes_from_the_client_certificate() {
+        let ca = client_ca();
+        let (client_pem, _) = client_cert(&ca, "alice");
+        let der = match rustls_pemfile::read_one(&mut client_pem.as_bytes()).unwrap() {
+            Some(Item::X509Certificate(der)) => der,
+            item => panic!("unexpected {item:?}"),
+        };
+        assert_eq!(common_name(&der).as_deref(), Some("alice"));
+
+        // 没有 CN 的证书
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let anonymous = rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        assert_eq!(common_name(&anonymous), None);
+    }
+
+    #[test]
+    fn mismatched_or_missing_files_are_rejected() {
+        let (cert_path, key_path) = write_pair(&localhost_cert());
+        let (_, other_key_path) = write_pair(&localhost_cert());
+
+        assert!(matches!(
+            server_tls_config(&cert_path, &other_key_path, None),
+            Err(TlsError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            server_tls_config(&key_path, &key_path, None),
+            Err(TlsError::NoCertificate(_))
+        ));
+        assert!(matches!(
+            server_tls_config(&cert_path, &cert_path, None),
+            Err(TlsError::NoKey(_))
+        ));
+        let missing = cert_path.with_file_name("missing.pem");
+        assert!(matches!(
+            server_tls_config(&missing, &key_path, None),
+            Err(TlsError::Read(..))
+        ));
+
+        for path in [&cert_path, &other_key_path] {
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+
+// 同一用户对同一 url 在 window 内只能投一票, 被限流拒绝的投票不会重新计时
+#[derive(Debug)]
+pub struct VoteRateLimiter {
+    window: Duration,
+    // (用户, url) 最近一次被接受的投票时间
+    last_votes: DashMap<(String, String), Instant>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl VoteRateLimiter {
+    pub fn new(window: Duration) -> Self {
+        VoteRateLimiter {
+            window,
+            last_votes: DashMap::new(),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    // 允许时记录本次投票; 否则返回还需要等待的时间
+    pub fn check(&self, user_id: &str, url: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        self.sweep(now);
+
+        match self
+            .last_votes
+            .entry((user_id.to_string(), url.to_string()))
+        {
+            Entry::Occupied(mut last) => {
+                let elapsed = now.duration_since(*last.get());
+                if elapsed < self.window {
+                    return Err(self.window - elapsed);
+                }
+                last.insert(now);
+            }
+            Entry::Vacant(last) => {
+                last.insert(now);
+            }
+        }
+
+        Ok(())
+    }
+
+    // 每过一个 window 清理一次已经过期的记录, 避免记录无限增长
+    fn sweep(&self, now: Instant) {
+        {
+            let mut last_sweep = self.last_sweep.lock().unwrap();
+            if now.duration_since(*last_sweep) < self.window {
+                return;
+            }
+            *last_sweep = now;
+        }
+
+        self.last_votes
+            .retain(|_, last| now.duration_since(*last) < self.window);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    #[test]
+    fn repeated_vote_reports_the_remaining_wait() {
+        let limiter = VoteRateLimiter::new(Duration::from_secs(60));
+        assert_eq!(limiter.check("alice", "https://a/"), Ok(()));
+
+        let retry_after = limiter.check("alice", "https://a/").unwrap_err();
+        assert!(retry_after <= Duration::from_secs(60));
+        assert!(retry_after > Duration::from_secs(59));
+
+        // 其他用户和其他 url 各自计时
+        assert_eq!(limiter.check("bob", "https://a/"), Ok(()));
+        assert_eq!(limiter.check("alice", "https://b/"), Ok(()));
+    }
+
+    #[test]
+    fn rejected_vote_does_not_restart_the_window() {
+        let limiter = VoteRateLimiter::new(Duration::from_millis(100));
+        assert_eq!(limiter.check("alice", "https://a/"), Ok(()));
+
+        sleep(Duration::from_millis(60));
+        let retry_after = limiter.check("alice", "https://a/").unwrap_err();
+        assert!(retry_after <= Duration::from_millis(40));
+
+        sleep(Duration::from_millis(50));
+        assert_eq!(limiter.check("alice", "https://a/"), Ok(()));
+        assert!(limiter.check("alice", "https://a/").is_err());
+    }
+
+    #[test]
+    fn sweep_drops_expired_votes() {
+        let limiter = VoteRateLimiter::new(Duration::from_millis(50));
+        limiter.check("alice", "https://a/").unwrap();
+        limiter.check("bob", "https://a/").unwrap();
+        assert_eq!(limiter.last_votes.len(), 2);
+
+        sleep(Duration::from_millis(60));
+        limiter.check("carol", "https://a/").unwrap();
+        let users: Vec<String> = limiter
+            .last_votes
+            .iter()
+            .map(|entry| entry.key().0.clone())
+            .collect();
+        assert_eq!(users, ["carol"]);
+    }
+}
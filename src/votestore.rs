@@ -0,0 +1,1125 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use crate::voting::{top_urls_request::Order, voting_request::Vote};
+
+// 一个 url 每种票的票数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    pub up: u64,
+    pub down: u64,
+    pub abstain: u64,
+}
+
+impl Tally {
+    pub fn total(&self) -> u64 {
+        self.up + self.down + self.abstain
+    }
+
+    pub fn count_mut(&mut self, vote: Vote) -> &mut u64 {
+        match vote {
+            Vote::Up => &mut self.up,
+            Vote::Down => &mut self.down,
+            Vote::Abstain => &mut self.abstain,
+        }
+    }
+}
+
+// 投票计数的存储, 修改计数与通知 watch 的订阅者在同一把锁内进行
+pub trait VoteStore: Debug + Send + Sync {
+    // 没有投过票的 url 返回 None
+    fn get(&self, url: &str) -> Result<Option<Tally>, VoteStoreError>;
+
+    // 记录一张匿名票, 返回包括这一票在内的计数
+    fn record(&self, url: &str, vote: Vote) -> Result<Tally, VoteStoreError>;
+
+    // 记录 user_id 的票, 改票时把一票从原来的票移到新的票; 与该用户上一次投的票相同时返回 None
+    fn record_user(
+        &self,
+        user_id: &str,
+        url: &str,
+        vote: Vote,
+    ) -> Result<Option<Tally>, VoteStoreError>;
+
+    // 依次记录一批票, user_id 为 None 时是匿名票; 整批只加一次锁, 不会与其他修改交错,
+    // 每张票的结果与依次调用 record 或 record_user 相同
+    fn record_batch(
+        &self,
+        user_id: Option<&str>,
+        votes: &[(&str, Vote)],
+    ) -> Result<Vec<Option<Tally>>, VoteStoreError>;
+
+    // 撤销一张匿名票, 没有可撤销的票时返回 None
+    fn unvote(&self, url: &str, vote: Vote) -> Result<Option<Tally>, VoteStoreError>;
+
+    // 撤销 user_id 投的票, 该用户没有投过这种票时返回 None
+    fn unvote_user(
+        &self,
+        user_id: &str,
+        url: &str,
+        vote: Vote,
+    ) -> Result<Option<Tally>, VoteStoreError>;
+
+    // 按 order 从高到低的前 limit 个 url, 分数相同时按 url 排序
+    fn top_n(&self, limit: usize, order: Order) -> Result<Vec<(String, Tally)>, VoteStoreError>;
+
+    // 订阅 url 的计数, 第一个值为订阅时的计数
+    fn watch(&self, url: &str) -> Result<watch::Receiver<Tally>, VoteStoreError>;
+
+    // 清空 url 的计数和用户投过的票, None 时清空所有 url; 返回清空的 url 个数
+    fn reset(&self, url: Option<&str>) -> Result<u64, VoteStoreError>;
+
+    // 把还没有保存的计数写入存储, 关闭服务前调用
+    fn flush(&self) -> Result<(), VoteStoreError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct VoteStoreError(Box<dyn std::error::Error + Send + Sync>);
+
+impl fmt::Display for VoteStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "vote store failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for VoteStoreError {}
+
+impl From<io::Error> for VoteStoreError {
+    fn from(e: io::Error) -> Self {
+        VoteStoreError(Box::new(e))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for VoteStoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        VoteStoreError(Box::new(e))
+    }
+}
+
+// watch_votes 订阅的 url, 调用方持有 url 计数的锁时通知, 订阅者看到的计数与修改顺序一致
+#[derive(Debug, Default)]
+struct Watchers(DashMap<String, watch::Sender<Tally>>);
+
+impl Watchers {
+    // 没有订阅者时移除
+    fn notify(&self, url: &str, tally: Tally) {
+        self.0
+            .remove_if(url, |_, watcher| watcher.send(tally).is_err());
+    }
+
+    // current 为调用方在持有 url 计数的锁时读到的计数
+    fn subscribe(&self, url: &str, current: Tally) -> watch::Receiver<Tally> {
+        self.0
+            .entry(url.to_string())
+            .or_insert_with(|| watch::channel(current).0)
+            .subscribe()
+    }
+}
+
+// 弃权票不影响净票数
+fn score(tally: Tally, order: Order) -> i128 {
+    match order {
+        Order::Net => tally.up as i128 - tally.down as i128,
+        Order::Total => tally.total() as i128,
+    }
+}
+
+// 快照文件的格式, url 按字典序保存
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    tallies: BTreeMap<String, Tally>,
+    #[serde(default)]
+    user_votes: Vec<UserVoteJson>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UserVoteJson {
+    user_id: String,
+    url: String,
+    // Vote 的名称, 如 "UP"
+    vote: String,
+}
+
+// 默认的存储, 未配置快照文件时重启后计数清零
+#[derive(Debug, Default)]
+pub struct InMemoryVoteStore {
+    tallies: DashMap<String, Tally>,
+    // tallies 中匿名票的部分, unvote 只撤销匿名票
+    anonymous: DashMap<String, Tally>,
+    // 每个 (用户, url) 最后一次投的票
+    user_votes: DashMap<(String, String), Vote>,
+    watchers: Watchers,
+    // 单票的修改持有读锁, record_batch 持有写锁
+    writes: RwLock<()>,
+    // flush 时写入的快照文件
+    snapshot: Option<PathBuf>,
+    // 同一时刻只有一次 flush 写临时文件
+    save: Mutex<()>,
+}
+
+impl InMemoryVoteStore {
+    // 从 path 恢复计数, flush 时写回 path; 文件不存在时从空的计数开始,
+    // 文件损坏时记录日志后从空的计数开始, 下一次 flush 会覆盖损坏的文件
+    pub fn with_snapshot(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let snapshot = match load_snapshot(&path) {
+            Ok(snapshot) => snapshot,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => {
+                tracing::warn!(
+                    path = %path.display(),
+                    error = %e,
+                    "failed to load vote snapshot, starting empty"
+                );
+                Snapshot::default()
+            }
+        };
+
+        let user_votes: DashMap<_, _> = snapshot
+            .user_votes
+            .into_iter()
+            .filter_map(|user_vote| {
+                let vote = Vote::from_str_name(&user_vote.vote)?;
+                Some(((user_vote.user_id, user_vote.url), vote))
+            })
+            .collect();
+        // 快照中不单独保存匿名票, 由计数减去用户的票得到
+        let anonymous: DashMap<_, _> = snapshot.tallies.clone().into_iter().collect();
+        for user_vote in user_votes.iter() {
+            if let Some(mut tally) = anonymous.get_mut(&user_vote.key().1) {
+                let count = tally.count_mut(*user_vote.value());
+                *count = count.saturating_sub(1);
+            }
+        }
+
+        InMemoryVoteStore {
+            tallies: snapshot.tallies.into_iter().collect(),
+            anonymous,
+            user_votes,
+            snapshot: Some(path),
+            ..Default::default()
+        }
+    }
+
+    // 每隔 interval 写一次快照, 写入失败时记录日志, 下一次继续尝试; shutdown 取消后停止, 最后一次由 flush 写入
+    pub fn spawn_flusher(
+        self: Arc<Self>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // 第一次 tick 立即完成, 启动时不需要写回刚读到的快照
+            ticker.tick().await;
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.cancelled() => break,
+                }
+                let store = self.clone();
+                let result = tokio::task::spawn_blocking(move || store.flush()).await;
+                if let Ok(Err(e)) = result {
+                    tracing::warn!(error = %e, "failed to write vote snapshot");
+                }
+            }
+        })
+    }
+
+    // 先锁匿名票的计数再锁总计数
+    fn record_anonymous(&self, url: &str, vote: Vote) -> Tally {
+        let mut anonymous = self.anonymous.entry(url.to_string()).or_default();
+        *anonymous.count_mut(vote) += 1;
+        self.count(url, vote)
+    }
+
+    // 先锁用户的记录再锁计数
+    fn record_for(&self, user_id: &str, url: &str, vote: Vote) -> Option<Tally> {
+        match self
+            .user_votes
+            .entry((user_id.to_string(), url.to_string()))
+        {
+            Entry::Occupied(mut last) => {
+                if *last.get() == vote {
+                    return None;
+                }
+                let mut tally = self.tallies.entry(url.to_string()).or_default();
+                let from = tally.count_mut(*last.get());
+                *from = from.saturating_sub(1);
+                *tally.count_mut(vote) += 1;
+                last.insert(vote);
+                self.watchers.notify(url, *tally);
+                Some(*tally)
+            }
+            Entry::Vacant(last) => {
+                let tally = self.count(url, vote);
+                last.insert(vote);
+                Some(tally)
+            }
+        }
+    }
+
+    fn count(&self, url: &str, vote: Vote) -> Tally {
+        let mut tally = self.tallies.entry(url.to_string()).or_default();
+        *tally.count_mut(vote) += 1;
+        self.watchers.notify(url, *tally);
+        *tally
+    }
+}
+
+impl VoteStore for InMemoryVoteStore {
+    // 复制出来的是某一时刻完整的计数, 不会读到投票更新了一半的结果
+    fn get(&self, url: &str) -> Result<Option<Tally>, VoteStoreError> {
+        Ok(self.tallies.get(url).map(|tally| *tally))
+    }
+
+    fn record(&self, url: &str, vote: Vote) -> Result<Tally, VoteStoreError> {
+        let _writes = self.writes.read().unwrap();
+        Ok(self.record_anonymous(url, vote))
+    }
+
+    fn record_user(
+        &self,
+        user_id: &str,
+        url: &str,
+        vote: Vote,
+    ) -> Result<Option<Tally>, VoteStoreError> {
+        let _writes = self.writes.read().unwrap();
+        Ok(self.record_for(user_id, url, vote))
+    }
+
+    fn record_batch(
+        &self,
+        user_id: Option<&str>,
+        votes: &[(&str, Vote)],
+    ) -> Result<Vec<Option<Tally>>, VoteStoreError> {
+        let _writes = self.writes.write().unwrap();
+        Ok(votes
+            .iter()
+            .map(|&(url, vote)| match user_id {
+                Some(user_id) => self.record_for(user_id, url, vote),
+                None => Some(self.record_anonymous(url, vote)),
+            })
+            .collect())
+    }
+
+    // 与 SqliteVoteStore 一样只撤销匿名票, 不会减掉用户投的票;
+    // 在匿名票计数的锁内检查并减少, 并发撤销不会减到 0 以下
+    fn unvote(&self, url: &str, vote: Vote) -> Result<Option<Tally>, VoteStoreError> {
+        let _writes = self.writes.read().unwrap();
+        let Some(mut anonymous) = self.anonymous.get_mut(url) else {
+            return Ok(None);
+        };
+        let count = anonymous.count_mut(vote);
+        let Some(remaining) = count.checked_sub(1) else {
+            return Ok(None);
+        };
+        let Some(mut tally) = self.tallies.get_mut(url) else {
+            return Ok(None);
+        };
+        *count = remaining;
+        let total = tally.count_mut(vote);
+        *total = total.saturating_sub(1);
+        self.watchers.notify(url, *tally);
+        Ok(Some(*tally))
+    }
+
+    fn unvote_user(
+        &self,
+        user_id: &str,
+        url: &str,
+        vote: Vote,
+    ) -> Result<Option<Tally>, VoteStoreError> {
+        let _writes = self.writes.read().unwrap();
+        let Entry::Occupied(last) = self
+            .user_votes
+            .entry((user_id.to_string(), url.to_string()))
+        else {
+            return Ok(None);
+        };
+        if *last.get() != vote {
+            return Ok(None);
+        }
+
+        let Some(mut tally) = self.tallies.get_mut(url) else {
+            return Ok(None);
+        };
+        let count = tally.count_mut(vote);
+        *count = count.saturating_sub(1);
+        last.remove();
+        self.watchers.notify(url, *tally);
+        Ok(Some(*tally))
+    }
+
+    fn top_n(&self, limit: usize, order: Order) -> Result<Vec<(String, Tally)>, VoteStoreError> {
+        let mut tallies: Vec<_> = self
+            .tallies
+            .iter()
+            .map(|tally| (tally.key().clone(), *tally.value()))
+            .collect();
+        tallies.sort_by(|(a_url, a), (b_url, b)| {
+            score(*b, order)
+                .cmp(&score(*a, order))
+                .then_with(|| a_url.cmp(b_url))
+        });
+        tallies.truncate(limit);
+
+        Ok(tallies)
+    }
+
+    // 持有计数的锁订阅, 订阅前后的投票既不会漏掉也不会重复
+    fn watch(&self, url: &str) -> Result<watch::Receiver<Tally>, VoteStoreError> {
+        let tally = self.tallies.entry(url.to_string());
+        let current = match &tally {
+            Entry::Occupied(tally) => *tally.get(),
+            Entry::Vacant(_) => Tally::default(),
+        };
+
+        Ok(self.watchers.subscribe(url, current))
+    }
+
+    // 持有计数的锁清零并通知, 之后移除仍为 0 票的计数; 同时进行的投票可能在清空之后重新计入
+    fn reset(&self, url: Option<&str>) -> Result<u64, VoteStoreError> {
+        let urls = match url {
+            Some(url) => vec![url.to_string()],
+            None => self
+                .tallies
+                .iter()
+                .map(|tally| tally.key().clone())
+                .collect(),
+        };
+        match url {
+            Some(url) => {
+                self.user_votes.retain(|(_, voted), _| voted != url);
+                self.anonymous.remove(url);
+            }
+            None => {
+                self.user_votes.clear();
+                self.anonymous.clear();
+            }
+        }
+
+        let mut cleared = 0;
+        for url in &urls {
+            let Some(mut tally) = self.tallies.get_mut(url) else {
+                continue;
+            };
+            *tally = Tally::default();
+            self.watchers.notify(url, *tally);
+            drop(tally);
+            self.tallies.remove_if(url, |_, tally| tally.total() == 0);
+            cleared += 1;
+        }
+
+        Ok(cleared)
+    }
+
+    // 逐个 url 复制计数, 快照中不同 url 的计数可能来自不同时刻
+    fn flush(&self) -> Result<(), VoteStoreError> {
+        let Some(path) = &self.snapshot else {
+            return Ok(());
+        };
+
+        let snapshot = Snapshot {
+            tallies: self
+                .tallies
+                .iter()
+                .map(|tally| (tally.key().clone(), *tally.value()))
+                .collect(),
+            user_votes: self
+                .user_votes
+                .iter()
+                .map(|user_vote| UserVoteJson {
+                    user_id: user_vote.key().0.clone(),
+                    url: user_vote.key().1.clone(),
+                    vote: user_vote.value().as_str_name().to_string(),
+                })
+                .collect(),
+        };
+        let _save = self.save.lock().unwrap();
+        save_snapshot(path, &snapshot)?;
+        Ok(())
+    }
+}
+
+fn load_snapshot(path: &Path) -> io::Result<Snapshot> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
+
+// 与 featurejson::save_json 相同, 先写临时文件落盘再改名, 崩溃后不会留下写了一半的快照
+fn save_snapshot(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, snapshot)?;
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteVoteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::{path::Path, sync::Mutex, time::Duration};
+
+    use rusqlite::{params, Connection, OptionalExtension};
+    use tokio::sync::watch;
+
+    use super::{Order, Tally, Vote, VoteStore, VoteStoreError, Watchers};
+    use crate::featurestore::now_millis;
+
+    // 依次执行, 已经执行过的个数记在 PRAGMA user_version 中
+    const MIGRATIONS: &[&str] = &[
+        "
+        CREATE TABLE votes (
+            id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL,
+            -- 匿名票为 NULL
+            user_id TEXT,
+            -- 1 为赞成票, -1 为反对票
+            vote INTEGER NOT NULL CHECK (vote IN (1, -1)),
+            -- unix 毫秒
+            updated_at INTEGER NOT NULL
+        );
+        CREATE UNIQUE INDEX votes_by_user ON votes (url, user_id) WHERE user_id IS NOT NULL;
+        CREATE INDEX votes_by_url ON votes (url);
+        CREATE VIEW vote_tallies AS
+            SELECT url, SUM(vote = 1) AS up_count, SUM(vote = -1) AS down_count
+            FROM votes GROUP BY url;
+    ",
+        "
+        -- 0 为弃权票, SQLite 不能修改 CHECK 约束, 需要重建表
+        DROP VIEW vote_tallies;
+        CREATE TABLE votes_new (
+            id INTEGER PRIMARY KEY,
+            url TEXT NOT NULL,
+            user_id TEXT,
+            -- 1 为赞成票, -1 为反对票, 0 为弃权票
+            vote INTEGER NOT NULL CHECK (vote IN (1, -1, 0)),
+            updated_at INTEGER NOT NULL
+        );
+        INSERT INTO votes_new SELECT id, url, user_id, vote, updated_at FROM votes;
+        DROP TABLE votes;
+        ALTER TABLE votes_new RENAME TO votes;
+        CREATE UNIQUE INDEX votes_by_user ON votes (url, user_id) WHERE user_id IS NOT NULL;
+        CREATE INDEX votes_by_url ON votes (url);
+        CREATE VIEW vote_tallies AS
+            SELECT url,
+                SUM(vote = 1) AS up_count,
+                SUM(vote = -1) AS down_count,
+                SUM(vote = 0) AS abstain_count
+            FROM votes GROUP BY url;
+    ",
+    ];
+
+    // 写操作在同一个连接上串行执行, 通知订阅者时仍持有连接的锁
+    #[derive(Debug)]
+    pub struct SqliteVoteStore {
+        conn: Mutex<Connection>,
+        watchers: Watchers,
+    }
+
+    impl SqliteVoteStore {
+        // 打开或创建数据库, 并执行还没有执行过的迁移
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, VoteStoreError> {
+            let mut conn = Connection::open(path)?;
+            conn.busy_timeout(Duration::from_secs(5))?;
+            migrate(&mut conn)?;
+
+            Ok(SqliteVoteStore {
+                conn: Mutex::new(conn),
+                watchers: Watchers::default(),
+            })
+        }
+
+        fn write(
+            &self,
+            url: &str,
+            f: impl FnOnce(&Connection) -> rusqlite::Result<bool>,
+        ) -> Result<Option<Tally>, VoteStoreError> {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            if !f(&tx)? {
+                return Ok(None);
+            }
+            let tally = tally(&tx, url)?.unwrap_or_default();
+            tx.commit()?;
+
+            self.watchers.notify(url, tally);
+            Ok(Some(tally))
+        }
+    }
+
+    fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", (applied + 1) as i64)?;
+            tx.commit()?;
+        }
+
+        Ok(())
+    }
+
+    const TALLY_COLUMNS: &str = "up_count, down_count, abstain_count";
+
+    // 从第 first 列起依次读取 TALLY_COLUMNS
+    fn tally_from_row(row: &rusqlite::Row<'_>, first: usize) -> rusqlite::Result<Tally> {
+        Ok(Tally {
+            up: row.get::<_, i64>(first)? as u64,
+            down: row.get::<_, i64>(first + 1)? as u64,
+            abstain: row.get::<_, i64>(first + 2)? as u64,
+        })
+    }
+
+    fn tally(conn: &Connection, url: &str) -> rusqlite::Result<Option<Tally>> {
+        conn.query_row(
+            &format!("SELECT {} FROM vote_tallies WHERE url = ?1", TALLY_COLUMNS),
+            [url],
+            |row| tally_from_row(row, 0),
+        )
+        .optional()
+    }
+
+    fn insert_vote(conn: &Connection, url: &str, vote: Vote) -> rusqlite::Result<bool> {
+        conn.execute(
+            "INSERT INTO votes (url, user_id, vote, updated_at) VALUES (?1, NULL, ?2, ?3)",
+            params![url, vote_value(vote), now_millis()],
+        )?;
+        Ok(true)
+    }
+
+    // 与该用户上一次投的票相同时不修改, 返回 false
+    fn upsert_user_vote(
+        conn: &Connection,
+        user_id: &str,
+        url: &str,
+        vote: Vote,
+    ) -> rusqlite::Result<bool> {
+        let last: Option<i64> = conn
+            .query_row(
+                "SELECT vote FROM votes WHERE url = ?1 AND user_id = ?2",
+                params![url, user_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match last {
+            Some(last) if last == vote_value(vote) => return Ok(false),
+            Some(_) => conn.execute(
+                "UPDATE votes SET vote = ?3, updated_at = ?4 WHERE url = ?1 AND user_id = ?2",
+                params![url, user_id, vote_value(vote), now_millis()],
+            )?,
+            None => conn.execute(
+                "INSERT INTO votes (url, user_id, vote, updated_at) VALUES (?1, ?2, ?3, ?4)",
+                params![url, user_id, vote_value(vote), now_millis()],
+            )?,
+        };
+        Ok(true)
+    }
+
+    fn vote_value(vote: Vote) -> i64 {
+        match vote {
+            Vote::Up => 1,
+            Vote::Down => -1,
+            Vote::Abstain => 0,
+        }
+    }
+
+    impl VoteStore for SqliteVoteStore {
+        fn get(&self, url: &str) -> Result<Option<Tally>, VoteStoreError> {
+            Ok(tally(&self.conn.lock().unwrap(), url)?)
+        }
+
+        fn record(&self, url: &str, vote: Vote) -> Result<Tally, VoteStoreError> {
+            let tally = self.write(url, |conn| insert_vote(conn, url, vote))?;
+
+            Ok(tally.unwrap_or_default())
+        }
+
+        fn record_user(
+            &self,
+            user_id: &str,
+            url: &str,
+            vote: Vote,
+        ) -> Result<Option<Tally>, VoteStoreError> {
+            self.write(url, |conn| upsert_user_vote(conn, user_id, url, vote))
+        }
+
+        // 整批在同一个事务中写入, 提交后再按顺序通知订阅者
+        fn record_batch(
+            &self,
+            user_id: Option<&str>,
+            votes: &[(&str, Vote)],
+        ) -> Result<Vec<Option<Tally>>, VoteStoreError> {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            let mut results = Vec::with_capacity(votes.len());
+            for &(url, vote) in votes {
+                let changed = match user_id {
+                    Some(user_id) => upsert_user_vote(&tx, user_id, url, vote)?,
+                    None => insert_vote(&tx, url, vote)?,
+                };
+                results.push(match changed {
+                    true => Some(tally(&tx, url)?.unwrap_or_default()),
+                    false => None,
+                });
+            }
+            tx.commit()?;
+
+            for (&(url, _), tally) in votes.iter().zip(&results) {
+                if let Some(tally) = tally {
+                    self.watchers.notify(url, *tally);
+                }
+            }
+            Ok(results)
+        }
+
+        // 只撤销匿名投的票
+        fn unvote(&self, url: &str, vote: Vote) -> Result<Option<Tally>, VoteStoreError> {
+            self.write(url, |conn| {
+                let removed = conn.execute(
+                    "DELETE FROM votes WHERE id = (
+                        SELECT id FROM votes WHERE url = ?1 AND user_id IS NULL AND vote = ?2 LIMIT 1
+                    )",
+                    params![url, vote_value(vote)],
+                )?;
+                Ok(removed > 0)
+            })
+        }
+
+        fn unvote_user(
+            &self,
+            user_id: &str,
+            url: &str,
+            vote: Vote,
+        ) -> Result<Option<Tally>, VoteStoreError> {
+            self.write(url, |conn| {
+                let removed = conn.execute(
+                    "DELETE FROM votes WHERE url = ?1 AND user_id = ?2 AND vote = ?3",
+                    params![url, user_id, vote_value(vote)],
+                )?;
+                Ok(removed > 0)
+            })
+        }
+
+        fn top_n(
+            &self,
+            limit: usize,
+            order: Order,
+        ) -> Result<Vec<(String, Tally)>, VoteStoreError> {
+            let score = match order {
+                Order::Net => "up_count - down_count",
+                Order::Total => "up_count + down_count + abstain_count",
+            };
+            let conn = self.conn.lock().unwrap();
+            let mut statement = conn.prepare(&format!(
+                "SELECT url, {} FROM vote_tallies ORDER BY {} DESC, url LIMIT ?1",
+                TALLY_COLUMNS, score
+            ))?;
+            let rows = statement.query_map([limit as i64], |row| {
+                Ok((row.get::<_, String>(0)?, tally_from_row(row, 1)?))
+            })?;
+
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        }
+
+        fn watch(&self, url: &str) -> Result<watch::Receiver<Tally>, VoteStoreError> {
+            let conn = self.conn.lock().unwrap();
+            let current = tally(&conn, url)?.unwrap_or_default();
+
+            Ok(self.watchers.subscribe(url, current))
+        }
+
+        fn reset(&self, url: Option<&str>) -> Result<u64, VoteStoreError> {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction()?;
+            let urls = {
+                let mut statement =
+                    tx.prepare("SELECT DISTINCT url FROM votes WHERE ?1 IS NULL OR url = ?1")?;
+                let rows = statement.query_map([url], |row| row.get::<_, String>(0))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            tx.execute("DELETE FROM votes WHERE ?1 IS NULL OR url = ?1", [url])?;
+            tx.commit()?;
+
+            for url in &urls {
+                self.watchers.notify(url, Tally::default());
+            }
+            Ok(urls.len() as u64)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::votestore::InMemoryVoteStore;
+
+        fn user_version(conn: &Connection) -> i64 {
+            conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+                .unwrap()
+        }
+
+        #[test]
+        fn migrations_run_once_in_order() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            migrate(&mut conn).unwrap();
+            assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+
+            // 再次执行时跳过已经执行过的迁移
+            migrate(&mut conn).unwrap();
+            assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+        }
+
+        #[test]
+        fn abstain_migration_keeps_existing_votes() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.pragma_update(None, "user_version", 1).unwrap();
+            conn.execute_batch(
+                "INSERT INTO votes (id, url, user_id, vote, updated_at) VALUES
+                    (1, 'a', NULL, 1, 10),
+                    (2, 'a', NULL, -1, 20),
+                    (3, 'a', 'alice', 1, 30),
+                    (4, 'b', 'alice', -1, 40);",
+            )
+            .unwrap();
+            // 旧的表不接受弃权票
+            assert!(conn
+                .execute(
+                    "INSERT INTO votes (url, vote, updated_at) VALUES ('a', 0, 50)",
+                    []
+                )
+                .is_err());
+
+            migrate(&mut conn).unwrap();
+            assert_eq!(user_version(&conn), MIGRATIONS.len() as i64);
+            let rows: Vec<(i64, String, Option<String>, i64, i64)> = conn
+                .prepare("SELECT id, url, user_id, vote, updated_at FROM votes ORDER BY id")
+                .unwrap()
+                .query_map([], |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                })
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap();
+            assert_eq!(
+                rows,
+                [
+                    (1, "a".to_string(), None, 1, 10),
+                    (2, "a".to_string(), None, -1, 20),
+                    (3, "a".to_string(), Some("alice".to_string()), 1, 30),
+                    (4, "b".to_string(), Some("alice".to_string()), -1, 40),
+                ]
+            );
+            assert_eq!(
+                tally(&conn, "a").unwrap(),
+                Some(Tally {
+                    up: 2,
+                    down: 1,
+                    abstain: 0
+                })
+            );
+
+            // 重建后的表接受弃权票, 唯一索引仍然有效
+            conn.execute(
+                "INSERT INTO votes (url, vote, updated_at) VALUES ('a', 0, 50)",
+                [],
+            )
+            .unwrap();
+            assert!(conn
+                .execute(
+                    "INSERT INTO votes (url, user_id, vote, updated_at) VALUES ('a', 'alice', 0, 60)",
+                    [],
+                )
+                .is_err());
+        }
+
+        type Step = dyn Fn(&dyn VoteStore) -> Option<Tally>;
+
+        #[test]
+        fn matches_in_memory_store() {
+            let sqlite = SqliteVoteStore::open(":memory:").unwrap();
+            let memory = InMemoryVoteStore::default();
+            let stores: [&dyn VoteStore; 2] = [&sqlite, &memory];
+
+            // 每一步在两个存储上的结果相同
+            let steps: [&Step; 14] = [
+                &|store| Some(store.record("a", Vote::Up).unwrap()),
+                &|store| Some(store.record("a", Vote::Abstain).unwrap()),
+                &|store| store.record_user("alice", "a", Vote::Up).unwrap(),
+                // 重复的票
+                &|store| store.record_user("alice", "a", Vote::Up).unwrap(),
+                // 改票
+                &|store| store.record_user("alice", "a", Vote::Down).unwrap(),
+                &|store| store.record_user("bob", "a", Vote::Down).unwrap(),
+                // 反对票都是用户投的, 匿名撤销不影响
+                &|store| store.unvote("a", Vote::Down).unwrap(),
+                &|store| store.unvote("a", Vote::Up).unwrap(),
+                // 没有可撤销的票
+                &|store| store.unvote("a", Vote::Up).unwrap(),
+                &|store| store.unvote("a", Vote::Abstain).unwrap(),
+                &|store| store.unvote_user("alice", "a", Vote::Up).unwrap(),
+                &|store| store.unvote_user("alice", "a", Vote::Down).unwrap(),
+                &|store| store.record_user("alice", "b", Vote::Up).unwrap(),
+                &|store| store.get("a").unwrap(),
+            ];
+            for (i, step) in steps.iter().enumerate() {
+                assert_eq!(step(stores[0]), step(stores[1]), "step {i}");
+            }
+
+            assert_eq!(
+                sqlite.top_n(10, Order::Total).unwrap(),
+                memory.top_n(10, Order::Total).unwrap()
+            );
+            assert_eq!(sqlite.get("c").unwrap(), None);
+        }
+
+        #[test]
+        fn record_batch_matches_in_memory_store() {
+            let sqlite = SqliteVoteStore::open(":memory:").unwrap();
+            let memory = InMemoryVoteStore::default();
+            let votes = [
+                ("a", Vote::Up),
+                ("a", Vote::Up),
+                ("b", Vote::Down),
+                ("a", Vote::Down),
+            ];
+
+            for user_id in [None, Some("alice")] {
+                assert_eq!(
+                    sqlite.record_batch(user_id, &votes).unwrap(),
+                    memory.record_batch(user_id, &votes).unwrap()
+                );
+            }
+            assert_eq!(sqlite.get("a").unwrap(), memory.get("a").unwrap());
+            assert_eq!(sqlite.get("b").unwrap(), memory.get("b").unwrap());
+        }
+
+        #[test]
+        fn concurrent_writes_keep_the_tallies() {
+            let path = std::env::temp_dir().join(format!("votes-{}.db", uuid::Uuid::new_v4()));
+            crate::votestore::tests::write_concurrently(&SqliteVoteStore::open(&path).unwrap());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn votes_survive_reopen() {
+            let path = std::env::temp_dir().join(format!("votes-{}.db", uuid::Uuid::new_v4()));
+            let tally = {
+                let store = SqliteVoteStore::open(&path).unwrap();
+                store.record("a", Vote::Up).unwrap();
+                store
+                    .record_user("alice", "a", Vote::Abstain)
+                    .unwrap()
+                    .unwrap()
+            };
+
+            let store = SqliteVoteStore::open(&path).unwrap();
+            assert_eq!(store.get("a").unwrap(), Some(tally));
+            // 用户的票也恢复了
+            assert_eq!(
+                store.record_user("alice", "a", Vote::Abstain).unwrap(),
+                None
+            );
+
+            drop(store);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_snapshot_path() -> PathBuf {
+        std::env::temp_dir().join(format!("votes-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    // 等到快照文件中 url 的计数为 want, 最多等 5 秒
+    async fn wait_for_snapshot(path: &Path, url: &str, want: Tally) {
+        for _ in 0..500 {
+            if let Ok(snapshot) = load_snapshot(path) {
+                if snapshot.tallies.get(url) == Some(&want) {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("snapshot never reached {want:?}");
+    }
+
+    const WRITERS: usize = 8;
+
+    // 每个线程投 50 张匿名赞成票再撤销 20 张, 用户先投反对票再改成赞成, 最后在 b 上弃权
+    pub(super) fn write_concurrently(store: &dyn VoteStore) {
+        std::thread::scope(|scope| {
+            for i in 0..WRITERS {
+                scope.spawn(move || {
+                    let user_id = format!("user-{i}");
+                    for _ in 0..50 {
+                        store.record("a", Vote::Up).unwrap();
+                    }
+                    for _ in 0..20 {
+                        store.unvote("a", Vote::Up).unwrap().unwrap();
+                    }
+                    store
+                        .record_user(&user_id, "a", Vote::Down)
+                        .unwrap()
+                        .unwrap();
+                    store.record_user(&user_id, "a", Vote::Up).unwrap().unwrap();
+                    store
+                        .record_user(&user_id, "b", Vote::Abstain)
+                        .unwrap()
+                        .unwrap();
+                });
+            }
+        });
+
+        let writers = WRITERS as u64;
+        assert_eq!(
+            store.get("a").unwrap(),
+            Some(Tally {
+                up: writers * 31,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            store.get("b").unwrap(),
+            Some(Tally {
+                abstain: writers,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn concurrent_writes_keep_the_tallies() {
+        write_concurrently(&InMemoryVoteStore::default());
+    }
+
+    #[test]
+    fn record_batch_matches_sequential_votes() {
+        let batch = InMemoryVoteStore::default();
+        let sequential = InMemoryVoteStore::default();
+        let votes = [
+            ("a", Vote::Up),
+            ("a", Vote::Up),
+            ("b", Vote::Down),
+            ("a", Vote::Down),
+        ];
+
+        let results = batch.record_batch(Some("alice"), &votes).unwrap();
+        let expected: Vec<_> = votes
+            .iter()
+            .map(|&(url, vote)| sequential.record_user("alice", url, vote).unwrap())
+            .collect();
+        assert_eq!(results, expected);
+        // 重复的票返回 None, 改票把一票移到反对票
+        assert_eq!(results[1], None);
+        assert_eq!(
+            results[3],
+            Some(Tally {
+                down: 1,
+                ..Default::default()
+            })
+        );
+
+        let results = batch.record_batch(None, &votes).unwrap();
+        let counts: Vec<_> = results.iter().map(|tally| tally.unwrap().total()).collect();
+        assert_eq!(counts, [2, 3, 2, 4]);
+        // 批量记录的匿名票也可以撤销
+        assert!(batch.unvote("b", Vote::Down).unwrap().is_some());
+    }
+
+    #[test]
+    fn anonymous_unvote_keeps_user_votes() {
+        let store = InMemoryVoteStore::default();
+        store.record_user("alice", "a", Vote::Up).unwrap();
+
+        // 只有用户的票时没有可撤销的匿名票
+        assert_eq!(store.unvote("a", Vote::Up).unwrap(), None);
+        assert_eq!(store.get("a").unwrap().unwrap().up, 1);
+
+        store.record("a", Vote::Up).unwrap();
+        assert_eq!(store.unvote("a", Vote::Up).unwrap().unwrap().up, 1);
+        assert_eq!(store.unvote("a", Vote::Up).unwrap(), None);
+        // 用户的票仍然可以由该用户撤销
+        assert_eq!(
+            store
+                .unvote_user("alice", "a", Vote::Up)
+                .unwrap()
+                .unwrap()
+                .up,
+            0
+        );
+    }
+
+    #[test]
+    fn snapshot_restores_the_anonymous_share() {
+        let path = temp_snapshot_path();
+        let store = InMemoryVoteStore::with_snapshot(&path);
+        store.record("a", Vote::Up).unwrap();
+        store.record_user("alice", "a", Vote::Up).unwrap();
+        store.flush().unwrap();
+
+        let store = InMemoryVoteStore::with_snapshot(&path);
+        assert_eq!(store.unvote("a", Vote::Up).unwrap().unwrap().up, 1);
+        assert_eq!(store.unvote("a", Vote::Up).unwrap(), None);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn flusher_writes_snapshots_until_shutdown() {
+        let path = temp_snapshot_path();
+        let store = Arc::new(InMemoryVoteStore::with_snapshot(&path));
+        let shutdown = CancellationToken::new();
+        let flusher = store
+            .clone()
+            .spawn_flusher(Duration::from_millis(10), shutdown.clone());
+
+        let tally = store.record("a", Vote::Up).unwrap();
+        wait_for_snapshot(&path, "a", tally).await;
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), flusher)
+            .await
+            .expect("flusher ignored shutdown")
+            .unwrap();
+
+        // 停止后不再写入, 最后的计数由 flush 写入
+        let tally = store.record("a", Vote::Down).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_ne!(load_snapshot(&path).unwrap().tallies.get("a"), Some(&tally));
+        store.flush().unwrap();
+        assert_eq!(load_snapshot(&path).unwrap().tallies.get("a"), Some(&tally));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
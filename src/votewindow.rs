@@ -0,0 +1,131 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::{votestore::Tally, voting::voting_request::Vote};
+
+// 每个桶覆盖的秒数, 窗口内的计数精确到一个桶
+pub const BUCKET_SECS: u64 = 10;
+// 支持查询的最长窗口, 更早的桶会被清理
+pub const MAX_WINDOW_SECS: u64 = 3600;
+
+#[derive(Debug)]
+struct Bucket {
+    // 自 start 起第几个桶
+    index: u64,
+    tally: Tally,
+}
+
+// 按时间分桶记录每个 url 投出的票, 撤销和改票不会减少已经记录的票;
+// 使用 tokio 的时钟, 测试中可以用 tokio::time::pause 推进时间
+#[derive(Debug)]
+pub struct VoteWindows {
+    start: Instant,
+    buckets: DashMap<String, VecDeque<Bucket>>,
+}
+
+impl Default for VoteWindows {
+    fn default() -> Self {
+        VoteWindows {
+            start: Instant::now(),
+            buckets: DashMap::new(),
+        }
+    }
+}
+
+impl VoteWindows {
+    // 覆盖 secs 秒前那一刻的桶
+    fn index_before(&self, secs: u64) -> u64 {
+        self.start.elapsed().as_secs().saturating_sub(secs) / BUCKET_SECS
+    }
+
+    pub fn record(&self, url: &str, vote: Vote) {
+        let index = self.index_before(0);
+        let mut buckets = self.buckets.entry(url.to_string()).or_default();
+        if buckets.back().is_none_or(|bucket| bucket.index != index) {
+            buckets.push_back(Bucket {
+                index,
+                tally: Tally::default(),
+            });
+        }
+        *buckets.back_mut().unwrap().tally.count_mut(vote) += 1;
+    }
+
+    // 最近 window_secs 秒投出的票, 包括覆盖窗口起点的整个桶
+    pub fn count(&self, url: &str, window_secs: u64) -> Tally {
+        let oldest = self.index_before(window_secs);
+        let mut tally = Tally::default();
+        if let Some(buckets) = self.buckets.get(url) {
+            for bucket in buckets
+                .iter()
+                .rev()
+                .take_while(|bucket| bucket.index >= oldest)
+            {
+                tally.up += bucket.tally.up;
+                tally.down += bucket.tally.down;
+                tally.abstain += bucket.tally.abstain;
+            }
+        }
+
+        tally
+    }
+
+    // 删除 url 的所有桶, None 时删除所有 url 的桶
+    pub fn clear(&self, url: Option<&str>) {
+        match url {
+            Some(url) => {
+                self.buckets.remove(url);
+            }
+            None => self.buckets.clear(),
+        }
+    }
+
+    // 删除超出 MAX_WINDOW_SECS 的桶
+    pub fn prune(&self) {
+        let oldest = self.index_before(MAX_WINDOW_SECS);
+        self.buckets.retain(|_, buckets| {
+            while buckets.front().is_some_and(|bucket| bucket.index < oldest) {
+                buckets.pop_front();
+            }
+            !buckets.is_empty()
+        });
+    }
+
+    pub fn spawn_pruner(
+        self: Arc<Self>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => self.prune(),
+                    _ = shutdown.cancelled() => break,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pruner_stops_on_shutdown() {
+        let shutdown = CancellationToken::new();
+        let pruner = Arc::new(VoteWindows::default())
+            .spawn_pruner(Duration::from_millis(10), shutdown.clone());
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!pruner.is_finished());
+
+        shutdown.cancel();
+        tokio::time::timeout(Duration::from_secs(5), pruner)
+            .await
+            .expect("pruner ignored shutdown")
+            .unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+// 用零宽字符在文本中嵌入不可见的水印, 用于追踪内容泄露
+// 每个 bit 对应一个字符: 1 为 U+200D (ZWJ), 0 为 U+200C (ZWNJ), 高位在前
+
+const ONE: char = '\u{200D}';
+const ZERO: char = '\u{200C}';
+const BITS: usize = u64::BITS as usize;
+
+fn is_zero_width(c: char) -> bool {
+    c == ONE || c == ZERO
+}
+
+// 水印插在第一个字符之后, 避免被首尾的 trim 去掉; 并且不与文本中原有的零宽字符相邻
+pub fn embed_watermark(text: &str, id: u64) -> String {
+    let split = text
+        .char_indices()
+        .zip(text.chars().skip(1).map(Some).chain([None]))
+        .find(|((_, c), next)| !is_zero_width(*c) && !next.is_some_and(is_zero_width))
+        .map_or(text.len(), |((i, c), _)| i + c.len_utf8());
+    let (head, tail) = text.split_at(split);
+
+    let mut marked = String::with_capacity(text.len() + BITS * ONE.len_utf8());
+    marked.push_str(head);
+    marked.extend(
+        (0..BITS)
+            .rev()
+            .map(|i| if id >> i & 1 == 1 { ONE } else { ZERO }),
+    );
+    marked.push_str(tail);
+
+    marked
+}
+
+// 取第一段恰好 64 个连续的零宽字符; emoji 序列中零散的 ZWJ 不会被误认为水印
+pub fn extract_watermark(text: &str) -> Option<u64> {
+    let mut id = 0u64;
+    let mut len = 0;
+
+    for c in text.chars().chain(std::iter::once('\0')) {
+        if is_zero_width(c) {
+            id = id << 1 | (c == ONE) as u64;
+            len += 1;
+        } else if len == BITS {
+            return Some(id);
+        } else {
+            id = 0;
+            len = 0;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for id in [0, 1, 42, u64::MAX] {
+            let marked = embed_watermark("Hello, world!", id);
+            assert_eq!(extract_watermark(&marked), Some(id));
+        }
+    }
+
+    #[test]
+    fn visible_text_is_unchanged() {
+        let marked = embed_watermark("  Hello, 世界 👋  ", 0xdead_beef);
+        let visible: String = marked.chars().filter(|c| !is_zero_width(*c)).collect();
+        assert_eq!(visible, "  Hello, 世界 👋  ");
+        assert_eq!(extract_watermark(marked.trim()), Some(0xdead_beef));
+    }
+
+    #[test]
+    fn text_without_watermark() {
+        assert_eq!(extract_watermark("Hello, world!"), None);
+        assert_eq!(extract_watermark(""), None);
+        // emoji 序列中的 ZWJ
+        assert_eq!(extract_watermark("👨\u{200D}👩\u{200D}👧"), None);
+    }
+}
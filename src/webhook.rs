@@ -0,0 +1,264 @@
+use std::{collections::HashSet, time::Duration};
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::{featurestore::now_millis, votestore::Tally};
+
+// 缓冲满时丢弃计数更新, 不阻塞投票
+const UPDATE_BUFFER: usize = 1024;
+// 第一次失败后最多重试的次数
+const MAX_RETRIES: u32 = 3;
+// 第 n 次重试前等待 RETRY_BACKOFF * 2^(n-1)
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+enum Update {
+    Tally(String, Tally),
+    // 计数被清空, None 表示所有 url
+    Reset(Option<String>),
+}
+
+#[derive(Debug, Serialize)]
+struct ThresholdPayload<'a> {
+    url: &'a str,
+    up_count: u64,
+    down_count: u64,
+    abstain_count: u64,
+    timestamp_millis: i64,
+}
+
+// url 的净票数达到 threshold 时向 target POST 一次 JSON, 回落到 threshold 以下后才会再次通知
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    updates: mpsc::Sender<Update>,
+}
+
+impl WebhookNotifier {
+    // 启动发送通知的后台任务, 需要在 tokio 运行时中调用
+    pub fn spawn(target: impl Into<String>, threshold: u64) -> Self {
+        let (updates, receiver) = mpsc::channel(UPDATE_BUFFER);
+        tokio::spawn(notify_crossings(
+            receiver,
+            target.into(),
+            threshold,
+            reqwest::Client::new(),
+        ));
+
+        WebhookNotifier { updates }
+    }
+
+    // 投票或撤销后的最新计数
+    pub fn publish(&self, url: &str, tally: Tally) {
+        self.send(Update::Tally(url.to_string(), tally));
+    }
+
+    pub fn reset(&self, url: Option<&str>) {
+        self.send(Update::Reset(url.map(str::to_string)));
+    }
+
+    fn send(&self, update: Update) {
+        if let Err(e) = self.updates.try_send(update) {
+            tracing::warn!(error = %e, "webhook update dropped");
+        }
+    }
+}
+
+// 每次越过阈值单独发送, 一个慢的请求不会推迟其他 url 的通知
+async fn notify_crossings(
+    mut receiver: mpsc::Receiver<Update>,
+    target: String,
+    threshold: u64,
+    client: reqwest::Client,
+) {
+    let mut crossed = HashSet::new();
+    while let Some(update) = receiver.recv().await {
+        let (url, tally) = match update {
+            Update::Tally(url, tally) => (url, tally),
+            Update::Reset(Some(url)) => {
+                crossed.remove(&url);
+                continue;
+            }
+            Update::Reset(None) => {
+                crossed.clear();
+                continue;
+            }
+        };
+
+        let net = tally.up as i128 - tally.down as i128;
+        if net < threshold as i128 {
+            crossed.remove(&url);
+        } else if crossed.insert(url.clone()) {
+            tokio::spawn(deliver(client.clone(), target.clone(), url, tally));
+        }
+    }
+}
+
+async fn deliver(client: reqwest::Client, target: String, url: String, tally: Tally) {
+    let payload = ThresholdPayload {
+        url: &url,
+        up_count: tally.up,
+        down_count: tally.down,
+        abstain_count: tally.abstain,
+        timestamp_millis: now_millis(),
+    };
+
+    for attempt in 0..=MAX_RETRIES {
+        if attempt > 0 {
+            tokio::time::sleep(RETRY_BACKOFF * 2u32.pow(attempt - 1)).await;
+        }
+        let result = client
+            .post(&target)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match result {
+            Ok(_) => return,
+            Err(e) => tracing::warn!(url, attempt, error = %e, "webhook delivery failed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use tokio::time::{timeout, Instant};
+
+    use super::*;
+
+    // 收到的每次 POST: 时间, 请求体, 是否返回成功
+    type Attempt = (Instant, serde_json::Value, bool);
+
+    // 前 failures 次请求返回 500 的 webhook 接收端
+    fn mock_receiver(failures: usize) -> (String, mpsc::UnboundedReceiver<Attempt>) {
+        let (attempts, receiver) = mpsc::unbounded_channel();
+        let failures = Arc::new(AtomicUsize::new(failures));
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State((failures, attempts)): State<(
+                        Arc<AtomicUsize>,
+                        mpsc::UnboundedSender<Attempt>,
+                    )>,
+                     Json(body): Json<serde_json::Value>| async move {
+                        let failed = failures
+                            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                            .is_ok();
+                        attempts.send((Instant::now(), body, !failed)).unwrap();
+                        if failed {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state((failures, attempts));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(server);
+
+        (url, receiver)
+    }
+
+    async fn next_attempt(attempts: &mut mpsc::UnboundedReceiver<Attempt>) -> Attempt {
+        timeout(Duration::from_secs(5), attempts.recv())
+            .await
+            .expect("webhook attempt")
+            .unwrap()
+    }
+
+    fn up(up: u64, down: u64) -> Tally {
+        Tally {
+            up,
+            down,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn crossing_posts_the_counts_once() {
+        let (url, mut attempts) = mock_receiver(0);
+        let notifier = WebhookNotifier::spawn(url, 2);
+        notifier.publish("https://a/", up(1, 0));
+        notifier.publish("https://a/", up(2, 0));
+        // 仍在阈值之上, 不再通知
+        notifier.publish("https://a/", up(3, 0));
+        notifier.publish("https://a/", up(4, 1));
+
+        let (_, body, delivered) = next_attempt(&mut attempts).await;
+        assert!(delivered);
+        assert_eq!(body["url"], "https://a/");
+        assert_eq!(body["up_count"], 2);
+        assert_eq!(body["down_count"], 0);
+        assert_eq!(body["abstain_count"], 0);
+        assert!(body["timestamp_millis"].as_i64().unwrap() > 0);
+
+        // 回落到阈值以下后再次越过才会通知
+        notifier.publish("https://a/", up(4, 3));
+        notifier.publish("https://a/", up(5, 3));
+        let (_, body, _) = next_attempt(&mut attempts).await;
+        assert_eq!(
+            (body["up_count"].as_u64(), body["down_count"].as_u64()),
+            (Some(5), Some(3))
+        );
+
+        // 清空后越过阈值也会通知
+        notifier.reset(Some("https://a/"));
+        notifier.publish("https://a/", up(2, 0));
+        let (_, body, _) = next_attempt(&mut attempts).await;
+        assert_eq!(body["up_count"], 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(attempts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_delivery_is_retried_with_backoff() {
+        let (url, mut attempts) = mock_receiver(2);
+        let notifier = WebhookNotifier::spawn(url, 1);
+        notifier.publish("https://a/", up(1, 0));
+
+        let mut times = Vec::new();
+        for delivered in [false, false, true] {
+            let (at, body, ok) = next_attempt(&mut attempts).await;
+            assert_eq!(ok, delivered);
+            assert_eq!(body["url"], "https://a/");
+            times.push(at);
+        }
+        assert!(times[1] - times[0] >= RETRY_BACKOFF);
+        assert!(times[2] - times[1] >= RETRY_BACKOFF * 2);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(attempts.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn delivery_gives_up_after_the_last_retry() {
+        let (url, mut attempts) = mock_receiver(usize::MAX);
+        let notifier = WebhookNotifier::spawn(url, 1);
+        notifier.publish("https://a/", up(1, 0));
+
+        for _ in 0..=MAX_RETRIES {
+            let (_, _, delivered) = next_attempt(&mut attempts).await;
+            assert!(!delivered);
+        }
+        // 最后一次重试之后不会再有请求
+        let after = RETRY_BACKOFF * 2u32.pow(MAX_RETRIES) + Duration::from_millis(200);
+        assert!(timeout(after, attempts.recv()).await.is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::{Error, ErrorKind, Result};
+
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    tungstenite::{self, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::framing::{CallbackBack, ChunkReader, ChunkWriter, FramedConnection, Transport};
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// 与 TcpClient 相同的帧格式, 每个帧放在一个 WebSocket binary 消息里
+pub type WebSocketClient<T> = FramedConnection<WsStream, T>;
+
+impl<T> FramedConnection<WsStream, T>
+where
+    T: CallbackBack + Send + Sync + 'static,
+{
+    // url 为 ws://host:port/path
+    pub async fn connect(url: &str, router: T) -> Result<Self> {
+        let (stream, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .map_err(into_io_error)?;
+        Self::new(url, stream, router)
+    }
+}
+
+fn into_io_error(e: tungstenite::Error) -> Error {
+    match e {
+        tungstenite::Error::Io(e) => e,
+        e => Error::other(e),
+    }
+}
+
+impl Transport for WsStream {
+    type Reader = SplitStream<WsStream>;
+    type Writer = SplitSink<WsStream, Message>;
+
+    fn split(self) -> (Self::Reader, Self::Writer) {
+        let (sink, stream) = StreamExt::split(self);
+        (stream, sink)
+    }
+}
+
+#[tonic::async_trait]
+impl ChunkReader for SplitStream<WsStream> {
+    // ping/pong 由 tungstenite 自动处理, 不会以帧的形式出现
+    async fn read_chunk(&mut self, buf: &mut Vec<u8>) -> Result<bool> {
+        loop {
+            match self.next().await.transpose().map_err(into_io_error)? {
+                None | Some(Message::Close(_)) => return Ok(false),
+                Some(Message::Binary(data)) => {
+                    buf.extend_from_slice(&data);
+                    return Ok(true);
+                }
+                Some(Message::Text(_)) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "unexpected text message",
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ChunkWriter for SplitSink<WsStream, Message> {
+    async fn write_chunk(&mut self, chunk: Vec<u8>) -> Result<()> {
+        self.send(Message::Binary(chunk))
+            .await
+            .map_err(into_io_error)
+    }
+}